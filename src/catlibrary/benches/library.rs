@@ -0,0 +1,108 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+
+use cat_library::clock::{Rng, SeededRng};
+use cat_library::library::{Book, Library};
+
+const WORDS: &[&str] = &[
+    "cat", "kipper", "nap", "sofa", "library", "stacks", "whiskers", "tuna", "shelf", "sunbeam",
+    "closet", "holiday", "page", "treatise", "spinal", "arts",
+];
+
+/// About `len` bytes of words, the same ones every time for the same `rng`.
+fn prose(rng: &dyn Rng, len: usize) -> String {
+    let mut text = String::with_capacity(len + 16);
+    while text.len() < len {
+        let word = WORDS[rng.next_u64() as usize % WORDS.len()];
+        text.push_str(word);
+        text.push(if rng.next_u64().is_multiple_of(12) {
+            '\n'
+        } else {
+            ' '
+        });
+    }
+    text
+}
+
+fn book(rng: &dyn Rng, content_len: usize) -> Book {
+    Book {
+        title: prose(rng, 20).trim().to_string(),
+        author: prose(rng, 8).trim().to_string(),
+        description: prose(rng, 60).trim().to_string(),
+        content: prose(rng, content_len),
+        language: None,
+    }
+}
+
+fn library(rt: &Runtime, books: usize, content_len: usize) -> Library {
+    let rng = SeededRng::new(books as u64 ^ content_len as u64);
+    rt.block_on(async {
+        let lib = Library::new();
+        for _ in 0..books {
+            lib.add(book(&rng, content_len), Library::OPERATOR)
+                .await
+                .unwrap();
+        }
+        lib
+    })
+}
+
+fn search(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("search");
+    for books in [100, 1_000] {
+        for content_len in [256, 16 * 1024] {
+            let lib = library(&rt, books, content_len);
+            let id = BenchmarkId::from_parameter(format!("{books} books, {content_len}B"));
+            group.bench_with_input(id, &lib, |b, lib| {
+                b.to_async(&rt)
+                    .iter(|| lib.search("kipper nap", Library::OPERATOR));
+            });
+        }
+    }
+    group.finish();
+}
+
+fn lookup_checkouts_by_guest(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("lookup_checkouts_by_guest");
+    for books in [100, 1_000] {
+        let lib = library(&rt, books, 256);
+        let reader = rt
+            .block_on(lib.register_guest("10.0.0.2".parse().unwrap(), "reader"))
+            .unwrap();
+        let found = rt.block_on(lib.search("", Library::OPERATOR));
+        for &(_sim, book_id, ..) in found.iter().step_by(10) {
+            lib.checkout(book_id, reader).unwrap();
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(books), &lib, |b, lib| {
+            b.to_async(&rt)
+                .iter(|| lib.lookup_checkouts_by_guest(reader));
+        });
+    }
+    group.finish();
+}
+
+fn add(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let rng = SeededRng::new(0);
+    let mut group = c.benchmark_group("add");
+    for content_len in [256, 16 * 1024] {
+        let book = book(&rng, content_len);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{content_len}B")),
+            &book,
+            |b, book| {
+                b.to_async(&rt).iter_batched(
+                    || (Library::new(), book.clone()),
+                    |(lib, book)| async move { lib.add(book, Library::OPERATOR).await.unwrap() },
+                    BatchSize::SmallInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search, lookup_checkouts_by_guest, add);
+criterion_main!(benches);
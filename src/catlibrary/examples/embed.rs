@@ -0,0 +1,68 @@
+//! The library engine on its own, with no sockets or shell: open a library,
+//! let a guest in, and lend them a book while listening to what happens.
+//!
+//! ```console
+//! $ cargo run --example embed
+//! ```
+
+use core::net::{IpAddr, Ipv4Addr};
+use core::time::Duration;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use cat_library::{Book, Library, LibraryEvent, ManualClock, SeededRng, UpdateEntryError};
+
+fn book(title: &str, content: &str) -> Book {
+    Book {
+        title: String::from(title),
+        author: String::from("whiskers"),
+        description: String::from("a classic"),
+        content: String::from(content),
+        language: None,
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    /* a clock and dice of our own, so every run goes the same way */
+    let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+    let library = Library::builder()
+        .clock(clock.clone())
+        .rng(Arc::new(SeededRng::new(7)))
+        .operator_nick("librarian")
+        .books([
+            book("The Long Nap", "zzz\nzzz\n"),
+            book("Tuna, a Memoir", "it was delicious.\n"),
+        ])
+        .build()
+        .await;
+
+    let mut events = library.subscribe();
+    let mittens = library
+        .register_guest(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), "mittens")
+        .await?;
+
+    let found = library.search("Nap", mittens).await;
+    let (_score, nap, _meta, _matched) = *found.first().expect("the nap book is on the shelves");
+    println!("found '{}'", library.lookup_title(nap).await);
+
+    library.checkout(nap, mittens)?;
+    assert_eq!(
+        Err(UpdateEntryError::AlreadyCheckedOut(mittens)),
+        library.checkout(nap, Library::OPERATOR)
+    );
+
+    clock.advance(Duration::from_secs(3 * 60 * 60));
+    let held = library.checkin(nap, mittens)?;
+    println!("mittens had it for {}s", held.as_secs());
+
+    while let Ok(event) = events.try_recv() {
+        let what = match event {
+            LibraryEvent::CheckedOut(_) => "checked out",
+            LibraryEvent::CheckedIn(_) => "checked in",
+            _ => "something else",
+        };
+        println!("{what}: '{}'", library.lookup_title(event.book_id()).await);
+    }
+    Ok(())
+}
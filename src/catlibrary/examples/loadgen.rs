@@ -0,0 +1,203 @@
+//! Many guests at once against a running library, timing each command.
+//!
+//! ```console
+//! $ cargo run --release &
+//! $ cargo run --release --example loadgen -- --clients 50
+//! ```
+
+use anyhow::Context;
+use core::net::{IpAddr, Ipv4Addr, SocketAddr};
+use core::time::Duration;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::task::JoinSet;
+
+use cat_library::client::Client;
+use cat_library::shell::SET_PASSPHRASE_PROMPT;
+
+const PROMPT: &str = "; ";
+const ITEM_PROMPT: &str = "which item number? ";
+const QUERIES: &[&str] = &["cat", "kipper", "library", ""];
+
+struct Args {
+    addr: SocketAddr,
+    clients: usize,
+    rounds: usize,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Self {
+            addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 6868),
+            clients: 10,
+            rounds: 20,
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let mut value = |flag: &str| {
+                argv.next()
+                    .with_context(|| format!("{flag} requires a value"))
+            };
+            match arg.as_str() {
+                "--addr" => args.addr = value("--addr")?.parse().context("invalid --addr")?,
+                "--clients" => {
+                    args.clients = value("--clients")?.parse().context("invalid --clients")?;
+                }
+                "--rounds" => {
+                    args.rounds = value("--rounds")?.parse().context("invalid --rounds")?;
+                }
+                _ => anyhow::bail!("unrecognized argument '{arg}'"),
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// How long each command took, by name.
+type Timings = BTreeMap<&'static str, Vec<Duration>>;
+
+/// Connect as guest number `n`. The library knows guests by address, so on
+/// loopback each one gets an address of its own.
+async fn connect(addr: SocketAddr, n: usize) -> anyhow::Result<TcpStream> {
+    let socket = TcpSocket::new_v4()?;
+    if addr.ip().is_loopback() {
+        let [_, _, hi, lo] = u32::try_from(n + 2)?.to_be_bytes();
+        let local = IpAddr::V4(Ipv4Addr::new(127, 1, hi, lo));
+        socket.bind(SocketAddr::new(local, 0))?;
+    }
+    Ok(socket.connect(addr).await?)
+}
+
+/// Run `cmd`, answering whatever it asks from `answers` in turn, until it's
+/// back at the prompt.
+async fn timed(
+    client: &mut Client<TcpStream>,
+    timings: &mut Timings,
+    name: &'static str,
+    cmd: &str,
+    answers: &[(&str, &str)],
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    client.send(cmd).await?;
+    let prompts: Vec<&str> = answers
+        .iter()
+        .map(|&(prompt, _)| prompt)
+        .chain([PROMPT])
+        .collect();
+    loop {
+        let (idx, _text) = client.expect_any(&prompts).await?;
+        match answers.get(idx) {
+            Some((_prompt, answer)) => client.send(answer).await?,
+            None => break,
+        }
+    }
+    timings.entry(name).or_default().push(start.elapsed());
+    Ok(())
+}
+
+async fn guest(addr: SocketAddr, n: usize, rounds: usize) -> anyhow::Result<Timings> {
+    let mut timings = Timings::new();
+    let mut client = Client::new(connect(addr, n).await?);
+    client.expect(PROMPT).await?;
+
+    /* returning to the kiosk from last time? then this just reads */
+    let nick = format!("loadgen {n} {}", std::process::id());
+    timed(
+        &mut client,
+        &mut timings,
+        "register",
+        "read",
+        &[
+            ("what is it? ", &nick),
+            (SET_PASSPHRASE_PROMPT, ""),
+            (ITEM_PROMPT, ""),
+        ],
+    )
+    .await?;
+
+    for round in 0..rounds {
+        let query = QUERIES[(n + round) % QUERIES.len()];
+        let search = [("search query? ", query)];
+        let choose = [("search query? ", query), (ITEM_PROMPT, "1")];
+        timed(&mut client, &mut timings, "search", "search", &search).await?;
+        timed(&mut client, &mut timings, "checkout", "checkout", &choose).await?;
+        timed(
+            &mut client,
+            &mut timings,
+            "read",
+            "read",
+            &[(ITEM_PROMPT, "1"), (":", "q")],
+        )
+        .await?;
+        timed(
+            &mut client,
+            &mut timings,
+            "checkin",
+            "checkin",
+            &[(ITEM_PROMPT, "1")],
+        )
+        .await?;
+    }
+
+    client.send("quit").await?;
+    Ok(timings)
+}
+
+/// The `p`th percentile of `sorted`.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse()?;
+
+    let start = Instant::now();
+    let mut guests = JoinSet::new();
+    for n in 0..args.clients {
+        guests.spawn(guest(args.addr, n, args.rounds));
+    }
+    let mut timings = Timings::new();
+    let mut failed = 0;
+    while let Some(result) = guests.join_next().await {
+        match result? {
+            Ok(times) => {
+                for (name, mut times) in times {
+                    timings.entry(name).or_default().append(&mut times);
+                }
+            }
+            Err(err) => {
+                eprintln!("a guest gave up: {err:#}");
+                failed += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} guests, {} rounds each, in {:.2}s ({failed} gave up)",
+        args.clients,
+        args.rounds,
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "{:<10} {:>8} {:>10} {:>10} {:>10}",
+        "command", "count", "per sec", "p50 ms", "p99 ms"
+    );
+    for (name, times) in &mut timings {
+        times.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!(
+            "{name:<10} {:>8} {:>10.1} {:>10.2} {:>10.2}",
+            times.len(),
+            times.len() as f64 / elapsed.as_secs_f64(),
+            ms(percentile(times, 0.50)),
+            ms(percentile(times, 0.99)),
+        );
+    }
+
+    anyhow::ensure!(failed == 0, "{failed} of {} guests gave up", args.clients);
+    Ok(())
+}
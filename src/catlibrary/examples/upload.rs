@@ -0,0 +1,101 @@
+//! Add text files to a running library, one book each, without answering
+//! any prompts.
+//!
+//! ```console
+//! $ cargo run --example upload -- --author whiskers naps.txt tuna.txt
+//! ```
+//!
+//! Register from this address interactively first; books are added as you.
+
+use anyhow::Context;
+use core::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use tokio::net::TcpStream;
+
+use cat_library::client::Client;
+use cat_library::library::Book;
+use cat_library::upload;
+
+const PROMPT: &str = "; ";
+
+struct Args {
+    addr: SocketAddr,
+    author: String,
+    files: Vec<PathBuf>,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Self {
+            addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 6868),
+            author: String::new(),
+            files: Vec::new(),
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let mut value = |flag: &str| {
+                argv.next()
+                    .with_context(|| format!("{flag} requires a value"))
+            };
+            match arg.as_str() {
+                "--addr" => args.addr = value("--addr")?.parse().context("invalid --addr")?,
+                "--author" => args.author = value("--author")?,
+                _ if arg.starts_with("--") => anyhow::bail!("unrecognized argument '{arg}'"),
+                _ => args.files.push(arg.into()),
+            }
+        }
+        anyhow::ensure!(!args.author.is_empty(), "--author is required");
+        Ok(args)
+    }
+}
+
+/// `book` framed for `upload`.
+fn frame(book: &Book) -> String {
+    let mut framed = format!(
+        "TITLE: {}\nAUTHOR: {}\nDESC: {}\n\n",
+        book.title, book.author, book.description
+    );
+    framed.push_str(&book.content);
+    framed.push_str(upload::TERMINATOR);
+    framed.push_str(&upload::digest(&book.content));
+    framed
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse()?;
+    let mut client = Client::new(TcpStream::connect(args.addr).await?);
+    let (idx, _text) = client.expect_any(&[PROMPT, "what is it? "]).await?;
+    anyhow::ensure!(idx == 0, "register from this address first");
+
+    let mut failed = 0;
+    for path in &args.files {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let title = path.file_stem().unwrap_or_default().to_string_lossy();
+        let book = Book {
+            title: title.into_owned(),
+            author: args.author.clone(),
+            description: String::new(),
+            content: Book::content_from_lines(text.lines().map(String::from)),
+            language: None,
+        };
+
+        client.send("upload").await?;
+        client.send(&frame(&book)).await?;
+        let reply = client.expect(PROMPT).await?;
+        let reply = reply.lines().next().unwrap_or_default();
+        println!("{}: {reply}", path.display());
+        if !reply.starts_with("OK ") {
+            failed += 1;
+        }
+    }
+
+    client.send("quit").await?;
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} of {} uploads failed",
+        args.files.len()
+    );
+    Ok(())
+}
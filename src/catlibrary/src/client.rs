@@ -0,0 +1,72 @@
+use anyhow::Context;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The guest's side of a session: type a line, then wait for the prompt that
+/// says the server wants the next one, the way a person would.
+#[derive(Debug)]
+pub struct Client<S> {
+    stream: S,
+    /// Sent since the last prompt we waited for.
+    seen: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            seen: Vec::new(),
+        }
+    }
+
+    /// Type `line` and press enter.
+    pub async fn send(&mut self, line: &str) -> anyhow::Result<()> {
+        self.stream.write_all(line.as_bytes()).await?;
+        self.stream.write_all(b"\n").await?;
+        Ok(self.stream.flush().await?)
+    }
+
+    /// Everything the server sent up to and including `prompt`.
+    pub async fn expect(&mut self, prompt: &str) -> anyhow::Result<String> {
+        let (_idx, text) = self.expect_any(&[prompt]).await?;
+        Ok(text)
+    }
+
+    /// Like [`Client::expect`], for when the server may ask any of `prompts`
+    /// next. Says which one it was.
+    pub async fn expect_any(&mut self, prompts: &[&str]) -> anyhow::Result<(usize, String)> {
+        loop {
+            /* the server only stops talking once it's waiting on us */
+            let asked = prompts
+                .iter()
+                .position(|prompt| self.seen.ends_with(prompt.as_bytes()));
+            if let Some(idx) = asked {
+                let text = String::from_utf8_lossy(&self.seen).into_owned();
+                self.seen.clear();
+                return Ok((idx, text));
+            }
+
+            let mut buf = [0; 1024];
+            let n = self
+                .stream
+                .read(&mut buf)
+                .await
+                .with_context(|| format!("failed waiting for {prompts:?}"))?;
+            if n == 0 {
+                let seen = String::from_utf8_lossy(&self.seen);
+                anyhow::bail!("hung up waiting for {prompts:?} after {seen:?}");
+            }
+            self.seen.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    /// Answer `prompt` with `line` once it's asked.
+    pub async fn ask(&mut self, prompt: &str, line: &str) -> anyhow::Result<String> {
+        let text = self.expect(prompt).await?;
+        self.send(line).await?;
+        Ok(text)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
@@ -0,0 +1,101 @@
+use core::fmt::Debug;
+use core::time::Duration;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Where the library gets the time from. [`SystemClock`] outside of tests.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+pub struct ManualClock(Mutex<SystemTime>);
+
+impl ManualClock {
+    pub fn new(at: SystemTime) -> Self {
+        Self(Mutex::new(at))
+    }
+
+    pub fn set(&self, at: SystemTime) {
+        *self.0.lock().unwrap() = at;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Where the library gets dice rolls from. [`OsRng`] outside of tests.
+/// Secrets never come from here; see [`secret_bytes`].
+pub trait Rng: Debug + Send + Sync {
+    fn next_u64(&self) -> u64;
+}
+
+/// The operating system's own randomness, as fit for salts and tokens as
+/// anything gets.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsRng;
+
+impl Rng for OsRng {
+    fn next_u64(&self) -> u64 {
+        u64::from_le_bytes(secret_bytes())
+    }
+}
+
+/// The same numbers every time for the same seed. Not for anything secret.
+#[derive(Debug)]
+pub struct SeededRng(Mutex<u64>);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(seed))
+    }
+}
+
+impl Rng for SeededRng {
+    /// SplitMix64.
+    fn next_u64(&self) -> u64 {
+        let mut state = self.0.lock().unwrap();
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// `N` bytes straight from the operating system, for salts and tokens,
+/// whatever [`Rng`] the library was given.
+pub fn secret_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    /* a secret made up some weaker way is worse than none */
+    getrandom::getrandom(&mut bytes).expect("the OS has no randomness to give");
+    bytes
+}
+
+/// `N` bytes from `rng`.
+pub fn random_bytes<const N: usize>(rng: &dyn Rng) -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let noise = rng.next_u64().to_le_bytes();
+        chunk.copy_from_slice(&noise[..chunk.len()]);
+    }
+    bytes
+}
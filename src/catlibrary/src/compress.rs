@@ -0,0 +1,150 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::library::{self, Book, BookID};
+
+/// A book as the library keeps it: the cover as-is, the content deflated.
+#[derive(Debug)]
+pub struct StoredBook {
+    pub title: String,
+    pub author: String,
+    pub description: String,
+    pub language: Option<String>,
+
+    /// Shared, so edits to the cover don't copy it.
+    content: Arc<[u8]>,
+    content_len: usize,
+    /// Byte offset of the start of each line of content, as split by
+    /// [`Book::lines`].
+    line_offsets: Arc<[usize]>,
+}
+
+impl StoredBook {
+    pub fn new(book: &Book) -> Self {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(book.content.as_bytes())
+            .expect("writing to a Vec can't fail");
+        let content = encoder.finish().expect("writing to a Vec can't fail");
+
+        let mut line_offsets = Vec::new();
+        let mut offset = 0;
+        for line in book.content.split_inclusive('\n') {
+            line_offsets.push(offset);
+            offset += line.len();
+        }
+
+        Self {
+            title: book.title.clone(),
+            author: book.author.clone(),
+            description: book.description.clone(),
+            language: book.language.clone(),
+            content: content.into(),
+            content_len: book.content.len(),
+            line_offsets: line_offsets.into(),
+        }
+    }
+
+    /// The same content, in the same language, under a different cover.
+    pub fn with_cover(&self, title: String, author: String, description: String) -> Self {
+        Self {
+            title,
+            author,
+            description,
+            language: self.language.clone(),
+            content: Arc::clone(&self.content),
+            content_len: self.content_len,
+            line_offsets: Arc::clone(&self.line_offsets),
+        }
+    }
+
+    pub fn unpack(&self) -> Book {
+        let mut content = String::with_capacity(self.content_len);
+        DeflateDecoder::new(&*self.content)
+            .read_to_string(&mut content)
+            .expect("stored content must inflate to what was deflated");
+        Book {
+            title: self.title.clone(),
+            author: self.author.clone(),
+            description: self.description.clone(),
+            content,
+            language: self.language.clone(),
+        }
+    }
+
+    /// As in [`Book::cover_key`].
+    pub fn cover_key(&self) -> (String, String) {
+        (
+            library::normalize_name(&self.title),
+            library::normalize_name(&self.author),
+        )
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_offsets.len()
+    }
+
+    pub fn line_offsets(&self) -> &[usize] {
+        &self.line_offsets
+    }
+
+    /// Bytes of content before deflating.
+    pub fn content_len(&self) -> usize {
+        self.content_len
+    }
+
+    /// Bytes spent holding the content, line index included.
+    pub fn stored_len(&self) -> usize {
+        self.content.len() + self.line_offsets.len() * size_of::<usize>()
+    }
+}
+
+/// The last few books unpacked, least recently used first.
+///
+/// Entries remember which [`StoredBook`] they came from, so one that was
+/// replaced since (e.g. by an edit) is simply never hit again.
+#[derive(Debug)]
+pub struct BookCache {
+    capacity: usize,
+    entries: VecDeque<(BookID, Arc<StoredBook>, Arc<Book>)>,
+}
+
+impl BookCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity + 1),
+        }
+    }
+
+    pub fn get(&mut self, id: BookID, stored: &Arc<StoredBook>) -> Option<Arc<Book>> {
+        let idx = self
+            .entries
+            .iter()
+            .position(|(cached, from, _)| *cached == id && Arc::ptr_eq(from, stored))?;
+        let entry = self.entries.remove(idx)?;
+        let book = Arc::clone(&entry.2);
+        self.entries.push_back(entry);
+        Some(book)
+    }
+
+    pub fn insert(&mut self, id: BookID, stored: Arc<StoredBook>, book: Arc<Book>) {
+        self.entries.retain(|(cached, ..)| *cached != id);
+        self.entries.push_back((id, stored, book));
+        if self.capacity < self.entries.len() {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
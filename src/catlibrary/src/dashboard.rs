@@ -0,0 +1,119 @@
+use core::time::Duration;
+use std::sync::Arc;
+
+use crate::library::{Library, LibraryEvent, LibraryMetrics, PoolUsage};
+
+/// How long since a connected guest's last command before the dashboard
+/// says they're idle.
+pub const IDLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Somebody connected: their nickname, if they've picked one, the command
+/// they're running, if any, and how long ago they last ran a command, if they've
+/// registered.
+pub type SessionRow = (Option<Arc<str>>, Option<&'static str>, Option<Duration>);
+
+/// One screenful of how the library is doing, for the operator's
+/// `dashboard`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dashboard {
+    pub uptime: Duration,
+    /// Everyone connected, in order of arrival.
+    pub sessions: Vec<SessionRow>,
+    pub metrics: LibraryMetrics,
+    /// The library's recent events, oldest first: how long ago each
+    /// happened, and the title of the book it happened to.
+    pub events: Vec<(Duration, LibraryEvent, String)>,
+    pub pool: PoolUsage,
+}
+
+impl Dashboard {
+    pub async fn gather(library: &Library) -> Self {
+        let now = library.now();
+        let mut sessions = Vec::new();
+        for (guest, activity) in library.visitors() {
+            let idle = library
+                .last_seen(guest)
+                .map(|at| now.duration_since(at).unwrap_or(Duration::ZERO));
+            sessions.push((library.lookup_nick(guest).await, activity, idle));
+        }
+
+        let mut events = Vec::new();
+        for (at, event) in library.recent_events() {
+            let ago = now.duration_since(at).unwrap_or(Duration::ZERO);
+            let title = library.lookup_title(event.book_id()).await;
+            events.push((ago, event, title));
+        }
+
+        Self {
+            uptime: library.uptime(),
+            sessions,
+            metrics: library.metrics().await,
+            events,
+            pool: library.pool_usage().await,
+        }
+    }
+
+    /// The dashboard, one line per entry, without line endings.
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = vec![format!("up {}", format_duration(self.uptime))];
+
+        lines.push(format!("{} connected:", self.sessions.len()));
+        for (nick, activity, idle) in &self.sessions {
+            let nick = nick.as_deref().unwrap_or("(looking around)");
+            let activity = activity.unwrap_or("at the prompt");
+            /* only worth a mention once it looks like they've wandered off */
+            let idle = match idle {
+                Some(idle) if IDLE_AFTER <= *idle => format!(", idle {}", format_duration(*idle)),
+                _ => String::new(),
+            };
+            lines.push(format!("  {nick}: {activity}{idle}"));
+        }
+
+        let LibraryMetrics {
+            books,
+            trashed,
+            checked_out,
+            guests,
+            ..
+        } = self.metrics;
+        lines.push(format!(
+            "{books} on the shelves, {trashed} in the trash, {checked_out} checked out; {guests} guests"
+        ));
+
+        lines.push("recent events:".to_string());
+        for (ago, event, title) in &self.events {
+            let what = match event {
+                LibraryEvent::Added(_) => "added",
+                LibraryEvent::CheckedOut(_) => "checked out",
+                LibraryEvent::CheckedIn(_) => "checked in",
+                LibraryEvent::Removed(_) => "removed",
+                LibraryEvent::Restored(_) => "restored",
+            };
+            let ago = format_duration(*ago);
+            lines.push(format!("  {ago} ago: {what} \"{title}\""));
+        }
+
+        let PoolUsage {
+            books,
+            content_bytes,
+            stored_bytes,
+        } = self.pool;
+        lines.push(format!(
+            "pool: {books} books, {content_bytes} bytes of content kept in {stored_bytes}"
+        ));
+        lines
+    }
+}
+
+/// `duration` to the second, biggest unit first, e.g. `1h02m03s`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours != 0 {
+        format!("{hours}h{mins:02}m{secs:02}s")
+    } else if mins != 0 {
+        format!("{mins}m{secs:02}s")
+    } else {
+        format!("{secs}s")
+    }
+}
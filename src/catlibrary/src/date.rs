@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Days since the Unix epoch, in UTC. Times before the epoch count as day 0.
+pub fn day_number(at: SystemTime) -> i64 {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    (secs / 86_400) as i64
+}
+
+/// Proleptic Gregorian (year, month, day) of a day number.
+///
+/// See Howard Hinnant's `civil_from_days`.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Day number of a proleptic Gregorian (year, month, day).
+///
+/// See Howard Hinnant's `days_from_civil`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// `YYYY-MM-DD`, in UTC.
+pub fn format_date(at: SystemTime) -> String {
+    let (year, month, day) = civil_from_days(day_number(at));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ`, in UTC, to the second.
+pub fn format_timestamp(at: SystemTime) -> String {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (hours, mins, secs) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    format!("{}T{hours:02}:{mins:02}:{secs:02}Z", format_date(at))
+}
+
+/// The time [`format_timestamp`] wrote as `s`.
+pub fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let (date, time) = s.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+    let mut time = time.splitn(3, ':');
+    let hours: u64 = time.next()?.parse().ok()?;
+    let mins: u64 = time.next()?.parse().ok()?;
+    let secs: u64 = time.next()?.parse().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || 24 <= hours
+        || 60 <= mins
+        || 60 <= secs
+    {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86_400 + hours * 3600 + mins * 60 + secs;
+    Some(UNIX_EPOCH + core::time::Duration::from_secs(secs))
+}
@@ -0,0 +1,157 @@
+//! What an edit changed in a book, line by line, for showing its author
+//! before it's saved.
+//!
+//! Lines are compared whole, as [`str::lines`] splits them, so whether the
+//! last one ends in a newline makes no difference.
+
+use core::cmp;
+
+/// How many unchanged lines [`unified`] shows either side of a change.
+pub const CONTEXT: usize = 3;
+
+/// Most cells the table behind [`diff`] may have. Changes too tangled to fit
+/// come out as the whole changed stretch removed and written anew, which is
+/// still right, just not as short as it could be.
+const MAX_TABLE: usize = 1 << 22;
+
+/// One line of a diff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/// Every line of `old` and `new`, in order, marked with whether it's in both,
+/// only `new`, or only `old`. Keeps as many lines the same as it can, and
+/// puts what was removed before what replaced it.
+pub fn diff<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    /* most edits touch a few lines in the middle; only those need the table */
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut out: Vec<Line<'a>> = old[..prefix].iter().map(|line| Line::Same(line)).collect();
+    if a.len().saturating_mul(b.len()) > MAX_TABLE {
+        out.extend(a.iter().map(|line| Line::Removed(line)));
+        out.extend(b.iter().map(|line| Line::Added(line)));
+    } else {
+        longest_common(a, b, &mut out);
+    }
+    out.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| Line::Same(line)),
+    );
+    out
+}
+
+/// The diff of `a` and `b` by their longest common subsequence, onto `out`.
+fn longest_common<'a>(a: &[&'a str], b: &[&'a str], out: &mut Vec<Line<'a>>) {
+    /* lengths[i * width + j]: how long the longest one of a[i..] and b[j..] is */
+    let width = b.len() + 1;
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i * width + j] = if a[i] == b[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                cmp::max(lengths[(i + 1) * width + j], lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            out.push(Line::Same(a[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            out.push(Line::Removed(a[i]));
+            i += 1;
+        } else {
+            out.push(Line::Added(b[j]));
+            j += 1;
+        }
+    }
+    out.extend(a[i..].iter().map(|line| Line::Removed(line)));
+    out.extend(b[j..].iter().map(|line| Line::Added(line)));
+}
+
+/// How many lines `diff` adds, and how many it removes.
+pub fn counts(diff: &[Line]) -> (usize, usize) {
+    diff.iter()
+        .fold((0, 0), |(added, removed), line| match line {
+            Line::Same(_) => (added, removed),
+            Line::Added(_) => (added + 1, removed),
+            Line::Removed(_) => (added, removed + 1),
+        })
+}
+
+/// `diff` unified-style: each run of changes under an `@@ -l,n +l,n @@`
+/// header, with added lines marked `+`, removed ones `-`, and up to
+/// [`CONTEXT`] unchanged ones around them. Nothing, if nothing changed.
+pub fn unified(diff: &[Line]) -> Vec<String> {
+    /* which stretches of the diff to show, overlapping ones merged */
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (idx, line) in diff.iter().enumerate() {
+        if let Line::Same(_) = line {
+            continue;
+        }
+        let start = idx.saturating_sub(CONTEXT);
+        let end = cmp::min(idx + CONTEXT + 1, diff.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = Vec::new();
+    /* lines of the old and new versions before where we are */
+    let (mut old_at, mut new_at) = (0, 0);
+    let mut shown = 0;
+    for (start, end) in hunks {
+        let (skipped_old, skipped_new) = lengths(&diff[shown..start]);
+        old_at += skipped_old;
+        new_at += skipped_new;
+        let hunk = &diff[start..end];
+        let (old_len, new_len) = lengths(hunk);
+        /* an empty side is numbered by the line before it, as diff does */
+        let from = |at: usize, len: usize| if len == 0 { at } else { at + 1 };
+        out.push(format!(
+            "@@ -{},{old_len} +{},{new_len} @@",
+            from(old_at, old_len),
+            from(new_at, new_len)
+        ));
+        for line in hunk {
+            out.push(match line {
+                Line::Same(line) => format!(" {line}"),
+                Line::Added(line) => format!("+{line}"),
+                Line::Removed(line) => format!("-{line}"),
+            });
+        }
+        old_at += old_len;
+        new_at += new_len;
+        shown = end;
+    }
+    out
+}
+
+/// How many lines of the old and new versions `lines` covers.
+fn lengths(lines: &[Line]) -> (usize, usize) {
+    let (added, removed) = counts(lines);
+    let same = lines.len() - added - removed;
+    (same + removed, same + added)
+}
@@ -1,9 +1,24 @@
 use core::cmp;
+use core::num::IntErrorKind;
 use std::borrow::Cow;
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
 
-use crate::library::{Book, Library, Metadata};
-use crate::shell;
+use crate::date;
+use crate::library::{Book, BookID, GuestId, Library, Metadata};
+use crate::messages::Msg;
+use crate::sanitize;
+use crate::shell::{self, Notices, Session};
+
+/// How many other books a cover page suggests.
+pub const SIMILAR_BOOKS: usize = 3;
+
+/// While inserting, how many lines go by between counts of how many so far.
+pub const INSERT_FEEDBACK: usize = 100;
+
+/// Turns reverse video on around a highlighted match.
+pub const HIGHLIGHT_ON: &str = "\x1B[7m";
+/// Turns it back off.
+pub const HIGHLIGHT_OFF: &str = "\x1B[27m";
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Passback {
@@ -11,6 +26,85 @@ pub enum Passback {
     Continue,
 }
 
+/// A line, as typed at the `:` prompt, to be found in the buffer only when
+/// it's used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// Line `n`, counting from 1.
+    Absolute(usize),
+    /// `n` lines after the current one, or before it if negative.
+    Relative(i64),
+    Current,
+    Last,
+}
+
+/// Which end of the buffer an [`Address`] fell off, having been held to the
+/// line at that end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutOfBounds {
+    BeforeFirst,
+    PastLast,
+}
+
+impl Address {
+    /// `N`, `+N`, `-N`, `.` or `$`. A sign needs a number after it, and
+    /// numbers too big to count saturate.
+    pub fn parse(s: &str) -> Option<Self> {
+        fn number<T: core::str::FromStr<Err = core::num::ParseIntError>>(
+            digits: &str,
+            max: T,
+        ) -> Option<T> {
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            match digits.parse() {
+                Ok(n) => Some(n),
+                Err(err) if *err.kind() == IntErrorKind::PosOverflow => Some(max),
+                Err(_) => None,
+            }
+        }
+
+        match s {
+            "." => Some(Self::Current),
+            "$" => Some(Self::Last),
+            _ => {
+                if let Some(digits) = s.strip_prefix('+') {
+                    number(digits, i64::MAX).map(Self::Relative)
+                } else if let Some(digits) = s.strip_prefix('-') {
+                    number(digits, i64::MAX).map(|n| Self::Relative(-n))
+                } else {
+                    number(s, usize::MAX).map(Self::Absolute)
+                }
+            }
+        }
+    }
+
+    /// The index of the line this is, with `cur_line` current in a buffer of
+    /// `num_lines`. An address outside the buffer is held to the nearest end,
+    /// and says which.
+    pub fn resolve(self, cur_line: usize, num_lines: usize) -> (usize, Option<OutOfBounds>) {
+        let last = num_lines.saturating_sub(1);
+        let idx = match self {
+            Self::Current => return (cmp::min(cur_line, last), None),
+            Self::Last => return (last, None),
+            Self::Absolute(n) => n.checked_sub(1),
+            Self::Relative(by) => {
+                let magnitude = usize::try_from(by.unsigned_abs()).unwrap_or(usize::MAX);
+                if by < 0 {
+                    cur_line.checked_sub(magnitude)
+                } else {
+                    Some(cur_line.saturating_add(magnitude))
+                }
+            }
+        };
+        match idx {
+            None => (0, Some(OutOfBounds::BeforeFirst)),
+            Some(idx) if last < idx => (last, Some(OutOfBounds::PastLast)),
+            Some(idx) => (idx, None),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Command {
     Quit,
@@ -19,7 +113,13 @@ pub enum Command {
     CountLines,
     LineNext(usize),
     LinePrev(usize),
-    LineGotoIdx(usize),
+    PageNext,
+    PagePrev,
+    Goto(Address),
+    SetMargin(bool),
+    SetHighlight(bool),
+    SetContext(usize),
+    SetPager(bool),
     // SetSearch(String),
     // SearchPrev,
     // SearchNext,
@@ -32,34 +132,51 @@ pub enum Command {
 }
 
 impl Command {
+    /// The next command typed. `<enter>` (or a space) turns a page if
+    /// `paging`, and goes on a line if not.
     pub async fn build<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
         stream: &mut S,
-        num_lines: usize,
+        paging: bool,
     ) -> anyhow::Result<Option<Self>> {
         let try_cmd = shell::readln(stream, ":").await?;
 
-        for (prefix, offset, ctor) in [
-            ("", 1, Self::LineGotoIdx as fn(usize) -> Self),
-            ("j", 0, Self::LineNext),
-            ("k", 0, Self::LinePrev),
+        if let Some(addr) = Address::parse(&try_cmd) {
+            return Ok(Some(Self::Goto(addr)));
+        }
+        for (prefix, ctor) in [
+            ("j", Self::LineNext as fn(usize) -> Self),
+            ("k", Self::LinePrev),
         ] {
             if let Some(try_by) = try_cmd.strip_prefix(prefix) {
                 if let Ok(num) = try_by.parse::<usize>() {
-                    let adjusted = num.saturating_sub(offset);
-                    return Ok(Some(ctor(adjusted)));
+                    return Ok(Some(ctor(num)));
                 }
             }
         }
+        if let Some(lines) = try_cmd.strip_prefix("set context ") {
+            if let Ok(lines) = lines.trim().parse::<usize>() {
+                return Ok(Some(Self::SetContext(lines)));
+            }
+        }
 
         let cmd = match try_cmd.as_str() {
             "q" | "quit" => Self::Quit,
             "?" | "h" | "help" => Self::Help,
             "p" => Self::Print,
             "l" => Self::CountLines,
+            "" if paging => Self::PageNext,
             "" | "j" => Self::LineNext(1),
+            "f" => Self::PageNext,
+            "b" => Self::PagePrev,
             "k" => Self::LinePrev(1),
-            "g" => Self::LineGotoIdx(0),
-            "G" => Self::LineGotoIdx(num_lines.saturating_sub(1)),
+            "g" => Self::Goto(Address::Absolute(1)),
+            "G" => Self::Goto(Address::Last),
+            "set margin" => Self::SetMargin(true),
+            "set nomargin" => Self::SetMargin(false),
+            "set highlight" => Self::SetHighlight(true),
+            "set nohighlight" => Self::SetHighlight(false),
+            "set pager" => Self::SetPager(true),
+            "set nopager" => Self::SetPager(false),
             "i" => Self::Insert,
             "a" => Self::Append,
             "c" => Self::Change,
@@ -73,23 +190,83 @@ impl Command {
 pub struct Editor<'vec, 'src> {
     lines: &'vec mut Vec<Cow<'src, str>>,
     readonly: bool,
+    /// How tall the guest's terminal is, if we know, for paging help.
+    pub rows: Option<usize>,
+    /// How wide the guest's terminal is, if we know, for fitting the margin.
+    pub cols: Option<usize>,
+    /// Between a line's number and its text.
+    pub separator: &'static str,
+    /// Whether lines are printed with their numbers.
+    margin: bool,
+    /// What to pick out wherever it appears, like the search that led here.
+    highlight: Option<String>,
+    /// Whether [`Editor::highlight`] is picked out, for terminals that
+    /// can't show it.
+    highlighting: bool,
+    /// How many lines to show either side of where a jump lands.
+    context: usize,
+    /// Whether `<enter>` turns a page rather than going on a line.
+    pub paging: bool,
+    /// The last line that may be shown, if not the end, and what to tell
+    /// anyone who tries to go past it.
+    pub max_line: Option<(usize, &'static str)>,
+    /// Where to hear news from, and as whom, to pass on at the `:` prompt.
+    pub notices: Option<(&'vec mut Notices, &'vec Library, GuestId)>,
 
     // NOTE: always refers to a valid line
     cur_line: usize,
     prev_line_printed: Option<usize>,
+    /// Whether the last move was a jump, to be shown with its
+    /// [`Editor::context`] rather than as a run of lines.
+    jumped: bool,
+    /// The last line shown after the current one by a jump, until the next
+    /// command, so stepping on doesn't show them again.
+    context_end: Option<usize>,
+    /// Whether the first page is about to be shown whole, to say so if
+    /// that's the whole book.
+    first_page: bool,
+    /// How many lines in the furthest line printed so far is.
+    lines_reached: usize,
+    /// Where the last address fell off the buffer, to say after printing.
+    out_of_bounds: Option<OutOfBounds>,
     linum_pad: usize,
 
     prev_cmd: Option<Command>,
 }
 
 impl<'vec, 'src> Editor<'vec, 'src> {
+    pub const DEFAULT_SEPARATOR: &'static str = " | ";
+    /// What the separator shrinks to when the terminal is too narrow for it.
+    pub const NARROW_SEPARATOR: &'static str = "|";
+    /// Fewest columns to leave for text before the margin shrinks.
+    pub const MIN_TEXT_COLS: usize = 20;
+    /// Lines shown either side of where a jump lands, unless `set context`.
+    pub const DEFAULT_CONTEXT: usize = 2;
+    /// How tall a terminal is taken to be when it hasn't said, for paging.
+    pub const DEFAULT_ROWS: usize = 24;
+
     pub fn new(lines: &'vec mut Vec<Cow<'src, str>>, readonly: bool) -> Self {
         let mut editor = Self {
             lines,
             readonly,
+            rows: None,
+            cols: None,
+            separator: Self::DEFAULT_SEPARATOR,
+            margin: true,
+            highlight: None,
+            highlighting: true,
+            context: Self::DEFAULT_CONTEXT,
+            paging: false,
+            max_line: None,
+            notices: None,
 
             cur_line: 0,
             prev_line_printed: None,
+            jumped: false,
+            context_end: None,
+            first_page: false,
+            lines_reached: 0,
+            out_of_bounds: None,
             linum_pad: 0,
 
             prev_cmd: None,
@@ -99,32 +276,135 @@ impl<'vec, 'src> Editor<'vec, 'src> {
         editor
     }
 
+    /// Sized for `session`'s terminal, passing on its notices.
+    pub fn for_session(mut self, session: &'vec mut Session, library: &'vec Library) -> Self {
+        self.rows = session.rows;
+        self.cols = session.cols;
+        self.paging = session.paging;
+        let guest = session.guest;
+        self.notices = session
+            .notices
+            .as_mut()
+            .map(|notices| (notices, library, guest));
+        self
+    }
+
     pub fn num_lines(&self) -> usize {
         self.lines.len()
     }
 
+    /// How many lines in the guest has got, by the furthest line shown.
+    pub fn lines_reached(&self) -> usize {
+        self.lines_reached
+    }
+
+    /// The index of the line the guest is on.
+    pub fn cur_line(&self) -> usize {
+        self.cur_line
+    }
+
+    /// Start at line index `idx`, or the last line if there aren't that many,
+    /// printing just that line rather than everything before it.
+    pub fn start_at(&mut self, idx: usize) {
+        let idx = cmp::min(idx, self.lines.len().saturating_sub(1));
+        self.cur_line = idx;
+        self.prev_line_printed = idx.checked_sub(1);
+    }
+
+    /// Show the whole first page straight away rather than just its first
+    /// line, unless something has already moved it further in.
+    pub fn open_at_first_page(&mut self) {
+        if self.cur_line != 0 {
+            return;
+        }
+        self.cur_line = cmp::min(self.page_len(), self.lines.len()).saturating_sub(1);
+        self.prev_line_printed = None;
+        self.first_page = true;
+    }
+
+    /// Whether the guest is somewhere on the page reading opens on.
+    pub fn on_first_page(&self) -> bool {
+        self.cur_line < self.page_len()
+    }
+
+    /// Pick out `term` wherever it appears, and start at the first line it
+    /// appears on, unless that's past [`Editor::max_line`].
+    pub fn highlight(&mut self, term: &str) {
+        let term = sanitize::strip_ansi(term);
+        if term.is_empty() {
+            return;
+        }
+        let first = self
+            .lines
+            .iter()
+            .position(|line| sanitize::strip_ansi(line).contains(&term));
+        if let Some(idx) = first.filter(|&idx| self.max_line.is_none_or(|(max, _)| idx <= max)) {
+            self.start_at(idx);
+        }
+        self.highlight = Some(term);
+    }
+
+    /// How many lines a page turns: a screenful, less a row for the prompt.
+    fn page_len(&self) -> usize {
+        let rows = self.rows.unwrap_or(Self::DEFAULT_ROWS);
+        rows.saturating_sub(1).max(1)
+    }
+
     fn recompute_pad(&mut self) {
         let pad = usize::checked_ilog10(self.lines.len()).unwrap_or(0) + 1;
         let pad = usize::try_from(pad).unwrap_or(usize::MAX);
         self.linum_pad = pad;
     }
 
-    fn fmt_margin(pad: usize, idx: usize) -> String {
+    /// The separator, or a narrower one if the terminal can't spare it.
+    fn fit_separator(&self) -> &'static str {
+        let wide = self.linum_pad + shell::display_width(self.separator) + Self::MIN_TEXT_COLS;
+        match self.cols {
+            Some(cols) if cols < wide => Self::NARROW_SEPARATOR,
+            _ => self.separator,
+        }
+    }
+
+    /// Also the prompt for typing line `idx`, so it lines up with the rest.
+    fn fmt_margin(&self, idx: usize) -> String {
+        if !self.margin {
+            return String::new();
+        }
+        let pad = self.linum_pad;
         let linum = idx + 1;
-        format!("{linum:>pad$} |	")
+        format!("{linum:>pad$}{}", self.fit_separator())
     }
 
-    fn fmt_line(pad: usize, lines: &[Cow<'_, str>], idx: usize) -> String {
-        let line = &lines[idx];
-        let margin = Self::fmt_margin(pad, idx);
-        format!("{margin}{line}\n")
+    /// Line `idx`, with a `>` for a separator if it's `marked`. Without the
+    /// margin there's nowhere to put one.
+    fn fmt_line(&self, idx: usize, marked: bool) -> String {
+        let line = &self.lines[idx];
+        let mut margin = self.fmt_margin(idx);
+        if marked {
+            margin = margin.replace('|', ">");
+        }
+        match self.highlight.as_deref().filter(|_| self.highlighting) {
+            Some(term) => format!("{margin}{}\n", highlight(line, term)),
+            None => format!("{margin}{line}\n"),
+        }
     }
 
-    fn clamp_line(&mut self) {
-        self.cur_line = cmp::min(self.cur_line, self.lines.len().saturating_sub(1));
+    /// Returns what to say if the current line was held back at
+    /// [`Editor::max_line`].
+    fn clamp_line(&mut self) -> Option<&'static str> {
+        let last = self.lines.len().saturating_sub(1);
+        let mut refusal = None;
+        if let Some((max, why)) = self.max_line.filter(|&(max, _)| max < last) {
+            if self.cur_line > max {
+                self.cur_line = max;
+                refusal = Some(why);
+            }
+        }
+        self.cur_line = cmp::min(self.cur_line, last);
         if self.lines.is_empty() {
             self.lines.push(Cow::Borrowed(""));
         }
+        refusal
     }
 
     async fn insert_lines_at<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
@@ -133,18 +413,25 @@ impl<'vec, 'src> Editor<'vec, 'src> {
         start_idx: usize,
     ) -> anyhow::Result<()> {
         self.cur_line = start_idx;
+        let mut inserted = 0;
         loop {
-            let prompt = Self::fmt_margin(self.linum_pad, self.cur_line);
-            let line = shell::readln(stream, &prompt).await?;
+            let prompt = self.fmt_margin(self.cur_line);
+            let line = shell::read_text_line(stream, &prompt, Library::UPLOAD_MAX_LEN).await?;
 
-            if line == "." {
+            let Some(line) = line.filter(|line| line != ".") else {
                 self.prev_line_printed = Some(self.cur_line);
                 break;
-            }
+            };
 
             self.recompute_pad();
             self.lines.insert(self.cur_line, Cow::Owned(line));
             self.cur_line += 1;
+            inserted += 1;
+            if inserted % INSERT_FEEDBACK == 0 {
+                stream
+                    .write_all(format!("({inserted} lines added.)\n").as_bytes())
+                    .await?;
+            }
         }
 
         Ok(())
@@ -158,28 +445,58 @@ impl<'vec, 'src> Editor<'vec, 'src> {
             .chain(if skip_cur { None } else { Some(self.cur_line) })
     }
 
+    /// The lines within [`Editor::context`] of the current one, which is
+    /// marked, up to [`Editor::max_line`].
+    async fn print_context<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> anyhow::Result<()> {
+        let mut last = self.lines.len().saturating_sub(1);
+        if let Some((max, _)) = self.max_line {
+            last = cmp::min(last, max);
+        }
+        let start = self.cur_line.saturating_sub(self.context);
+        let end = cmp::min(self.cur_line.saturating_add(self.context), last);
+        for idx in start..=end {
+            let line = self.fmt_line(idx, idx == self.cur_line);
+            shell::clear_line(stream).await?;
+            stream.write_all(line.as_bytes()).await?;
+        }
+        self.prev_line_printed = Some(self.cur_line);
+        self.context_end = Some(end);
+        self.lines_reached = cmp::max(self.lines_reached, end + 1);
+        Ok(())
+    }
+
     async fn print<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
         &mut self,
         stream: &mut S,
     ) -> anyhow::Result<()> {
         /* make sure current line is valid index */
-        self.clamp_line();
+        let refusal = self.clamp_line();
+
+        if core::mem::take(&mut self.jumped) {
+            self.print_context(stream).await?;
+            return self.print_notes(stream, refusal).await;
+        }
 
         /* determine whether to move the cursor back. this only makes sense if
          * we are trying to hide the prior prompt, to prevent broken up buffer
          * lines. so, we need to make sure that the prior line is really the
          * prompt. */
-        let directly_printed_line_prev = match self.prev_cmd {
-            Some(Command::LineGotoIdx(idx)) if Some(idx) < self.prev_line_printed => true,
+        let directly_printed_line_prev = matches!(
+            self.prev_cmd,
             Some(Command::LinePrev(_))
-            | Some(Command::Print)
-            | Some(Command::Insert)
-            | Some(Command::Append)
-            | Some(Command::Change)
-            | Some(Command::Delete)
-            | None => true,
-            _ => false,
-        };
+                | Some(Command::PagePrev)
+                | Some(Command::Print)
+                | Some(Command::SetMargin(_))
+                | Some(Command::SetHighlight(_))
+                | Some(Command::Insert)
+                | Some(Command::Append)
+                | Some(Command::Change)
+                | Some(Command::Delete)
+                | None
+        );
         let will_print_buf_lines = self.print_range().next().is_some();
         if !directly_printed_line_prev && will_print_buf_lines {
             shell::move_cursor_prev(stream).await?;
@@ -187,10 +504,38 @@ impl<'vec, 'src> Editor<'vec, 'src> {
 
         /* print whatever range of lines needs to be visually updated */
         for idx in self.print_range() {
-            let line = Self::fmt_line(self.linum_pad, &self.lines, idx);
+            let line = self.fmt_line(idx, false);
             shell::clear_line(stream).await?;
             stream.write_all(line.as_bytes()).await?;
             self.prev_line_printed = Some(idx);
+            self.lines_reached = cmp::max(self.lines_reached, idx + 1);
+        }
+        if core::mem::take(&mut self.first_page) && self.cur_line + 1 == self.num_lines() {
+            stream.write_all(b"[end of book]\n").await?;
+        }
+
+        self.print_notes(stream, refusal).await
+    }
+
+    /// Say where the last address fell off the buffer, if it did, and why the
+    /// current line was held back, if it was.
+    async fn print_notes<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        refusal: Option<&'static str>,
+    ) -> anyhow::Result<()> {
+        let missed = match self.out_of_bounds.take() {
+            Some(OutOfBounds::BeforeFirst) => Some("there's no line before 1.".to_string()),
+            Some(OutOfBounds::PastLast) => {
+                Some(format!("there are only {} lines.", self.num_lines()))
+            }
+            None => None,
+        };
+        if let Some(missed) = missed {
+            stream.write_all(format!("{missed}\n").as_bytes()).await?;
+        }
+        if let Some(why) = refusal {
+            stream.write_all(format!("{why}\n").as_bytes()).await?;
         }
 
         Ok(())
@@ -201,39 +546,62 @@ impl<'vec, 'src> Editor<'vec, 'src> {
         stream: &mut S,
         cmd: Command,
     ) -> anyhow::Result<Passback> {
+        let shown_to = self.context_end.take();
         match (self.readonly, cmd) {
             (_, Command::Quit) => return Ok(Passback::Quit),
 
             (_, Command::Help) => {
-                const HELP: &'static [(bool, &str, &str)] = &[
+                const HELP: &[(bool, &str, &str)] = &[
                     (false, "q, quit", "quit reading."),
                     (false, "?, h, help", "list commands."),
                     (false, "print", "print first through current lines."),
-                    (false, "l, lines", "print line count."),
+                    (false, "l, lines", "print line, word and character counts."),
                     (
                         false,
                         "<enter>, j, j<N>",
                         "move by next N lines [default: 1].",
                     ),
                     (false, "k, k<N>", "move by previous N lines [default: 1]."),
+                    (false, "f, b", "move a page forward or back."),
                     (false, "g", "goto first line."),
-                    (false, "G", "goto last line."),
-                    (false, "<N>", "goto line N."),
+                    (false, "G, $", "goto last line."),
+                    (
+                        false,
+                        "<N>, +<N>, -<N>, .",
+                        "goto line N, N lines on or back, or this one.",
+                    ),
+                    (false, "set nomargin", "hide line numbers, for copying."),
+                    (false, "set margin", "show line numbers again."),
+                    (
+                        false,
+                        "set nohighlight",
+                        "stop picking out what you searched for.",
+                    ),
+                    (false, "set highlight", "pick it out again."),
+                    (false, "set pager", "make <enter> and space turn a page."),
+                    (false, "set nopager", "make them go on a line again."),
+                    (
+                        false,
+                        "set context <N>",
+                        "show N lines around where a jump lands [default: 2].",
+                    ),
                     (true, "i", "insert new line before."),
                     (true, "a", "insert new line after."),
                     (true, "c", "replace current line."),
                     (true, "d", "delete current line."),
                 ];
-                let max_left = HELP.iter().map(|t| t.1.chars().count()).max().unwrap();
+                let max_left = HELP
+                    .iter()
+                    .map(|t| shell::display_width(t.1))
+                    .max()
+                    .unwrap();
                 let help_pad = max_left + 8;
-                for &(writes, left, right) in HELP {
-                    if self.readonly && writes {
-                        continue;
-                    }
-                    stream
-                        .write_all(format!(" {left:<help_pad$}{right}\n").as_bytes())
-                        .await?;
-                }
+                let lines: Vec<String> = HELP
+                    .iter()
+                    .filter(|&&(writes, ..)| !(self.readonly && writes))
+                    .map(|(_, left, right)| format!(" {}{right}", shell::pad(left, help_pad)))
+                    .collect();
+                shell::page(stream, &lines, self.rows).await?;
             }
 
             (_, Command::Print) => {
@@ -241,12 +609,33 @@ impl<'vec, 'src> Editor<'vec, 'src> {
             }
 
             (_, Command::CountLines) => {
+                let lines = self.num_lines();
+                let words: usize = self
+                    .lines
+                    .iter()
+                    .map(|line| line.split_whitespace().count())
+                    .sum();
+                let chars: usize = self.lines.iter().map(|line| line.chars().count()).sum();
+                let plural = |count: usize| if count == 1 { "" } else { "s" };
                 stream
-                    .write_all(format!("{}\n", self.num_lines()).as_bytes())
+                    .write_all(
+                        format!(
+                            "{lines} line{}, {words} word{}, {chars} character{}.\n",
+                            plural(lines),
+                            plural(words),
+                            plural(chars)
+                        )
+                        .as_bytes(),
+                    )
                     .await?;
             }
 
             (_, Command::LineNext(by)) => {
+                /* on from whatever a jump already showed past this line */
+                if let Some(end) = shown_to {
+                    self.prev_line_printed = Some(end);
+                    self.cur_line = end;
+                }
                 self.cur_line = self.cur_line.saturating_add(by);
             }
 
@@ -254,9 +643,47 @@ impl<'vec, 'src> Editor<'vec, 'src> {
                 self.cur_line = self.cur_line.saturating_sub(by);
             }
 
-            (_, Command::LineGotoIdx(index)) => {
-                self.prev_line_printed = Some(self.cur_line);
+            (_, Command::PageNext) => {
+                /* a page on from the last line shown, jump or not */
+                if let Some(end) = shown_to {
+                    self.prev_line_printed = Some(end);
+                    self.cur_line = end;
+                }
+                self.cur_line = self.cur_line.saturating_add(self.page_len());
+            }
+
+            (_, Command::PagePrev) => {
+                /* the page before the one ending here, whole, or the first */
+                let page = self.page_len();
+                let first_page_end = cmp::min(page, self.num_lines()).saturating_sub(1);
+                self.cur_line = cmp::max(self.cur_line.saturating_sub(page), first_page_end);
+                self.prev_line_printed = self.cur_line.checked_sub(page);
+            }
+
+            (_, Command::Goto(addr)) => {
+                let (index, out_of_bounds) = addr.resolve(self.cur_line, self.num_lines());
                 self.cur_line = index;
+                self.out_of_bounds = out_of_bounds;
+                self.jumped = true;
+            }
+
+            (_, Command::SetMargin(margin)) => {
+                self.margin = margin;
+                /* reprint just the current line, the new way */
+                self.prev_line_printed = self.cur_line.checked_sub(1);
+            }
+
+            (_, Command::SetHighlight(highlighting)) => {
+                self.highlighting = highlighting;
+                self.prev_line_printed = self.cur_line.checked_sub(1);
+            }
+
+            (_, Command::SetContext(lines)) => {
+                self.context = lines;
+            }
+
+            (_, Command::SetPager(paging)) => {
+                self.paging = paging;
             }
 
             (true, _) => {
@@ -273,9 +700,12 @@ impl<'vec, 'src> Editor<'vec, 'src> {
 
             (false, Command::Change) => {
                 let idx = self.cur_line;
-                let prompt = Self::fmt_margin(self.linum_pad, idx);
-                let line = shell::readln(stream, &prompt).await?;
-                self.lines[idx] = Cow::Owned(line);
+                let prompt = self.fmt_margin(idx);
+                if let Some(line) =
+                    shell::read_text_line(stream, &prompt, Library::UPLOAD_MAX_LEN).await?
+                {
+                    self.lines[idx] = Cow::Owned(line);
+                }
                 self.prev_line_printed = Some(idx);
             }
 
@@ -298,8 +728,13 @@ impl<'vec, 'src> Editor<'vec, 'src> {
             /* print buffer */
             self.print(stream).await?;
 
+            /* pass on news, but never while a line is being typed */
+            if let Some((notices, library, guest)) = &mut self.notices {
+                notices.drain(stream, library, *guest).await?;
+            }
+
             /* take command */
-            if let Some(cmd) = Command::build(stream, self.num_lines()).await? {
+            if let Some(cmd) = Command::build(stream, self.paging).await? {
                 match self.handle_cmd(stream, cmd).await? {
                     Passback::Continue => continue 'outer,
                     Passback::Quit => break 'outer,
@@ -317,8 +752,10 @@ impl<'vec, 'src> Editor<'vec, 'src> {
 pub async fn cover_page<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     stream: &mut S,
     library: &Library,
+    book_id: BookID,
     book: &Book,
     meta: Metadata,
+    viewer: GuestId,
 ) -> anyhow::Result<()> {
     stream.write_all(b"\n").await?;
     stream
@@ -344,30 +781,231 @@ pub async fn cover_page<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
             .as_bytes(),
         )
         .await?;
-    if let Some(nick) = library.lookup_guest_by_addr(meta.added_by).await {
+    let readers = match library.unique_readers(book_id) {
+        0 => String::new(),
+        1 => String::from(", by 1 reader"),
+        n => format!(", by {n} readers"),
+    };
+    stream
+        .write_all(
+            format!(
+                "	[Read {} time{}{readers}.]\n",
+                meta.reads,
+                if meta.reads == 1 { "" } else { "s" }
+            )
+            .as_bytes(),
+        )
+        .await?;
+    if let Some(nick) = library.lookup_nick(meta.added_by).await {
         stream
             .write_all(format!("	[Added by guest '{nick}'.]\n").as_bytes())
             .await?;
     }
+    if meta.protected {
+        stream.write_all(b"\t[A library notice.]\n").await?;
+    }
+    if let Some(language) = &book.language {
+        stream
+            .write_all(format!("	[Written in {language}.]\n").as_bytes())
+            .await?;
+    }
+    if library.is_being_read(book_id, viewer) {
+        stream
+            .write_all(b"	[Someone is reading this right now.]\n")
+            .await?;
+    }
+    if let Some(percent) = library.completion(book_id, viewer).await {
+        stream
+            .write_all(format!("	[You're {percent}% through.]\n").as_bytes())
+            .await?;
+    }
+    if viewer == Library::OPERATOR || viewer == meta.added_by {
+        let stats = library.reading_stats(book_id).await;
+        if stats.readers != 0 {
+            stream
+                .write_all(
+                    format!(
+                        "	[{} reader{}, {}% through on average.]\n",
+                        stats.readers,
+                        if stats.readers == 1 { "" } else { "s" },
+                        stats.average_completion
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+        }
+    }
+    let similar = library.similar_to(book_id, SIMILAR_BOOKS, viewer).await;
+    if !similar.is_empty() {
+        stream.write_all(b"\n\tYou might also like:\n").await?;
+    }
+    for other in similar {
+        let other = library.lookup_book_by_id(other).await;
+        stream
+            .write_all(format!("\t\t'{}' by {}\n", other.title, other.author).as_bytes())
+            .await?;
+    }
     stream.write_all(b"\n").await?;
 
     Ok(())
 }
 
-pub async fn read_book<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+/// Everything the library knows about a book, one field to a line, without
+/// opening it. Who added it is left out for books nobody owns.
+pub async fn info_panel<S: AsyncWrite + Unpin>(
     stream: &mut S,
     library: &Library,
+    book_id: BookID,
     book: &Book,
     meta: Metadata,
+) -> anyhow::Result<()> {
+    let nick = |guest| async move {
+        library
+            .lookup_nick(guest)
+            .await
+            .map_or_else(|| String::from("somebody"), |nick| format!("'{nick}'"))
+    };
+    let mut fields = vec![
+        ("title", format!("'{}'", book.title)),
+        ("author", book.author.clone()),
+        (
+            "language",
+            book.language
+                .clone()
+                .unwrap_or_else(|| String::from("not given")),
+        ),
+    ];
+    if !meta.communal {
+        fields.push(("added by", nick(meta.added_by).await));
+    }
+    if let Some(at) = library.added_at(book_id) {
+        fields.push(("added on", date::format_date(at)));
+    }
+    let status = match (meta.removed, meta.checked_out_by) {
+        (Some(_), _) => String::from("in the trash"),
+        _ if meta.communal => String::from("communal; anyone may read it"),
+        (None, Some(by)) => match meta.checked_out_at {
+            Some(at) => format!(
+                "checked out by {} since {}",
+                nick(by).await,
+                date::format_date(at)
+            ),
+            None => format!("checked out by {}", nick(by).await),
+        },
+        (None, None) => String::from("on the shelves"),
+    };
+    fields.push(("status", status));
+    fields.push(("checkouts", meta.checkouts.to_string()));
+    fields.push(("reads", meta.reads.to_string()));
+    fields.push(("readers", library.unique_readers(book_id).to_string()));
+    if meta.protected {
+        fields.push(("notice", String::from("a library notice")));
+    }
+
+    stream.write_all(b"\n").await?;
+    for (name, value) in fields {
+        stream
+            .write_all(format!("\t{:<10} {value}\n", format!("{name}:")).as_bytes())
+            .await?;
+    }
+    stream.write_all(b"\n").await?;
+    Ok(())
+}
+
+/// The cover, then the book to page through from its first page, with
+/// whatever the guest last searched for picked out. If they left off partway
+/// through last time, they may pick up there instead of at the start.
+pub async fn read_book<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    book_id: BookID,
+    book: &Book,
+    session: &mut Session,
 ) -> anyhow::Result<()> {
     /* cover page */
-    cover_page(stream, library, book, meta).await?;
+    let meta = library.lookup_metadata(book_id);
+    cover_page(stream, library, book_id, book, meta, session.guest).await?;
+
+    /* however the editor is left, even by hanging up */
+    let _reading = library.start_reading(book_id, session.guest);
 
     /* readonly edit view over book contents */
-    let mut lines: Vec<Cow<'_, str>> = book.content.lines().map(Cow::Borrowed).collect();
+    let mut lines: Vec<Cow<'_, str>> = book.lines().map(Cow::Borrowed).collect();
     let readonly = true;
-    let mut editor = Editor::new(&mut lines, readonly);
+    let query = session.last_query.clone();
+    let guest = session.guest;
+    /* only somebody the library knows has a place to keep */
+    let keeps_place = session.is_registered();
+    let mut resume = None;
+    if let Some(idx) = library.bookmark(book_id, guest).filter(|_| keeps_place) {
+        let line = idx + 1;
+        let prompt = format!("you left off at line {line}. pick up there?");
+        if shell::confirm(stream, library, &prompt, true).await? {
+            shell::say(stream, library, Msg::ResumingAt { line }).await?;
+            resume = Some(idx);
+        }
+    }
+    let mut editor = Editor::new(&mut lines, readonly).for_session(session, library);
+    if let Some(query) = &query {
+        editor.highlight(query);
+    }
+    if let Some(idx) = resume {
+        editor.start_at(idx);
+    }
+    editor.open_at_first_page();
+    let result = editor.enter(stream).await;
+    if keeps_place {
+        /* however far they got, even if they hung up */
+        library.record_progress(book_id, guest, editor.lines_reached());
+        /* nothing to come back to on the page it opens on, or once they've
+         * seen the end */
+        let at = editor.cur_line();
+        let done = editor.on_first_page() || editor.lines_reached() == editor.num_lines();
+        library.set_bookmark(book_id, guest, Some(at).filter(|_| !done));
+    }
+    session.paging = editor.paging;
+    result
+}
+
+/// Like [`read_book`], but only up to the first `max_lines` lines. Trying to
+/// read further gets `why` instead.
+pub async fn preview_book<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    book_id: BookID,
+    book: &Book,
+    session: &mut Session,
+    max_lines: usize,
+    why: &'static str,
+) -> anyhow::Result<()> {
+    let meta = library.lookup_metadata(book_id);
+    cover_page(stream, library, book_id, book, meta, session.guest).await?;
+    let Some(max_line) = max_lines.checked_sub(1) else {
+        stream.write_all(format!("{why}\n").as_bytes()).await?;
+        return Ok(());
+    };
+
+    let mut lines: Vec<Cow<'_, str>> = book.lines().map(Cow::Borrowed).collect();
+    let readonly = true;
+    let query = session.last_query.clone();
+    let mut editor = Editor::new(&mut lines, readonly).for_session(session, library);
+    editor.max_line = Some((max_line, why));
+    if let Some(query) = &query {
+        editor.highlight(query);
+    }
     editor.enter(stream).await?;
+    session.paging = editor.paging;
 
     Ok(())
 }
+
+/// `line` with every `term` in it picked out in reverse video. A line that
+/// has any is stripped of escape sequences first, so the only ones shown are
+/// ours and a match can't land in the middle of somebody else's.
+pub fn highlight<'a>(line: &'a str, term: &str) -> Cow<'a, str> {
+    let clean = sanitize::strip_ansi(line);
+    if term.is_empty() || !clean.contains(term) {
+        return Cow::Borrowed(line);
+    }
+    Cow::Owned(clean.replace(term, &format!("{HIGHLIGHT_ON}{term}{HIGHLIGHT_OFF}")))
+}
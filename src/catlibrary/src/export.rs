@@ -0,0 +1,79 @@
+use crate::library::Book;
+use crate::sanitize;
+
+pub const BEGIN: &str = "---BEGIN BOOK---";
+pub const END: &str = "---END BOOK---";
+
+/// Render `book` as plain text between [`BEGIN`] and [`END`] lines, so a
+/// client can cut it out of the session and save it.
+///
+/// Like PGP's dash-escaping, content lines starting with `-` get a `- `
+/// prefix so they can never be mistaken for the end of the block.
+pub fn write_block(book: &Book) -> String {
+    let mut out = String::new();
+    out.push_str(BEGIN);
+    out.push('\n');
+    for (header, value) in [
+        ("Title", &book.title),
+        ("Author", &book.author),
+        ("Description", &book.description),
+    ] {
+        let value = sanitize::strip_ansi(value).replace('\n', " ");
+        out.push_str(&format!("{header}: {value}\n"));
+    }
+    if let Some(language) = &book.language {
+        out.push_str(&format!("Language: {language}\n"));
+    }
+    out.push('\n');
+    for line in sanitize::strip_ansi(&book.content).split_terminator('\n') {
+        if line.starts_with('-') {
+            out.push_str("- ");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str(END);
+    out.push('\n');
+    out
+}
+
+/// Parse the first block written by [`write_block`] out of `text`.
+pub fn read_block(text: &str) -> Option<Book> {
+    let mut lines = text.lines().skip_while(|&line| line != BEGIN).skip(1);
+
+    let mut book = Book {
+        title: String::new(),
+        author: String::new(),
+        description: String::new(),
+        content: String::new(),
+        language: None,
+    };
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        let (header, value) = line.split_once(": ")?;
+        if header == "Language" {
+            /* a tag that isn't one is as good as none */
+            book.language = Book::language_tag(value);
+            continue;
+        }
+        let dst = match header {
+            "Title" => &mut book.title,
+            "Author" => &mut book.author,
+            "Description" => &mut book.description,
+            _ => continue,
+        };
+        *dst = value.to_string();
+    }
+
+    for line in lines {
+        if line == END {
+            return Some(book);
+        }
+        book.content
+            .push_str(line.strip_prefix("- ").unwrap_or(line));
+        book.content.push('\n');
+    }
+    None
+}
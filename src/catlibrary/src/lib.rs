@@ -1,6 +1,63 @@
+//! The Cat Library: a lending library for text, served over plain TCP.
+//!
+//! The engine is [`Library`], and doesn't need the shell to be useful. Build
+//! one, put books on its shelves, and search, check out and listen to it from
+//! your own runtime:
+//!
+//! ```
+//! use cat_library::{Book, Library};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let library = Library::builder().build().await;
+//! let mut events = library.subscribe();
+//! let id = library
+//!     .add(
+//!         Book {
+//!             title: "The Long Nap".into(),
+//!             author: "whiskers".into(),
+//!             description: String::new(),
+//!             content: "zzz\n".into(),
+//!             language: Some("en".into()),
+//!         },
+//!         Library::OPERATOR,
+//!     )
+//!     .await
+//!     .unwrap();
+//! library.checkout(id, Library::OPERATOR).unwrap();
+//! assert_eq!(Ok(cat_library::LibraryEvent::Added(id)), events.try_recv());
+//! # }
+//! ```
+//!
+//! `examples/embed.rs` goes further. Everything else here is the shell that
+//! guests talk to, and what it needs.
+
+pub mod client;
+pub mod clock;
+pub mod compress;
+pub mod dashboard;
+pub mod date;
+pub mod diff;
 pub mod editor;
+pub mod export;
 pub mod library;
+pub mod messages;
+pub mod metrics;
+pub mod records;
+pub mod sanitize;
+pub mod seed;
+pub mod server;
 pub mod shell;
+pub mod snapshot;
+pub mod transcript;
+pub mod upload;
 
 #[cfg(test)]
 mod tests;
+
+pub use clock::{Clock, ManualClock, OsRng, Rng, SeededRng, SystemClock};
+pub use library::{
+    AddError, Book, BookID, ClaimError, Field, Filters, GuestId, GuestbookError, Library,
+    LibraryBuilder, LibraryEvent, Match, MergeError, Merged, Metadata, RegisterError, Subscription,
+    UpdateEntryError,
+};
@@ -1,22 +1,239 @@
-use core::cmp::Ordering;
+use arc_swap::ArcSwap;
+use core::cmp::{self, Ordering, Reverse};
 use core::net::{IpAddr, Ipv4Addr};
-use dashmap::DashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use core::sync::atomic::{self, AtomicU64};
+use core::time::Duration;
+use dashmap::mapref::entry::Entry;
+use dashmap::{DashMap, DashSet};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{btree_map, BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::Instant;
+
+use crate::clock::{self, Clock, OsRng, Rng, SystemClock};
+use crate::compress::{BookCache, StoredBook};
+use crate::date;
+use crate::messages::Flavor;
+use crate::metrics::{self, Counter};
+use crate::sanitize;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Book {
     pub title: String,
     pub author: String,
     pub description: String,
+    /// Lines, each ending in `\n`, including the last. No lines at all is
+    /// the empty string. See [`Book::lines`] and [`Book::content_from_lines`];
+    /// a missing `\n` on the last line is tolerated, but never written.
     pub content: String,
+    /// What it's written in, as a tag like `en` or `pt-BR`, if anyone said.
+    /// See [`Book::language_tag`].
+    pub language: Option<String>,
 }
 
 // TODO: (title, author) should be sacred
 
+impl Book {
+    pub fn field(&self, field: Field) -> &str {
+        match field {
+            Field::Title => &self.title,
+            Field::Author => &self.author,
+            Field::Description => &self.description,
+            Field::Content => &self.content,
+        }
+    }
+
+    /// The title and author as they're compared to tell books apart, as in
+    /// [`normalize_name`], so "The Cat" and "the  cat" are the same book.
+    pub fn cover_key(&self) -> (String, String) {
+        (normalize_name(&self.title), normalize_name(&self.author))
+    }
+
+    /// Every line of content, exactly as written, blank ones included.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.content.split_terminator('\n')
+    }
+
+    /// `tag` as a language tag, cased the usual way (`pt-BR`, `zh-Hant`), or
+    /// `None` if it doesn't look like one. Only the shape is checked: two or
+    /// three letters, then any number of `-`-separated subtags of one to
+    /// eight letters or digits.
+    pub fn language_tag(tag: &str) -> Option<String> {
+        let mut subtags = tag.trim().split('-');
+        let language = subtags.next()?;
+        if !(2..=3).contains(&language.len()) || !language.bytes().all(|b| b.is_ascii_alphabetic())
+        {
+            return None;
+        }
+        let mut out = language.to_ascii_lowercase();
+        for subtag in subtags {
+            if !(1..=8).contains(&subtag.len())
+                || !subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+            {
+                return None;
+            }
+            out.push('-');
+            match subtag.len() {
+                /* regions and scripts */
+                2 => out.push_str(&subtag.to_ascii_uppercase()),
+                4 => {
+                    out.push_str(&subtag[..1].to_ascii_uppercase());
+                    out.push_str(&subtag[1..].to_ascii_lowercase());
+                }
+                _ => out.push_str(&subtag.to_ascii_lowercase()),
+            }
+        }
+        Some(out)
+    }
+
+    /// Whether a `lang:` search for `filter` finds the book: it's in that
+    /// language, or a more particular kind of it (`fr` finds `fr-CA`).
+    /// Books nobody gave a language are never found this way.
+    pub fn in_language(&self, filter: &str) -> bool {
+        language_matches(self.language.as_deref(), filter)
+    }
+
+    /// Content holding exactly `lines`, for [`Book::lines`] to give back.
+    pub fn content_from_lines<I: IntoIterator<Item = S>, S: AsRef<str>>(lines: I) -> String {
+        let mut content = String::new();
+        for line in lines {
+            content.push_str(line.as_ref());
+            content.push('\n');
+        }
+        content
+    }
+}
+
+/// `s` with every run of whitespace, newlines included, made a single space,
+/// and none at either end.
+pub fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `name` as it's compared with others: case folded, and with whitespace as
+/// in [`collapse_whitespace`], so "  cat 1 " is "Cat 1".
+pub fn normalize_name(name: &str) -> String {
+    collapse_whitespace(name).to_lowercase()
+}
+
+fn language_matches(language: Option<&str>, filter: &str) -> bool {
+    let Some(language) = language else {
+        return false;
+    };
+    let len = filter.len();
+    language
+        .get(..len)
+        .is_some_and(|start| start.eq_ignore_ascii_case(filter))
+        && matches!(language.as_bytes().get(len), None | Some(b'-'))
+}
+
+/// Every book `guest` has an entry for in `map`.
+fn books_of<V>(map: &DashMap<(GuestId, BookID), V>, guest: GuestId) -> Vec<BookID> {
+    map.iter()
+        .filter_map(|entry| (entry.key().0 == guest).then_some(entry.key().1))
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Author,
+    Description,
+    Content,
+}
+
+impl Field {
+    pub const ALL: [Self; 4] = [Self::Title, Self::Author, Self::Description, Self::Content];
+
+    /// How much a match in this field counts for in search, so a title that
+    /// matches outranks content that matches just as well.
+    pub const fn weight(self) -> f64 {
+        match self {
+            Self::Title => 1.0,
+            Self::Author => 0.9,
+            Self::Description => 0.8,
+            Self::Content => 0.7,
+        }
+    }
+}
+
+/// Where a search query turned up inside a book's description or content.
+/// Byte offsets into [`Book::field`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub field: Field,
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// What a search query asks of books besides matching it, from words like
+/// `lang:fr` and `available:` among the rest. See [`Library::filters`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Filters<'a> {
+    /// Only books in this language, or a kind of it.
+    pub language: Option<&'a str>,
+    /// Only books on the shelves that nobody has checked out.
+    pub available: bool,
+}
+
+impl Filters<'_> {
+    fn admit(&self, stored: &StoredBook, meta: &Metadata) -> bool {
+        if self
+            .language
+            .is_some_and(|filter| !language_matches(stored.language.as_deref(), filter))
+        {
+            return false;
+        }
+        !self.available || (meta.is_free() && !meta.is_hidden())
+    }
+}
+
+/// A search result, ordered from worst to best: by score, then earliest
+/// added, like [`Library::search`] lists them.
+struct Ranked((f64, BookID, Metadata, Option<Match>));
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (sim, id, ..) = self.0;
+        let (other_sim, other_id, ..) = other.0;
+        sim.total_cmp(&other_sim).then(other_id.cmp(&id))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+/// [`Library::search`] results for books with the same title and author,
+/// collapsed into one by [`Library::collapse_copies`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Copies {
+    /// The best-scoring copy, as it was found, but with the metadata of every
+    /// copy summed up: it's only checked out if all of them are.
+    pub best: (f64, BookID, Metadata, Option<Match>),
+    /// Every copy, best first.
+    pub ids: Vec<BookID>,
+    /// How many copies were on the shelves and free.
+    pub available: usize,
+}
+
 impl PartialOrd for Book {
     fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
-        Some(self.title.cmp(&rhs.title))
+        Some(self.cmp(rhs))
     }
 }
 
@@ -28,17 +245,43 @@ impl Ord for Book {
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Metadata {
-    pub added_by: IpAddr,
+    pub added_by: GuestId,
     pub checkouts: u64,
-    pub checked_out_by: Option<IpAddr>,
+    /// Times the book was opened to read, however many checkouts that took.
+    pub reads: u64,
+    pub checked_out_by: Option<GuestId>,
+    /// When [`Metadata::checked_out_by`] checked it out.
+    pub checked_out_at: Option<SystemTime>,
+    /// Belongs to everybody at once, like the guestbook, so it can be read
+    /// without checking it out and can never be checked out.
+    pub communal: bool,
+    /// Taken off the shelves by the operator: hidden from every guest.
+    pub removed: Option<Removal>,
+    /// A library notice, which only the operator may change or throw out,
+    /// whoever it says added it.
+    pub protected: bool,
+}
+
+/// Why a book isn't on the shelves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Removal {
+    /// In the trash since then. It may still be restored.
+    Trashed(SystemTime),
+    /// Gone for good. Only its [`BookID`] is left, so it's never reused.
+    Purged,
 }
 
 impl Metadata {
-    pub fn new(added_by: IpAddr) -> Self {
+    pub fn new(added_by: GuestId) -> Self {
         Self {
             added_by,
             checkouts: 0,
+            reads: 0,
             checked_out_by: None,
+            checked_out_at: None,
+            communal: false,
+            removed: None,
+            protected: false,
         }
     }
 
@@ -46,250 +289,2797 @@ impl Metadata {
         self.checked_out_by.is_none()
     }
 
+    pub const fn is_hidden(&self) -> bool {
+        self.removed.is_some()
+    }
+
+    /// Whether `guest` may find this book at all. Only the operator sees into
+    /// the trash, and nobody sees what was purged.
+    pub fn visible_to(&self, guest: GuestId) -> bool {
+        match self.removed {
+            None => true,
+            Some(Removal::Trashed(_)) => guest == Library::OPERATOR,
+            Some(Removal::Purged) => false,
+        }
+    }
+
     pub fn register_checkout(&mut self) {
         self.checkouts = self.checkouts.saturating_add(1);
     }
 
-    pub fn set_checkout(&mut self, guest: IpAddr) -> Option<IpAddr> {
+    pub fn unregister_checkout(&mut self) {
+        self.checkouts = self.checkouts.saturating_sub(1);
+    }
+
+    pub fn register_read(&mut self) {
+        self.reads = self.reads.saturating_add(1);
+    }
+
+    pub fn set_checkout(&mut self, guest: GuestId, at: SystemTime) -> Option<GuestId> {
         let old = self.checked_out_by;
         self.checked_out_by = Some(guest);
+        self.checked_out_at = Some(at);
         old
     }
 
-    pub fn set_checkin(&mut self) -> Option<IpAddr> {
+    pub fn set_checkin(&mut self) -> Option<GuestId> {
+        self.checked_out_at = None;
         self.checked_out_by.take()
     }
+
+    /// How long it's been checked out as of `now`, if it is.
+    pub fn held_for(&self, now: SystemTime) -> Option<Duration> {
+        let at = self.checked_out_at?;
+        Some(now.duration_since(at).unwrap_or(Duration::ZERO))
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum UpdateEntryError {
-    AlreadyCheckedOut(IpAddr),
+    AlreadyCheckedOut(GuestId),
     AlreadyCheckedIn,
     GuestMismatch,
+    Communal,
+    /// The book is in the trash, or gone.
+    Removed,
+    /// Only books in the trash can be restored.
+    NotTrashed,
+    /// Somebody has it open to read, so it can't change under them.
+    BeingRead,
+    /// A library notice, and only the operator may change it.
+    Protected,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum RegisterError {
-    AlreadyRegistered,
-    NicknameTaken,
+impl core::fmt::Display for UpdateEntryError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::AlreadyCheckedOut(by) => write!(f, "already checked out by {by:?}"),
+            Self::AlreadyCheckedIn => f.write_str("already checked in"),
+            Self::GuestMismatch => f.write_str("belongs to another guest"),
+            Self::Communal => f.write_str("belongs to everyone"),
+            Self::Removed => f.write_str("taken off the shelves"),
+            Self::NotTrashed => f.write_str("not in the trash"),
+            Self::BeingRead => f.write_str("someone is reading it"),
+            Self::Protected => f.write_str("a library notice"),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct Guest {
-    addr: IpAddr,
-    pub nick: Arc<str>,
+impl std::error::Error for UpdateEntryError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuestbookError {
+    NoGuestbook,
+    Empty,
+    TooLong,
+    DailyLimit,
 }
 
-impl Guest {
-    pub fn new(addr: IpAddr, nick: &str) -> Self {
-        Self {
-            addr,
-            nick: Arc::from(nick),
+impl core::fmt::Display for GuestbookError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoGuestbook => f.write_str("no guestbook"),
+            Self::Empty => f.write_str("empty message"),
+            Self::TooLong => f.write_str("message too long"),
+            Self::DailyLimit => f.write_str("signed too often today"),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[repr(transparent)]
-pub struct BookID(usize);
+impl std::error::Error for GuestbookError {}
 
-#[derive(Debug)]
-pub struct Library {
-    /// Push-only pool of books. Indices are unique and stable mappings to books.
-    book_pool: RwLock<Vec<Arc<Book>>>,
+/// Where a [`Library::subscribe`]r hears about [`LibraryEvent`]s. One that
+/// falls more than [`Library::EVENT_CAPACITY`] behind misses the oldest.
+pub type Subscription = broadcast::Receiver<LibraryEvent>;
 
-    /// Table of book metadata. This is expected to be frequently read and
-    /// written to as books are checked in and out.
-    book_meta: DashMap<BookID, Metadata>,
+/// A change to the catalog, published to every [`Library::subscribe`]r.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LibraryEvent {
+    Added(BookID),
+    CheckedOut(BookID),
+    CheckedIn(BookID),
+    Removed(BookID),
+    Restored(BookID),
+}
 
-    // NOTE: (sorted ascending by IpAddr, sorted ascending by nickname)
-    guests: RwLock<(Vec<Guest>, Vec<Arc<str>>)>,
+impl LibraryEvent {
+    /// The book this happened to.
+    pub const fn book_id(self) -> BookID {
+        match self {
+            Self::Added(id)
+            | Self::CheckedOut(id)
+            | Self::CheckedIn(id)
+            | Self::Removed(id)
+            | Self::Restored(id) => id,
+        }
+    }
 }
 
-impl Library {
-    pub const OPERATOR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+/// One checkout, from when the book went out to when it came back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Loan {
+    pub book_id: BookID,
+    pub guest: GuestId,
+    pub out: SystemTime,
+    /// `None` while it's still out.
+    pub back: Option<SystemTime>,
+}
 
-    pub fn new() -> Self {
-        let operator = Guest::new(Self::OPERATOR, "cat in the machine");
-        Self {
-            book_pool: RwLock::new(Vec::new()),
-            book_meta: DashMap::new(),
-            guests: RwLock::new((vec![operator.clone()], vec![operator.nick])),
+/// News held for a guest until they're next at a prompt, even if that's
+/// another visit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mail {
+    /// `by` checked out `book_id`, which the guest added.
+    CheckedOut { by: GuestId, book_id: BookID },
+}
+
+/// How many guests have opened a book, and how far they got on average.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadingStats {
+    pub readers: usize,
+    /// In whole percent, rounded down.
+    pub average_completion: u32,
+}
+
+/// How far `lines_read` lines is through `lines`, in whole percent, rounded
+/// down. Lines that have since been cut from the book don't count twice.
+pub fn percent_through(lines_read: usize, lines: usize) -> u32 {
+    if lines == 0 {
+        return 100;
+    }
+    let percent = cmp::min(lines_read, lines) * 100 / lines;
+    u32::try_from(percent).unwrap_or(100)
+}
+
+/// Receive the next event, skipping past any that were dropped because this
+/// receiver fell behind. Returns `None` once the library is gone.
+pub async fn recv_event<T: Clone>(events: &mut broadcast::Receiver<T>) -> Option<T> {
+    loop {
+        match events.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::debug!(missed, "event subscriber lagged");
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
         }
     }
+}
 
-    pub async fn with_collection<I: IntoIterator<Item = Book>>(collection: I) -> Self {
-        let lib = Self::new();
-        for book in collection {
-            lib.add(book, Self::OPERATOR).await;
+/// Like [`recv_event`], but `None` right away if nothing has happened yet.
+pub fn try_recv_event<T: Clone>(events: &mut broadcast::Receiver<T>) -> Option<T> {
+    loop {
+        match events.try_recv() {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                tracing::debug!(missed, "event subscriber lagged");
+            }
+            Err(broadcast::error::TryRecvError::Empty | broadcast::error::TryRecvError::Closed) => {
+                return None
+            }
         }
-        lib
     }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegisterError {
+    AlreadyRegistered,
+    NicknameTaken,
+    /// There are [`Library::guest_limit`] guests already, and none of them
+    /// could be evicted.
+    Full,
+}
 
-    pub async fn lookup_guest_by_addr(&self, addr: IpAddr) -> Option<Arc<str>> {
-        let (guests, _nicks) = &*self.guests.read().await;
-        match guests.binary_search_by_key(&addr, |guest| guest.addr) {
-            Ok(idx) => Some(Arc::clone(&guests[idx].nick)),
-            Err(_idx) => None,
+impl core::fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::AlreadyRegistered => f.write_str("address already registered"),
+            Self::NicknameTaken => f.write_str("nickname taken"),
+            Self::Full => f.write_str("no room for more guests"),
         }
     }
+}
 
-    pub async fn register_guest(
-        &self,
-        addr: IpAddr,
-        nick: impl Into<Arc<str>>,
-    ) -> Result<Arc<str>, RegisterError> {
-        let (ref mut guests, nicks) = &mut *self.guests.write().await;
+impl std::error::Error for RegisterError {}
 
-        let nick: Arc<str> = nick.into();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AddError {
+    /// The guest has [`Library::contribution_limit`] books on the shelves
+    /// already.
+    ContributionLimit,
+    /// The same book is on the shelves already, as this one. Only from
+    /// [`Library::add_unique`].
+    Duplicate(BookID),
+}
 
-        /* check if this nickname is taken */
-        match nicks.binary_search(&nick) {
-            Ok(_idx) => return Err(RegisterError::NicknameTaken),
-            Err(idx) => nicks.insert(idx, Arc::clone(&nick)),
+impl core::fmt::Display for AddError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::ContributionLimit => f.write_str("added the maximum number of books"),
+            Self::Duplicate(book_id) => write!(f, "already on the shelves as {book_id}"),
         }
+    }
+}
 
-        /* associate address with nickname */
-        match guests.binary_search_by_key(&addr, |guest| guest.addr) {
-            Ok(_) => Err(RegisterError::AlreadyRegistered),
-            Err(idx) => {
-                let guest = Guest {
-                    addr,
-                    nick: Arc::clone(&nick),
-                };
-                guests.insert(idx, guest);
-                Ok(nick)
-            }
+impl std::error::Error for AddError {}
+
+/// What [`Library::merge_guests`] moved from one guest to the other, or
+/// would have.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Merged {
+    /// Books they added.
+    pub books: usize,
+    /// Books they have out right now.
+    pub checkouts: usize,
+    /// Checkouts in the records, back or not.
+    pub loans: usize,
+    pub bookmarks: usize,
+    /// Books they'd got some way into.
+    pub progress: usize,
+    /// News they hadn't heard yet.
+    pub mail: usize,
+}
+
+/// One change [`Library::reshelve`] makes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reshelve {
+    /// Put a new book on the shelves as the operator's, as a library notice
+    /// or not and in the trash or not, showing `added_by` as who added it.
+    Add {
+        book: Book,
+        added_by: Option<String>,
+        notice: bool,
+        trashed: Option<SystemTime>,
+    },
+    /// Make a book that's there already say `book`, be a library notice or
+    /// not, and be in the trash or not.
+    Update {
+        book_id: BookID,
+        book: Book,
+        notice: bool,
+        trashed: Option<SystemTime>,
+    },
+    /// Take a book away for good, trash or no trash.
+    Remove(BookID),
+}
+
+/// What [`Library::reshelve`] did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reshelved {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    /// Books taken back from whoever had them out, since they went away or
+    /// in the trash.
+    pub returned: Vec<(BookID, GuestId)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    SameGuest,
+    /// The operator can be merged into, but never away.
+    Operator,
+    /// One of them isn't registered, or not anymore.
+    Unregistered,
+    /// The guest to merge away is connected right now.
+    Connected,
+}
+
+impl core::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SameGuest => f.write_str("can't merge a guest into themselves"),
+            Self::Operator => f.write_str("can't merge the operator away"),
+            Self::Unregistered => f.write_str("not a registered guest"),
+            Self::Connected => f.write_str("still connected"),
         }
     }
+}
 
-    pub async fn is_empty(&self) -> bool {
-        self.book_pool.read().await.is_empty()
+impl std::error::Error for MergeError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClaimError {
+    UnknownNickname,
+    NoPassphrase,
+    WrongPassphrase,
+}
+
+impl core::fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnknownNickname => f.write_str("unknown nickname"),
+            Self::NoPassphrase => f.write_str("no passphrase set"),
+            Self::WrongPassphrase => f.write_str("wrong passphrase"),
+        }
     }
+}
 
-    pub async fn lookup_book_by_id(&self, id: BookID) -> Arc<Book> {
-        let pool = self.book_pool.read().await;
-        let book = Arc::clone(&pool[id.0]);
-        book
+impl std::error::Error for ClaimError {}
+
+/// Stable identity of a guest. Unlike their address, this never changes, so
+/// checkouts and contributions follow a guest who claims their nickname from
+/// somewhere new.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct GuestId(usize);
+
+/// Salted SHA-256 of a guest's passphrase.
+#[derive(Clone)]
+struct Passphrase {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+/// SHA-256 of a login token. Tokens are random, so they need no salt.
+fn token_digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+impl Passphrase {
+    fn new(passphrase: &str) -> Self {
+        let salt = clock::secret_bytes();
+        let hash = Self::digest(&salt, passphrase);
+        Self { salt, hash }
     }
 
-    pub async fn lookup_checkouts_by_guest(&self, guest: IpAddr) -> Vec<(BookID, Metadata)> {
-        // TODO: inefficient
+    fn digest(salt: &[u8; 16], passphrase: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(salt);
+        hasher.update(passphrase.as_bytes());
+        hasher.finalize().into()
+    }
 
-        let mut found = Vec::new();
-        for entry in self.book_meta.iter() {
-            let (&id, &meta) = entry.pair();
-            if meta.checked_out_by == Some(guest) {
-                found.push((id, meta));
-            }
-        }
-        found
+    fn verify(&self, passphrase: &str) -> bool {
+        let hash = Self::digest(&self.salt, passphrase);
+        /* don't bail at the first differing byte */
+        hash.iter()
+            .zip(self.hash.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
     }
+}
 
-    pub fn lookup_metadata(&self, id: BookID) -> Metadata {
-        *self.book_meta.get(&id).unwrap()
+impl core::fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Passphrase(..)")
     }
+}
 
-    pub async fn search(&self, query: &str) -> Vec<(f64, BookID, Metadata)> {
-        fn cmp(book: &Book, query: &str) -> Option<f64> {
-            if query == "" {
-                return Some(1.0);
-            }
+#[derive(Clone, Debug)]
+pub struct Guest {
+    pub id: GuestId,
+    /// Where they last connected from, until somebody else claims it or
+    /// they're evicted or merged away.
+    addr: Option<IpAddr>,
+    pub nick: Arc<str>,
+    passphrase: Option<Passphrase>,
+    token: Option<[u8; 32]>,
+}
+
+impl Guest {
+    pub fn new(id: GuestId, addr: IpAddr, nick: &str) -> Self {
+        Self {
+            id,
+            addr: Some(addr),
+            nick: Arc::from(nick),
+            passphrase: None,
+            token: None,
+        }
+    }
+}
 
-            const THRESHOLD: f64 = 0.4;
-            let mut sim = None;
+#[derive(Debug)]
+struct Guests {
+    /// Indexed by `GuestId`. Push-only, like the book pool.
+    by_id: Vec<Guest>,
 
-            let query_len = query.chars().count();
+    // NOTE: sorted ascending by IpAddr
+    by_addr: Vec<(IpAddr, GuestId)>,
 
-            for src in [&book.title, &book.author, &book.description, &book.content] {
-                /* compare whole similarity */
-                let whole_sim = strsim::normalized_damerau_levenshtein(query, src);
+    // NOTE: sorted ascending by nickname
+    by_nick: Vec<(Arc<str>, GuestId)>,
 
-                /* compare percentage of containment */
-                let instances = src.match_indices(query).count();
-                let instances_len = instances * query_len;
-                let substr_sim = if instances_len == 0 {
-                    0.0
-                } else {
-                    src.len() as f64 / instances_len as f64
-                };
+    // NOTE: sorted ascending by token digest
+    by_token: Vec<([u8; 32], GuestId)>,
+}
 
-                for cur in [whole_sim, substr_sim] {
-                    match sim {
-                        None => sim = Some(cur),
-                        Some(prev) => {
-                            if prev < cur {
-                                sim = Some(cur);
-                            }
-                        }
-                    }
-                }
-            }
+impl Guests {
+    fn lookup_addr(&self, addr: IpAddr) -> Result<usize, usize> {
+        self.by_addr
+            .binary_search_by_key(&addr, |&(addr, _id)| addr)
+    }
 
-            let sim = sim.unwrap();
+    fn lookup_nick(&self, nick: &str) -> Result<usize, usize> {
+        self.by_nick
+            .binary_search_by(|(other, _id)| (**other).cmp(nick))
+    }
 
-            if THRESHOLD <= sim {
-                Some(sim)
-            } else {
-                None
-            }
-        }
+    fn lookup_token(&self, digest: &[u8; 32]) -> Result<usize, usize> {
+        self.by_token
+            .binary_search_by_key(digest, |&(digest, _id)| digest)
+    }
+}
 
-        let mut found = Vec::new();
-        {
-            let pool = self.book_pool.read().await;
-            for (idx, book) in pool.iter().enumerate() {
-                let book_id = BookID(idx);
-                if let Some(sim) = cmp(book, query) {
-                    let meta = self.lookup_metadata(book_id);
-                    found.push((sim, book_id, meta));
-                }
-            }
-        }
-        // HA HA HA
-        found.sort_by(|(a, _, _), (b, _, _)| b.partial_cmp(a).unwrap_or(Ordering::Less));
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct BookID(usize);
 
-        found
+impl core::fmt::Display for BookID {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    pub async fn add(&self, book: impl Into<Arc<Book>>, guest: IpAddr) -> BookID {
-        let mut pool = self.book_pool.write().await;
-        let book_id: BookID = BookID(pool.len());
-        let book: Arc<Book> = book.into();
-        pool.push(book);
+/// Marks a book as being read by a guest until dropped. See
+/// [`Library::start_reading`].
+#[derive(Debug)]
+#[must_use = "the book stops being read as soon as this is dropped"]
+pub struct Reading<'lib> {
+    library: &'lib Library,
+    key: (GuestId, BookID),
+}
 
-        let old = self.book_meta.insert(book_id, Metadata::new(guest));
-        debug_assert!(
-            old.is_none(),
-            "it would be weird if this BookID already existed"
-        );
+impl Drop for Reading<'_> {
+    fn drop(&mut self) {
+        self.library
+            .reading
+            .remove_if_mut(&self.key, |_key, sessions| {
+                *sessions -= 1;
+                *sessions == 0
+            });
+    }
+}
 
-        book_id
+/// Marks a connection as open until dropped, so the operator can see who's
+/// around and what they're up to. See [`Library::visit`].
+#[derive(Debug)]
+#[must_use = "the visit is forgotten as soon as this is dropped"]
+pub struct Visit<'lib> {
+    library: &'lib Library,
+    key: u64,
+}
+
+impl Visit<'_> {
+    /// Note that the visitor is now `guest`, running the command named
+    /// `activity`, or sitting at the prompt if that's `None`.
+    pub fn update(&self, guest: GuestId, activity: Option<&'static str>) {
+        self.library.visits.insert(self.key, (guest, activity));
     }
+}
 
-    pub fn checkout(&self, book_id: BookID, guest: IpAddr) -> Result<(), UpdateEntryError> {
-        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
-        match meta.checked_out_by {
-            Some(by) => Err(UpdateEntryError::AlreadyCheckedOut(by)),
-            None => {
-                meta.set_checkout(guest);
-                meta.register_checkout();
-                Ok(())
-            }
-        }
+impl Drop for Visit<'_> {
+    fn drop(&mut self) {
+        self.library.visits.remove(&self.key);
     }
+}
 
-    pub fn checkin(&self, book_id: BookID, guest: IpAddr) -> Result<(), UpdateEntryError> {
-        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
-        if let Some(by) = meta.checked_out_by {
-            if by == guest {
-                meta.set_checkin();
-                Ok(())
-            } else {
-                Err(UpdateEntryError::GuestMismatch)
-            }
-        } else {
-            Err(UpdateEntryError::AlreadyCheckedIn)
+/// How much the book pool is holding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolUsage {
+    /// Every book ever added, trash and all.
+    pub books: usize,
+    /// Bytes of content, as written.
+    pub content_bytes: usize,
+    /// Bytes actually spent on content, deflated and indexed.
+    pub stored_bytes: usize,
+}
+
+/// How big the library is at one moment, and how it's been used, for the
+/// operator.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LibraryMetrics {
+    /// On the shelves, that is, not counting the trash.
+    pub books: usize,
+    pub trashed: usize,
+    pub checked_out: usize,
+    pub guests: usize,
+    /// Per command, by name, how many times it's been run.
+    pub commands: Vec<(&'static str, u64)>,
+}
+
+#[derive(Debug)]
+pub struct Library {
+    /// Push-only pool of books. Indices are unique and stable mappings to books.
+    /// The lock is only written to grow the pool (or to serialize whole
+    /// operations); a book is swapped out from under a read lock, and readers
+    /// get either the old one or the new one.
+    book_pool: RwLock<Vec<ArcSwap<StoredBook>>>,
+    /// Recently read books, already unpacked.
+    book_cache: Mutex<BookCache>,
+
+    /// Table of book metadata. This is expected to be frequently read and
+    /// written to as books are checked in and out.
+    book_meta: DashMap<BookID, Metadata>,
+    /// When each book was put on the shelves.
+    added_at: DashMap<BookID, SystemTime>,
+
+    guests: RwLock<Guests>,
+    /// Addresses the operator has turned away.
+    banned: DashSet<IpAddr>,
+    /// Per address, when its run of nonsense started and how long it is.
+    strikes: DashMap<IpAddr, (Instant, usize)>,
+    /// Addresses turned away for sending too much nonsense, until when.
+    soft_banned: DashMap<IpAddr, Instant>,
+    /// How many strikes in a row earn an address a soft ban.
+    strike_limit: usize,
+    /// How long ago a strike may be and still count towards the limit.
+    strike_window: Duration,
+    /// How long a soft ban lasts.
+    soft_ban_for: Duration,
+    /// Every open connection, in order of arrival: who it is and what they're
+    /// running.
+    visits: DashMap<u64, (GuestId, Option<&'static str>)>,
+    next_visit: AtomicU64,
+    /// When each guest last did anything, for evicting stale ones.
+    last_seen: DashMap<GuestId, SystemTime>,
+    /// Who has what open to read right now, and in how many sessions.
+    reading: DashMap<(GuestId, BookID), usize>,
+    /// How many lines into each book each guest has ever got.
+    progress: DashMap<(GuestId, BookID), usize>,
+    /// [`Library::progress`] summed up per book: how many guests got each
+    /// number of lines in, for [`Library::reading_stats`].
+    reach: DashMap<BookID, BTreeMap<usize, usize>>,
+    /// Per book, every guest who's opened it to read, up to
+    /// [`Library::MAX_UNIQUE_READERS`].
+    readers: DashMap<BookID, HashSet<GuestId>>,
+    /// Where each guest left off in each book they haven't finished, as the
+    /// index of the line they were on.
+    bookmarks: DashMap<(GuestId, BookID), usize>,
+    /// Per book, the checkout it's out on. Only touched with the book's
+    /// metadata held.
+    open_loans: DashMap<BookID, Loan>,
+    /// The last [`Library::LOAN_HISTORY`] checkouts to come back, in the
+    /// order they did, for the operator's records.
+    loans: Mutex<VecDeque<Loan>>,
+    /// Per guest, mail they haven't been at a prompt to hear yet.
+    mailboxes: DashMap<GuestId, Vec<Mail>>,
+    /// Per author and book, the day number they were last told about a
+    /// checkout of it.
+    mailed: DashMap<(GuestId, BookID), i64>,
+    /// Authors who'd rather not hear about their books.
+    unnotified: DashSet<GuestId>,
+    /// Where the books on the shelves are saved, for [`Library::data_file`].
+    data_file: Option<PathBuf>,
+    /// Who a snapshot says added each book put back on the shelves. Only
+    /// shown; the books stay the operator's, whoever registers the nickname.
+    saved_added_by: DashMap<BookID, Arc<str>>,
+    /// Most guests to keep registered at once.
+    guest_limit: usize,
+    /// How long a guest must have been idle before they may be evicted to
+    /// make room.
+    guest_stale_after: Duration,
+    /// Most books any one guest but the operator may have on the shelves.
+    contribution_limit: usize,
+    /// How many lines of a book anyone may preview without checking it out.
+    preview_lines: usize,
+    /// How the shell words what it says to guests.
+    flavor: Flavor,
+
+    /// Catalog change feed. Slow subscribers lose the oldest events rather
+    /// than holding up the library.
+    events: broadcast::Sender<LibraryEvent>,
+    /// The last few events and when they happened, for the operator.
+    recent_events: Mutex<VecDeque<(SystemTime, LibraryEvent)>>,
+    /// The books last checked back in, oldest first, for anyone to see.
+    just_returned: Mutex<VecDeque<BookID>>,
+    /// The operator's announcements, for everyone connected.
+    announcements: broadcast::Sender<Arc<str>>,
+    /// Bumped whenever a book goes on or off the shelves or changes, so
+    /// whoever saves them can tell when there's something new.
+    changes: AtomicU64,
+
+    /// How many times each shell command has been run, by name.
+    command_counts: DashMap<&'static str, u64>,
+
+    guestbook: OnceLock<BookID>,
+    /// Per guest, the day number they last signed the guestbook and how many
+    /// times they signed it that day. Only touched under the pool write lock.
+    guestbook_signatures: DashMap<GuestId, (i64, u32)>,
+
+    /// When the library opened, for its uptime.
+    started: SystemTime,
+    clock: Arc<dyn Clock>,
+    rng: Arc<dyn Rng>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`Library`] with its options set up front, and books on the shelves
+/// before anyone can look.
+#[derive(Debug)]
+pub struct LibraryBuilder {
+    clock: Arc<dyn Clock>,
+    rng: Arc<dyn Rng>,
+    operator_nick: Option<String>,
+    guest_limit: (usize, Duration),
+    contribution_limit: usize,
+    preview_lines: usize,
+    flavor: Flavor,
+    strike_limit: (usize, Duration, Duration),
+    books: Vec<Book>,
+    guestbook: bool,
+    data_file: Option<PathBuf>,
+}
+
+impl Default for LibraryBuilder {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            rng: Arc::new(OsRng),
+            operator_nick: None,
+            guest_limit: (usize::MAX, Library::DEFAULT_GUEST_STALE_AFTER),
+            contribution_limit: usize::MAX,
+            preview_lines: Library::DEFAULT_PREVIEW_LINES,
+            flavor: Flavor::default(),
+            strike_limit: (
+                Library::DEFAULT_STRIKE_LIMIT,
+                Library::DEFAULT_STRIKE_WINDOW,
+                Library::DEFAULT_SOFT_BAN_FOR,
+            ),
+            books: Vec::new(),
+            guestbook: false,
+            data_file: None,
+        }
+    }
+}
+
+impl LibraryBuilder {
+    /// Tell the time with `clock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Roll dice with `rng`. Salts and tokens come from the OS regardless.
+    pub fn rng(mut self, rng: Arc<dyn Rng>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// See [`Library::with_operator_nick`].
+    pub fn operator_nick(mut self, nick: &str) -> Self {
+        self.operator_nick = Some(nick.to_string());
+        self
+    }
+
+    /// See [`Library::set_guest_limit`].
+    pub fn guest_limit(mut self, limit: usize, stale_after: Duration) -> Self {
+        self.guest_limit = (limit, stale_after);
+        self
+    }
+
+    /// See [`Library::set_contribution_limit`].
+    pub fn contribution_limit(mut self, limit: usize) -> Self {
+        self.contribution_limit = limit;
+        self
+    }
+
+    /// See [`Library::set_preview_lines`].
+    pub fn preview_lines(mut self, lines: usize) -> Self {
+        self.preview_lines = lines;
+        self
+    }
+
+    /// See [`Library::set_flavor`].
+    pub fn flavor(mut self, flavor: Flavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    /// See [`Library::set_strike_limit`].
+    pub fn strike_limit(mut self, limit: usize, window: Duration, ban_for: Duration) -> Self {
+        self.strike_limit = (limit, window, ban_for);
+        self
+    }
+
+    /// Open with `books` on the shelves, added by the operator in order and
+    /// protected as library notices.
+    pub fn books(mut self, books: impl IntoIterator<Item = Book>) -> Self {
+        self.books.extend(books);
+        self
+    }
+
+    /// Open with the communal guestbook, after any [`LibraryBuilder::books`].
+    pub fn guestbook(mut self, guestbook: bool) -> Self {
+        self.guestbook = guestbook;
+        self
+    }
+
+    /// See [`Library::data_file`].
+    pub fn data_file(mut self, path: PathBuf) -> Self {
+        self.data_file = Some(path);
+        self
+    }
+
+    pub async fn build(self) -> Library {
+        let mut lib = Library::new_with(self.clock, self.rng);
+        lib.data_file = self.data_file;
+        if let Some(nick) = &self.operator_nick {
+            lib = lib.with_operator_nick(nick);
+        }
+        let (limit, stale_after) = self.guest_limit;
+        lib.set_guest_limit(limit, stale_after);
+        lib.set_contribution_limit(self.contribution_limit);
+        lib.set_preview_lines(self.preview_lines);
+        lib.set_flavor(self.flavor);
+        let (limit, window, ban_for) = self.strike_limit;
+        lib.set_strike_limit(limit, window, ban_for);
+        for book in self.books {
+            let book_id = lib
+                .add(book, Library::OPERATOR)
+                .await
+                .expect("the operator has no contribution limit");
+            lib.toggle_protected(book_id);
+        }
+        if self.guestbook {
+            lib.open_guestbook().await;
+        }
+        lib
+    }
+}
+
+impl Library {
+    pub const OPERATOR: GuestId = GuestId(0);
+    /// Stands in for a guest who is only looking around and hasn't picked a
+    /// nickname yet. Nobody is ever registered under it.
+    pub const NOBODY: GuestId = GuestId(usize::MAX);
+    pub const OPERATOR_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    /// What the operator goes by, unless renamed with
+    /// [`Library::with_operator_nick`].
+    pub const DEFAULT_OPERATOR_NICK: &str = "cat in the machine";
+
+    /// How many times a guest may sign the guestbook per (UTC) day.
+    pub const GUESTBOOK_DAILY_LIMIT: u32 = 3;
+    /// Longest guestbook message, in characters.
+    pub const GUESTBOOK_MAX_LEN: usize = 200;
+
+    /// Longest content an upload may have, in bytes.
+    pub const UPLOAD_MAX_LEN: usize = 1 << 20;
+    /// Longest announcement, in characters. Longer ones are cut short.
+    pub const ANNOUNCEMENT_MAX_LEN: usize = 200;
+
+    /// How many events a subscriber may fall behind before it starts missing
+    /// them.
+    pub const EVENT_CAPACITY: usize = 64;
+    /// How many events [`Library::recent_events`] remembers.
+    pub const RECENT_EVENTS: usize = 10;
+    /// How many books [`Library::just_returned`] remembers.
+    pub const JUST_RETURNED: usize = 10;
+    /// How many returned checkouts [`Library::loans`] remembers. Books
+    /// still out are always kept.
+    pub const LOAN_HISTORY: usize = 10_000;
+
+    /// How long a guest must have been idle before they may be evicted, if
+    /// not set otherwise.
+    pub const DEFAULT_GUEST_STALE_AFTER: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+    /// How many strikes in a row earn an address a soft ban, if not set
+    /// otherwise.
+    pub const DEFAULT_STRIKE_LIMIT: usize = 10;
+    /// How long strikes count towards a soft ban, if not set otherwise.
+    pub const DEFAULT_STRIKE_WINDOW: Duration = Duration::from_secs(60);
+    /// How long a soft ban lasts, if not set otherwise.
+    pub const DEFAULT_SOFT_BAN_FOR: Duration = Duration::from_secs(10 * 60);
+
+    /// How many lines of a book may be previewed, if not set otherwise.
+    pub const DEFAULT_PREVIEW_LINES: usize = 20;
+
+    /// Most guests to remember per book for [`Library::unique_readers`].
+    /// Past that, a book's count stops going up.
+    pub const MAX_UNIQUE_READERS: usize = 4096;
+
+    /// Most books [`Library::similar_to`] looks through, newest first.
+    pub const SIMILAR_SCAN: usize = 1000;
+
+    /// How many unpacked books to keep around for rereading.
+    pub const BOOK_CACHE_CAPACITY: usize = 16;
+
+    pub fn new() -> Self {
+        Self::new_with(Arc::new(SystemClock), Arc::new(OsRng))
+    }
+
+    /// An empty library that tells the time with `clock` and rolls dice with
+    /// `rng`, so tests can pick both.
+    pub fn new_with(clock: Arc<dyn Clock>, rng: Arc<dyn Rng>) -> Self {
+        let operator = Guest::new(
+            Self::OPERATOR,
+            Self::OPERATOR_ADDR,
+            Self::DEFAULT_OPERATOR_NICK,
+        );
+        let guests = Guests {
+            by_addr: vec![(Self::OPERATOR_ADDR, operator.id)],
+            by_nick: vec![(Arc::clone(&operator.nick), operator.id)],
+            by_id: vec![operator],
+            by_token: Vec::new(),
+        };
+        Self {
+            book_pool: RwLock::new(Vec::new()),
+            book_cache: Mutex::new(BookCache::new(Self::BOOK_CACHE_CAPACITY)),
+            book_meta: DashMap::new(),
+            guests: RwLock::new(guests),
+            banned: DashSet::new(),
+            strikes: DashMap::new(),
+            soft_banned: DashMap::new(),
+            strike_limit: Self::DEFAULT_STRIKE_LIMIT,
+            strike_window: Self::DEFAULT_STRIKE_WINDOW,
+            soft_ban_for: Self::DEFAULT_SOFT_BAN_FOR,
+            visits: DashMap::new(),
+            next_visit: AtomicU64::new(0),
+            last_seen: DashMap::new(),
+            reading: DashMap::new(),
+            progress: DashMap::new(),
+            reach: DashMap::new(),
+            readers: DashMap::new(),
+            added_at: DashMap::new(),
+            bookmarks: DashMap::new(),
+            open_loans: DashMap::new(),
+            loans: Mutex::new(VecDeque::new()),
+            mailboxes: DashMap::new(),
+            mailed: DashMap::new(),
+            unnotified: DashSet::new(),
+            data_file: None,
+            saved_added_by: DashMap::new(),
+            guest_limit: usize::MAX,
+            guest_stale_after: Self::DEFAULT_GUEST_STALE_AFTER,
+            contribution_limit: usize::MAX,
+            preview_lines: Self::DEFAULT_PREVIEW_LINES,
+            flavor: Flavor::default(),
+            events: broadcast::channel(Self::EVENT_CAPACITY).0,
+            recent_events: Mutex::new(VecDeque::with_capacity(Self::RECENT_EVENTS + 1)),
+            just_returned: Mutex::new(VecDeque::with_capacity(Self::JUST_RETURNED + 1)),
+            announcements: broadcast::channel(Self::EVENT_CAPACITY).0,
+            changes: AtomicU64::new(0),
+            command_counts: DashMap::new(),
+            guestbook: OnceLock::new(),
+            guestbook_signatures: DashMap::new(),
+            started: clock.now(),
+            clock,
+            rng,
+        }
+    }
+
+    /// What time the library thinks it is.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// A random number, from the library's [`Rng`].
+    pub fn random(&self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: LibraryEvent) {
+        {
+            let mut recent = self.recent_events.lock().unwrap();
+            recent.push_back((self.now(), event));
+            if Self::RECENT_EVENTS < recent.len() {
+                recent.pop_front();
+            }
+        }
+        // NOTE: an error here only means nobody is listening
+        let _ = self.events.send(event);
+    }
+
+    /// The last [`Library::RECENT_EVENTS`] events and when they happened,
+    /// oldest first.
+    pub fn recent_events(&self) -> Vec<(SystemTime, LibraryEvent)> {
+        self.recent_events.lock().unwrap().iter().copied().collect()
+    }
+
+    /// The last [`Library::JUST_RETURNED`] books checked back in, newest
+    /// first, each only once. Whether they're still free is up to the caller.
+    pub fn just_returned(&self) -> Vec<BookID> {
+        self.just_returned
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .copied()
+            .collect()
+    }
+
+    fn note_returned(&self, book_id: BookID) {
+        let mut returned = self.just_returned.lock().unwrap();
+        returned.retain(|&id| id != book_id);
+        returned.push_back(book_id);
+        if Self::JUST_RETURNED < returned.len() {
+            returned.pop_front();
+        }
+    }
+
+    /// How long the library has been open.
+    pub fn uptime(&self) -> Duration {
+        self.now()
+            .duration_since(self.started)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    pub fn listen(&self) -> broadcast::Receiver<Arc<str>> {
+        self.announcements.subscribe()
+    }
+
+    /// Tell everyone who's [`Library::listen`]ing `message`, flattened onto
+    /// one line and cut short at [`Library::ANNOUNCEMENT_MAX_LEN`]. Returns
+    /// what was announced, or `None` if that left nothing to say.
+    pub fn announce(&self, message: &str) -> Option<Arc<str>> {
+        let message = sanitize::strip_ansi(message).replace(['\n', '\t'], " ");
+        let message = message.trim();
+        if message.is_empty() {
+            return None;
+        }
+        let message: Arc<str> = match message.char_indices().nth(Self::ANNOUNCEMENT_MAX_LEN) {
+            Some((cut, _)) => format!("{}…", &message[..cut]).into(),
+            None => message.into(),
+        };
+        // NOTE: an error here only means nobody is listening
+        let _ = self.announcements.send(Arc::clone(&message));
+        Some(message)
+    }
+
+    /// Set up a library with options other than the defaults.
+    pub fn builder() -> LibraryBuilder {
+        LibraryBuilder::default()
+    }
+
+    /// The same library, with the operator going by `nick` instead. Another
+    /// guest's nickname can't be taken, so it's left alone if `nick` is in use.
+    pub fn with_operator_nick(mut self, nick: &str) -> Self {
+        let guests = self.guests.get_mut();
+        if guests.lookup_nick(nick).is_ok() {
+            return self;
+        }
+
+        let old_idx = guests.lookup_nick(&guests.by_id[Self::OPERATOR.0].nick);
+        guests.by_nick.remove(old_idx.unwrap());
+        let nick: Arc<str> = Arc::from(nick);
+        guests.by_id[Self::OPERATOR.0].nick = Arc::clone(&nick);
+        let new_idx = guests.lookup_nick(&nick).unwrap_err();
+        guests.by_nick.insert(new_idx, (nick, Self::OPERATOR));
+        self
+    }
+
+    pub async fn lookup_guest_by_addr(&self, addr: IpAddr) -> Option<GuestId> {
+        let guests = self.guests.read().await;
+        let idx = guests.lookup_addr(addr).ok()?;
+        Some(guests.by_addr[idx].1)
+    }
+
+    pub async fn lookup_guest_by_nick(&self, nick: &str) -> Option<GuestId> {
+        let guests = self.guests.read().await;
+        let idx = guests.lookup_nick(nick).ok()?;
+        Some(guests.by_nick[idx].1)
+    }
+
+    /// Where `guest` last connected from. Only for connection setup; nothing
+    /// past that should need a guest's address.
+    async fn addr_of(&self, guest: GuestId) -> Option<IpAddr> {
+        let guests = self.guests.read().await;
+        guests.by_id.get(guest.0).and_then(|guest| guest.addr)
+    }
+
+    pub async fn lookup_nick(&self, guest: GuestId) -> Option<Arc<str>> {
+        let guests = self.guests.read().await;
+        guests
+            .by_id
+            .get(guest.0)
+            .map(|guest| Arc::clone(&guest.nick))
+    }
+
+    pub async fn register_guest(
+        &self,
+        addr: IpAddr,
+        nick: impl Into<Arc<str>>,
+    ) -> Result<GuestId, RegisterError> {
+        let guests = &mut *self.guests.write().await;
+
+        let nick: Arc<str> = nick.into();
+
+        /* check if this address already has a nickname */
+        if guests.lookup_addr(addr).is_ok() {
+            return Err(RegisterError::AlreadyRegistered);
+        }
+
+        /* check if this nickname is taken */
+        if guests.lookup_nick(&nick).is_ok() {
+            return Err(RegisterError::NicknameTaken);
+        }
+
+        /* make room, if there's none and somebody has been away long enough */
+        let now = self.now();
+        if self.guest_limit <= guests.by_nick.len() {
+            let before = now.checked_sub(self.guest_stale_after).unwrap_or(now);
+            if self.evict_stale_locked(guests, before) == 0 {
+                return Err(RegisterError::Full);
+            }
+        }
+        let addr_idx = guests.lookup_addr(addr).unwrap_err();
+        let nick_idx = guests.lookup_nick(&nick).unwrap_err();
+
+        let id = GuestId(guests.by_id.len());
+        self.last_seen.insert(id, now);
+        guests.by_nick.insert(nick_idx, (Arc::clone(&nick), id));
+        guests.by_addr.insert(addr_idx, (addr, id));
+        guests.by_id.push(Guest {
+            id,
+            addr: Some(addr),
+            nick,
+            passphrase: None,
+            token: None,
+        });
+        Ok(id)
+    }
+
+    /// Keep at most `limit` guests registered, evicting ones idle for at least
+    /// `stale_after` to make room for new ones.
+    pub fn set_guest_limit(&mut self, limit: usize, stale_after: Duration) {
+        self.guest_limit = limit;
+        self.guest_stale_after = stale_after;
+    }
+
+    pub fn guest_limit(&self) -> usize {
+        self.guest_limit
+    }
+
+    /// Let each guest but the operator have at most `limit` books on the
+    /// shelves. Books they added that went in the trash don't count.
+    pub fn set_contribution_limit(&mut self, limit: usize) {
+        self.contribution_limit = limit;
+    }
+
+    pub fn contribution_limit(&self) -> usize {
+        self.contribution_limit
+    }
+
+    pub fn guest_stale_after(&self) -> Duration {
+        self.guest_stale_after
+    }
+
+    pub fn set_preview_lines(&mut self, lines: usize) {
+        self.preview_lines = lines;
+    }
+
+    pub fn preview_lines(&self) -> usize {
+        self.preview_lines
+    }
+
+    pub fn set_flavor(&mut self, flavor: Flavor) {
+        self.flavor = flavor;
+    }
+
+    pub fn flavor(&self) -> Flavor {
+        self.flavor
+    }
+
+    /// Note that `guest` is still around.
+    pub fn touch(&self, guest: GuestId) {
+        if guest != Self::NOBODY {
+            self.last_seen.insert(guest, self.now());
+        }
+    }
+
+    /// When `guest` last ran a command, or registered, if they're known.
+    pub fn last_seen(&self, guest: GuestId) -> Option<SystemTime> {
+        self.last_seen.get(&guest).map(|at| *at)
+    }
+
+    /// Note that `guest` is reading `book_id` for as long as the returned
+    /// [`Reading`] is around.
+    pub fn start_reading(&self, book_id: BookID, guest: GuestId) -> Reading<'_> {
+        let key = (guest, book_id);
+        *self.reading.entry(key).or_default() += 1;
+        Reading { library: self, key }
+    }
+
+    /// Note that a connection is open, as `guest`, for as long as the
+    /// returned [`Visit`] is around.
+    pub fn visit(&self, guest: GuestId) -> Visit<'_> {
+        let key = self.next_visit.fetch_add(1, atomic::Ordering::Relaxed);
+        self.visits.insert(key, (guest, None));
+        Visit { library: self, key }
+    }
+
+    /// Who's connected right now and what they're running, in order of
+    /// arrival. Guests still looking around are [`Library::NOBODY`].
+    pub fn visitors(&self) -> Vec<(GuestId, Option<&'static str>)> {
+        let mut visits: Vec<_> = self
+            .visits
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect();
+        visits.sort_unstable_by_key(|(key, _)| *key);
+        visits.into_iter().map(|(_, visit)| visit).collect()
+    }
+
+    /// Whether anybody but `viewer` is reading `book_id` right now.
+    pub fn is_being_read(&self, book_id: BookID, viewer: GuestId) -> bool {
+        self.reading
+            .iter()
+            .any(|entry| matches!(*entry.key(), (guest, id) if id == book_id && guest != viewer))
+    }
+
+    /// Whether anybody at all is reading `book_id` right now.
+    pub fn has_readers(&self, book_id: BookID) -> bool {
+        self.reading.iter().any(|entry| entry.key().1 == book_id)
+    }
+
+    /// Every book being read right now and who's reading it, by guest.
+    pub fn readers(&self) -> Vec<(GuestId, BookID)> {
+        let mut readers: Vec<_> = self.reading.iter().map(|entry| *entry.key()).collect();
+        readers.sort_unstable();
+        readers
+    }
+
+    /// Note that `guest` got `lines_read` lines into `book_id`, unless they
+    /// once got further.
+    pub fn record_progress(&self, book_id: BookID, guest: GuestId, lines_read: usize) {
+        match self.progress.entry((guest, book_id)) {
+            Entry::Occupied(mut furthest) => {
+                let was = *furthest.get();
+                if was < lines_read {
+                    furthest.insert(lines_read);
+                    self.move_reach(book_id, Some(was), Some(lines_read));
+                }
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(lines_read);
+                self.move_reach(book_id, None, Some(lines_read));
+            }
+        }
+    }
+
+    /// Move one guest in `book_id`'s [`Library::reach`] from having got
+    /// `from` lines in to `to`, either being `None` for not at all.
+    fn move_reach(&self, book_id: BookID, from: Option<usize>, to: Option<usize>) {
+        let mut reach = self.reach.entry(book_id).or_default();
+        if let Some(from) = from {
+            if let btree_map::Entry::Occupied(mut guests) = reach.entry(from) {
+                *guests.get_mut() -= 1;
+                if *guests.get() == 0 {
+                    guests.remove();
+                }
+            }
+        }
+        if let Some(to) = to {
+            *reach.entry(to).or_default() += 1;
+        }
+    }
+
+    /// How many lines into `book_id` `guest` has ever got, if they've opened
+    /// it at all.
+    pub fn progress(&self, book_id: BookID, guest: GuestId) -> Option<usize> {
+        self.progress.get(&(guest, book_id)).map(|lines| *lines)
+    }
+
+    /// Where `guest` left off in `book_id`, if they did: the index of the
+    /// line they were on.
+    pub fn bookmark(&self, book_id: BookID, guest: GuestId) -> Option<usize> {
+        self.bookmarks.get(&(guest, book_id)).map(|idx| *idx)
+    }
+
+    /// Remember that `guest` left off at line index `idx` of `book_id`, or
+    /// forget where they were, if `None`.
+    pub fn set_bookmark(&self, book_id: BookID, guest: GuestId, idx: Option<usize>) {
+        match idx {
+            Some(idx) => self.bookmarks.insert((guest, book_id), idx),
+            None => self.bookmarks.remove(&(guest, book_id)).map(|(_, idx)| idx),
+        };
+    }
+
+    /// How far through `book_id` `guest` is, against the book as it is now.
+    pub async fn completion(&self, book_id: BookID, guest: GuestId) -> Option<u32> {
+        let lines_read = self.progress(book_id, guest)?;
+        let lines = self.lookup_cover(book_id).await.line_count();
+        Some(percent_through(lines_read, lines))
+    }
+
+    /// How many guests have opened `book_id`, and how far they got.
+    pub async fn reading_stats(&self, book_id: BookID) -> ReadingStats {
+        let lines = self.lookup_cover(book_id).await.line_count();
+        let Some(reach) = self.reach.get(&book_id) else {
+            return ReadingStats::default();
+        };
+        let (mut readers, mut total) = (0, 0);
+        for (&lines_read, &guests) in reach.iter() {
+            readers += guests;
+            total += u64::from(percent_through(lines_read, lines)) * guests as u64;
+        }
+        if readers == 0 {
+            return ReadingStats::default();
+        }
+        ReadingStats {
+            readers,
+            average_completion: u32::try_from(total / readers as u64).unwrap_or(100),
+        }
+    }
+
+    /// Forget every guest last seen before `before` who has nothing checked
+    /// out and never added a book, freeing their nickname, address and login
+    /// token. Returns how many were evicted. The operator is never evicted.
+    pub async fn evict_stale(&self, before: SystemTime) -> usize {
+        let guests = &mut *self.guests.write().await;
+        self.evict_stale_locked(guests, before)
+    }
+
+    fn evict_stale_locked(&self, guests: &mut Guests, before: SystemTime) -> usize {
+        let mut keep = HashSet::new();
+        for entry in self.book_meta.iter() {
+            let meta = entry.value();
+            keep.insert(meta.added_by);
+            keep.extend(meta.checked_out_by);
+        }
+        let stale = |id: GuestId| {
+            id != Self::OPERATOR
+                && !keep.contains(&id)
+                && self.last_seen.get(&id).is_none_or(|seen| *seen < before)
+        };
+
+        let evicted: HashSet<GuestId> = guests
+            .by_nick
+            .iter()
+            .map(|&(_, id)| id)
+            .filter(|&id| stale(id))
+            .collect();
+        if evicted.is_empty() {
+            return 0;
+        }
+        guests.by_nick.retain(|(_, id)| !evicted.contains(id));
+        guests.by_addr.retain(|(_, id)| !evicted.contains(id));
+        guests.by_token.retain(|(_, id)| !evicted.contains(id));
+        for &id in &evicted {
+            let guest = &mut guests.by_id[id.0];
+            guest.addr = None;
+            guest.passphrase = None;
+            guest.token = None;
+            self.last_seen.remove(&id);
+            self.guestbook_signatures.remove(&id);
+        }
+        self.progress.retain(|&(guest, book_id), &mut lines_read| {
+            let keep = !evicted.contains(&guest);
+            if !keep {
+                self.move_reach(book_id, Some(lines_read), None);
+            }
+            keep
+        });
+        self.bookmarks
+            .retain(|(guest, _), _| !evicted.contains(guest));
+        for &id in &evicted {
+            self.mailboxes.remove(&id);
+            self.unnotified.remove(&id);
+        }
+        self.mailed.retain(|(guest, _), _| !evicted.contains(guest));
+        evicted.len()
+    }
+
+    /// Fold guest `from` into guest `into`, for one cat who registered twice.
+    /// Whatever `from` added, has out, has ever had out, has bookmarked, got
+    /// some way into or hasn't heard yet becomes `into`'s. Where both have a
+    /// bookmark in the same book, `into`'s stays. Then `from` is forgotten
+    /// as if evicted, nickname, address and all. With `dry_run`, nothing
+    /// changes, and only what would move is counted.
+    ///
+    /// The guest list is locked throughout, so neither can log in or be
+    /// evicted meanwhile. `from` mustn't be connected, so nothing of theirs
+    /// changes underfoot either.
+    pub async fn merge_guests(
+        &self,
+        from: GuestId,
+        into: GuestId,
+        dry_run: bool,
+    ) -> Result<Merged, MergeError> {
+        let guests = &mut *self.guests.write().await;
+        if from == into {
+            return Err(MergeError::SameGuest);
+        }
+        if from == Self::OPERATOR {
+            return Err(MergeError::Operator);
+        }
+        let registered = |id| guests.by_nick.iter().any(|&(_, other)| other == id);
+        if !registered(from) || !registered(into) {
+            return Err(MergeError::Unregistered);
+        }
+        if self.visits.iter().any(|visit| visit.value().0 == from) {
+            return Err(MergeError::Connected);
+        }
+
+        let mut merged = Merged::default();
+        for mut entry in self.book_meta.iter_mut() {
+            let meta = entry.value_mut();
+            if meta.added_by == from {
+                merged.books += 1;
+                if !dry_run {
+                    meta.added_by = into;
+                }
+            }
+            if meta.checked_out_by == Some(from) {
+                merged.checkouts += 1;
+                if !dry_run {
+                    meta.checked_out_by = Some(into);
+                }
+            }
+        }
+        /* checkouts take the loans with metadata held, so never the other way */
+        let mut merge_loan = |loan: &mut Loan| {
+            if loan.guest == from {
+                merged.loans += 1;
+                if !dry_run {
+                    loan.guest = into;
+                }
+            }
+        };
+        for mut loan in self.open_loans.iter_mut() {
+            merge_loan(&mut loan);
+        }
+        self.loans.lock().unwrap().iter_mut().for_each(merge_loan);
+
+        let bookmarked = books_of(&self.bookmarks, from);
+        let started = books_of(&self.progress, from);
+        let mailed = books_of(&self.mailed, from);
+        merged.bookmarks = bookmarked
+            .iter()
+            .filter(|&&book_id| !self.bookmarks.contains_key(&(into, book_id)))
+            .count();
+        merged.progress = started.len();
+        /* news for somebody who'd rather not hear it is thrown out */
+        let notifies = !self.unnotified.contains(&into);
+        merged.mail = self
+            .mailboxes
+            .get(&from)
+            .filter(|_| notifies)
+            .map_or(0, |mail| {
+                mail.iter()
+                    .filter(|&&Mail::CheckedOut { by, .. }| by != into)
+                    .count()
+            });
+        if dry_run {
+            return Ok(merged);
+        }
+
+        for book_id in bookmarked {
+            if let Some((_, idx)) = self.bookmarks.remove(&(from, book_id)) {
+                self.bookmarks.entry((into, book_id)).or_insert(idx);
+            }
+        }
+        for book_id in started {
+            if let Some((_, lines)) = self.progress.remove(&(from, book_id)) {
+                self.move_reach(book_id, Some(lines), None);
+                self.record_progress(book_id, into, lines);
+            }
+        }
+        for book_id in mailed {
+            if let Some((_, day)) = self.mailed.remove(&(from, book_id)) {
+                let mut last = self.mailed.entry((into, book_id)).or_insert(day);
+                *last = cmp::max(*last, day);
+            }
+        }
+        if let Some((_, mail)) = self.mailboxes.remove(&from).filter(|_| notifies) {
+            self.mailboxes.entry(into).or_default().extend(mail);
+        }
+        for mut mailbox in self.mailboxes.iter_mut() {
+            let author = *mailbox.key();
+            mailbox
+                .value_mut()
+                .retain_mut(|Mail::CheckedOut { by, .. }| {
+                    if *by == from {
+                        *by = into;
+                    }
+                    /* nobody hears about their own checkouts */
+                    *by != author
+                });
+        }
+        for mut readers in self.readers.iter_mut() {
+            if readers.remove(&from) {
+                readers.insert(into);
+            }
+        }
+        if let Some((_, seen)) = self.last_seen.remove(&from) {
+            let mut last = self.last_seen.entry(into).or_insert(seen);
+            *last = cmp::max(*last, seen);
+        }
+        self.unnotified.remove(&from);
+        self.guestbook_signatures.remove(&from);
+
+        guests.by_nick.retain(|&(_, id)| id != from);
+        guests.by_addr.retain(|&(_, id)| id != from);
+        guests.by_token.retain(|&(_, id)| id != from);
+        let guest = &mut guests.by_id[from.0];
+        guest.addr = None;
+        guest.passphrase = None;
+        guest.token = None;
+        Ok(merged)
+    }
+
+    /// Refuse connections from `addr` from now on. Returns whether it wasn't
+    /// banned already.
+    pub fn ban(&self, addr: IpAddr) -> bool {
+        self.banned.insert(addr)
+    }
+
+    /// Let `addr` back in. Returns whether it was banned.
+    pub fn unban(&self, addr: IpAddr) -> bool {
+        self.banned.remove(&addr).is_some()
+    }
+
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.banned.contains(&addr)
+    }
+
+    /// [`Library::ban`] wherever `guest` last connected from. Returns whether
+    /// that wasn't banned already.
+    pub async fn ban_guest(&self, guest: GuestId) -> bool {
+        match self.addr_of(guest).await {
+            Some(addr) => self.ban(addr),
+            None => false,
+        }
+    }
+
+    /// [`Library::unban`] wherever `guest` last connected from. Returns
+    /// whether that was banned.
+    pub async fn unban_guest(&self, guest: GuestId) -> bool {
+        match self.addr_of(guest).await {
+            Some(addr) => self.unban(addr),
+            None => false,
+        }
+    }
+
+    /// Soft-ban an address for `ban_for` once it gets `limit` strikes, each
+    /// within `window` of the first.
+    pub fn set_strike_limit(&mut self, limit: usize, window: Duration, ban_for: Duration) {
+        self.strike_limit = limit;
+        self.strike_window = window;
+        self.soft_ban_for = ban_for;
+    }
+
+    /// Count something nonsensical from `addr` against it, like an unknown
+    /// command. Returns whether that earned it a soft ban. The operator never
+    /// gets one.
+    pub fn strike(&self, addr: IpAddr) -> bool {
+        if addr == Self::OPERATOR_ADDR {
+            return false;
+        }
+        let now = Instant::now();
+        let mut strikes = self.strikes.entry(addr).or_insert((now, 0));
+        let (since, count) = &mut *strikes;
+        if self.strike_window < now - *since {
+            *since = now;
+            *count = 0;
+        }
+        *count += 1;
+        if *count < self.strike_limit {
+            return false;
+        }
+        drop(strikes);
+        self.strikes.remove(&addr);
+        self.soft_banned.insert(addr, now + self.soft_ban_for);
+        true
+    }
+
+    /// Forget `addr`'s strikes, now that it's made sense.
+    pub fn forgive(&self, addr: IpAddr) {
+        self.strikes.remove(&addr);
+    }
+
+    pub fn is_soft_banned(&self, addr: IpAddr) -> bool {
+        self.soft_banned
+            .remove_if(&addr, |_, until| *until <= Instant::now());
+        self.soft_banned.contains_key(&addr)
+    }
+
+    /// Forget soft bans that are over and strikes too old to count, so
+    /// addresses that stop by once don't pile up. Returns how many soft bans
+    /// were over.
+    pub fn prune_strikes(&self) -> usize {
+        let now = Instant::now();
+        self.strikes
+            .retain(|_, (since, _)| now - *since <= self.strike_window);
+        let before = self.soft_banned.len();
+        self.soft_banned.retain(|_, until| now < *until);
+        before - self.soft_banned.len()
+    }
+
+    /// Let `guest` claim their nickname from other addresses with `passphrase`.
+    pub async fn set_passphrase(&self, guest: GuestId, passphrase: &str) {
+        let guests = &mut *self.guests.write().await;
+        guests.by_id[guest.0].passphrase = Some(Passphrase::new(passphrase));
+    }
+
+    /// Move the guest called `nick` over to `addr`, if `passphrase` is theirs.
+    /// Their old address forgets them, and if `addr` belonged to somebody
+    /// else, it doesn't anymore.
+    pub async fn claim_guest(
+        &self,
+        addr: IpAddr,
+        nick: &str,
+        passphrase: &str,
+    ) -> Result<GuestId, ClaimError> {
+        let guests = &mut *self.guests.write().await;
+
+        let Ok(nick_idx) = guests.lookup_nick(nick) else {
+            return Err(ClaimError::UnknownNickname);
+        };
+        let id = guests.by_nick[nick_idx].1;
+        let guest = &mut guests.by_id[id.0];
+        match &guest.passphrase {
+            None => return Err(ClaimError::NoPassphrase),
+            Some(stored) if !stored.verify(passphrase) => {
+                return Err(ClaimError::WrongPassphrase);
+            }
+            Some(_) => {}
+        }
+
+        /* their old address only forgets them if it's still theirs */
+        if let Some(old_addr) = guest.addr.replace(addr) {
+            if let Ok(idx) = guests.lookup_addr(old_addr) {
+                if guests.by_addr[idx].1 == id {
+                    guests.by_addr.remove(idx);
+                }
+            }
+        }
+        match guests.lookup_addr(addr) {
+            Ok(idx) => {
+                let displaced = core::mem::replace(&mut guests.by_addr[idx].1, id);
+                if displaced != id {
+                    guests.by_id[displaced.0].addr = None;
+                }
+            }
+            Err(idx) => guests.by_addr.insert(idx, (addr, id)),
+        }
+        Ok(id)
+    }
+
+    /// Give `guest` a fresh login token, invalidating any older one. Only a
+    /// digest is kept, so this is the one chance to show it to them.
+    pub async fn issue_token(&self, guest: GuestId) -> String {
+        let token: String = clock::secret_bytes::<16>()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let digest = token_digest(&token);
+
+        let guests = &mut *self.guests.write().await;
+        if let Some(old) = guests.by_id[guest.0].token.replace(digest) {
+            if let Ok(idx) = guests.lookup_token(&old) {
+                guests.by_token.remove(idx);
+            }
+        }
+        match guests.lookup_token(&digest) {
+            Ok(idx) => guests.by_token[idx].1 = guest,
+            Err(idx) => guests.by_token.insert(idx, (digest, guest)),
+        }
+        token
+    }
+
+    /// Resolve a login token to its guest. Unlike [`Library::claim_guest`]
+    /// this leaves addresses alone, so guests sharing an address (say, behind
+    /// the same NAT) can each be themselves.
+    pub async fn login(&self, token: &str) -> Option<GuestId> {
+        let guests = self.guests.read().await;
+        let idx = guests.lookup_token(&token_digest(token)).ok()?;
+        Some(guests.by_token[idx].1)
+    }
+
+    pub async fn metrics(&self) -> LibraryMetrics {
+        let (mut books, mut trashed, mut checked_out) = (0, 0, 0);
+        for entry in self.book_meta.iter() {
+            let meta = entry.value();
+            match meta.removed {
+                None => books += 1,
+                Some(Removal::Trashed(_)) => trashed += 1,
+                Some(Removal::Purged) => {}
+            }
+            if !meta.is_free() {
+                checked_out += 1;
+            }
+        }
+        let guests = self.guests.read().await.by_nick.len();
+        LibraryMetrics {
+            books,
+            trashed,
+            checked_out,
+            guests,
+            commands: self.command_counts(),
+        }
+    }
+
+    pub async fn pool_usage(&self) -> PoolUsage {
+        let pool = self.book_pool.read().await;
+        let mut usage = PoolUsage {
+            books: pool.len(),
+            ..PoolUsage::default()
+        };
+        for stored in pool.iter() {
+            let stored = stored.load();
+            usage.content_bytes += stored.content_len();
+            usage.stored_bytes += stored.stored_len();
+        }
+        usage
+    }
+
+    /// Count a run of the command called `name`.
+    pub fn record_command(&self, name: &'static str) {
+        *self.command_counts.entry(name).or_insert(0) += 1;
+    }
+
+    /// Every command that has been run at least once, by name, and how many
+    /// times.
+    pub fn command_counts(&self) -> Vec<(&'static str, u64)> {
+        let mut counts: Vec<(&'static str, u64)> = self
+            .command_counts
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect();
+        counts.sort_unstable();
+        counts
+    }
+
+    /// How many times a book has gone on or off the shelves or changed since
+    /// the library opened. Nothing has if it's the same as last time.
+    pub fn changes(&self) -> u64 {
+        self.changes.load(atomic::Ordering::Acquire)
+    }
+
+    fn changed(&self) {
+        self.changes.fetch_add(1, atomic::Ordering::Release);
+    }
+
+    /// Where the books on the shelves are saved, if anywhere, for the
+    /// operator to `reload` after editing it by hand.
+    pub fn data_file(&self) -> Option<&Path> {
+        self.data_file.as_deref()
+    }
+
+    /// Every book on the shelves, communal ones too, oldest first.
+    pub fn shelved(&self) -> Vec<BookID> {
+        let mut shelved: Vec<BookID> = self
+            .book_meta
+            .iter()
+            .filter(|entry| !entry.is_hidden())
+            .map(|entry| *entry.key())
+            .collect();
+        shelved.sort_unstable();
+        shelved
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.book_pool.read().await.is_empty()
+    }
+
+    pub async fn lookup_book_by_id(&self, id: BookID) -> Arc<Book> {
+        let stored = self.book_pool.read().await[id.0].load_full();
+        self.unpack(id, stored, true)
+    }
+
+    /// The cover of `id`, and what's known of its content without unpacking
+    /// it, for when that's all that's needed.
+    pub async fn lookup_cover(&self, id: BookID) -> Arc<StoredBook> {
+        self.book_pool.read().await[id.0].load_full()
+    }
+
+    /// Just the title of `id`, without unpacking the rest.
+    pub async fn lookup_title(&self, id: BookID) -> String {
+        self.book_pool.read().await[id.0].load().title.clone()
+    }
+
+    /// Like [`Library::lookup_book_by_id`], for going over many books at once:
+    /// what's unpacked isn't kept in the cache.
+    pub async fn lookup_book_uncached(&self, id: BookID) -> Arc<Book> {
+        let stored = self.book_pool.read().await[id.0].load_full();
+        self.unpack(id, stored, false)
+    }
+
+    /// Unpack a book from the pool, going through the cache. Scans over the
+    /// whole pool shouldn't `remember` what they unpack, or every search would
+    /// flush out what guests are actually reading.
+    fn unpack(&self, id: BookID, stored: Arc<StoredBook>, remember: bool) -> Arc<Book> {
+        if let Some(book) = self.book_cache.lock().unwrap().get(id, &stored) {
+            return book;
+        }
+        let book = Arc::new(stored.unpack());
+        if remember {
+            let mut cache = self.book_cache.lock().unwrap();
+            cache.insert(id, stored, Arc::clone(&book));
+        }
+        book
+    }
+
+    /// Every book `guest` has checked out, oldest checkout first, so they're
+    /// numbered the same from one listing to the next.
+    pub async fn lookup_checkouts_by_guest(&self, guest: GuestId) -> Vec<(BookID, Metadata)> {
+        // TODO: inefficient
+
+        let mut found = Vec::new();
+        for entry in self.book_meta.iter() {
+            let (&id, &meta) = entry.pair();
+            if meta.checked_out_by == Some(guest) && meta.visible_to(guest) {
+                found.push((id, meta));
+            }
+        }
+        found.sort_by_key(|&(id, meta)| (meta.checked_out_at, id));
+        found
+    }
+
+    pub fn lookup_metadata(&self, id: BookID) -> Metadata {
+        *self.book_meta.get(&id).unwrap()
+    }
+
+    /// Score every book `requester` may see against `query`, best first.
+    /// Matches found inside a description or content also say where, so
+    /// callers can show a snippet. Books they can't see are never scored,
+    /// and neither are books the query's [`Filters`] leave out.
+    ///
+    /// Searches see the collection as of one moment: every book added before
+    /// the search started and none added after. Metadata is copied while the
+    /// pool is locked, so every result carries metadata for the book it names.
+    /// Checkouts don't take that lock, though, so each result's checkout state
+    /// is one it really had during the search, not necessarily all at once.
+    pub async fn search(
+        &self,
+        query: &str,
+        requester: GuestId,
+    ) -> Vec<(f64, BookID, Metadata, Option<Match>)> {
+        let start = std::time::Instant::now();
+        let (filters, query) = Self::filters(query);
+
+        let mut found = Vec::new();
+        for (book_id, stored, meta) in self.search_snapshot(requester).await {
+            if !filters.admit(&stored, &meta) {
+                continue;
+            }
+            let book = self.unpack(book_id, stored, false);
+            if let Some((sim, matched)) = Self::score(&book, &query) {
+                found.push((sim, book_id, meta, matched));
+            }
+        }
+        // HA HA HA
+        found.sort_by(|(a, ..), (b, ..)| b.partial_cmp(a).unwrap_or(Ordering::Less));
+
+        metrics::observe_search(start.elapsed());
+        found
+    }
+
+    /// The first `k` results [`Library::search`] would give, in the same
+    /// order, without keeping or sorting the rest.
+    pub async fn search_top_k(
+        &self,
+        query: &str,
+        requester: GuestId,
+        k: usize,
+    ) -> Vec<(f64, BookID, Metadata, Option<Match>)> {
+        let start = std::time::Instant::now();
+        let (filters, query) = Self::filters(query);
+
+        /* the worst of the best k so far is on top, to be knocked off */
+        let mut best: BinaryHeap<Reverse<Ranked>> = BinaryHeap::with_capacity(k);
+        for (book_id, stored, meta) in self.search_snapshot(requester).await {
+            if k == 0 {
+                break;
+            }
+            if !filters.admit(&stored, &meta) {
+                continue;
+            }
+            let book = self.unpack(book_id, stored, false);
+            let Some((sim, matched)) = Self::score(&book, &query) else {
+                continue;
+            };
+            let hit = Ranked((sim, book_id, meta, matched));
+            if best.len() < k {
+                best.push(Reverse(hit));
+            } else if best.peek().is_some_and(|Reverse(worst)| *worst < hit) {
+                best.pop();
+                best.push(Reverse(hit));
+            }
+        }
+        let found = best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(Ranked(hit))| hit)
+            .collect();
+
+        metrics::observe_search(start.elapsed());
+        found
+    }
+
+    /// The [`Filters`] in `query`, and the rest of the query to score books
+    /// against.
+    pub fn filters(query: &str) -> (Filters<'_>, Cow<'_, str>) {
+        let mut filters = Filters::default();
+        let mut rest = Vec::new();
+        for word in query.split_whitespace() {
+            if let Some(language) = word.strip_prefix("lang:") {
+                filters.language = Some(language);
+            } else if word == "available:" {
+                filters.available = true;
+            } else {
+                rest.push(word);
+            }
+        }
+        if filters == Filters::default() {
+            return (filters, Cow::Borrowed(query));
+        }
+        (filters, Cow::Owned(rest.join(" ")))
+    }
+
+    /// Every language the books `requester` may see are written in, with how
+    /// many are in each, by tag. Books with no language come last, as `None`.
+    pub async fn languages(&self, requester: GuestId) -> Vec<(Option<String>, usize)> {
+        let mut counts: HashMap<Option<String>, usize> = HashMap::new();
+        for (_book_id, stored, _meta) in self.search_snapshot(requester).await {
+            *counts.entry(stored.language.clone()).or_insert(0) += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        /* `None` sorts first, so flip it to the end */
+        counts.sort_by(|(a, _), (b, _)| a.is_none().cmp(&b.is_none()).then(a.cmp(b)));
+        counts
+    }
+
+    /// Every author of a book `requester` may see, as in [`normalize_name`],
+    /// and how many books each has, most first, then by name. Each goes by
+    /// however it was written on their oldest book.
+    pub async fn authors(&self, requester: GuestId) -> Vec<(String, usize)> {
+        let mut by_key: HashMap<String, (String, usize)> = HashMap::new();
+        for (_book_id, stored, _meta) in self.search_snapshot(requester).await {
+            let (_author, count) = by_key
+                .entry(normalize_name(&stored.author))
+                .or_insert_with(|| (stored.author.clone(), 0));
+            *count += 1;
+        }
+        let mut counts: Vec<_> = (by_key.into_iter())
+            .map(|(key, (author, count))| (key, author, count))
+            .collect();
+        counts.sort_by(|(a, _, a_count), (b, _, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        counts
+            .into_iter()
+            .map(|(_key, author, count)| (author, count))
+            .collect()
+    }
+
+    /// Every book by `author`, as in [`normalize_name`], that `requester` may
+    /// see, oldest first, as if searched for.
+    pub async fn books_by_author(
+        &self,
+        author: &str,
+        requester: GuestId,
+    ) -> Vec<(f64, BookID, Metadata, Option<Match>)> {
+        let author = normalize_name(author);
+        self.search_snapshot(requester)
+            .await
+            .into_iter()
+            .filter(|(_, stored, _)| normalize_name(&stored.author) == author)
+            .map(|(book_id, _, meta)| (1.0, book_id, meta, None))
+            .collect()
+    }
+
+    /// Up to `n` other books like `book_id`, going by their titles and
+    /// descriptions and whether they share its author, best first. Leaves
+    /// out copies of it, communal books, and books `requester` can't see or
+    /// already has. Only the newest [`Library::SIMILAR_SCAN`] books are
+    /// looked at, so it's cheap enough for every cover page.
+    pub async fn similar_to(&self, book_id: BookID, n: usize, requester: GuestId) -> Vec<BookID> {
+        /* weak likenesses, like sharing a couple of letters, don't count */
+        const THRESHOLD: f64 = 0.2;
+
+        let book = self.lookup_book_by_id(book_id).await;
+        let (title, author) = book.cover_key();
+        let description = book.description.to_lowercase();
+
+        let mut scored: Vec<(f64, BookID)> = self
+            .search_snapshot(requester)
+            .await
+            .into_iter()
+            .rev()
+            .filter(|(id, _, meta)| {
+                *id != book_id
+                    && !meta.is_hidden()
+                    && !meta.communal
+                    && meta.checked_out_by != Some(requester)
+            })
+            .take(Self::SIMILAR_SCAN)
+            .filter_map(|(id, stored, _)| {
+                let (other_title, other_author) = (
+                    normalize_name(&stored.title),
+                    normalize_name(&stored.author),
+                );
+                if (&other_title, &other_author) == (&title, &author) {
+                    return None;
+                }
+                let other_description = stored.description.to_lowercase();
+                let sim = 0.5 * strsim::sorensen_dice(&title, &other_title)
+                    + 0.3 * strsim::sorensen_dice(&description, &other_description)
+                    + if other_author == author { 0.2 } else { 0.0 };
+                (sim >= THRESHOLD).then_some((sim, id))
+            })
+            .collect();
+        /* ties go to the oldest, so the same books come up every time */
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        scored.into_iter().take(n).map(|(_, id)| id).collect()
+    }
+
+    /// Every book `requester` may see, as of now. Taken first, so scoring
+    /// doesn't hold up adds.
+    async fn search_snapshot(
+        &self,
+        requester: GuestId,
+    ) -> Vec<(BookID, Arc<StoredBook>, Metadata)> {
+        let pool = self.book_pool.read().await;
+        pool.iter()
+            .enumerate()
+            .map(|(idx, book)| (BookID(idx), book, self.lookup_metadata(BookID(idx))))
+            .filter(|(_, _, meta)| meta.visible_to(requester))
+            .map(|(book_id, book, meta)| (book_id, book.load_full(), meta))
+            .collect()
+    }
+
+    /// How well `book` matches `query`, and where, if well enough at all.
+    /// Each field's similarity is scaled by its [`Field::weight`]; whether
+    /// the book matches at all goes by the best one unscaled.
+    fn score(book: &Book, query: &str) -> Option<(f64, Option<Match>)> {
+        if query.is_empty() {
+            return Some((1.0, None));
+        }
+
+        const THRESHOLD: f64 = 0.4;
+        let mut sim: Option<(f64, Option<Match>)> = None;
+        let mut best = 0.0_f64;
+
+        let query_len = query.chars().count();
+
+        for field in Field::ALL {
+            let src = book.field(field);
+
+            /* compare whole similarity */
+            let whole_sim = strsim::normalized_damerau_levenshtein(query, src);
+
+            /* compare percentage of containment */
+            let mut instances = src.match_indices(query);
+            let first = instances.next().map(|(offset, _)| offset);
+            let instances_len = (first.is_some() as usize + instances.count()) * query_len;
+            let substr_sim = if instances_len == 0 {
+                0.0
+            } else {
+                src.len() as f64 / instances_len as f64
+            };
+            let substr_match = match (field, first) {
+                (Field::Description | Field::Content, Some(offset)) => Some(Match {
+                    field,
+                    offset,
+                    len: query.len(),
+                }),
+                _ => None,
+            };
+
+            best = best.max(whole_sim).max(substr_sim);
+            for (field_sim, matched) in [(whole_sim, None), (substr_sim, substr_match)] {
+                let cur = (field_sim * field.weight(), matched);
+                match sim {
+                    None => sim = Some(cur),
+                    Some(prev) => {
+                        if prev.0 < cur.0 {
+                            sim = Some(cur);
+                        }
+                    }
+                }
+            }
+        }
+
+        let sim = sim.unwrap();
+
+        if THRESHOLD <= best {
+            Some(sim)
+        } else {
+            None
+        }
+    }
+
+    /// Group `found` by title and author, as in [`Book::cover_key`], keeping
+    /// the order they were found in, so identical books are listed once.
+    pub async fn collapse_copies(
+        &self,
+        found: Vec<(f64, BookID, Metadata, Option<Match>)>,
+    ) -> Vec<Copies> {
+        let mut collapsed: Vec<Copies> = Vec::new();
+        let mut keys: HashMap<(String, String), usize> = HashMap::new();
+        let pool = self.book_pool.read().await;
+        for hit in found {
+            let (_sim, book_id, meta, _matched) = hit;
+            let free = meta.is_free() && !meta.is_hidden();
+            let key = pool[book_id.0].load().cover_key();
+            match keys.get(&key) {
+                Some(&idx) => {
+                    let copies = &mut collapsed[idx];
+                    copies.ids.push(book_id);
+                    let total = &mut copies.best.2;
+                    total.checkouts = total.checkouts.saturating_add(meta.checkouts);
+                    total.reads = total.reads.saturating_add(meta.reads);
+                    if free {
+                        copies.available += 1;
+                        total.checked_out_by = None;
+                    }
+                }
+                None => {
+                    keys.insert(key, collapsed.len());
+                    collapsed.push(Copies {
+                        best: hit,
+                        ids: vec![book_id],
+                        available: usize::from(free),
+                    });
+                }
+            }
+        }
+        collapsed
+    }
+
+    /// Put `book` on the shelves as added by `guest`, unless they're at
+    /// their [`Library::contribution_limit`]. Whitespace in its title and
+    /// author is collapsed first, as in [`collapse_whitespace`].
+    pub async fn add(&self, book: Book, guest: GuestId) -> Result<BookID, AddError> {
+        self.add_checked(book, guest, false).await
+    }
+
+    /// Like [`Library::add`], but refuses a book that's on the shelves
+    /// already. It's looked for with the pool locked, so two guests adding
+    /// the same book at once can't both get it in.
+    pub async fn add_unique(&self, book: Book, guest: GuestId) -> Result<BookID, AddError> {
+        self.add_checked(book, guest, true).await
+    }
+
+    async fn add_checked(
+        &self,
+        book: Book,
+        guest: GuestId,
+        unique: bool,
+    ) -> Result<BookID, AddError> {
+        let book = Book {
+            title: collapse_whitespace(&book.title),
+            author: collapse_whitespace(&book.author),
+            ..book
+        };
+        /* deflate before locking; it's the slow part */
+        let stored = Arc::new(StoredBook::new(&book));
+        let mut pool = self.book_pool.write().await;
+        /* counted with the pool locked, so two adds can't both squeeze in */
+        if self.at_contribution_limit(guest) {
+            return Err(AddError::ContributionLimit);
+        }
+        if let Some(book_id) = unique.then(|| self.duplicate_in(&pool, &book)).flatten() {
+            return Err(AddError::Duplicate(book_id));
+        }
+        Ok(self.shelve(&mut pool, stored, guest))
+    }
+
+    /// Put `stored` on the shelves at the end of `pool`, the write-locked
+    /// [`Library::book_pool`], as added by `guest`.
+    fn shelve(
+        &self,
+        pool: &mut Vec<ArcSwap<StoredBook>>,
+        stored: Arc<StoredBook>,
+        guest: GuestId,
+    ) -> BookID {
+        let book_id: BookID = BookID(pool.len());
+        pool.push(ArcSwap::new(stored));
+
+        let old = self.book_meta.insert(book_id, Metadata::new(guest));
+        self.added_at.insert(book_id, self.now());
+        debug_assert!(
+            old.is_none(),
+            "it would be weird if this BookID already existed"
+        );
+        metrics::incr(Counter::BooksAdded);
+        self.changed();
+        self.publish(LibraryEvent::Added(book_id));
+        book_id
+    }
+
+    /// Put `book` back on the shelves as the operator's, showing `nick` as
+    /// who added it. A nickname proves nothing after a restart, so whoever
+    /// registers it later doesn't get the book.
+    pub async fn add_as(&self, book: Book, nick: &str) -> BookID {
+        /* added by the operator, so no contribution limit turns it away */
+        let book_id = self
+            .add(book, Self::OPERATOR)
+            .await
+            .unwrap_or_else(|_| unreachable!("the operator has no contribution limit"));
+        self.saved_added_by.insert(book_id, Arc::from(nick));
+        book_id
+    }
+
+    /// The nickname of whoever added `book_id`, if not the operator,
+    /// including who a snapshot said added it after [`Library::add_as`].
+    pub async fn added_by_nick(&self, book_id: BookID) -> Option<Arc<str>> {
+        let added_by = self.lookup_metadata(book_id).added_by;
+        if added_by != Self::OPERATOR {
+            return self.lookup_nick(added_by).await;
+        }
+        self.saved_added_by
+            .get(&book_id)
+            .map(|nick| Arc::clone(&nick))
+    }
+
+    /// How many books `guest` has added that are still on the shelves.
+    pub fn contributions(&self, guest: GuestId) -> usize {
+        self.book_meta
+            .iter()
+            .filter(|entry| entry.added_by == guest && !entry.is_hidden())
+            .count()
+    }
+
+    /// Whether `guest` has as many books on the shelves as they may add.
+    /// Never true of the operator.
+    pub fn at_contribution_limit(&self, guest: GuestId) -> bool {
+        /* no limit, no need to count */
+        guest != Self::OPERATOR
+            && self.contribution_limit != usize::MAX
+            && self.contribution_limit <= self.contributions(guest)
+    }
+
+    /// A book on the shelves just like `book`, cover and content, if there
+    /// is one.
+    pub async fn find_duplicate(&self, book: &Book) -> Option<BookID> {
+        self.duplicate_in(&self.book_pool.read().await, book)
+    }
+
+    /// [`Library::find_duplicate`], with the pool locked already.
+    fn duplicate_in(&self, pool: &[ArcSwap<StoredBook>], book: &Book) -> Option<BookID> {
+        let (title, author) = book.cover_key();
+        /* only unpack what might match */
+        pool.iter()
+            .enumerate()
+            .map(|(idx, stored)| (BookID(idx), stored.load_full()))
+            .filter(|(book_id, stored)| {
+                normalize_name(&stored.title) == title
+                    && normalize_name(&stored.author) == author
+                    && stored.description == book.description
+                    && stored.content_len() == book.content.len()
+                    && !self.lookup_metadata(*book_id).is_hidden()
+            })
+            .find(|(book_id, stored)| {
+                self.unpack(*book_id, Arc::clone(stored), false).content == book.content
+            })
+            .map(|(book_id, _)| book_id)
+    }
+
+    pub async fn update_metadata_fields(
+        &self,
+        book_id: BookID,
+        title: Option<String>,
+        author: Option<String>,
+        description: Option<String>,
+        requester: GuestId,
+    ) -> Result<(), UpdateEntryError> {
+        /* purging holds the write lock, so it can't happen between these */
+        let pool = self.book_pool.read().await;
+        let meta = self.lookup_metadata(book_id);
+        if meta.is_hidden() {
+            return Err(UpdateEntryError::Removed);
+        }
+        if meta.protected && requester != Self::OPERATOR {
+            return Err(UpdateEntryError::Protected);
+        }
+        if meta.added_by != requester {
+            return Err(UpdateEntryError::GuestMismatch);
+        }
+
+        /* swap in an updated copy; readers holding the old Arc are unaffected,
+         * and a concurrent swap just means trying again on top of it */
+        pool[book_id.0].rcu(|old| {
+            let pick = |new: &Option<String>, old: &String| new.as_ref().unwrap_or(old).clone();
+            old.with_cover(
+                collapse_whitespace(&pick(&title, &old.title)),
+                collapse_whitespace(&pick(&author, &old.author)),
+                pick(&description, &old.description),
+            )
+        });
+        self.changed();
+        Ok(())
+    }
+
+    /// Swap in `book` for the one at `book_id`, cover and content. Readers
+    /// already holding the old one keep it, and anyone who looks it up from
+    /// now on gets the new one; nobody ever sees half of each. Refused while
+    /// anyone has it open to read, so a book doesn't change mid-page, and
+    /// refused to anyone but the operator if it's a library notice.
+    pub async fn replace_book(
+        &self,
+        book_id: BookID,
+        book: Book,
+        requester: GuestId,
+    ) -> Result<(), UpdateEntryError> {
+        /* deflate before locking; it's the slow part */
+        let stored = Arc::new(StoredBook::new(&book));
+        /* purging holds the write lock, so it can't happen between these */
+        let pool = self.book_pool.read().await;
+        let meta = self.lookup_metadata(book_id);
+        if meta.is_hidden() {
+            return Err(UpdateEntryError::Removed);
+        }
+        if meta.protected && requester != Self::OPERATOR {
+            return Err(UpdateEntryError::Protected);
+        }
+        if self.has_readers(book_id) {
+            return Err(UpdateEntryError::BeingRead);
+        }
+        pool[book_id.0].store(stored);
+        self.changed();
+        Ok(())
+    }
+
+    /// Add the communal guestbook to the collection, unless it's there already.
+    pub async fn open_guestbook(&self) -> BookID {
+        let mut pool = self.book_pool.write().await;
+        if let Some(&id) = self.guestbook.get() {
+            return id;
+        }
+
+        let book_id = BookID(pool.len());
+        pool.push(ArcSwap::from_pointee(StoredBook::new(&Book {
+            title: String::from("Guestbook"),
+            author: String::from("everyone"),
+            description: String::from("Anyone may read it, and anyone may 'sign' it."),
+            content: String::new(),
+            language: None,
+        })));
+        let mut meta = Metadata::new(Self::OPERATOR);
+        meta.communal = true;
+        self.book_meta.insert(book_id, meta);
+        let _ = self.guestbook.set(book_id);
+        drop(pool);
+
+        self.publish(LibraryEvent::Added(book_id));
+        book_id
+    }
+
+    pub fn guestbook(&self) -> Option<BookID> {
+        self.guestbook.get().copied()
+    }
+
+    /// Sign the guestbook as `guest`, like "2024-06-01 whiskers: hi". Escape
+    /// sequences and control characters are dropped from `message` first.
+    pub async fn append_guestbook(
+        &self,
+        guest: GuestId,
+        message: &str,
+        now: SystemTime,
+    ) -> Result<(), GuestbookError> {
+        let book_id = self
+            .guestbook()
+            .filter(|&id| !self.lookup_metadata(id).is_hidden())
+            .ok_or(GuestbookError::NoGuestbook)?;
+
+        let message = sanitize::strip_ansi(message).replace(['\n', '\t'], " ");
+        let message = message.trim();
+        if message.is_empty() {
+            return Err(GuestbookError::Empty);
+        }
+        if Self::GUESTBOOK_MAX_LEN < message.chars().count() {
+            return Err(GuestbookError::TooLong);
+        }
+        let nick = self.lookup_nick(guest).await.unwrap_or_default();
+
+        /* the pool lock serializes signers, so the limit can't be raced */
+        let pool = self.book_pool.write().await;
+        let today = date::day_number(now);
+        let mut signed = self.guestbook_signatures.entry(guest).or_insert((today, 0));
+        if signed.0 != today {
+            *signed = (today, 0);
+        }
+        if Self::GUESTBOOK_DAILY_LIMIT <= signed.1 {
+            return Err(GuestbookError::DailyLimit);
+        }
+        signed.1 += 1;
+
+        let mut book = self.unpack(book_id, pool[book_id.0].load_full(), false);
+        Arc::make_mut(&mut book)
+            .content
+            .push_str(&format!("{} {nick}: {message}\n", date::format_date(now)));
+        pool[book_id.0].store(Arc::new(StoredBook::new(&book)));
+        Ok(())
+    }
+
+    pub fn checkout(&self, book_id: BookID, guest: GuestId) -> Result<(), UpdateEntryError> {
+        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+        if meta.is_hidden() {
+            return Err(UpdateEntryError::Removed);
+        }
+        if meta.communal {
+            return Err(UpdateEntryError::Communal);
+        }
+        match meta.checked_out_by {
+            Some(by) => Err(UpdateEntryError::AlreadyCheckedOut(by)),
+            None => {
+                let now = self.now();
+                meta.set_checkout(guest, now);
+                meta.register_checkout();
+                metrics::incr(Counter::Checkouts);
+                self.open_loans.insert(
+                    book_id,
+                    Loan {
+                        book_id,
+                        guest,
+                        out: now,
+                        back: None,
+                    },
+                );
+                let author = meta.added_by;
+                drop(meta);
+                self.publish(LibraryEvent::CheckedOut(book_id));
+                self.mail_author(author, Mail::CheckedOut { by: guest, book_id });
+                Ok(())
+            }
+        }
+    }
+
+    /// Tell `author` about `mail`, unless they did it themselves, have asked
+    /// not to hear, or already heard about the book today.
+    fn mail_author(&self, author: GuestId, mail: Mail) {
+        let Mail::CheckedOut { by, book_id } = mail;
+        if author == by || author == Self::NOBODY || self.unnotified.contains(&author) {
+            return;
+        }
+        let today = date::day_number(self.now());
+        let mut last = self.mailed.entry((author, book_id)).or_insert(i64::MIN);
+        if *last == today {
+            return;
+        }
+        *last = today;
+        drop(last);
+        self.mailboxes.entry(author).or_default().push(mail);
+    }
+
+    /// Whatever mail `guest` hasn't heard yet, oldest first, now that they're
+    /// hearing it.
+    pub fn take_mail(&self, guest: GuestId) -> Vec<Mail> {
+        self.mailboxes
+            .remove(&guest)
+            .map(|(_, mail)| mail)
+            .unwrap_or_default()
+    }
+
+    /// Whether `guest` hears about checkouts of the books they added.
+    pub fn notifies(&self, guest: GuestId) -> bool {
+        !self.unnotified.contains(&guest)
+    }
+
+    /// Start or stop telling `guest` about checkouts of the books they added.
+    /// Stopping throws out whatever they haven't heard yet.
+    pub fn set_notify(&self, guest: GuestId, notify: bool) {
+        if notify {
+            self.unnotified.remove(&guest);
+        } else {
+            self.unnotified.insert(guest);
+            self.mailboxes.remove(&guest);
+        }
+    }
+
+    /// Whether `guest` has any of `copies` checked out.
+    pub fn holds_any(&self, copies: &[BookID], guest: GuestId) -> bool {
+        copies
+            .iter()
+            .any(|&book_id| self.lookup_metadata(book_id).checked_out_by == Some(guest))
+    }
+
+    /// Check out whichever of `copies` is free first. If none are, the error
+    /// is the last copy's.
+    pub fn checkout_any(
+        &self,
+        copies: &[BookID],
+        guest: GuestId,
+    ) -> Result<BookID, UpdateEntryError> {
+        let mut result = Err(UpdateEntryError::Removed);
+        for &book_id in copies {
+            result = self.checkout(book_id, guest).map(|()| book_id);
+            if !matches!(
+                result,
+                Err(UpdateEntryError::AlreadyCheckedOut(_) | UpdateEntryError::Removed)
+            ) {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Count that `guest` opened the book, towards [`Metadata::reads`] and
+    /// [`Library::unique_readers`].
+    pub fn register_read(&self, book_id: BookID, guest: GuestId) {
+        self.book_meta.get_mut(&book_id).unwrap().register_read();
+        if guest == Self::NOBODY {
+            return;
+        }
+        let mut readers = self.readers.entry(book_id).or_default();
+        if readers.len() < Self::MAX_UNIQUE_READERS {
+            readers.insert(guest);
+        }
+    }
+
+    /// When `book_id` was put on the shelves, if it was added by anyone.
+    pub fn added_at(&self, book_id: BookID) -> Option<SystemTime> {
+        self.added_at.get(&book_id).map(|at| *at)
+    }
+
+    /// How many different guests have opened `book_id` to read, so a book
+    /// read widely stands apart from one read often by one guest.
+    pub fn unique_readers(&self, book_id: BookID) -> usize {
+        self.readers
+            .get(&book_id)
+            .map_or(0, |readers| readers.len())
+    }
+
+    /// Note that `book_id` came back `at`. Only call with its metadata held.
+    fn close_loan(&self, book_id: BookID, at: SystemTime) {
+        let Some((_, mut loan)) = self.open_loans.remove(&book_id) else {
+            return;
+        };
+        loan.back = Some(at);
+        let mut loans = self.loans.lock().unwrap();
+        if loans.len() == Self::LOAN_HISTORY {
+            loans.pop_front();
+        }
+        loans.push_back(loan);
+    }
+
+    /// The last [`Library::LOAN_HISTORY`] checkouts to come back and every
+    /// one still out, oldest first, except those undone.
+    pub fn loans(&self) -> Vec<Loan> {
+        let mut loans: Vec<_> = self.loans.lock().unwrap().iter().copied().collect();
+        loans.extend(self.open_loans.iter().map(|loan| *loan));
+        loans.sort_by_key(|loan| loan.out);
+        loans
+    }
+
+    /// Take back a checkout as if it never happened, including its count
+    /// towards [`Metadata::checkouts`].
+    pub fn undo_checkout(&self, book_id: BookID, guest: GuestId) -> Result<(), UpdateEntryError> {
+        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+        match meta.checked_out_by {
+            Some(by) if by == guest => {
+                meta.set_checkin();
+                meta.unregister_checkout();
+                self.open_loans.remove(&book_id);
+                drop(meta);
+                self.publish(LibraryEvent::CheckedIn(book_id));
+                Ok(())
+            }
+            Some(_) => Err(UpdateEntryError::GuestMismatch),
+            None => Err(UpdateEntryError::AlreadyCheckedIn),
+        }
+    }
+
+    /// Return a book `guest` has checked out, saying how long they had it.
+    pub fn checkin(&self, book_id: BookID, guest: GuestId) -> Result<Duration, UpdateEntryError> {
+        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+        if let Some(by) = meta.checked_out_by {
+            if by == guest {
+                let now = self.now();
+                let held = meta.held_for(now).unwrap_or(Duration::ZERO);
+                meta.set_checkin();
+                metrics::incr(Counter::Checkins);
+                self.close_loan(book_id, now);
+                drop(meta);
+                self.note_returned(book_id);
+                self.publish(LibraryEvent::CheckedIn(book_id));
+                Ok(held)
+            } else {
+                Err(UpdateEntryError::GuestMismatch)
+            }
+        } else {
+            Err(UpdateEntryError::AlreadyCheckedIn)
+        }
+    }
+
+    /// Put a book in the trash, hiding it from guests. Whoever had it checked
+    /// out has it returned for them, but not while anyone has it open. Only
+    /// the operator may throw out a library notice.
+    pub fn soft_delete(
+        &self,
+        book_id: BookID,
+        now: SystemTime,
+        requester: GuestId,
+    ) -> Result<(), UpdateEntryError> {
+        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+        if meta.is_hidden() {
+            return Err(UpdateEntryError::Removed);
+        }
+        if meta.protected && requester != Self::OPERATOR {
+            return Err(UpdateEntryError::Protected);
+        }
+        if self.has_readers(book_id) {
+            return Err(UpdateEntryError::BeingRead);
+        }
+        let returned = meta.set_checkin();
+        meta.removed = Some(Removal::Trashed(now));
+        if returned.is_some() {
+            self.close_loan(book_id, now);
+        }
+        drop(meta);
+        self.changed();
+
+        if returned.is_some() {
+            self.publish(LibraryEvent::CheckedIn(book_id));
+        }
+        self.publish(LibraryEvent::Removed(book_id));
+        Ok(())
+    }
+
+    /// Make a book a library notice, or stop it being one. Returns whether it
+    /// is one now.
+    pub fn toggle_protected(&self, book_id: BookID) -> bool {
+        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+        meta.protected = !meta.protected;
+        let protected = meta.protected;
+        drop(meta);
+        self.changed();
+        protected
+    }
+
+    /// Take a book back out of the trash.
+    pub fn restore(&self, book_id: BookID) -> Result<(), UpdateEntryError> {
+        let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+        let Some(Removal::Trashed(_)) = meta.removed else {
+            return Err(UpdateEntryError::NotTrashed);
+        };
+        meta.removed = None;
+        drop(meta);
+
+        self.changed();
+        self.publish(LibraryEvent::Restored(book_id));
+        Ok(())
+    }
+
+    /// Books in the trash, oldest first.
+    pub fn trash(&self) -> Vec<(BookID, SystemTime)> {
+        let mut trash: Vec<(BookID, SystemTime)> = self
+            .book_meta
+            .iter()
+            .filter_map(|entry| match entry.value().removed {
+                Some(Removal::Trashed(at)) => Some((*entry.key(), at)),
+                _ => None,
+            })
+            .collect();
+        trash.sort_unstable_by_key(|&(id, at)| (at, id));
+        trash
+    }
+
+    /// Permanently remove books that have been in the trash for at least
+    /// `retention`, returning how many.
+    pub async fn purge_trash(&self, now: SystemTime, retention: Duration) -> usize {
+        let pool = self.book_pool.write().await;
+        let mut purged = 0;
+        for (book_id, at) in self.trash() {
+            let expired = now.duration_since(at).unwrap_or_default() >= retention;
+            if !expired {
+                continue;
+            }
+            let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+            /* it may have been restored since we looked */
+            if meta.removed != Some(Removal::Trashed(at)) {
+                continue;
+            }
+            meta.removed = Some(Removal::Purged);
+            self.forget_book(&pool, book_id);
+            purged += 1;
+        }
+        purged
+    }
+
+    /// Throw away what's left of a purged book but its [`BookID`], with
+    /// `pool` write-locked.
+    fn forget_book(&self, pool: &[ArcSwap<StoredBook>], book_id: BookID) {
+        self.readers.remove(&book_id);
+        self.saved_added_by.remove(&book_id);
+        pool[book_id.0].store(Arc::new(StoredBook::new(&Book {
+            title: String::new(),
+            author: String::new(),
+            description: String::new(),
+            content: String::new(),
+            language: None,
+        })));
+    }
+
+    /// Make every one of `changes` with the pool write-locked throughout, so
+    /// nobody sees some made and others not. A book that goes away or in the
+    /// trash is returned for whoever had it out, and whoever has it open to
+    /// read keeps the copy they have.
+    pub async fn reshelve(&self, changes: Vec<Reshelve>) -> Reshelved {
+        /* deflate before locking; it's the slow part */
+        let stored: Vec<Option<Arc<StoredBook>>> = changes
+            .iter()
+            .map(|change| match change {
+                Reshelve::Add { book, .. } | Reshelve::Update { book, .. } => {
+                    Some(Arc::new(StoredBook::new(&Book {
+                        title: collapse_whitespace(&book.title),
+                        author: collapse_whitespace(&book.author),
+                        ..book.clone()
+                    })))
+                }
+                Reshelve::Remove(_) => None,
+            })
+            .collect();
+
+        let mut pool = self.book_pool.write().await;
+        let now = self.now();
+        let mut done = Reshelved::default();
+        let mut events = Vec::new();
+        for (change, stored) in changes.into_iter().zip(stored) {
+            let (book_id, notice, trashed) = match change {
+                Reshelve::Add {
+                    added_by,
+                    notice,
+                    trashed,
+                    ..
+                } => {
+                    let book_id = self.shelve(&mut pool, stored.unwrap(), Self::OPERATOR);
+                    if let Some(nick) = added_by {
+                        self.saved_added_by.insert(book_id, Arc::from(nick));
+                    }
+                    done.added += 1;
+                    (book_id, notice, trashed)
+                }
+                Reshelve::Update {
+                    book_id,
+                    notice,
+                    trashed,
+                    ..
+                } => {
+                    pool[book_id.0].store(stored.unwrap());
+                    done.updated += 1;
+                    (book_id, notice, trashed)
+                }
+                Reshelve::Remove(book_id) => {
+                    let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+                    if let Some(guest) = meta.set_checkin() {
+                        self.close_loan(book_id, now);
+                        done.returned.push((book_id, guest));
+                        events.push(LibraryEvent::CheckedIn(book_id));
+                    }
+                    if meta.removed.is_none() {
+                        events.push(LibraryEvent::Removed(book_id));
+                    }
+                    meta.removed = Some(Removal::Purged);
+                    drop(meta);
+                    self.forget_book(&pool, book_id);
+                    done.removed += 1;
+                    continue;
+                }
+            };
+
+            let mut meta = self.book_meta.get_mut(&book_id).unwrap();
+            meta.protected = notice;
+            match (meta.removed, trashed) {
+                (None, Some(at)) => {
+                    if let Some(guest) = meta.set_checkin() {
+                        self.close_loan(book_id, now);
+                        done.returned.push((book_id, guest));
+                        events.push(LibraryEvent::CheckedIn(book_id));
+                    }
+                    meta.removed = Some(Removal::Trashed(at));
+                    events.push(LibraryEvent::Removed(book_id));
+                }
+                (Some(Removal::Trashed(_)), None) => {
+                    meta.removed = None;
+                    events.push(LibraryEvent::Restored(book_id));
+                }
+                (Some(Removal::Trashed(_)), Some(at)) => meta.removed = Some(Removal::Trashed(at)),
+                (None, None) | (Some(Removal::Purged), _) => {}
+            }
+        }
+        self.changed();
+        drop(pool);
+
+        for event in events {
+            self.publish(event);
+        }
+        done
+    }
+
+    /// Panic if the library's bookkeeping disagrees with itself. Only
+    /// meaningful while nothing else is changing the library.
+    #[cfg(any(test, debug_assertions))]
+    pub async fn check_invariants(&self) {
+        let pool = self.book_pool.read().await;
+        assert_eq!(pool.len(), self.book_meta.len(), "every book has metadata");
+        let mut checked_out = Vec::new();
+        for entry in self.book_meta.iter() {
+            let (&id, meta) = entry.pair();
+            assert!(id.0 < pool.len(), "{id} has metadata but no book");
+            assert_eq!(
+                meta.checked_out_by.is_some(),
+                meta.checked_out_at.is_some(),
+                "{id} is checked out by somebody exactly when it's checked out at some time"
+            );
+            if let Some(by) = meta.checked_out_by {
+                assert!(!meta.is_hidden(), "{id} is hidden but checked out");
+                assert!(!meta.communal, "{id} is communal but checked out");
+                checked_out.push((id, by, meta.checked_out_at));
+            }
+        }
+        drop(pool);
+        if let Some(&guestbook) = self.guestbook.get() {
+            assert!(self.lookup_metadata(guestbook).communal);
+        }
+
+        /* open loans mirror checkouts, one each */
+        let mut open: Vec<_> = (self.open_loans.iter())
+            .map(|loan| (loan.book_id, loan.guest, Some(loan.out)))
+            .collect();
+        assert!(
+            (self.loans.lock().unwrap().iter()).all(|loan| loan.back.is_some()),
+            "returned loans have come back"
+        );
+        assert!(self.loans.lock().unwrap().len() <= Self::LOAN_HISTORY);
+        open.sort_unstable_by_key(|&(id, ..)| id);
+        checked_out.sort_unstable_by_key(|&(id, ..)| id);
+        assert_eq!(checked_out, open, "open loans mirror checkouts");
+
+        let guests = self.guests.read().await;
+        assert!(
+            guests.by_nick.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "nicknames are unique and sorted"
+        );
+        assert!(
+            guests.by_addr.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "addresses are unique and sorted"
+        );
+        assert!(
+            guests.by_token.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "tokens are unique and sorted"
+        );
+        for (nick, id) in &guests.by_nick {
+            assert_eq!(*nick, guests.by_id[id.0].nick, "{id:?}'s nickname");
+        }
+        for &(addr, id) in &guests.by_addr {
+            assert_eq!(Some(addr), guests.by_id[id.0].addr, "{id:?}'s address");
+        }
+        for guest in &guests.by_id {
+            if let Some(addr) = guest.addr {
+                let idx = guests.lookup_addr(addr).ok();
+                assert_eq!(
+                    Some(guest.id),
+                    idx.map(|idx| guests.by_addr[idx].1),
+                    "{:?} is found at their address",
+                    guest.id
+                );
+            }
+        }
+        for &(digest, id) in &guests.by_token {
+            assert_eq!(Some(digest), guests.by_id[id.0].token, "{id:?}'s token");
+        }
+        let mut registered: Vec<_> = guests.by_nick.iter().map(|&(_, id)| id).collect();
+        registered.sort_unstable();
+        registered.dedup();
+        assert_eq!(guests.by_nick.len(), registered.len(), "one nickname each");
+        for (id, by, _at) in checked_out {
+            assert!(
+                registered.binary_search(&by).is_ok(),
+                "{id} is checked out by {by:?}, who isn't registered"
+            );
         }
     }
 }
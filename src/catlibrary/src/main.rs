@@ -1,52 +1,33 @@
 use anyhow::Context;
 use core::net::Ipv4Addr;
 use core::net::SocketAddr;
-use tokio::io::{AsyncWriteExt, BufStream};
-use tokio::net::{TcpListener, TcpStream};
+use core::time::Duration;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
 use tracing::Level;
 
 use cat_library::library::{Book, Library};
-use cat_library::shell::{self, Command, Passback};
+use cat_library::metrics;
+use cat_library::records::{self, Format};
+use cat_library::seed;
+use cat_library::server::{self, Args};
+use cat_library::shell::Capabilities;
+use cat_library::snapshot::{self, Saver};
 
-const LISTEN_PORT: u16 = 6868;
-
-async fn process_socket(
-    stream: &mut BufStream<TcpStream>,
-    addr: SocketAddr,
-    library: &Library,
-) -> anyhow::Result<()> {
-    shell::register_guest(stream, library, addr)
-        .await
-        .context("failed to register guest")?;
-
-    loop {
-        let try_cmd = shell::readln(stream, "; ").await?;
-        if let Some(cmd) = Command::from_str(&try_cmd) {
-            let result = shell::do_cmd(stream, cmd, library, addr.ip()).await;
-            stream.flush().await?;
-            match result {
-                Ok(passback) => match passback {
-                    Passback::Continue => {}
-                    Passback::Quit => return Ok(()),
-                },
-                Err(err) => return Err(err.into()),
-            }
-        } else {
-            stream
-                .write_all(b"unknown command! try \"help\" for more info.\n")
-                .await?;
-        }
-    }
-}
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const STRIKE_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse(std::env::args().skip(1))?;
+
     tracing_subscriber::fmt()
         .with_max_level(Level::TRACE)
         .with_target(false)
         .init();
 
-    let library: Library = Library::with_collection([Book {
+    let welcome = Book {
         title: "I am Begging and Pleading".into(),
         author: "Server Operator".into(),
         description: "A critical message to all guests of the Cat Library.".into(),
@@ -65,38 +46,181 @@ async fn main() -> anyhow::Result<()> {
             "So, I ask that you please finish your kippers before entering the library.\n",
             "Thanks!\n",
         ).into(),
-    }]).await;
+        language: Some(String::from("en")),
+    };
+    let saved = match &args.data {
+        Some(path) => snapshot::load(path, args.keep_snapshots)
+            .with_context(|| format!("couldn't open the library from {}", path.display()))?,
+        None => None,
+    };
+    if let Some((entries, from)) = &saved {
+        eprintln!(
+            "Opening with {} books from {}.",
+            entries.len(),
+            from.display()
+        );
+        if Some(from) != args.data.as_ref() {
+            eprintln!(
+                "WARNING: {} is missing or corrupt! Opened with the older {} instead.",
+                args.data.as_ref().unwrap().display(),
+                from.display()
+            );
+        }
+    }
 
-    let listener =
-        TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), LISTEN_PORT)).await?;
+    let mut books = Vec::new();
+    if !args.no_welcome && saved.is_none() {
+        books.push(welcome);
+    }
+    if let Some(dir) = args.seed_dir.as_ref().filter(|_| saved.is_none()) {
+        let seeded = seed::read_dir(dir)
+            .with_context(|| format!("couldn't read seed books from {}", dir.display()))?;
+        eprintln!("Seeding {} books from {}.", seeded.len(), dir.display());
+        books.extend(seeded);
+    }
+    let mut builder = Library::builder();
+    if let Some(path) = &args.data {
+        builder = builder.data_file(path.clone());
+    }
+    let library = builder
+        .operator_nick(&args.operator_nick)
+        .guest_limit(args.max_guests, args.guest_stale_after)
+        .contribution_limit(args.max_books_per_guest)
+        .preview_lines(args.preview_lines)
+        .flavor(args.flavor)
+        .strike_limit(args.strike_limit, args.strike_window, args.soft_ban_for)
+        .books(books)
+        .guestbook(true)
+        .build()
+        .await;
 
-    eprintln!("Waiting for meows on port {LISTEN_PORT}!");
+    if let Some((entries, _from)) = saved {
+        snapshot::restore(&library, entries).await;
+    }
 
-    loop {
-        let (stream, addr) = listener.accept().await?;
-        stream.set_nodelay(true)?;
-        let mut stream = BufStream::new(stream);
+    let library = Arc::new(library);
+    let mut listeners = JoinSet::new();
+    for &bind in &args.binds {
+        let listener = TcpListener::bind(bind.addr)
+            .await
+            .with_context(|| format!("couldn't listen on {}", bind.addr))?;
+        let mode = match bind.caps {
+            Capabilities::MIRROR => " (read-only mirror)",
+            Capabilities::READ_ONLY => " (read-only)",
+            _ => "",
+        };
+        eprintln!("Waiting for meows on {}{mode}!", bind.addr);
+        listeners.spawn(server::serve(
+            listener,
+            bind,
+            Arc::clone(&library),
+            args.buffers,
+            args.transcripts.clone(),
+        ));
+    }
 
-        let span = tracing::span!(Level::INFO, "connection", addr = format_args!("{addr:?}"));
-        let _enter = span.enter();
-        tracing::trace!("we got a connection!");
+    if let Some(port) = args.metrics_port {
+        let metrics_listener =
+            TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)).await?;
+        eprintln!("Serving metrics on port {port}.");
+        let library = Arc::clone(&library);
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_listener, library).await {
+                tracing::error!("metrics listener died: {err:#}");
+            }
+        });
+    }
 
-        let result = process_socket(&mut stream, addr, &library).await;
-        match result {
-            Ok(()) => {}
-            Err(err) => {
-                if let Some(std::io::ErrorKind::BrokenPipe) = err
-                    .root_cause()
-                    .downcast_ref::<std::io::Error>()
-                    .map(|io_err| io_err.kind())
-                {
-                    // connection was closed Dramatically, let's not crash the server
-                } else {
-                    return Err(err.into());
+    let saver = args
+        .data
+        .clone()
+        .map(|path| Arc::new(Saver::new(&library, path, args.keep_snapshots)));
+    if let Some(saver) = &saver {
+        let saver = Arc::clone(saver);
+        let library = Arc::clone(&library);
+        let every = args.save_every;
+        tokio::spawn(async move { saver.run(&library, every).await });
+    }
+
+    /* `kill -HUP` does what the operator's `reload` does */
+    #[cfg(unix)]
+    if let Some(path) = args.data.clone() {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangups = signal(SignalKind::hangup()).context("failed to listen for SIGHUP")?;
+        let library = Arc::clone(&library);
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                match snapshot::reload_from(&library, &path).await {
+                    Ok(done) => tracing::info!(
+                        added = done.added,
+                        updated = done.updated,
+                        removed = done.removed,
+                        returned = done.returned.len(),
+                        "reloaded {}",
+                        path.display()
+                    ),
+                    Err(err) => tracing::error!("couldn't reload: {err:#}"),
+                }
+            }
+        });
+    }
+
+    {
+        let library = Arc::clone(&library);
+        let retention = args.trash_retention;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let purged = library.purge_trash(library.now(), retention).await;
+                if purged != 0 {
+                    tracing::info!(purged, "emptied old books out of the trash");
                 }
             }
+        });
+    }
+
+    {
+        let library = Arc::clone(&library);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STRIKE_PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let lifted = library.prune_strikes();
+                if lifted != 0 {
+                    tracing::info!(lifted, "soft bans ran out");
+                }
+            }
+        });
+    }
+
+    /* listeners only ever stop on errors, so otherwise run until ^C */
+    tokio::select! {
+        result = async {
+            while let Some(result) = listeners.join_next().await {
+                result??;
+            }
+            anyhow::Ok(())
+        } => result?,
+        result = tokio::signal::ctrl_c() => {
+            result.context("failed to listen for ^C")?;
+            tracing::info!("shutting down");
         }
+    }
 
-        tracing::trace!("goodbye!");
+    if let Some(saver) = &saver {
+        saver.save_if_changed(&library).await;
+    }
+    if let Some(path) = &args.records_out {
+        let format = Format::from_path(path);
+        let records = records::gather(&library).await;
+        std::fs::write(path, records::write(format, &records))
+            .with_context(|| format!("failed to write records to {}", path.display()))?;
+        tracing::info!(
+            records = records.len(),
+            path = format_args!("{}", path.display()),
+            "wrote checkout records"
+        );
     }
+    Ok(())
 }
@@ -0,0 +1,687 @@
+//! Everything the shell says to guests, worded in one place. Listings, books
+//! and prompts are left to whoever draws them.
+
+use core::time::Duration;
+
+use crate::library::Merged;
+
+/// A whole set of wordings for [`Msg`], chosen for the library at startup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Flavor {
+    #[default]
+    Plain,
+    /// Plain, with more cat in it.
+    Cat,
+}
+
+impl Flavor {
+    pub const ALL: &'static [Self] = &[Self::Plain, Self::Cat];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Plain => "plain",
+            Self::Cat => "cat",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|flavor| flavor.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Msg<'a> {
+    /* arriving */
+    Welcome,
+    WelcomeBack {
+        nick: &'a str,
+    },
+    Browsing,
+    BrowsingMirror,
+    VisitorNick {
+        nick: &'a str,
+    },
+    NotWelcome,
+    SoftBanned,
+    LoggedIn {
+        nick: &'a str,
+    },
+    UnknownToken,
+    UnknownNickname,
+    WrongPassphrase,
+    PassphraseSet,
+    KeepTokenSecret,
+    NicknameTaken,
+    AlreadyYou {
+        nick: &'a str,
+    },
+    LibraryFull,
+
+    /* at the prompt */
+    UnknownCommand,
+    NoSuchHistoryEntry,
+    ReadOnly,
+    Mirror,
+    OperatorOnly,
+    Nevermind,
+    Bye,
+    AnswerYesOrNo,
+    PickOneOf {
+        listed: &'a str,
+    },
+
+    /* picking books */
+    RankTooSmall {
+        min: usize,
+    },
+    RankTooLarge {
+        max: usize,
+    },
+    NotANumber,
+    LibraryEmpty,
+    NoMatches,
+    MoreMatches {
+        shown: usize,
+    },
+    BooksIn {
+        language: Option<&'a str>,
+        count: usize,
+    },
+    BooksBy {
+        rank: usize,
+        author: &'a str,
+        count: usize,
+    },
+    NoBooksBy {
+        author: &'a str,
+    },
+    JustReturned {
+        rank: usize,
+        title: &'a str,
+    },
+    NothingReturned,
+    NewOnShelves {
+        title: &'a str,
+        author: &'a str,
+    },
+
+    /* checkouts */
+    AlreadyYours,
+    CheckedOut {
+        rank: usize,
+    },
+    AlreadyCheckedOut {
+        rank: usize,
+        by: Option<&'a str>,
+    },
+    Communal {
+        rank: usize,
+    },
+    TakenOffShelves {
+        rank: usize,
+    },
+    CheckOutFirst,
+    OldestCheckoutFirst,
+    Returned {
+        rank: usize,
+    },
+    HeldFor {
+        title: &'a str,
+        held: Duration,
+    },
+    AlreadyCheckedIn {
+        rank: usize,
+    },
+    CheckedOutBySomebodyElse {
+        rank: usize,
+    },
+    TitleAlreadyCheckedIn {
+        title: &'a str,
+    },
+    TitleCheckedOutBySomebodyElse {
+        title: &'a str,
+    },
+    ReturnedBooks {
+        count: usize,
+    },
+    NothingToUndo,
+    TooLateToUndo,
+    UndidCheckout {
+        title: &'a str,
+    },
+    NoLongerYours {
+        title: &'a str,
+    },
+    NowYou {
+        nick: &'a str,
+    },
+
+    /* writing */
+    NotALanguage,
+    ContributionLimit,
+    DraftSummary {
+        title: &'a str,
+        author: &'a str,
+        description: &'a str,
+        lines: usize,
+    },
+    KeptDraft,
+    DiscardedDraft,
+    NotText,
+    Adding {
+        title: &'a str,
+    },
+    Done,
+    NoDraft,
+    Updated {
+        field: &'a str,
+        rank: usize,
+    },
+    OnlyAdderEdits {
+        rank: usize,
+    },
+    LibraryNotice {
+        rank: usize,
+    },
+    /* what an edit changed, before it's saved */
+    DiffSummary {
+        added: usize,
+        removed: usize,
+    },
+    NoChanges {
+        rank: usize,
+    },
+    NotSaved {
+        rank: usize,
+    },
+
+    /* the operator */
+    Trashed {
+        rank: usize,
+    },
+    AlreadyGone {
+        rank: usize,
+    },
+    BeingRead {
+        rank: usize,
+    },
+    Protected {
+        rank: usize,
+    },
+    Unprotected {
+        rank: usize,
+    },
+    TrashEmpty,
+    Purged {
+        count: usize,
+    },
+    Restored {
+        rank: &'a str,
+    },
+    NotInTrash {
+        rank: &'a str,
+    },
+    NobodyCalled {
+        nick: &'a str,
+    },
+    CantBanOperator,
+    Banned {
+        nick: &'a str,
+    },
+    AlreadyBanned {
+        nick: &'a str,
+    },
+    Unbanned {
+        nick: &'a str,
+    },
+    NotBanned {
+        nick: &'a str,
+    },
+    GuestCount {
+        count: usize,
+    },
+    GuestCountOf {
+        count: usize,
+        limit: usize,
+    },
+    GuestReading {
+        nick: &'a str,
+        title: &'a str,
+    },
+    Evicted {
+        count: usize,
+    },
+    MergePlan {
+        from: &'a str,
+        into: &'a str,
+        merged: Merged,
+    },
+    MergedGuests {
+        from: &'a str,
+        into: &'a str,
+    },
+    MergeSameGuest,
+    CantMergeOperator,
+    MergeUnregistered,
+    StillConnected {
+        nick: &'a str,
+    },
+    Announced,
+    Announcement {
+        text: &'a str,
+    },
+
+    /* aliases */
+    AliasSet {
+        name: &'a str,
+        expansion: &'a str,
+    },
+    InvalidAlias,
+    AlreadyACommand {
+        name: &'a str,
+    },
+    ShortForWhat {
+        name: &'a str,
+    },
+    ForgotAlias {
+        name: &'a str,
+    },
+    NoSuchAlias,
+
+    /* prompts */
+    PromptSet,
+    PromptReset,
+    InvalidPrompt,
+
+    /* terminal sizes */
+    SizeSet {
+        cols: usize,
+        rows: usize,
+    },
+    SizeReset,
+    InvalidSize,
+
+    /* authors */
+    CheckedOutYours {
+        nick: &'a str,
+        title: &'a str,
+    },
+    NotifyOn,
+    NotifyOff,
+    AnswerOnOrOff,
+
+    /* reading */
+    ResumingAt {
+        line: usize,
+    },
+
+    /* checkout records */
+    UnknownRecordFormat,
+
+    /* reloading the data file */
+    Reloaded {
+        added: usize,
+        updated: usize,
+        removed: usize,
+        returned: usize,
+    },
+    ReloadFailed,
+    NoDataFile,
+
+    /* the guestbook */
+    Signed,
+    NoGuestbook,
+    TooLong {
+        max: usize,
+    },
+    DailyLimit,
+}
+
+/// `""` for one of something, `"s"` for any other number.
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// `held` in the biggest whole unit that fits, e.g. `3 days`.
+fn how_long(held: Duration) -> String {
+    let mins = held.as_secs() / 60;
+    let (count, unit) = match mins {
+        0 => return "less than a minute".into(),
+        1..=59 => (mins, "minute"),
+        60..=1439 => (mins / 60, "hour"),
+        _ => (mins / 1440, "day"),
+    };
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    format!("{count} {unit}{}", plural(count))
+}
+
+impl Msg<'_> {
+    /// What to write, line endings and all. Anything `flavor` doesn't word
+    /// its own way is worded plainly.
+    pub fn render(&self, flavor: Flavor) -> String {
+        match flavor {
+            Flavor::Plain => None,
+            Flavor::Cat => self.cat(),
+        }
+        .unwrap_or_else(|| self.plain())
+    }
+
+    fn plain(&self) -> String {
+        match *self {
+            Self::Welcome => concat!(
+                "Welcome to the Cat Library!\n",
+                "this appears to be your first visit...\n",
+                "you will need to provide a nickname.\n",
+                "nicknames are public so that addresses can be private.\n",
+                "if you've visited from elsewhere, enter 'claim <nickname>' or 'login' instead.\n",
+            )
+            .into(),
+            Self::WelcomeBack { nick } => {
+                format!("Welcome back to the Cat Library!\nYour nickname is '{nick}'.\n")
+            }
+            Self::Browsing => concat!(
+                "Welcome to the Cat Library! feel free to look around.\n",
+                "you'll need a nickname to borrow or add books.\n",
+            )
+            .into(),
+            Self::BrowsingMirror => concat!(
+                "Welcome to the Cat Library's read-only mirror!\n",
+                "look around and read whatever you like; nothing here can be changed.\n",
+            )
+            .into(),
+            Self::VisitorNick { nick } => format!("you're '{nick}' while you're here.\n"),
+            Self::NotWelcome => "you are not welcome.\n".into(),
+            Self::SoftBanned => {
+                "that's a lot of things we didn't understand. come back in a little while.\n".into()
+            }
+            Self::LoggedIn { nick } => format!("welcome back, '{nick}'!\n"),
+            Self::UnknownToken => "that token doesn't match anybody.\n".into(),
+            Self::UnknownNickname => "nobody goes by that name.\n".into(),
+            Self::WrongPassphrase => "that passphrase doesn't match.\n".into(),
+            Self::PassphraseSet => "passphrase set.\n".into(),
+            Self::KeepTokenSecret => concat!(
+                "it will not be shown again. keep it secret!\n",
+                "use 'login' with it to be yourself from any address.\n",
+            )
+            .into(),
+            Self::NicknameTaken => "nickname is already taken.\n".into(),
+            Self::AlreadyYou { nick } => format!("you're already '{nick}'.\n"),
+            Self::LibraryFull => concat!(
+                "sorry, the library can't take any new guests right now.\n",
+                "if you've visited before, 'login' or 'claim' instead.\n",
+            )
+            .into(),
+
+            Self::UnknownCommand => "unknown command! try \"help\" for more info.\n".into(),
+            Self::NoSuchHistoryEntry => "no such history entry.\n".into(),
+            Self::ReadOnly => "can't change the library from this terminal.\n".into(),
+            Self::Mirror => "this is a read-only mirror; you can look and read, but nothing more.\n".into(),
+            Self::OperatorOnly => "only the operator can do that.\n".into(),
+            Self::Nevermind => "nevermind.\n".into(),
+            Self::Bye => "bye!\n".into(),
+            Self::AnswerYesOrNo => "please answer y or n.\n".into(),
+            Self::PickOneOf { listed } => format!("pick one of: {listed}.\n"),
+
+            Self::RankTooSmall { min } => format!("item number must be at least {min}.\n"),
+            Self::RankTooLarge { max } => format!("item number must be at most {max}.\n"),
+            Self::NotANumber => "that's not a number.\n".into(),
+            Self::LibraryEmpty => "the library is empty!\n".into(),
+            Self::NoMatches => "no matching books!\n".into(),
+            Self::MoreMatches { shown } => {
+                format!("only the best {shown} are listed; narrow it down to see the rest.\n")
+            }
+            Self::BooksIn {
+                language: Some(language),
+                count,
+            } => format!("{count} book{} in {language}.\n", plural(count)),
+            Self::BooksIn {
+                language: None,
+                count,
+            } => format!("{count} book{} in no language given.\n", plural(count)),
+            Self::BooksBy {
+                rank,
+                author,
+                count,
+            } => format!("{rank}. {author} ({count} book{})\n", plural(count)),
+            Self::NoBooksBy { author } => format!("no books by {author}.\n"),
+            Self::JustReturned { rank, title } => format!("{rank}. '{title}' is back.\n"),
+            Self::NothingReturned => "nothing has come back lately.\n".into(),
+            Self::NewOnShelves { title, author } => {
+                format!("(new on the shelves: '{title}' by {author}.)\n")
+            }
+
+            Self::AlreadyYours => "you already have this one; 'read' it!\n".into(),
+            Self::CheckedOut { rank } => format!("checked out item {rank}!\n"),
+            Self::AlreadyCheckedOut { rank, by: Some(by) } => {
+                format!("item {rank} is already checked out by '{by}'.\n")
+            }
+            Self::AlreadyCheckedOut { rank, by: None } => {
+                format!("item {rank} is already checked out.\n")
+            }
+            Self::Communal { rank } => format!("item {rank} belongs to everyone; just read it!\n"),
+            Self::TakenOffShelves { rank } => {
+                format!("item {rank} was just taken off the shelves.\n")
+            }
+            Self::CheckOutFirst => "check out some books first!\n".into(),
+            Self::OldestCheckoutFirst => "your books, oldest checkout first:\n".into(),
+            Self::Returned { rank } => format!("returned item {rank}.\n"),
+            Self::HeldFor { title, held } => {
+                format!("you had '{title}' for {}.\n", how_long(held))
+            }
+            Self::AlreadyCheckedIn { rank } => {
+                format!("item {rank} was returned already; nothing more to do.\n")
+            }
+            Self::CheckedOutBySomebodyElse { rank } => {
+                format!("item {rank} is checked out by somebody else.\n")
+            }
+            Self::TitleAlreadyCheckedIn { title } => {
+                format!("'{title}' was returned already.\n")
+            }
+            Self::TitleCheckedOutBySomebodyElse { title } => {
+                format!("'{title}' is checked out by somebody else.\n")
+            }
+            Self::ReturnedBooks { count } => format!("returned {count} book{}.\n", plural(count)),
+            Self::NothingToUndo => "nothing to undo.\n".into(),
+            Self::TooLateToUndo => {
+                "it's too late to undo that checkout; check it in instead.\n".into()
+            }
+            Self::UndidCheckout { title } => format!("undid your checkout of '{title}'.\n"),
+            Self::NoLongerYours { title } => {
+                format!("'{title}' isn't checked out to you anymore.\n")
+            }
+            Self::NowYou { nick } => format!("you are now '{nick}'.\n"),
+
+            Self::NotALanguage => "that's not a language tag, like en or pt-BR.\n".into(),
+            Self::ContributionLimit => "you've added the maximum number of books.\n".into(),
+            Self::DraftSummary {
+                title,
+                author,
+                description,
+                lines,
+            } => format!(
+                "'{title}', by {author}: {description} ({lines} line{})\n",
+                plural(lines)
+            ),
+            Self::KeptDraft => "kept it as a draft. 'resume' to keep writing.\n".into(),
+            Self::DiscardedDraft => "threw the draft away.\n".into(),
+            Self::NotText => "this doesn't look like text; it may be binary pasted in.\n".into(),
+            /* finished by `Done` once it's in */
+            Self::Adding { title } => format!("adding the book '{title}'..."),
+            Self::Done => "done!\n".into(),
+            Self::NoDraft => "no draft to resume.\n".into(),
+            Self::Updated { field, rank } => format!("updated the {field} of item {rank}.\n"),
+            Self::OnlyAdderEdits { rank } => {
+                format!("only the guest who added item {rank} can edit it.\n")
+            }
+            Self::LibraryNotice { rank } => {
+                format!("item {rank} is a library notice; only the operator can change it.\n")
+            }
+            Self::DiffSummary { added, removed } => format!(
+                "{added} line{} added, {removed} removed.\n",
+                plural(added)
+            ),
+            Self::NoChanges { rank } => format!("no changes to item {rank}.\n"),
+            Self::NotSaved { rank } => format!("left item {rank} as it was.\n"),
+
+            Self::Trashed { rank } => format!("put item {rank} in the trash.\n"),
+            Self::AlreadyGone { rank } => format!("item {rank} is already gone.\n"),
+            Self::BeingRead { rank } => {
+                format!("someone is reading item {rank} right now; try again later.\n")
+            }
+            Self::Protected { rank } => format!("item {rank} is a library notice now.\n"),
+            Self::Unprotected { rank } => format!("item {rank} isn't a library notice anymore.\n"),
+            Self::TrashEmpty => "the trash is empty.\n".into(),
+            Self::Purged { count } => format!("purged {count} book{} for good.\n", plural(count)),
+            Self::Restored { rank } => format!("restored item {rank}.\n"),
+            Self::NotInTrash { rank } => format!("item {rank} isn't in the trash anymore.\n"),
+            Self::NobodyCalled { nick } => format!("nobody is called {nick}.\n"),
+            Self::CantBanOperator => "can't ban the operator.\n".into(),
+            Self::Banned { nick } => format!("banned {nick}.\n"),
+            Self::AlreadyBanned { nick } => format!("{nick} is already banned.\n"),
+            Self::Unbanned { nick } => format!("unbanned {nick}.\n"),
+            Self::NotBanned { nick } => format!("{nick} isn't banned.\n"),
+            Self::GuestCount { count } => format!("{count} guests registered.\n"),
+            Self::GuestCountOf { count, limit } => {
+                format!("{count} of at most {limit} guests registered.\n")
+            }
+            Self::GuestReading { nick, title } => format!("'{nick}' is reading '{title}'.\n"),
+            Self::Evicted { count } => format!("evicted {count} guest{}.\n", plural(count)),
+            Self::MergePlan { from, into, merged } => {
+                let Merged {
+                    books,
+                    checkouts,
+                    loans,
+                    bookmarks,
+                    progress,
+                    mail,
+                } = merged;
+                format!(
+                    "merging '{from}' into '{into}' moves:\n\
+                     \t{books} book{} they added\n\
+                     \t{checkouts} book{} they have out\n\
+                     \t{loans} checkout{} in the records\n\
+                     \t{bookmarks} bookmark{}\n\
+                     \thow far they got in {progress} book{}\n\
+                     \t{mail} bit{} of news they haven't heard\n\
+                     and then '{from}' is forgotten.\n",
+                    plural(books),
+                    plural(checkouts),
+                    plural(loans),
+                    plural(bookmarks),
+                    plural(progress),
+                    plural(mail)
+                )
+            }
+            Self::MergedGuests { from, into } => format!("merged '{from}' into '{into}'.\n"),
+            Self::MergeSameGuest => "that's the same guest twice.\n".into(),
+            Self::CantMergeOperator => "can't merge the operator away.\n".into(),
+            Self::MergeUnregistered => "one of them isn't registered anymore.\n".into(),
+            Self::StillConnected { nick } => {
+                format!("'{nick}' is connected right now; try again once they've gone.\n")
+            }
+            Self::Announced => "announced.\n".into(),
+            Self::Announcement { text } => format!("(announcement: {text})\n"),
+
+            Self::AliasSet { name, expansion } => {
+                format!("'{name}' is short for '{expansion}' now.\n")
+            }
+            Self::InvalidAlias => "that can't be an alias.\n".into(),
+            Self::AlreadyACommand { name } => format!("'{name}' is already a command.\n"),
+            Self::ShortForWhat { name } => format!("'{name}' short for what?\n"),
+            Self::ForgotAlias { name } => format!("forgot '{name}'.\n"),
+            Self::NoSuchAlias => "no such alias.\n".into(),
+
+            Self::PromptSet => "prompt set.\n".into(),
+            Self::PromptReset => "back to the usual prompt.\n".into(),
+            Self::InvalidPrompt => {
+                "prompts are plain text, plus %n for your nickname, %c for your checkouts and %% for %.\n"
+                    .into()
+            }
+
+            Self::SizeSet { cols, rows } => {
+                format!("lists fit {cols} columns and pages {rows} rows now.\n")
+            }
+            Self::SizeReset => "lists and pages run as long as they like again.\n".into(),
+            Self::InvalidSize => "sizes look like 80x24, columns then rows.\n".into(),
+
+            Self::CheckedOutYours { nick, title } => {
+                format!("('{nick}' checked out your book '{title}'.)\n")
+            }
+            Self::NotifyOn => "you'll hear when somebody checks out a book you added.\n".into(),
+            Self::NotifyOff => "you won't hear about checkouts of your books.\n".into(),
+            Self::AnswerOnOrOff => "please answer on or off.\n".into(),
+
+            Self::ResumingAt { line } => format!("resuming at line {line}.\n"),
+
+            Self::UnknownRecordFormat => "records come as csv or json.\n".into(),
+
+            Self::Reloaded {
+                added,
+                updated,
+                removed,
+                returned,
+            } => format!(
+                "reloaded: {added} added, {updated} changed, {removed} gone \
+                 ({returned} taken back from whoever had them out).\n"
+            ),
+            Self::ReloadFailed => {
+                "couldn't reload the data file; see the log. nothing changed.\n".into()
+            }
+            Self::NoDataFile => "this library isn't kept in a data file.\n".into(),
+
+            Self::Signed => "signed the guestbook!\n".into(),
+            Self::NoGuestbook => "this library has no guestbook.\n".into(),
+            Self::TooLong { max } => format!("that's too long; keep it under {max} characters.\n"),
+            Self::DailyLimit => "you've signed enough for today. come back tomorrow!\n".into(),
+        }
+    }
+
+    fn cat(&self) -> Option<String> {
+        Some(match *self {
+            Self::Browsing => concat!(
+                "Welcome to the Cat Library! sniff around all you like.\n",
+                "you'll need a nickname to borrow or add books.\n",
+            )
+            .into(),
+            Self::NotWelcome => "*hisses* you are not welcome.\n".into(),
+            Self::SoftBanned => "*flattens ears* too much noise. come back after a nap.\n".into(),
+            Self::UnknownCommand => "mrrp? try \"help\" for more info.\n".into(),
+            Self::ReadOnly => "paws off! can't change the library from this terminal.\n".into(),
+            Self::Nevermind => "*flicks tail* nevermind.\n".into(),
+            Self::Bye => "*slinks off* bye!\n".into(),
+            Self::LibraryEmpty => "the shelves are bare; not even a hairball!\n".into(),
+            Self::NoMatches => "*sniffs around* no matching books!\n".into(),
+            Self::NewOnShelves { title, author } => {
+                format!("(*ears perk up* new on the shelves: '{title}' by {author}.)\n")
+            }
+            Self::CheckedOut { rank } => format!("item {rank} is yours! *purrs*\n"),
+            Self::CheckOutFirst => "nothing to paw at; check out some books first!\n".into(),
+            Self::Returned { rank } => format!("nudged item {rank} back onto the shelf.\n"),
+            Self::HeldFor { title, held } => {
+                format!("you kept '{title}' warm for {}.\n", how_long(held))
+            }
+            Self::ReturnedBooks { count } => {
+                format!(
+                    "nudged {count} book{} back onto the shelves.\n",
+                    plural(count)
+                )
+            }
+            Self::Done => "done! *kneads*\n".into(),
+            Self::DiscardedDraft => "*bats the draft off the table*\n".into(),
+            Self::Announcement { text } => format!("(*loud meow* announcement: {text})\n"),
+            Self::CheckedOutYours { nick, title } => {
+                format!("(*proud purr* '{nick}' checked out your book '{title}'.)\n")
+            }
+            Self::Signed => "left a pawprint in the guestbook!\n".into(),
+            Self::DailyLimit => "that's enough pawprints for today. come back tomorrow!\n".into(),
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,216 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpListener;
+
+use crate::library::{Library, LibraryMetrics};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    ConnectionsAccepted,
+    BooksAdded,
+    Checkouts,
+    Checkins,
+}
+
+impl Counter {
+    const ALL: [Self; 4] = [
+        Self::ConnectionsAccepted,
+        Self::BooksAdded,
+        Self::Checkouts,
+        Self::Checkins,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::ConnectionsAccepted => "catlib_connections_accepted_total",
+            Self::BooksAdded => "catlib_books_added_total",
+            Self::Checkouts => "catlib_checkouts_total",
+            Self::Checkins => "catlib_checkins_total",
+        }
+    }
+
+    const fn help(self) -> &'static str {
+        match self {
+            Self::ConnectionsAccepted => "Connections accepted by the listener.",
+            Self::BooksAdded => "Books added to the collection.",
+            Self::Checkouts => "Successful checkouts.",
+            Self::Checkins => "Successful checkins.",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gauge {
+    ActiveConnections,
+}
+
+impl Gauge {
+    const ALL: [Self; 1] = [Self::ActiveConnections];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::ActiveConnections => "catlib_connections_active",
+        }
+    }
+
+    const fn help(self) -> &'static str {
+        match self {
+            Self::ActiveConnections => "Guests currently connected.",
+        }
+    }
+}
+
+/// Upper bounds (in seconds) of the search latency histogram buckets.
+const SEARCH_BUCKETS: [f64; 6] = [0.0001, 0.001, 0.01, 0.1, 1.0, 10.0];
+
+struct Histogram {
+    buckets: [AtomicU64; SEARCH_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+static COUNTERS: [AtomicU64; Counter::ALL.len()] =
+    [const { AtomicU64::new(0) }; Counter::ALL.len()];
+static GAUGES: [AtomicU64; Gauge::ALL.len()] = [const { AtomicU64::new(0) }; Gauge::ALL.len()];
+static SEARCH_LATENCY: Histogram = Histogram {
+    buckets: [const { AtomicU64::new(0) }; SEARCH_BUCKETS.len()],
+    count: AtomicU64::new(0),
+    sum_micros: AtomicU64::new(0),
+};
+
+pub fn incr(counter: Counter) {
+    COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn gauge_incr(gauge: Gauge) {
+    GAUGES[gauge as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn gauge_decr(gauge: Gauge) {
+    GAUGES[gauge as usize].fetch_sub(1, Ordering::Relaxed);
+}
+
+pub fn observe_search(elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    for (bucket, &le) in SEARCH_LATENCY.buckets.iter().zip(SEARCH_BUCKETS.iter()) {
+        if secs <= le {
+            bucket.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    SEARCH_LATENCY.count.fetch_add(1, Ordering::Relaxed);
+    let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+    SEARCH_LATENCY
+        .sum_micros
+        .fetch_add(micros, Ordering::Relaxed);
+}
+
+/// Render every metric, along with `library`'s, in the Prometheus text
+/// exposition format.
+pub fn render(library: LibraryMetrics) -> String {
+    let mut out = String::new();
+
+    for (name, help, value) in [
+        ("catlib_books_total", "Books on the shelves.", library.books),
+        (
+            "catlib_books_trashed",
+            "Books in the trash.",
+            library.trashed,
+        ),
+        (
+            "catlib_books_checked_out",
+            "Books currently checked out.",
+            library.checked_out,
+        ),
+        (
+            "catlib_guests_total",
+            "Guests ever registered.",
+            library.guests,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {value}");
+    }
+
+    for counter in Counter::ALL {
+        let value = COUNTERS[counter as usize].load(Ordering::Relaxed);
+        let name = counter.name();
+        let _ = writeln!(out, "# HELP {name} {}", counter.help());
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {value}");
+    }
+
+    for gauge in Gauge::ALL {
+        let value = GAUGES[gauge as usize].load(Ordering::Relaxed);
+        let name = gauge.name();
+        let _ = writeln!(out, "# HELP {name} {}", gauge.help());
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {value}");
+    }
+
+    out.push_str("# HELP catlib_commands_total Shell commands executed, by command.\n");
+    out.push_str("# TYPE catlib_commands_total counter\n");
+    for (command, value) in library.commands {
+        let _ = writeln!(
+            out,
+            "catlib_commands_total{{command=\"{command}\"}} {value}"
+        );
+    }
+
+    let name = "catlib_search_duration_seconds";
+    let _ = writeln!(out, "# HELP {name} Time spent in Library::search.");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (bucket, le) in SEARCH_LATENCY.buckets.iter().zip(SEARCH_BUCKETS) {
+        let value = bucket.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {value}");
+    }
+    let count = SEARCH_LATENCY.count.load(Ordering::Relaxed);
+    let sum = SEARCH_LATENCY.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+    let _ = writeln!(out, "{name}_sum {sum}");
+    let _ = writeln!(out, "{name}_count {count}");
+
+    out
+}
+
+/// Answer every HTTP request on `listener` with the current metrics page.
+///
+/// This is deliberately not a real HTTP server: the request line and headers
+/// are read and ignored, whatever the path.
+pub async fn serve(listener: TcpListener, library: Arc<Library>) -> anyhow::Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let library = Arc::clone(&library);
+        tokio::spawn(async move {
+            let mut stream = BufStream::new(stream);
+            let result: std::io::Result<()> = async {
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    let n = stream.read_line(&mut line).await?;
+                    if n == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let body = render(library.metrics().await);
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await?;
+                stream.write_all(body.as_bytes()).await?;
+                stream.flush().await
+            }
+            .await;
+            if let Err(err) = result {
+                tracing::debug!(
+                    addr = format_args!("{addr}"),
+                    "metrics scrape failed: {err}"
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,299 @@
+//! Checkout history as CSV or JSON, for the operator's spreadsheets.
+//!
+//! Both formats write the same columns: the book's title, the borrower's
+//! nickname, when it went out and came back (`YYYY-MM-DDTHH:MM:SSZ`, UTC),
+//! and how many seconds it was out. The last two are empty (or `null`) for
+//! books still out.
+
+use core::time::Duration;
+use std::time::SystemTime;
+
+use crate::date;
+use crate::library::Library;
+
+pub const BEGIN: &str = "---BEGIN RECORDS---";
+pub const END: &str = "---END RECORDS---";
+pub const COLUMNS: [&str; 5] = ["title", "borrower", "out", "back", "seconds"];
+
+/// One checkout, ready to write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub title: String,
+    pub borrower: String,
+    pub out: SystemTime,
+    pub back: Option<SystemTime>,
+}
+
+impl Record {
+    /// How long the book was out, if it's back.
+    pub fn duration(&self) -> Option<Duration> {
+        let back = self.back?;
+        Some(back.duration_since(self.out).unwrap_or(Duration::ZERO))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// JSON for `.json` files, CSV for anything else.
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// Every checkout in `library`'s history, oldest first.
+pub async fn gather(library: &Library) -> Vec<Record> {
+    let mut records = Vec::new();
+    for loan in library.loans() {
+        records.push(Record {
+            title: library.lookup_title(loan.book_id).await,
+            borrower: library
+                .lookup_nick(loan.guest)
+                .await
+                .map(|nick| nick.to_string())
+                .unwrap_or_default(),
+            out: loan.out,
+            back: loan.back,
+        });
+    }
+    records
+}
+
+/// `records` in `format`, header (or brackets) included.
+pub fn write<'a>(format: Format, records: impl IntoIterator<Item = &'a Record>) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Csv => {
+            out.push_str(&COLUMNS.join(","));
+            out.push('\n');
+            for record in records {
+                let fields = fields(record);
+                let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                out.push_str(&fields.join(","));
+                out.push('\n');
+            }
+        }
+        Format::Json => {
+            out.push('[');
+            for (i, record) in records.into_iter().enumerate() {
+                out.push_str(if i == 0 { "\n  {" } else { ",\n  {" });
+                let fields = fields(record);
+                for (j, (column, field)) in COLUMNS.iter().zip(&fields).enumerate() {
+                    if j != 0 {
+                        out.push_str(", ");
+                    }
+                    let value = match *column {
+                        "back" | "seconds" if field.is_empty() => "null".to_string(),
+                        "seconds" => field.clone(),
+                        _ => json_string(field),
+                    };
+                    out.push_str(&format!("\"{column}\": {value}"));
+                }
+                out.push('}');
+            }
+            out.push_str("\n]\n");
+        }
+    }
+    out
+}
+
+/// [`write`], between [`BEGIN`] and [`END`] lines.
+pub fn write_block<'a>(format: Format, records: impl IntoIterator<Item = &'a Record>) -> String {
+    format!("{BEGIN}\n{}{END}\n", write(format, records))
+}
+
+/// Parse what [`write`] wrote, or `None` if it's malformed.
+pub fn read(format: Format, text: &str) -> Option<Vec<Record>> {
+    let rows = match format {
+        Format::Csv => {
+            let mut rows = csv_rows(text)?.into_iter();
+            if rows.next()? != COLUMNS {
+                return None;
+            }
+            rows.collect()
+        }
+        Format::Json => json_rows(text)?,
+    };
+    rows.into_iter()
+        .map(|row| {
+            let [title, borrower, out, back, _seconds] = <[String; 5]>::try_from(row).ok()?;
+            let back = match back.as_str() {
+                "" => None,
+                back => Some(date::parse_timestamp(back)?),
+            };
+            Some(Record {
+                title,
+                borrower,
+                out: date::parse_timestamp(&out)?,
+                back,
+            })
+        })
+        .collect()
+}
+
+fn fields(record: &Record) -> [String; 5] {
+    [
+        record.title.clone(),
+        record.borrower.clone(),
+        date::format_timestamp(record.out),
+        record.back.map(date::format_timestamp).unwrap_or_default(),
+        record
+            .duration()
+            .map(|duration| duration.as_secs().to_string())
+            .unwrap_or_default(),
+    ]
+}
+
+/// `field`, quoted if it has anything CSV would otherwise misread.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// RFC 4180 rows, with newlines allowed inside quoted fields.
+fn csv_rows(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(c),
+            ',' => row.push(core::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(core::mem::take(&mut field));
+                rows.push(core::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Some(rows)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The rows of what [`write`] writes as JSON: an array of flat objects whose
+/// values are strings, numbers or `null`, in [`COLUMNS`] order.
+fn json_rows(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut chars = text.trim().chars().peekable();
+    let skip_ws = |chars: &mut core::iter::Peekable<core::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    let mut rows = Vec::new();
+    if chars.next()? != '[' {
+        return None;
+    }
+    loop {
+        skip_ws(&mut chars);
+        match chars.next()? {
+            ']' => break,
+            ',' => continue,
+            '{' => {}
+            _ => return None,
+        }
+        let mut row = Vec::new();
+        loop {
+            skip_ws(&mut chars);
+            match chars.next()? {
+                '}' => break,
+                ',' => continue,
+                '"' => {}
+                _ => return None,
+            }
+            let column = json_unescape(&mut chars)?;
+            skip_ws(&mut chars);
+            if chars.next()? != ':' || COLUMNS.get(row.len()) != Some(&column.as_str()) {
+                return None;
+            }
+            skip_ws(&mut chars);
+            let value = if chars.next_if_eq(&'"').is_some() {
+                json_unescape(&mut chars)?
+            } else {
+                let mut bare = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric()) {
+                    bare.push(c);
+                }
+                match bare.as_str() {
+                    "null" => String::new(),
+                    n if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => bare,
+                    _ => return None,
+                }
+            };
+            row.push(value);
+        }
+        rows.push(row);
+    }
+    Some(rows)
+}
+
+/// The rest of a JSON string whose opening quote has been read.
+fn json_unescape(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let hex: String = chars.take(4).collect();
+                    out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+}
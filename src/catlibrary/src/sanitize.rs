@@ -0,0 +1,64 @@
+/// Remove terminal escape sequences and other control characters from
+/// `text`, keeping newlines and tabs, so it can't recolor, move the cursor
+/// around, or ring the bell of whoever it's shown to.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                /* CSI: parameters and intermediates, then one final byte */
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                /* OSC and friends: up to BEL or ST */
+                Some(']' | 'P' | '_' | '^' | 'X') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                /* charset selection and such: intermediates, then one final byte */
+                Some('\x20'..='\x2f') => {
+                    for c in chars.by_ref() {
+                        if !('\x20'..='\x2f').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                /* anything else is a two-character sequence */
+                _ => {}
+            },
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether `text` reads as text at all, rather than binary pasted in: no
+/// more than one character in ten may be a control character or a stand-in
+/// for bytes that weren't UTF-8. Escape sequences count as text here, since
+/// [`strip_ansi`] deals with those.
+pub fn looks_like_text(text: &str) -> bool {
+    let mut total = 0usize;
+    let mut odd = 0usize;
+    for c in text.chars() {
+        total += 1;
+        let control = c.is_control() && !matches!(c, '\n' | '\t' | '\r' | '\x1b');
+        if control || c == char::REPLACEMENT_CHARACTER {
+            odd += 1;
+        }
+    }
+    odd.saturating_mul(10) <= total
+}
@@ -0,0 +1,110 @@
+//! Books to open the library with, read from a directory of `.txt` and `.md`
+//! files, one book each.
+//!
+//! A book is titled after its file, unless the file starts with front matter:
+//!
+//! ```text
+//! ---
+//! title: The Long Nap
+//! author: whiskers
+//! description: optional, like this line
+//! language: en, or any other language tag; also optional
+//! ---
+//! the content, as many lines as it takes
+//! ```
+
+use std::io;
+use std::path::Path;
+
+use crate::library::Book;
+
+/// Files with any other extension are left alone.
+pub const EXTENSIONS: &[&str] = &["txt", "md"];
+/// The line that opens and closes front matter.
+pub const FRONT_MATTER: &str = "---";
+/// Who wrote a book whose front matter doesn't say.
+pub const UNKNOWN_AUTHOR: &str = "anonymous";
+
+/// `text`, from a file named `stem` (its extension aside), as a book.
+pub fn parse(stem: &str, text: &str) -> Book {
+    let mut book = Book {
+        title: stem.replace('_', " "),
+        author: String::from(UNKNOWN_AUTHOR),
+        description: String::new(),
+        content: String::new(),
+        language: None,
+    };
+    let mut body = text.lines();
+    let mut lines = text.lines();
+    if lines.next() == Some(FRONT_MATTER) {
+        let mut fields: Vec<(&str, String)> = Vec::new();
+        for line in lines.by_ref() {
+            if line == FRONT_MATTER {
+                /* only front matter that ends is front matter */
+                body = lines;
+                for (dst, value) in fields {
+                    if dst == "language" {
+                        book.language = Book::language_tag(&value);
+                        continue;
+                    }
+                    let dst = match dst {
+                        "title" => &mut book.title,
+                        "author" => &mut book.author,
+                        "description" => &mut book.description,
+                        _ => continue,
+                    };
+                    *dst = value;
+                }
+                break;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if !value.is_empty() {
+                fields.push((key, value.to_string()));
+            }
+        }
+    }
+    book.content = Book::content_from_lines(body);
+    book
+}
+
+/// Every book in `dir`, in file name order. Files that aren't UTF-8 or have
+/// nothing in them are skipped with a warning.
+pub fn read_dir(dir: &Path) -> io::Result<Vec<Book>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let wanted = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| EXTENSIONS.contains(&ext));
+        if wanted && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort_unstable();
+
+    let mut books = Vec::new();
+    for path in paths {
+        let Ok(text) = String::from_utf8(std::fs::read(&path)?) else {
+            tracing::warn!(
+                path = format_args!("{}", path.display()),
+                "skipped a seed book that isn't UTF-8"
+            );
+            continue;
+        };
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let book = parse(&stem, &text);
+        if book.content.trim().is_empty() {
+            tracing::warn!(
+                path = format_args!("{}", path.display()),
+                "skipped an empty seed book"
+            );
+            continue;
+        }
+        books.push(book);
+    }
+    Ok(books)
+}
@@ -0,0 +1,348 @@
+//! Listening for guests: what to listen on, from the command line, and a
+//! session for each guest who turns up.
+
+use anyhow::Context;
+use core::future::Future;
+use core::net::{Ipv4Addr, SocketAddr};
+use core::time::Duration;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{Instrument, Level};
+
+use crate::library::Library;
+use crate::messages::Flavor;
+use crate::metrics::{self, Counter, Gauge};
+use crate::shell::{self, BufferSizes, Capabilities, ShellError};
+use crate::snapshot;
+use crate::transcript::Transcript;
+
+/// Where guests connect, if no `--bind` says otherwise.
+pub const LISTEN_PORT: u16 = 6868;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bind {
+    pub addr: SocketAddr,
+    /// What sessions from here may do.
+    pub caps: Capabilities,
+}
+
+impl Bind {
+    /// `<addr>:<port>`, optionally followed by `=readonly` or `=readwrite`.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (addr, mode) = s.split_once('=').unwrap_or((s, "readwrite"));
+        let caps = match mode {
+            "readwrite" => Capabilities::FULL,
+            "readonly" => Capabilities::READ_ONLY,
+            _ => anyhow::bail!("unknown listener mode '{mode}'"),
+        };
+        let addr = addr
+            .parse()
+            .with_context(|| format!("invalid listen address '{addr}'"))?;
+        Ok(Self { addr, caps })
+    }
+}
+
+/// The command line.
+#[derive(Debug)]
+pub struct Args {
+    /// Addresses to listen for guests on. Localhost only, if none are given.
+    pub binds: Vec<Bind>,
+    /// Whether every listener is a read-only mirror, whatever its mode.
+    pub read_only: bool,
+
+    /// Serve Prometheus-style metrics over HTTP on this port, if set.
+    pub metrics_port: Option<u16>,
+
+    /// How much of each connection to buffer each way.
+    pub buffers: BufferSizes,
+
+    /// Record every session to its own file in this directory, if set.
+    pub transcripts: Option<PathBuf>,
+
+    /// Write every checkout to this file on shutdown, as JSON if it ends in
+    /// `.json` and CSV otherwise, if set.
+    pub records_out: Option<PathBuf>,
+
+    /// Keep the books on the shelves in this file, if set: open with what's
+    /// in it, and save to it as they change and on shutdown.
+    pub data: Option<PathBuf>,
+    /// How often to save to `data`, if anything changed.
+    pub save_every: Duration,
+    /// How many older copies of `data` to keep.
+    pub keep_snapshots: usize,
+
+    /// Open with a book for every text file in this directory, if set, and
+    /// if there was nothing in `data` to open with.
+    pub seed_dir: Option<PathBuf>,
+    /// Whether to leave out the operator's welcome book.
+    pub no_welcome: bool,
+
+    /// How long books stay in the trash before they're purged for good.
+    pub trash_retention: Duration,
+
+    /// Most guests to keep registered at once.
+    pub max_guests: usize,
+
+    /// How long a guest must have been idle to be evicted for a new one.
+    pub guest_stale_after: Duration,
+
+    /// Most books each guest but the operator may have on the shelves.
+    pub max_books_per_guest: usize,
+
+    /// What the operator goes by.
+    pub operator_nick: String,
+
+    /// How many lines of a book anyone may preview.
+    pub preview_lines: usize,
+
+    /// How the shell words what it says.
+    pub flavor: Flavor,
+
+    /// How many strikes in a row, each within `strike_window` of the first,
+    /// soft-ban an address for `soft_ban_for`.
+    pub strike_limit: usize,
+    pub strike_window: Duration,
+    pub soft_ban_for: Duration,
+}
+
+impl Args {
+    /// Parse `argv`, the arguments after the program's name.
+    pub fn parse(argv: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut args = Self {
+            binds: Vec::new(),
+            read_only: false,
+            metrics_port: None,
+            buffers: BufferSizes::default(),
+            transcripts: None,
+            records_out: None,
+            data: None,
+            save_every: snapshot::DEFAULT_EVERY,
+            keep_snapshots: snapshot::DEFAULT_KEEP,
+            seed_dir: None,
+            no_welcome: false,
+            trash_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            max_guests: usize::MAX,
+            guest_stale_after: Library::DEFAULT_GUEST_STALE_AFTER,
+            max_books_per_guest: usize::MAX,
+            operator_nick: String::from(Library::DEFAULT_OPERATOR_NICK),
+            preview_lines: Library::DEFAULT_PREVIEW_LINES,
+            flavor: Flavor::default(),
+            strike_limit: Library::DEFAULT_STRIKE_LIMIT,
+            strike_window: Library::DEFAULT_STRIKE_WINDOW,
+            soft_ban_for: Library::DEFAULT_SOFT_BAN_FOR,
+        };
+        let mut argv = argv.into_iter();
+        while let Some(arg) = argv.next() {
+            let mut value = |flag: &str| {
+                argv.next()
+                    .with_context(|| format!("{flag} requires a value"))
+            };
+            match arg.as_str() {
+                "--metrics-port" => {
+                    args.metrics_port = Some(
+                        value("--metrics-port")?
+                            .parse()
+                            .context("invalid --metrics-port")?,
+                    );
+                }
+                "--read-buf" => {
+                    args.buffers.read =
+                        value("--read-buf")?.parse().context("invalid --read-buf")?;
+                    anyhow::ensure!(args.buffers.read != 0, "invalid --read-buf");
+                }
+                "--write-buf" => {
+                    args.buffers.write = value("--write-buf")?
+                        .parse()
+                        .context("invalid --write-buf")?;
+                    anyhow::ensure!(args.buffers.write != 0, "invalid --write-buf");
+                }
+                "--transcripts" => {
+                    args.transcripts = Some(value("--transcripts")?.into());
+                }
+                "--records-out" => {
+                    args.records_out = Some(value("--records-out")?.into());
+                }
+                "--data" => {
+                    args.data = Some(value("--data")?.into());
+                }
+                "--save-minutes" => {
+                    let minutes: u64 = value("--save-minutes")?
+                        .parse()
+                        .context("invalid --save-minutes")?;
+                    anyhow::ensure!(minutes != 0, "invalid --save-minutes");
+                    args.save_every = Duration::from_secs(minutes * 60);
+                }
+                "--keep-snapshots" => {
+                    args.keep_snapshots = value("--keep-snapshots")?
+                        .parse()
+                        .context("invalid --keep-snapshots")?;
+                }
+                "--seed-dir" => {
+                    args.seed_dir = Some(value("--seed-dir")?.into());
+                }
+                "--no-welcome" => {
+                    args.no_welcome = true;
+                }
+                "--trash-retention-days" => {
+                    let days: u64 = value("--trash-retention-days")?
+                        .parse()
+                        .context("invalid --trash-retention-days")?;
+                    args.trash_retention = Duration::from_secs(days * 24 * 60 * 60);
+                }
+                "--max-guests" => {
+                    args.max_guests = value("--max-guests")?
+                        .parse()
+                        .context("invalid --max-guests")?;
+                }
+                "--guest-stale-days" => {
+                    let days: u64 = value("--guest-stale-days")?
+                        .parse()
+                        .context("invalid --guest-stale-days")?;
+                    args.guest_stale_after = Duration::from_secs(days * 24 * 60 * 60);
+                }
+                "--max-books-per-guest" => {
+                    args.max_books_per_guest = value("--max-books-per-guest")?
+                        .parse()
+                        .context("invalid --max-books-per-guest")?;
+                }
+                "--operator-nick" => {
+                    args.operator_nick = value("--operator-nick")?.trim().to_owned();
+                    anyhow::ensure!(!args.operator_nick.is_empty(), "invalid --operator-nick");
+                }
+                "--preview-lines" => {
+                    args.preview_lines = value("--preview-lines")?
+                        .parse()
+                        .context("invalid --preview-lines")?;
+                }
+                "--flavor" => {
+                    args.flavor =
+                        Flavor::from_name(&value("--flavor")?).context("invalid --flavor")?;
+                }
+                "--strikes" => {
+                    args.strike_limit = value("--strikes")?.parse().context("invalid --strikes")?;
+                    anyhow::ensure!(args.strike_limit != 0, "invalid --strikes");
+                }
+                "--strike-window-secs" => {
+                    let secs: u64 = value("--strike-window-secs")?
+                        .parse()
+                        .context("invalid --strike-window-secs")?;
+                    args.strike_window = Duration::from_secs(secs);
+                }
+                "--soft-ban-minutes" => {
+                    let minutes: u64 = value("--soft-ban-minutes")?
+                        .parse()
+                        .context("invalid --soft-ban-minutes")?;
+                    args.soft_ban_for = Duration::from_secs(minutes * 60);
+                }
+                "--bind" => {
+                    args.binds.push(Bind::parse(&value("--bind")?)?);
+                }
+                "--read-only" => {
+                    args.read_only = true;
+                }
+                _ => anyhow::bail!("unrecognized argument '{arg}'"),
+            }
+        }
+        if args.binds.is_empty() {
+            args.binds.push(Bind {
+                addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), LISTEN_PORT),
+                caps: Capabilities::FULL,
+            });
+        }
+        if args.read_only {
+            for bind in &mut args.binds {
+                bind.caps = Capabilities::MIRROR;
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Accept guests on `listener`, each in a session of their own, for as long
+/// as it works.
+pub async fn serve(
+    listener: TcpListener,
+    bind: Bind,
+    library: Arc<Library>,
+    buffers: BufferSizes,
+    transcripts: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    accept_each(&listener, |stream, addr| {
+        let span = tracing::span!(
+            Level::INFO,
+            "connection",
+            addr = format_args!("{addr:?}"),
+            listener = format_args!("{}", bind.addr),
+            caps = format_args!("{:?}", bind.caps),
+        );
+        let library = Arc::clone(&library);
+        let transcripts = transcripts.clone();
+        let session = async move {
+            tracing::trace!("we got a connection!");
+            stream.set_nodelay(true)?;
+            let stream = buffers.wrap(stream);
+            let mut stream = match &transcripts {
+                None => Transcript::disabled(stream),
+                Some(dir) => {
+                    let at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+                    let path = dir.join(format!("{at}-{addr}.log"));
+                    match Transcript::create(stream, &path, shell::SECRET_PROMPTS).await {
+                        Ok(stream) => stream,
+                        Err((stream, err)) => {
+                            tracing::warn!("couldn't start transcript {}: {err}", path.display());
+                            Transcript::disabled(stream)
+                        }
+                    }
+                }
+            };
+
+            metrics::gauge_incr(Gauge::ActiveConnections);
+            let result = shell::process_socket(&mut stream, addr, &library, bind.caps).await;
+            metrics::gauge_decr(Gauge::ActiveConnections);
+            match result {
+                // connection was closed Dramatically, let's not crash the server
+                Ok(()) | Err(ShellError::ClientGone | ShellError::TurnedAway) => {}
+                Err(err @ ShellError::Protocol(_)) => tracing::info!("hung up: {err}"),
+                Err(ShellError::Io(err)) => tracing::warn!("lost a connection: {err}"),
+                Err(err @ ShellError::Library(_)) => tracing::error!("ended a session: {err}"),
+                Err(ShellError::Internal(err)) => return Err(err),
+            }
+
+            tracing::trace!("goodbye!");
+            anyhow::Ok(())
+        };
+        session.instrument(span)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Accept connections on `listener` until that fails, running `session` on
+/// each in a task of its own. A session that fails or panics is logged, and
+/// nobody else's is the worse for it.
+pub async fn accept_each<F, Fut>(listener: &TcpListener, mut session: F) -> io::Result<()>
+where
+    F: FnMut(TcpStream, SocketAddr) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let mut sessions = JoinSet::new();
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            Some(ended) = sessions.join_next() => {
+                match ended {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => tracing::error!("a session failed: {err:#}"),
+                    Err(err) => tracing::error!("a session died: {err}"),
+                }
+                continue;
+            }
+        };
+        metrics::incr(Counter::ConnectionsAccepted);
+        sessions.spawn(session(stream, addr));
+    }
+}
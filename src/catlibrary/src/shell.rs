@@ -1,27 +1,610 @@
-use core::net::{IpAddr, SocketAddr};
-use core::num::{IntErrorKind, ParseIntError};
+use anyhow::Context;
+use core::cmp;
+use core::fmt;
+use core::net::SocketAddr;
+use core::num::IntErrorKind;
+use core::pin::Pin;
+use core::task::{self, Poll};
+use core::time::Duration;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream,
+    ReadBuf,
+};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
 use tracing::Level;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use crate::compress::StoredBook;
+use crate::dashboard::Dashboard;
+use crate::date;
+use crate::diff;
 use crate::editor::{self, Editor};
-use crate::library::{Book, BookID, Library, Metadata, RegisterError, UpdateEntryError};
+use crate::export;
+use crate::library::{
+    self, AddError, Book, BookID, ClaimError, Copies, Field, GuestId, GuestbookError, Library,
+    LibraryEvent, Mail, Match, MergeError, Metadata, RegisterError, UpdateEntryError,
+};
+use crate::messages::Msg;
+use crate::records::{self, Format};
+use crate::sanitize;
+use crate::snapshot;
+use crate::upload::{self, Upload, UploadError};
+
+/// How wide to assume a terminal is when the guest's hasn't said.
+pub const DEFAULT_COLS: usize = 80;
+/// Most books a search turns up. Nobody reads further down than this.
+pub const SEARCH_RESULTS: usize = 100;
+
+pub const PASSPHRASE_PROMPT: &str = "passphrase? ";
+pub const TOKEN_PROMPT: &str = "login token? ";
+pub const TOKEN_LABEL: &str = "your login token is ";
+pub const SET_PASSPHRASE_PROMPT: &str =
+    "set a passphrase to claim this nickname elsewhere (enter to skip): ";
+
+/// Text immediately followed by a secret (a guest's answer, or a token shown
+/// to them) that must never be written anywhere else, e.g. transcripts.
+pub const SECRET_PROMPTS: &[&str] = &[
+    PASSPHRASE_PROMPT,
+    SET_PASSPHRASE_PROMPT,
+    TOKEN_PROMPT,
+    TOKEN_LABEL,
+];
+
+/// What a session may do besides look around, set when it connects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Run commands that change the library, as in [`Command::mutates`].
+    pub change: bool,
+    /// Pick a nickname, or become somebody already registered. Sessions
+    /// that can't are given a made-up nickname when they need one.
+    pub register: bool,
+    /// Read any book on the shelves without checking it out.
+    pub read_freely: bool,
+}
+
+impl Capabilities {
+    pub const FULL: Self = Self {
+        change: true,
+        register: true,
+        read_freely: false,
+    };
+    /// For kiosks: guests may look, and read what they have out, but not
+    /// change anything.
+    pub const READ_ONLY: Self = Self {
+        change: false,
+        register: true,
+        read_freely: false,
+    };
+    /// For public mirrors: anyone may read anything, but nobody changes
+    /// anything or registers.
+    pub const MIRROR: Self = Self {
+        change: false,
+        register: false,
+        read_freely: true,
+    };
+
+    pub const fn allows(self, cmd: Command) -> bool {
+        (self.change || !cmd.mutates()) && (self.register || !cmd.registers())
+    }
+
+    /// What to say when refusing a command these don't allow.
+    pub const fn refusal(self) -> Msg<'static> {
+        if self.register {
+            Msg::ReadOnly
+        } else {
+            Msg::Mirror
+        }
+    }
+}
+
+/// Per-connection state that outlives a single command.
+#[derive(Debug)]
+pub struct Session {
+    /// [`Library::NOBODY`] until the guest needs to register.
+    pub guest: GuestId,
+    /// Where the guest is connecting from, to register them when they first
+    /// need to be somebody.
+    pub addr: Option<SocketAddr>,
+    /// What the guest may do, e.g. nothing that changes the library at
+    /// kiosks.
+    pub caps: Capabilities,
+    /// What a guest who can't register goes by, once they need a name. The
+    /// library never hears of it.
+    pub visitor: Option<String>,
+
+    /// The guest's most recent checkout and when it happened, for as long as
+    /// it may still be undone.
+    last_checkout: Option<(BookID, Instant)>,
+
+    /// What the guest last searched for, to pick out when they read.
+    pub last_query: Option<String>,
+    /// Lines entered at the `; ` prompt.
+    pub history: History,
+    /// Shorthands the guest made up this session.
+    pub aliases: Aliases,
+    /// What the guest sees at the `; ` prompt, if they changed it.
+    pub prompt: Prompt,
+    /// News from the rest of the library, for guests who are listening.
+    pub notices: Option<Notices>,
+
+    /// A book the guest stepped away from before adding it.
+    pub draft: Option<Draft>,
+
+    /// How tall the guest's terminal is, if they've said with `size`, for
+    /// paging.
+    pub rows: Option<usize>,
+    /// How wide the guest's terminal is, if they've said with `size`, for
+    /// laying out lists.
+    pub cols: Option<usize>,
+    /// Whether `<enter>` turns a whole page while reading, as the guest last
+    /// left it with `set pager`.
+    pub paging: bool,
+}
+
+impl Session {
+    /// How long a checkout can be taken back with `undo`.
+    pub const UNDO_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(guest: GuestId) -> Self {
+        Self {
+            guest,
+            addr: None,
+            caps: Capabilities::FULL,
+            visitor: None,
+            last_checkout: None,
+            last_query: None,
+            history: History::default(),
+            aliases: Aliases::default(),
+            prompt: Prompt::default(),
+            notices: None,
+            draft: None,
+            rows: None,
+            cols: None,
+            paging: false,
+        }
+    }
+
+    /// A session for a guest from `addr` who hasn't registered yet.
+    pub fn browsing(addr: SocketAddr) -> Self {
+        Self {
+            addr: Some(addr),
+            ..Self::new(Library::NOBODY)
+        }
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.guest != Library::NOBODY
+    }
+}
+
+/// A book being written with `add`, not yet in the library.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Draft {
+    pub title: String,
+    pub author: String,
+    pub description: String,
+    pub language: Option<String>,
+    pub lines: Vec<String>,
+}
+
+/// A request to run an earlier line from [`History`] again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recall {
+    /// `!!`
+    Last,
+    /// `!<n>`
+    Entry(usize),
+}
+
+impl Recall {
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.strip_prefix('!')?;
+        if rest == "!" {
+            return Some(Self::Last);
+        }
+        if rest.is_empty() || !rest.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        rest.parse().ok().map(Self::Entry)
+    }
+}
+
+/// The last [`History::CAPACITY`] lines entered at the prompt, numbered from
+/// 1 for the first line of the session, like a shell's.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: VecDeque<String>,
+    /// How many entries were dropped off the front to stay in capacity.
+    dropped: usize,
+}
+
+impl History {
+    pub const CAPACITY: usize = 100;
+
+    pub fn push(&mut self, line: String) {
+        self.entries.push_back(line);
+        if Self::CAPACITY < self.entries.len() {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    pub fn recall(&self, recall: Recall) -> Option<&str> {
+        let entry = match recall {
+            Recall::Last => self.entries.back(),
+            Recall::Entry(n) => n
+                .checked_sub(self.dropped + 1)
+                .and_then(|idx| self.entries.get(idx)),
+        };
+        entry.map(String::as_str)
+    }
+
+    /// Each entry with its number.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        let first = self.dropped + 1;
+        self.entries
+            .iter()
+            .enumerate()
+            .map(move |(idx, line)| (first + idx, line.as_str()))
+    }
+}
+
+/// A guest's own names for command lines, like `sd` for `search dune`.
+#[derive(Debug, Default)]
+pub struct Aliases {
+    // NOTE: sorted ascending by name
+    by_name: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AliasError {
+    /// Names are one word, and can't start with `!`, which is for history.
+    InvalidName,
+    /// Already means a command.
+    Reserved,
+    EmptyExpansion,
+}
+
+impl Aliases {
+    fn lookup(&self, name: &str) -> Result<usize, usize> {
+        self.by_name
+            .binary_search_by(|(other, _expansion)| other.as_str().cmp(name))
+    }
+
+    /// Make `name` mean `expansion`, replacing what it meant before.
+    pub fn set(&mut self, name: &str, expansion: &str) -> Result<(), AliasError> {
+        if name.is_empty() || name.contains(char::is_whitespace) || name.starts_with('!') {
+            return Err(AliasError::InvalidName);
+        }
+        if Command::from_str(name).is_some() {
+            return Err(AliasError::Reserved);
+        }
+        let expansion = expansion.trim();
+        if expansion.is_empty() {
+            return Err(AliasError::EmptyExpansion);
+        }
+
+        match self.lookup(name) {
+            Ok(idx) => self.by_name[idx].1 = expansion.to_string(),
+            Err(idx) => self
+                .by_name
+                .insert(idx, (name.to_string(), expansion.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Forget `name`, returning what it meant.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let idx = self.lookup(name).ok()?;
+        Some(self.by_name.remove(idx).1)
+    }
+
+    /// `line` with its first word expanded, if that's an alias, keeping the
+    /// rest of the line after the expansion. Expansions aren't expanded again,
+    /// so an alias can't lead to another one, or to itself.
+    pub fn expand(&self, line: &str) -> Option<String> {
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let idx = self.lookup(name).ok()?;
+        let expansion = &self.by_name[idx].1;
+        let rest = rest.trim();
+        if rest.is_empty() {
+            Some(expansion.clone())
+        } else {
+            Some(format!("{expansion} {rest}"))
+        }
+    }
+
+    /// Each alias and what it means, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.by_name
+            .iter()
+            .map(|(name, expansion)| (name.as_str(), expansion.as_str()))
+    }
+}
+
+/// What the guest sees at the top-level prompt: plain text with `%n` for
+/// their nickname, `%c` for how many books they have checked out and `%%`
+/// for `%`, always followed by a space.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prompt {
+    template: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptError {
+    /// A `%` not followed by `n`, `c` or `%`, or a control character.
+    Invalid,
+    TooLong,
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self {
+            template: String::from(";"),
+        }
+    }
+}
+
+impl Prompt {
+    /// Longest template, in characters.
+    pub const MAX_LEN: usize = 32;
+
+    pub fn new(template: &str) -> Result<Self, PromptError> {
+        let template = template.trim();
+        if Self::MAX_LEN < template.chars().count() {
+            return Err(PromptError::TooLong);
+        }
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' if !matches!(chars.next(), Some('n' | 'c' | '%')) => {
+                    return Err(PromptError::Invalid)
+                }
+                c if c.is_control() => return Err(PromptError::Invalid),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+
+    /// Whether rendering needs the guest's checkout count, which takes
+    /// looking through the whole catalog.
+    pub fn shows_checkouts(&self) -> bool {
+        self.template.contains("%c")
+    }
+
+    pub fn render(&self, nick: &str, checkouts: usize) -> String {
+        let mut out = String::with_capacity(self.template.len() + 1);
+        let mut chars = self.template.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push_str(nick),
+                Some('c') => out.push_str(&checkouts.to_string()),
+                _ => out.push('%'),
+            }
+        }
+        out.push(' ');
+        out
+    }
+}
+
+/// News of what other guests did, the operator's announcements and the
+/// guest's mail, held until the guest is at a prompt.
+///
+/// Nothing writes notices on its own. They're only written by [`Notices::drain`],
+/// which is only called right before the `; ` prompt and the editor's `:`
+/// prompt, so they never land in the middle of a line being typed, a
+/// multi-line answer, or a book being written.
+#[derive(Debug)]
+pub struct Notices {
+    events: broadcast::Receiver<LibraryEvent>,
+    announcements: broadcast::Receiver<Arc<str>>,
+}
+
+impl Notices {
+    pub fn new(library: &Library) -> Self {
+        Self {
+            events: library.subscribe(),
+            announcements: library.listen(),
+        }
+    }
+
+    /// Write whatever `guest` hasn't heard about yet, mail included.
+    pub async fn drain<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        library: &Library,
+        guest: GuestId,
+    ) -> anyhow::Result<()> {
+        while let Some(text) = library::try_recv_event(&mut self.announcements) {
+            say(stream, library, Msg::Announcement { text: &text }).await?;
+        }
+        for mail in library.take_mail(guest) {
+            let Mail::CheckedOut { by, book_id } = mail;
+            let nick = library.lookup_nick(by).await.unwrap_or_default();
+            let title = library.lookup_title(book_id).await;
+            say(
+                stream,
+                library,
+                Msg::CheckedOutYours {
+                    nick: &nick,
+                    title: &title,
+                },
+            )
+            .await?;
+        }
+        while let Some(event) = library::try_recv_event(&mut self.events) {
+            let LibraryEvent::Added(book_id) = event else {
+                continue;
+            };
+            let meta = library.lookup_metadata(book_id);
+            if meta.added_by == guest || meta.communal || !meta.visible_to(guest) {
+                continue;
+            }
+            let book = library.lookup_book_by_id(book_id).await;
+            let (title, author) = (&book.title, &book.author);
+            say(stream, library, Msg::NewOnShelves { title, author }).await?;
+        }
+        Ok(())
+    }
+}
 
 pub enum Passback {
     Continue,
     Quit,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Why a session ended early, so the server knows whether to shrug, log or
+/// give up.
+#[derive(Debug)]
+pub enum ShellError {
+    /// The guest hung up mid-conversation. Nothing to do about it.
+    ClientGone,
+    /// We hung up on the guest for sending too much nonsense.
+    TurnedAway,
+    /// The guest sent something we can't make sense of at all, like a line
+    /// that isn't UTF-8. They're not around to be told, most likely.
+    Protocol(String),
+    /// Talking to the guest failed some other way. Their session is over,
+    /// but nobody else's need be.
+    Io(io::Error),
+    /// The library refused a change the shell thought it couldn't, so the
+    /// two disagree about the rules. A bug, but only in this session.
+    Library(UpdateEntryError),
+    /// A bug on our side.
+    Internal(anyhow::Error),
+}
+
+pub type ShellResult<T> = Result<T, ShellError>;
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClientGone => f.write_str("client hung up"),
+            Self::TurnedAway => f.write_str("client turned away"),
+            Self::Protocol(what) => write!(f, "client sent {what}"),
+            Self::Io(err) => write!(f, "talking to client: {err}"),
+            Self::Library(err) => write!(f, "library unexpectedly refused: {err}"),
+            Self::Internal(err) => write!(f, "{err:#}"),
+        }
+    }
+}
+
+impl std::error::Error for ShellError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ClientGone | Self::TurnedAway | Self::Protocol(_) => None,
+            Self::Io(err) => Some(err),
+            Self::Library(err) => Some(err),
+            Self::Internal(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+/// Whether a connection failing with `kind` just means the guest left.
+fn hung_up(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+impl From<io::Error> for ShellError {
+    fn from(err: io::Error) -> Self {
+        if hung_up(err.kind()) {
+            Self::ClientGone
+        } else {
+            Self::Io(err)
+        }
+    }
+}
+
+impl From<UpdateEntryError> for ShellError {
+    fn from(err: UpdateEntryError) -> Self {
+        Self::Library(err)
+    }
+}
+
+/// Helpers that return [`anyhow::Result`] mostly fail because a write did,
+/// so an I/O error at the root is sorted like any other.
+impl From<anyhow::Error> for ShellError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<Self>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return Self::from(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<UpdateEntryError>() {
+            Ok(err) => return Self::Library(err),
+            Err(err) => err,
+        };
+        match err.root_cause().downcast_ref::<io::Error>() {
+            Some(io_err) if hung_up(io_err.kind()) => Self::ClientGone,
+            Some(io_err) => Self::Io(io::Error::new(io_err.kind(), format!("{err:#}"))),
+            None => Self::Internal(err),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Command {
     None,
     Help,
     Quit,
     Search,
+    Languages,
+    Authors,
+    JustReturned,
+    Author,
+    Preview,
+    Info,
+    Peek,
     CheckOut,
     CheckIn,
+    CheckInAll,
+    Shelf,
     Read,
     Add,
+    Upload,
+    Edit,
+    Undo,
+    Login,
+    Sign,
+    Export,
+    History,
+    Alias,
+    Unalias,
+    Prompt,
+    Size,
+    Notify,
+    Resume,
+    Delete,
+    Trash,
+    Protect,
+    Admin,
+    Guests,
+    Merge,
+    Announce,
+    Dashboard,
+    Records,
+    Reload,
     Meow,
 }
 
@@ -30,10 +613,42 @@ impl Command {
         Self::Help,
         Self::Quit,
         Self::Search,
+        Self::Languages,
+        Self::Authors,
+        Self::JustReturned,
+        Self::Author,
+        Self::Preview,
+        Self::Info,
+        Self::Peek,
         Self::CheckOut,
         Self::CheckIn,
+        Self::CheckInAll,
+        Self::Shelf,
         Self::Read,
         Self::Add,
+        Self::Upload,
+        Self::Edit,
+        Self::Undo,
+        Self::Login,
+        Self::Sign,
+        Self::Export,
+        Self::History,
+        Self::Alias,
+        Self::Unalias,
+        Self::Prompt,
+        Self::Size,
+        Self::Notify,
+        Self::Resume,
+        Self::Delete,
+        Self::Trash,
+        Self::Protect,
+        Self::Admin,
+        Self::Guests,
+        Self::Merge,
+        Self::Announce,
+        Self::Dashboard,
+        Self::Records,
+        Self::Reload,
     ];
 
     pub const fn short(self) -> &'static str {
@@ -42,44 +657,199 @@ impl Command {
             Self::Help => "h",
             Self::Quit => "q",
             Self::Search => "s",
+            Self::Languages => "lang",
+            Self::Authors => "au",
+            Self::JustReturned => "jr",
+            Self::Author => "by",
+            Self::Preview => "pv",
+            Self::Info => "i",
+            Self::Peek => "pk",
             Self::CheckOut => "co",
             Self::CheckIn => "ci",
+            Self::CheckInAll => "cia",
+            Self::Shelf => "sh",
             Self::Read => "r",
             Self::Add => "a",
+            Self::Upload => "up",
+            Self::Edit => "e",
+            Self::Undo => "u",
+            Self::Login => "l",
+            Self::Sign => "g",
+            Self::Export => "x",
+            Self::History => "hi",
+            Self::Alias => "al",
+            Self::Unalias => "ua",
+            Self::Prompt => "pr",
+            Self::Size => "sz",
+            Self::Notify => "nt",
+            Self::Resume => "re",
+            Self::Delete => "rm",
+            Self::Trash => "t",
+            Self::Protect => "pt",
+            Self::Admin => "ad",
+            Self::Guests => "gu",
+            Self::Merge => "mg",
+            Self::Announce => "an",
+            Self::Dashboard => "db",
+            Self::Records => "rec",
+            Self::Reload => "rl",
             Self::Meow => self.long(),
         }
     }
 
+    /// Whether the command can change the library, and so is refused in
+    /// read-only sessions.
+    pub const fn mutates(self) -> bool {
+        matches!(
+            self,
+            Self::CheckOut
+                | Self::CheckIn
+                | Self::CheckInAll
+                | Self::Shelf
+                | Self::Add
+                | Self::Upload
+                | Self::Edit
+                | Self::Undo
+                | Self::Sign
+                | Self::Resume
+                | Self::Delete
+                | Self::Trash
+                | Self::Protect
+                | Self::Admin
+                | Self::Guests
+                | Self::Merge
+                | Self::Reload
+        )
+    }
+
+    /// Whether the command makes the guest somebody they weren't, or has the
+    /// library remember something about them, so is refused where guests
+    /// can't register.
+    pub const fn registers(self) -> bool {
+        matches!(self, Self::Login | Self::Notify)
+    }
+
+    /// Whether the command acts as somebody, so an unregistered guest has to
+    /// register first.
+    pub const fn needs_guest(self) -> bool {
+        !matches!(
+            self,
+            Self::None
+                | Self::Help
+                | Self::Quit
+                | Self::Search
+                | Self::Languages
+                | Self::Authors
+                | Self::JustReturned
+                | Self::Preview
+                | Self::Info
+                | Self::Login
+                | Self::History
+                | Self::Alias
+                | Self::Unalias
+                | Self::Prompt
+                | Self::Size
+                | Self::Meow
+        )
+    }
+
     pub const fn long(self) -> &'static str {
         match self {
             Self::None => "",
             Self::Help => "help",
             Self::Quit => "quit",
             Self::Search => "search",
+            Self::Languages => "languages",
+            Self::Authors => "authors",
+            Self::JustReturned => "justreturned",
+            Self::Author => "author",
+            Self::Preview => "preview",
+            Self::Info => "info",
+            Self::Peek => "peek",
             Self::CheckOut => "checkout",
             Self::CheckIn => "checkin",
+            Self::CheckInAll => "checkinall",
+            Self::Shelf => "shelf",
             Self::Read => "read",
             Self::Add => "add",
+            Self::Upload => "upload",
+            Self::Edit => "edit",
+            Self::Undo => "undo",
+            Self::Login => "login",
+            Self::Sign => "sign",
+            Self::Export => "export",
+            Self::History => "history",
+            Self::Alias => "alias",
+            Self::Unalias => "unalias",
+            Self::Prompt => "prompt",
+            Self::Size => "size",
+            Self::Notify => "notify",
+            Self::Resume => "resume",
+            Self::Delete => "delete",
+            Self::Trash => "trash",
+            Self::Protect => "protect",
+            Self::Admin => "admin",
+            Self::Guests => "guests",
+            Self::Merge => "merge",
+            Self::Announce => "announce",
+            Self::Dashboard => "dashboard",
+            Self::Records => "records",
+            Self::Reload => "reload",
             Self::Meow => "meow",
         }
     }
 
+    /// Most meows [`Command::Meow`] will answer with at once.
+    pub const MAX_MEOWS: usize = 3;
+
+    /// How many times `s` says "meow", if that's all it says.
+    pub fn meows(s: &str) -> Option<usize> {
+        let mut words = 0;
+        for word in s.split_whitespace() {
+            if !word.eq_ignore_ascii_case(Self::Meow.long()) {
+                return None;
+            }
+            words += 1;
+        }
+        (words != 0).then_some(words)
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
-        if s.contains("meow") {
+        if Self::meows(s).is_some() {
             return Some(Self::Meow);
         }
         if s.is_empty() {
             return Some(Self::None);
         }
-        for &test in Self::ALL {
-            if [test.short(), test.long()].contains(&s) {
-                return Some(test);
-            }
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|test| [test.short(), test.long()].contains(&s))
+    }
+
+    /// The command a whole line asks for, and whatever else is on the line
+    /// after it, to answer the command's first question.
+    pub fn parse_line(line: &str) -> Option<(Self, &str)> {
+        if let Some(cmd) = Self::from_str(line) {
+            return Some((cmd, ""));
         }
-        None
+        let (word, rest) = line.split_once(' ')?;
+        Some((Self::from_str(word)?, rest.trim()))
     }
 }
 
+/// Tell the guest `msg`, worded in the library's [`Flavor`](crate::messages::Flavor).
+pub async fn say<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    msg: Msg<'_>,
+) -> io::Result<()> {
+    stream
+        .write_all(msg.render(library.flavor()).as_bytes())
+        .await
+}
+
 pub async fn move_cursor_prev<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     stream: &mut S,
 ) -> anyhow::Result<()> {
@@ -94,395 +864,2327 @@ pub async fn clear_line<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     Ok(())
 }
 
+/// Draw `lines` over the `height` lines above the cursor, one per line, and
+/// blank out whatever of those they don't cover. The cursor ends up right
+/// after the last of `lines`.
+pub async fn redraw_region<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    height: usize,
+    lines: &[String],
+) -> anyhow::Result<()> {
+    for _ in 0..height {
+        move_cursor_prev(stream).await?;
+    }
+    for line in lines {
+        clear_line(stream).await?;
+        stream.write_all(line.as_bytes()).await?;
+        stream.write_all(b"\n").await?;
+    }
+    let leftover = height.saturating_sub(lines.len());
+    for _ in 0..leftover {
+        clear_line(stream).await?;
+        stream.write_all(b"\n").await?;
+    }
+    for _ in 0..leftover {
+        move_cursor_prev(stream).await?;
+    }
+    Ok(())
+}
+
 pub async fn readln<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     stream: &mut S,
     prompt: &str,
-) -> anyhow::Result<String> {
+) -> ShellResult<String> {
+    Ok(readln_or_eof(stream, prompt).await?.unwrap_or_default())
+}
+
+/// A line of text as the guest typed it, spaces and all, without its line
+/// ending. `None` once the guest has hung up. Lines longer than `max_len`
+/// bytes are refused before they're all read in.
+pub async fn read_text_line<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    prompt: &str,
+    max_len: usize,
+) -> ShellResult<Option<String>> {
+    stream.write_all(prompt.as_bytes()).await?;
+    stream.flush().await?;
+    /* room for a `\r\n` after the longest line there may be */
+    let room = max_len.saturating_add(2);
+    let mut buf = Vec::new();
+    let mut limited = (&mut *stream).take(room.try_into().unwrap_or(u64::MAX));
+    if limited.read_until(b'\n', &mut buf).await? == 0 {
+        return Ok(None);
+    }
+    let too_long = || ShellError::Protocol(format!("a line longer than {max_len} bytes"));
+    if buf.len() == room && !buf.ends_with(b"\n") {
+        return Err(too_long());
+    }
+    let Ok(line) = String::from_utf8(buf) else {
+        return Err(ShellError::Protocol(String::from(
+            "a line that isn't UTF-8",
+        )));
+    };
+    let line = strip_line_ending(&line);
+    if line.len() > max_len {
+        return Err(too_long());
+    }
+    Ok(Some(line.to_string()))
+}
+
+/// `line` without its `\n` or `\r\n`, so Windows and Unix clients read the
+/// same.
+fn strip_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// A stream whose next line reads as `ahead`, as if the guest had typed it
+/// ahead of being asked. Whatever nobody reads is dropped with it.
+struct Typeahead<'s, S> {
+    inner: &'s mut S,
+    ahead: Vec<u8>,
+    pos: usize,
+}
+
+impl<'s, S> Typeahead<'s, S> {
+    /// Nothing is typed ahead if `ahead` is empty.
+    fn new(inner: &'s mut S, ahead: &str) -> Self {
+        let ahead = if ahead.is_empty() {
+            Vec::new()
+        } else {
+            format!("{ahead}\n").into_bytes()
+        };
+        Self {
+            inner,
+            ahead,
+            pos: 0,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Typeahead<'_, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let ahead = &this.ahead[this.pos..];
+        if ahead.is_empty() {
+            return Pin::new(&mut *this.inner).poll_read(cx, buf);
+        }
+        let len = cmp::min(ahead.len(), buf.remaining());
+        buf.put_slice(&ahead[..len]);
+        this.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncBufRead + Unpin> AsyncBufRead for Typeahead<'_, S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos < this.ahead.len() {
+            return Poll::Ready(Ok(&this.ahead[this.pos..]));
+        }
+        Pin::new(&mut *this.inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if this.pos < this.ahead.len() {
+            this.pos += amt;
+        } else {
+            Pin::new(&mut *this.inner).consume(amt);
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Typeahead<'_, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Like [`readln`], but `None` once the guest has hung up.
+async fn readln_or_eof<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    prompt: &str,
+) -> ShellResult<Option<String>> {
     let mut buf = String::new();
     stream.write_all(prompt.as_bytes()).await?;
     stream.flush().await?;
     match stream.read_line(&mut buf).await {
+        Ok(0) => Ok(None),
         Ok(_n) => {
-            // XXX: reallocation here is silly (bad)
-            let trimmed = buf.trim().to_string();
-            Ok(trimmed)
-        }
-
-        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-            todo!();
+            let trimmed = strip_line_ending(&buf).trim();
+            if trimmed.len() != buf.len() {
+                buf = trimmed.to_string();
+            }
+            Ok(Some(buf))
         }
+        /* a socket with nothing yet shouldn't say so to an async reader */
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(ShellError::Io(e)),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Err(ShellError::Protocol(
+            String::from("a line that isn't UTF-8"),
+        )),
         Err(e) => Err(e.into()),
     }
 }
 
-pub async fn register_guest<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+/// Write `lines`, pausing after every screenful for terminals `rows` tall.
+/// With no `rows`, they're all written at once.
+pub async fn page<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     stream: &mut S,
-    library: &Library,
-    addr: SocketAddr,
+    lines: &[String],
+    rows: Option<usize>,
 ) -> anyhow::Result<()> {
-    let span = tracing::span!(Level::INFO, "register_guest", addr = format_args!("{addr}"));
-    let _enter = span.enter();
-
-    let nick: Option<Arc<str>> = library.lookup_guest_by_addr(addr.ip()).await;
-
-    if let Some(nick) = nick {
-        tracing::info!(nick = &*nick, "welcome back");
-        stream
-            .write_all(b"Welcome back to the Cat Library!\n")
-            .await?;
-        stream.write_all(b"Your nickname is '").await?;
-        stream.write_all(nick.as_bytes()).await?;
-        stream.write_all(b"'.\n").await?;
-    } else {
-        stream.write_all(b"Welcome to the Cat Library!\n").await?;
-        for line in [
-            "this appears to be your first visit...",
-            "you will need to provide a nickname.",
-            "nicknames are public so that addresses can be private.",
-        ] {
+    /* leave a row for the prompt */
+    let screenful = rows.map_or(usize::MAX, |rows| rows.saturating_sub(1).max(1));
+    for (idx, screen) in lines.chunks(screenful).enumerate() {
+        if idx != 0 && !pause(stream).await? {
+            break;
+        }
+        for line in screen {
             stream.write_all(line.as_bytes()).await?;
             stream.write_all(b"\n").await?;
         }
-
-        loop {
-            let nick = readln(stream, "what is it? ").await?;
-            if nick.is_empty() {
-                continue;
-            }
-            match library.register_guest(addr.ip(), nick).await {
-                Ok(nick) => {
-                    tracing::info!(nick = &*nick, "registered new guest");
-                    break;
-                }
-                Err(err) => match err {
-                    RegisterError::AlreadyRegistered => break,
-                    RegisterError::NicknameTaken => {
-                        stream.write_all(b"nickname is already taken").await?;
-                    }
-                },
-            }
-        }
     }
-
     Ok(())
 }
 
-pub async fn enumerate_entries<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+/// How many unintelligible answers [`confirm`], [`choose_one`] and
+/// [`choose_rank`] put up with before giving up, as if the guest had
+/// cancelled.
+pub const PROMPT_TRIES: usize = 3;
+
+/// Ask a yes or no question. An empty answer takes `default`; hanging up or
+/// never answering sensibly is a no.
+pub async fn confirm<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     stream: &mut S,
     library: &Library,
-    entries: impl ExactSizeIterator<Item = (f64, BookID, Metadata)>,
-) -> anyhow::Result<()> {
-    for (idx, (_sim, book_id, meta)) in entries.enumerate() {
-        let rank = idx + 1;
-        let book = library.lookup_book_by_id(book_id).await;
-        let presence = if meta.is_free() { "[in] " } else { "[out]" };
-        stream
-            .write_all(
-                format!("{rank}. {presence} '{}', by {}.\n", book.title, book.author).as_bytes(),
-            )
-            .await?;
+    prompt: &str,
+    default: bool,
+) -> anyhow::Result<bool> {
+    let hint = if default { "(Y/n)" } else { "(y/N)" };
+    let prompt = format!("{prompt} {hint} ");
+    for _ in 0..PROMPT_TRIES {
+        let Some(answer) = readln_or_eof(stream, &prompt).await? else {
+            return Ok(false);
+        };
+        match answer.to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => say(stream, library, Msg::AnswerYesOrNo).await?,
+        }
     }
-    Ok(())
+    Ok(false)
 }
 
-pub async fn choose_rank<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+/// Ask the guest to pick one of `options`, by name, a prefix only it has, or
+/// its number from 1. An empty answer, hanging up or never answering
+/// sensibly picks nothing.
+pub async fn choose_one<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
     stream: &mut S,
-    num_items: usize,
-) -> anyhow::Result<Option<usize>> {
-    enum RankError {
-        TooSmall,
-        TooLarge,
+    library: &Library,
+    prompt: &str,
+    options: &[&str],
+) -> anyhow::Result<Option<usize>> {
+    let listed = options.join(", ");
+    let prompt = format!("{prompt} ({listed}) ");
+    for _ in 0..PROMPT_TRIES {
+        let Some(answer) = readln_or_eof(stream, &prompt).await? else {
+            return Ok(None);
+        };
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        if let Some(idx) = options.iter().position(|&option| option == answer) {
+            return Ok(Some(idx));
+        }
+        if let Some(idx) = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .filter(|&idx| idx < options.len())
+        {
+            return Ok(Some(idx));
+        }
+        let mut prefixed = options
+            .iter()
+            .enumerate()
+            .filter(|(_, option)| option.starts_with(answer.as_str()));
+        match (prefixed.next(), prefixed.next()) {
+            (Some((idx, _)), None) => return Ok(Some(idx)),
+            _ => say(stream, library, Msg::PickOneOf { listed: &listed }).await?,
+        }
+    }
+    Ok(None)
+}
+
+/// Wait for the guest to press enter. Returns false if they hung up instead.
+pub async fn pause<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> anyhow::Result<bool> {
+    Ok(readln_or_eof(stream, "-- more --").await?.is_some())
+}
+
+/// Count something nonsensical from `addr` against it, and if that was one
+/// too many, say goodbye.
+async fn strike<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    addr: SocketAddr,
+) -> ShellResult<()> {
+    if !library.strike(addr.ip()) {
+        return Ok(());
     }
+    tracing::warn!(addr = format_args!("{addr}"), "soft-banned an address");
+    say(stream, library, Msg::SoftBanned).await?;
+    Err(ShellError::TurnedAway)
+}
+
+pub async fn register_guest<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    addr: SocketAddr,
+) -> ShellResult<GuestId> {
+    let span = tracing::span!(Level::INFO, "register_guest", addr = format_args!("{addr}"));
+    let _enter = span.enter();
+
+    if let Some(guest) = library.lookup_guest_by_addr(addr.ip()).await {
+        let nick = library.lookup_nick(guest).await.unwrap_or_default();
+        tracing::info!(nick = &*nick, "welcome back");
+        say(stream, library, Msg::WelcomeBack { nick: &nick }).await?;
+        return Ok(guest);
+    }
+
+    say(stream, library, Msg::Welcome).await?;
+
+    loop {
+        /* another connection from the same address may have registered it */
+        if let Some(guest) = library.lookup_guest_by_addr(addr.ip()).await {
+            let nick = library.lookup_nick(guest).await.unwrap_or_default();
+            say(stream, library, Msg::AlreadyYou { nick: &nick }).await?;
+            return Ok(guest);
+        }
+
+        let nick = readln(stream, "what is it? ").await?;
+        if nick.is_empty() {
+            continue;
+        }
+
+        if nick == "login" {
+            let token = readln(stream, TOKEN_PROMPT).await?;
+            match library.login(&token).await {
+                Some(guest) => {
+                    let nick = library.lookup_nick(guest).await.unwrap_or_default();
+                    tracing::info!(nick = &*nick, "guest logged in");
+                    say(stream, library, Msg::LoggedIn { nick: &nick }).await?;
+                    return Ok(guest);
+                }
+                None => say(stream, library, Msg::UnknownToken).await?,
+            }
+            strike(stream, library, addr).await?;
+            continue;
+        }
+
+        if let Some(claimed) = nick.strip_prefix("claim ").map(str::trim) {
+            let passphrase = readln(stream, PASSPHRASE_PROMPT).await?;
+            match library.claim_guest(addr.ip(), claimed, &passphrase).await {
+                Ok(guest) => {
+                    tracing::info!(nick = claimed, "guest claimed nickname");
+                    say(stream, library, Msg::LoggedIn { nick: claimed }).await?;
+                    return Ok(guest);
+                }
+                Err(ClaimError::UnknownNickname) => {
+                    say(stream, library, Msg::UnknownNickname).await?;
+                }
+                Err(ClaimError::NoPassphrase | ClaimError::WrongPassphrase) => {
+                    say(stream, library, Msg::WrongPassphrase).await?;
+                }
+            }
+            strike(stream, library, addr).await?;
+            continue;
+        }
+
+        match library.register_guest(addr.ip(), nick).await {
+            Ok(guest) => {
+                let nick = library.lookup_nick(guest).await.unwrap_or_default();
+                tracing::info!(nick = &*nick, "registered new guest");
+                let passphrase = readln(stream, SET_PASSPHRASE_PROMPT).await?;
+                if !passphrase.is_empty() {
+                    library.set_passphrase(guest, &passphrase).await;
+                    say(stream, library, Msg::PassphraseSet).await?;
+                }
+                let token = library.issue_token(guest).await;
+                /* written separately so transcripts can leave the token out */
+                stream.write_all(TOKEN_LABEL.as_bytes()).await?;
+                stream.write_all(token.as_bytes()).await?;
+                stream.write_all(b"\n").await?;
+                say(stream, library, Msg::KeepTokenSecret).await?;
+                return Ok(guest);
+            }
+            Err(err) => match err {
+                RegisterError::AlreadyRegistered => {
+                    /* while they were typing; they keep the nick they had */
+                    continue;
+                }
+                RegisterError::NicknameTaken => {
+                    say(stream, library, Msg::NicknameTaken).await?;
+                    strike(stream, library, addr).await?;
+                }
+                RegisterError::Full => {
+                    tracing::warn!("turned away a new guest; the guest list is full");
+                    say(stream, library, Msg::LibraryFull).await?;
+                }
+            },
+        }
+    }
+}
+
+/// Make up a nickname for a guest in a session that can't register, the
+/// first time they need one. They stay [`Library::NOBODY`] to the library.
+async fn name_visitor<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+) -> ShellResult<()> {
+    if session.visitor.is_some() {
+        return Ok(());
+    }
+    let nick = format!("visitor {:08x}", library.random() as u32);
+    tracing::info!(nick = &*nick, "named a visitor");
+    say(stream, library, Msg::VisitorNick { nick: &nick }).await?;
+    session.visitor = Some(nick);
+    Ok(())
+}
+
+/// Roughly `width` characters of `text` surrounding the `len` bytes at
+/// `offset`, flattened onto one line, with ellipses wherever it was cut.
+/// `None` if those bytes aren't in `text`, say because it changed since it
+/// was searched.
+pub fn snippet(text: &str, offset: usize, len: usize, width: usize) -> Option<String> {
+    let before = text.get(..offset)?;
+    let matched = text.get(offset..offset.checked_add(len)?)?;
+    let after = &text[offset + len..];
+
+    let budget = width.saturating_sub(matched.chars().count());
+    let before_len = cmp::min(before.chars().count(), budget / 2);
+    let after_len = budget - before_len;
+
+    let mut out = String::new();
+    let skip = before.chars().count() - before_len;
+    if skip > 0 {
+        out.push('…');
+    }
+    out.extend(before.chars().skip(skip));
+    out.push_str(matched);
+    out.extend(after.chars().take(after_len));
+    if after.chars().nth(after_len).is_some() {
+        out.push('…');
+    }
+
+    Some(out.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// How many columns `text` takes up on a terminal.
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// `text`, padded with spaces out to `width` columns (or left alone, if it's
+/// already that wide).
+pub fn pad(text: &str, width: usize) -> String {
+    let fill = width.saturating_sub(display_width(text));
+    format!("{text}{:fill$}", "")
+}
+
+/// `text`, cut short with a `…` if it's wider than `width` columns.
+pub fn truncate(text: &str, width: usize) -> Cow<'_, str> {
+    if display_width(text) <= width {
+        return Cow::Borrowed(text);
+    }
+    /* leave a column for the ellipsis */
+    let budget = width.saturating_sub(1);
+    let mut used = 0;
+    let mut out = String::new();
+    for ch in text.chars() {
+        used += UnicodeWidthChar::width(ch).unwrap_or(0);
+        if budget < used {
+            break;
+        }
+        out.push(ch);
+    }
+    out.push('…');
+    Cow::Owned(out)
+}
+
+/// List `entries` in columns, fitting titles and authors into a terminal
+/// `cols` wide (or [`DEFAULT_COLS`], if we don't know).
+pub async fn enumerate_entries<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    entries: impl ExactSizeIterator<Item = (f64, BookID, Metadata, Option<Match>)>,
+    cols: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut rows = Vec::with_capacity(entries.len());
+    for (_sim, book_id, meta, matched) in entries {
+        let cover = library.lookup_cover(book_id).await;
+        let snippet = match_snippet(library, book_id, &cover, matched).await;
+        rows.push((cover, meta, snippet, None, false));
+    }
+    write_listing(stream, rows, cols).await
+}
+
+/// Whether the only copies of a book `guest` could get are the ones they
+/// already have.
+fn all_yours(library: &Library, copies: &Copies, guest: GuestId) -> bool {
+    copies.available == 0 && library.holds_any(&copies.ids, guest)
+}
+
+/// Like [`enumerate_entries`], but each entry stands for every copy of a
+/// book, and says how many of them there are, and whether `viewer` has the
+/// last of them.
+pub async fn enumerate_copies<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    entries: &[Copies],
+    viewer: GuestId,
+    cols: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut rows = Vec::with_capacity(entries.len());
+    for copies in entries {
+        let (_sim, book_id, meta, matched) = copies.best;
+        let cover = library.lookup_cover(book_id).await;
+        let snippet = match_snippet(library, book_id, &cover, matched).await;
+        let counts = (1 < copies.ids.len()).then_some((copies.ids.len(), copies.available));
+        let yours = all_yours(library, copies, viewer);
+        rows.push((cover, meta, snippet, counts, yours));
+    }
+    write_listing(stream, rows, cols).await
+}
+
+/// What of a book's `matched` field to show under it in a listing. Only a
+/// match in the content has it unpacked, and then not kept in the cache.
+async fn match_snippet(
+    library: &Library,
+    book_id: BookID,
+    cover: &StoredBook,
+    matched: Option<Match>,
+) -> Option<String> {
+    const SNIPPET_WIDTH: usize = 60;
+
+    let Match { field, offset, len } = matched?;
+    let text = match field {
+        Field::Title => &cover.title,
+        Field::Author => &cover.author,
+        Field::Description => &cover.description,
+        Field::Content => {
+            let book = library.lookup_book_uncached(book_id).await;
+            return snippet(&book.content, offset, len, SNIPPET_WIDTH);
+        }
+    };
+    snippet(text, offset, len, SNIPPET_WIDTH)
+}
+
+/// A book's cover, its metadata, the snippet of where the search matched
+/// it, how many copies it has and how many of those are available, if more
+/// than one, and whether the guest has it checked out.
+type ListingRow = (
+    Arc<StoredBook>,
+    Metadata,
+    Option<String>,
+    Option<(usize, usize)>,
+    bool,
+);
+
+async fn write_listing<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    rows: Vec<ListingRow>,
+    cols: Option<usize>,
+) -> anyhow::Result<()> {
+    /* the least room titles and authors get, however narrow the terminal */
+    const MIN_ROOM: usize = 16;
+
+    let rank_width = rows.len().to_string().len();
+    /* "[yours]" only widens the column when it's there */
+    let presence_width = if rows.iter().any(|row| row.4) { 7 } else { 5 };
+
+    /* everything but the title and author: "1. [in]  '", "', by " and "." */
+    let fixed = rank_width + presence_width + 5 + 6 + 1;
+    let room = cols
+        .unwrap_or(DEFAULT_COLS)
+        .saturating_sub(fixed)
+        .max(MIN_ROOM);
+    let widest = |field: fn(&StoredBook) -> &str| {
+        rows.iter()
+            .map(|(book, ..)| display_width(field(book)))
+            .max()
+            .unwrap_or(0)
+    };
+    let author_width = cmp::min(widest(|book| &book.author), room / 3);
+    let title_width = cmp::min(widest(|book| &book.title), room - author_width);
+
+    for (idx, (book, meta, snippet, counts, yours)) in rows.into_iter().enumerate() {
+        let rank = idx + 1;
+        let presence = if meta.is_hidden() {
+            "[bin]"
+        } else if yours {
+            "[yours]"
+        } else if meta.is_free() {
+            "[in]"
+        } else {
+            "[out]"
+        };
+        let title = truncate(&book.title, title_width);
+        let author = truncate(&book.author, author_width);
+        let pad = title_width - display_width(&title);
+        let counts = match counts {
+            Some((copies, available)) => format!(" ({copies} copies, {available} available)"),
+            None => String::new(),
+        };
+        let notice = if meta.protected {
+            " [library notice]"
+        } else {
+            ""
+        };
+        stream
+            .write_all(
+                format!(
+                    "{rank:>rank_width$}. {presence:<presence_width$} '{title}',{:pad$} by {author}.{counts}{notice}\n",
+                    ""
+                )
+                .as_bytes(),
+            )
+            .await?;
+        if let Some(snippet) = snippet {
+            stream
+                .write_all(format!("\t\"{snippet}\"\n").as_bytes())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// What came of asking the guest to pick one of some items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Choice {
+    /// The index of the item they picked.
+    Picked(usize),
+    /// There was nothing to pick from. Whatever found nothing has already
+    /// said so.
+    Nothing,
+    /// An empty answer or `q`, or they hung up.
+    Cancelled,
+    /// They never gave a number in range.
+    GaveUp,
+}
+
+/// The index `choice` picked. If it didn't pick one, tell the guest never
+/// mind, unless there was nothing to pick and they already know it.
+async fn picked<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    choice: Choice,
+) -> io::Result<Option<usize>> {
+    match choice {
+        Choice::Picked(index) => Ok(Some(index)),
+        Choice::Nothing => Ok(None),
+        Choice::Cancelled | Choice::GaveUp => {
+            say(stream, library, Msg::Nevermind).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Ask for the number of one of `num_items` items, and give back its index.
+/// An empty answer or `q` cancels; so does hanging up, or never answering
+/// sensibly.
+pub async fn choose_rank<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    num_items: usize,
+) -> anyhow::Result<Choice> {
+    if num_items == 0 {
+        return Ok(Choice::Nothing);
+    }
+
+    for _ in 0..PROMPT_TRIES {
+        let answer = readln(stream, "which item number? ").await?;
+        if answer.is_empty() || answer == "q" {
+            return Ok(Choice::Cancelled);
+        }
+        match parse_rank(&answer, num_items) {
+            Ok(index) => return Ok(Choice::Picked(index)),
+            Err(msg) => say(stream, library, msg).await?,
+        }
+    }
+    Ok(Choice::GaveUp)
+}
+
+/// The index of item `answer` of `num_items`, or what's wrong with it.
+fn parse_rank(answer: &str, num_items: usize) -> Result<usize, Msg<'static>> {
+    let min_rank = 1;
+    let max_rank = num_items;
+    match answer.parse::<usize>() {
+        Ok(rank) if rank < min_rank => Err(Msg::RankTooSmall { min: min_rank }),
+        Ok(rank) if max_rank < rank => Err(Msg::RankTooLarge { max: max_rank }),
+        Ok(rank) => Ok(rank - 1),
+        Err(err) if *err.kind() == IntErrorKind::PosOverflow => {
+            Err(Msg::RankTooLarge { max: max_rank })
+        }
+        Err(_) => Err(Msg::NotANumber),
+    }
+}
+
+pub async fn choose_entry<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    entries: impl ExactSizeIterator<Item = (f64, BookID, Metadata, Option<Match>)>,
+    cols: Option<usize>,
+) -> anyhow::Result<Choice> {
+    let len = entries.len();
+    enumerate_entries(stream, library, entries, cols).await?;
+    choose_rank(stream, library, len).await
+}
+
+pub async fn choose_copies<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    entries: &[Copies],
+    viewer: GuestId,
+    cols: Option<usize>,
+) -> anyhow::Result<Choice> {
+    enumerate_copies(stream, library, entries, viewer, cols).await?;
+    choose_rank(stream, library, entries.len()).await
+}
+
+/// Ask what to look for, and find it for the session's guest. What it was,
+/// less any filters, is kept to highlight on the cover page.
+pub async fn search<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+) -> anyhow::Result<Vec<(f64, BookID, Metadata, Option<Match>)>> {
+    let query = readln(stream, "search query? ").await?;
+    /* one more than is listed, to tell if there are more */
+    let mut search = library
+        .search_top_k(&query, session.guest, SEARCH_RESULTS + 1)
+        .await;
+    if search.len() > SEARCH_RESULTS {
+        search.truncate(SEARCH_RESULTS);
+        let msg = Msg::MoreMatches {
+            shown: SEARCH_RESULTS,
+        };
+        say(stream, library, msg).await?;
+    }
+    /* what's left is what to highlight */
+    let (_filters, words) = Library::filters(&query);
+    let query = words.into_owned();
+
+    if search.is_empty() {
+        let msg = if query.is_empty() {
+            Msg::LibraryEmpty
+        } else {
+            Msg::NoMatches
+        };
+        say(stream, library, msg).await?;
+    }
+
+    session.last_query = Some(query).filter(|query| !query.is_empty());
+    Ok(search)
+}
+
+/// Books `guest` may read right now: their checkouts, plus communal books.
+pub async fn readable_books(library: &Library, guest: GuestId) -> Vec<(BookID, Metadata)> {
+    let mut books = library.lookup_checkouts_by_guest(guest).await;
+    if let Some(guestbook) = library.guestbook() {
+        let meta = library.lookup_metadata(guestbook);
+        if !meta.is_hidden() {
+            books.push((guestbook, meta));
+        }
+    }
+    books
+}
+
+/// What can be done with a draft once the editor is left, as offered.
+const DRAFT_CHOICES: &[&str] = &[
+    "publish",
+    "title",
+    "author",
+    "description",
+    "content",
+    "keep",
+    "discard",
+];
+
+/// Put `draft` in the editor, then go over its cover with the guest until
+/// they add it to the library, keep it on the session for later, or throw it
+/// away. Not answering keeps it.
+async fn write_draft<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+    mut draft: Draft,
+) -> anyhow::Result<()> {
+    let mut editing = true;
+    loop {
+        if editing {
+            let mut lines: Vec<Cow<'_, str>> = draft.lines.drain(..).map(Cow::Owned).collect();
+            {
+                let mut editor = Editor::new(&mut lines, false).for_session(session, library);
+                editor.enter(stream).await?;
+            }
+            draft.lines = lines.into_iter().map(Cow::into_owned).collect();
+            editing = false;
+        }
+
+        let Draft {
+            title,
+            author,
+            description,
+            lines,
+            ..
+        } = &draft;
+        let lines = lines.len();
+        say(
+            stream,
+            library,
+            Msg::DraftSummary {
+                title,
+                author,
+                description,
+                lines,
+            },
+        )
+        .await?;
+
+        let field = match choose_one(stream, library, "now what?", DRAFT_CHOICES).await? {
+            Some(0) => {
+                /* garbage would greet every reader, so make sure they mean it */
+                if sanitize::looks_like_text(&Book::content_from_lines(&draft.lines)) {
+                    break;
+                }
+                say(stream, library, Msg::NotText).await?;
+                if confirm(stream, library, "publish it anyway?", false).await? {
+                    break;
+                }
+                continue;
+            }
+            Some(1) => (&mut draft.title, "Title? "),
+            Some(2) => (&mut draft.author, "Author? "),
+            Some(3) => (&mut draft.description, "Description? "),
+            Some(4) => {
+                editing = true;
+                continue;
+            }
+            Some(6) => {
+                say(stream, library, Msg::DiscardedDraft).await?;
+                return Ok(());
+            }
+            _ => {
+                session.draft = Some(draft);
+                say(stream, library, Msg::KeptDraft).await?;
+                return Ok(());
+            }
+        };
+        /* an empty answer leaves it as it was */
+        let (dst, prompt) = field;
+        let answer = readln(stream, prompt).await?;
+        if !answer.is_empty() {
+            *dst = answer;
+        }
+    }
+
+    let title = &draft.title;
+    say(stream, library, Msg::Adding { title }).await?;
+    stream.flush().await?;
+
+    let book = Book {
+        title: draft.title.clone(),
+        author: draft.author.clone(),
+        description: draft.description.clone(),
+        content: Book::content_from_lines(&draft.lines),
+        language: draft.language.clone(),
+    };
+    match library.add(book, session.guest).await {
+        Ok(_) => {
+            session.draft = None;
+            say(stream, library, Msg::Done).await?;
+        }
+        Err(AddError::ContributionLimit) => {
+            /* there may be room later, if one of theirs goes in the trash */
+            session.draft = Some(draft);
+            say(stream, library, Msg::ContributionLimit).await?;
+            say(stream, library, Msg::KeptDraft).await?;
+        }
+        Err(AddError::Duplicate(_)) => unreachable!("only unique adds refuse copies"),
+    }
+    Ok(())
+}
+
+/// Why the operator can't merge `from` into somebody.
+fn merge_refusal(err: MergeError, from: &str) -> Msg<'_> {
+    match err {
+        MergeError::SameGuest => Msg::MergeSameGuest,
+        MergeError::Operator => Msg::CantMergeOperator,
+        /* evicted since they were looked up */
+        MergeError::Unregistered => Msg::MergeUnregistered,
+        MergeError::Connected => Msg::StillConnected { nick: from },
+    }
+}
+
+/// The columns and rows in a terminal size like `80x24` or `80 24`.
+fn parse_size(answer: &str) -> Option<(usize, usize)> {
+    let (cols, rows) = answer
+        .split_once(['x', 'X', ' '])
+        .map(|(cols, rows)| (cols.trim(), rows.trim()))?;
+    let cols = cols.parse().ok().filter(|&cols| cols != 0)?;
+    let rows = rows.parse().ok().filter(|&rows| rows != 0)?;
+    Some((cols, rows))
+}
+
+/// Read `book_id`, one of the session's guest's checkouts.
+async fn read_checkout<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+    book_id: BookID,
+) -> anyhow::Result<()> {
+    if session
+        .last_checkout
+        .is_some_and(|(last, _at)| last == book_id)
+    {
+        /* it's been read, so it was no accident */
+        session.last_checkout = None;
+    }
+    library.register_read(book_id, session.guest);
+    let book = library.lookup_book_by_id(book_id).await;
+    editor::read_book(stream, library, book_id, &book, session).await
+}
+
+/// Return `book_id`, item `rank` of the session's guest's checkouts.
+async fn check_in<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+    book_id: BookID,
+    rank: usize,
+) -> anyhow::Result<()> {
+    if session
+        .last_checkout
+        .is_some_and(|(last, _at)| last == book_id)
+    {
+        session.last_checkout = None;
+    }
+    match library.checkin(book_id, session.guest) {
+        Ok(held) => {
+            say(stream, library, Msg::Returned { rank }).await?;
+            let book = library.lookup_book_by_id(book_id).await;
+            let title = &book.title;
+            say(stream, library, Msg::HeldFor { title, held }).await?;
+        }
+        Err(err) => match err {
+            /* it was theirs when listed, so it came back some other way
+             * meanwhile, e.g. from their other connection */
+            UpdateEntryError::AlreadyCheckedIn => {
+                say(stream, library, Msg::AlreadyCheckedIn { rank }).await?;
+            }
+            UpdateEntryError::GuestMismatch => {
+                say(stream, library, Msg::CheckedOutBySomebodyElse { rank }).await?;
+            }
+            err @ (UpdateEntryError::AlreadyCheckedOut(_)
+            | UpdateEntryError::Communal
+            | UpdateEntryError::Removed
+            | UpdateEntryError::NotTrashed
+            | UpdateEntryError::BeingRead
+            | UpdateEntryError::Protected) => return Err(ShellError::from(err).into()),
+        },
+    }
+    Ok(())
+}
+
+/// Read `book_id` without checking it out, or preview it if it's somebody
+/// else's and the session can't read freely.
+async fn peek<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+    book_id: BookID,
+    meta: Metadata,
+) -> anyhow::Result<()> {
+    let book: &Book = &*library.lookup_book_by_id(book_id).await;
+    /* somebody else's checkout is theirs to read; the rest of us preview */
+    let theirs = meta
+        .checked_out_by
+        .is_some_and(|holder| holder != session.guest);
+    if theirs && !session.caps.read_freely {
+        editor::preview_book(
+            stream,
+            library,
+            book_id,
+            book,
+            session,
+            library.preview_lines(),
+            "check it out when it's back to keep reading.",
+        )
+        .await?;
+    } else {
+        /* visitors to a mirror leave no trace */
+        if session.is_registered() {
+            library.register_read(book_id, session.guest);
+        }
+        editor::read_book(stream, library, book_id, book, session).await?;
+    }
+    Ok(())
+}
+
+/// Check out any free one of `copies`, item `rank`, for the session's guest.
+async fn check_out<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+    copies: &Copies,
+    rank: usize,
+) -> anyhow::Result<()> {
+    let guest = session.guest;
+    if all_yours(library, copies, guest) {
+        say(stream, library, Msg::AlreadyYours).await?;
+        return Ok(());
+    }
+    match library.checkout_any(&copies.ids, guest) {
+        Ok(book_id) => {
+            session.last_checkout = Some((book_id, Instant::now()));
+            say(stream, library, Msg::CheckedOut { rank }).await?;
+        }
+        Err(err) => match err {
+            UpdateEntryError::AlreadyCheckedOut(by) => {
+                let by = library.lookup_nick(by).await;
+                let by = by.as_deref();
+                say(stream, library, Msg::AlreadyCheckedOut { rank, by }).await?;
+            }
+            UpdateEntryError::Communal => {
+                say(stream, library, Msg::Communal { rank }).await?;
+            }
+            UpdateEntryError::Removed => {
+                say(stream, library, Msg::TakenOffShelves { rank }).await?;
+            }
+            err @ (UpdateEntryError::GuestMismatch
+            | UpdateEntryError::AlreadyCheckedIn
+            | UpdateEntryError::NotTrashed
+            | UpdateEntryError::BeingRead
+            | UpdateEntryError::Protected) => return Err(ShellError::from(err).into()),
+        },
+    }
+    Ok(())
+}
+
+/// Put the content of `book_id`, item `rank`, in the editor for the guest
+/// who added it, then show them what they changed and save it if they still
+/// want to.
+async fn edit_content<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    library: &Library,
+    session: &mut Session,
+    book_id: BookID,
+    rank: usize,
+) -> anyhow::Result<()> {
+    let guest = session.guest;
+    let meta = library.lookup_metadata(book_id);
+    if meta.protected && guest != Library::OPERATOR {
+        say(stream, library, Msg::LibraryNotice { rank }).await?;
+        return Ok(());
+    }
+    if meta.added_by != guest {
+        say(stream, library, Msg::OnlyAdderEdits { rank }).await?;
+        return Ok(());
+    }
+
+    let book = library.lookup_book_by_id(book_id).await;
+    let mut lines: Vec<Cow<'_, str>> = book.lines().map(Cow::Borrowed).collect();
+    {
+        let mut editor = Editor::new(&mut lines, false).for_session(session, library);
+        editor.enter(stream).await?;
+    }
+    let content = Book::content_from_lines(&lines);
+
+    let changes = diff::diff(&book.content, &content);
+    let (added, removed) = diff::counts(&changes);
+    if added == 0 && removed == 0 {
+        say(stream, library, Msg::NoChanges { rank }).await?;
+        return Ok(());
+    }
+    page(stream, &diff::unified(&changes), session.rows).await?;
+    say(stream, library, Msg::DiffSummary { added, removed }).await?;
+    let sure = if sanitize::looks_like_text(&content) {
+        confirm(stream, library, "save these changes?", true).await?
+    } else {
+        say(stream, library, Msg::NotText).await?;
+        confirm(stream, library, "save them anyway?", false).await?
+    };
+    if !sure {
+        say(stream, library, Msg::NotSaved { rank }).await?;
+        return Ok(());
+    }
+
+    let edited = Book {
+        content,
+        ..Book::clone(&book)
+    };
+    match library.replace_book(book_id, edited, guest).await {
+        Ok(()) => {
+            let field = "content";
+            say(stream, library, Msg::Updated { field, rank }).await?;
+        }
+        Err(UpdateEntryError::Removed) => {
+            say(stream, library, Msg::TakenOffShelves { rank }).await?;
+        }
+        Err(UpdateEntryError::BeingRead) => {
+            say(stream, library, Msg::BeingRead { rank }).await?;
+        }
+        Err(UpdateEntryError::Protected) => {
+            say(stream, library, Msg::LibraryNotice { rank }).await?;
+        }
+        Err(
+            err @ (UpdateEntryError::AlreadyCheckedOut(_)
+            | UpdateEntryError::AlreadyCheckedIn
+            | UpdateEntryError::GuestMismatch
+            | UpdateEntryError::Communal
+            | UpdateEntryError::NotTrashed),
+        ) => return Err(ShellError::from(err).into()),
+    }
+    Ok(())
+}
+
+pub async fn do_cmd<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    cmd: Command,
+    library: &Library,
+    session: &mut Session,
+) -> ShellResult<Passback> {
+    do_cmd_typed_ahead(stream, cmd, "", library, session).await
+}
+
+/// Like [`do_cmd`], with `ahead` as the answer to the command's first
+/// question, from the rest of the line the command was on.
+pub async fn do_cmd_typed_ahead<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    cmd: Command,
+    ahead: &str,
+    library: &Library,
+    session: &mut Session,
+) -> ShellResult<Passback> {
+    tracing::trace!(cmd = format_args!("{cmd:?}"), "received command");
+    if cmd != Command::None {
+        library.record_command(cmd.long());
+    }
+    if !session.caps.allows(cmd) {
+        say(stream, library, session.caps.refusal()).await?;
+        return Ok(Passback::Continue);
+    }
+    /* where anything may be read, reading doesn't start from checkouts */
+    let cmd = match cmd {
+        Command::Read if session.caps.read_freely => Command::Peek,
+        cmd => cmd,
+    };
+    if cmd.needs_guest() && !session.is_registered() {
+        if session.caps.register {
+            let addr = session
+                .addr
+                .expect("unregistered sessions know where they're from");
+            session.guest = register_guest(stream, library, addr)
+                .await
+                .context("failed to register guest")?;
+        } else {
+            name_visitor(stream, library, session).await?;
+        }
+    }
+    library.touch(session.guest);
+
+    /* with nothing to define, `alias` just lists what's defined */
+    if cmd == Command::Alias && ahead.is_empty() {
+        for (name, expansion) in session.aliases.iter() {
+            stream
+                .write_all(format!("{name} = {expansion}\n").as_bytes())
+                .await?;
+        }
+        return Ok(Passback::Continue);
+    }
+
+    /* registering asked questions of its own; the rest are the command's */
+    let mut stream = Typeahead::new(stream, ahead);
+    run_cmd(&mut stream, cmd, library, session).await
+}
+
+async fn run_cmd<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    cmd: Command,
+    library: &Library,
+    session: &mut Session,
+) -> ShellResult<Passback> {
+    let guest = session.guest;
+
+    match cmd {
+        Command::None => {}
+
+        Command::Help => {
+            const EXTRA: usize = 8;
+            let names = |cmd: &Command| format!("{}, {}", cmd.short(), cmd.long());
+            let names_width = Command::ALL
+                .iter()
+                .map(|cmd| display_width(&names(cmd)))
+                .max()
+                .expect("Command::ALL must not be empty");
+            let mut lines = Vec::new();
+            for cmd in Command::ALL {
+                let help_text = match cmd {
+                    Command::None => "doesn't do anything.",
+                    Command::Help => "ask for assistance.",
+                    Command::Quit => "Abandon all Data.",
+                    Command::Search => "search the library; lang:fr or available: narrow it down.",
+                    Command::Languages => "count the books in each language.",
+                    Command::Authors => "count the books by each author.",
+                    Command::JustReturned => "list books that were just checked back in.",
+                    Command::Author => "check out a book by an author, by name or number.",
+                    Command::CheckOut => "acquire a book, if it is available!",
+                    Command::CheckIn => "return a book.",
+                    Command::CheckInAll => "return every book you have out.",
+                    Command::Shelf => "list your checkouts once, then read or return them in turn.",
+                    Command::Preview => "read the first page of a book, even one that's out.",
+                    Command::Info => "see everything about a book without opening it.",
+                    Command::Peek => "read a book on the shelf without checking it out.",
+                    Command::Read => "peruse your checked out books.",
+                    Command::Add => "add a New Book to the library's collection.",
+                    Command::Upload => "add a book in one go, for scripts (see the README).",
+                    Command::Edit => "fix the cover or content of a book you added.",
+                    Command::Undo => "take back a checkout you just made.",
+                    Command::Login => "become yourself with your login token.",
+                    Command::Sign => "leave a note in the guestbook.",
+                    Command::Export => "print a checked out book whole, to save it.",
+                    Command::History => "list what you've entered; run one again with !<n> or !!.",
+                    Command::Alias => {
+                        "list your aliases, or make one: alias <name> <command line>."
+                    }
+                    Command::Unalias => "forget an alias.",
+                    Command::Prompt => {
+                        "change your prompt: %n is your nickname, %c your checkouts."
+                    }
+                    Command::Size => {
+                        "say how big your terminal is, like 80x24, to page and fit lists."
+                    }
+                    Command::Notify => "hear when your books are checked out: notify on or off.",
+                    Command::Resume => "keep writing the book you didn't add yet.",
+                    Command::Delete => "(operator) put a book in the trash.",
+                    Command::Trash => "(operator) restore books from the trash, or empty it.",
+                    Command::Protect => {
+                        "(operator) make a book a library notice guests can't change, or undo that."
+                    }
+                    Command::Admin => "(operator) ban or unban a guest's address.",
+                    Command::Guests => "(operator) count guests, and prune idle ones.",
+                    Command::Merge => "(operator) fold one cat's second nickname into the first.",
+                    Command::Announce => "(operator) tell everyone connected something.",
+                    Command::Dashboard => "(operator) watch the library; enter refreshes.",
+                    Command::Records => "(operator) print every checkout so far, as csv or json.",
+                    Command::Reload => "(operator) pick up changes made to the data file by hand.",
+                    Command::Meow => "(warning: meows at you).",
+                };
+
+                lines.push(format!(
+                    "{}{help_text}",
+                    pad(&names(cmd), names_width + EXTRA)
+                ));
+            }
+            page(stream, &lines, session.rows).await?;
+        }
+
+        Command::Search => {
+            let search = search(stream, library, session).await?;
+            let copies = library.collapse_copies(search).await;
+            enumerate_copies(stream, library, &copies, guest, session.cols).await?;
+        }
+
+        Command::Languages => {
+            let languages = library.languages(guest).await;
+            if languages.is_empty() {
+                say(stream, library, Msg::LibraryEmpty).await?;
+            }
+            for (language, count) in languages {
+                let language = language.as_deref();
+                say(stream, library, Msg::BooksIn { language, count }).await?;
+            }
+        }
+
+        Command::Authors => {
+            let authors = library.authors(guest).await;
+            if authors.is_empty() {
+                say(stream, library, Msg::LibraryEmpty).await?;
+            }
+            for (idx, (author, count)) in authors.iter().enumerate() {
+                let rank = idx + 1;
+                let msg = Msg::BooksBy {
+                    rank,
+                    author,
+                    count: *count,
+                };
+                say(stream, library, msg).await?;
+            }
+        }
+
+        Command::JustReturned => {
+            let mut rank = 0;
+            for book_id in library.just_returned() {
+                /* only what's still there to be had */
+                let meta = library.lookup_metadata(book_id);
+                if !meta.is_free() || !meta.visible_to(guest) {
+                    continue;
+                }
+                rank += 1;
+                let title = library.lookup_title(book_id).await;
+                say(
+                    stream,
+                    library,
+                    Msg::JustReturned {
+                        rank,
+                        title: &title,
+                    },
+                )
+                .await?;
+            }
+            if rank == 0 {
+                say(stream, library, Msg::NothingReturned).await?;
+            }
+        }
+
+        Command::Author => {
+            let answer = readln(stream, "author? ").await?;
+            /* a number picks from what `authors` lists */
+            let authors = library.authors(guest).await;
+            let author = match answer.parse::<usize>() {
+                Ok(rank) if (1..=authors.len()).contains(&rank) => authors[rank - 1].0.clone(),
+                _ => answer,
+            };
+            if author.is_empty() {
+                say(stream, library, Msg::Nevermind).await?;
+                return Ok(Passback::Continue);
+            }
+            let books = library.books_by_author(&author, guest).await;
+            if books.is_empty() {
+                say(stream, library, Msg::NoBooksBy { author: &author }).await?;
+                return Ok(Passback::Continue);
+            }
+            let mut copies = library.collapse_copies(books).await;
+            if copies.len() > SEARCH_RESULTS {
+                copies.truncate(SEARCH_RESULTS);
+                let msg = Msg::MoreMatches {
+                    shown: SEARCH_RESULTS,
+                };
+                say(stream, library, msg).await?;
+            }
+            let choice = choose_copies(stream, library, &copies, guest, session.cols).await?;
+            let Some(index) = picked(stream, library, choice).await? else {
+                return Ok(Passback::Continue);
+            };
+            /* looking up an author changes nothing; checking out does */
+            if session.caps.change {
+                check_out(stream, library, session, &copies[index], index + 1).await?;
+            } else if session.caps.read_freely {
+                let (_sim, book_id, meta, _matched) = copies[index].best;
+                peek(stream, library, session, book_id, meta).await?;
+            } else {
+                say(stream, library, session.caps.refusal()).await?;
+            }
+        }
+
+        Command::Preview => {
+            let search = search(stream, library, session).await?;
+            let copies = library.collapse_copies(search).await;
+            let choice = choose_copies(stream, library, &copies, guest, session.cols).await?;
+            if let Some(index) = picked(stream, library, choice).await? {
+                let (_sim, book_id, meta, _matched) = copies[index].best;
+                let why = if meta.is_free() {
+                    "check it out to keep reading."
+                } else {
+                    "check it out when it's back to keep reading."
+                };
+                let book: &Book = &*library.lookup_book_by_id(book_id).await;
+                editor::preview_book(
+                    stream,
+                    library,
+                    book_id,
+                    book,
+                    session,
+                    library.preview_lines(),
+                    why,
+                )
+                .await?;
+            }
+        }
+
+        Command::Info => {
+            let search = search(stream, library, session).await?;
+            let copies = library.collapse_copies(search).await;
+            let choice = choose_copies(stream, library, &copies, guest, session.cols).await?;
+            if let Some(index) = picked(stream, library, choice).await? {
+                let (_sim, book_id, _meta, _matched) = copies[index].best;
+                /* as it is now, and this copy's alone, not summed with the rest */
+                let meta = library.lookup_metadata(book_id);
+                let book: &Book = &*library.lookup_book_by_id(book_id).await;
+                editor::info_panel(stream, library, book_id, book, meta).await?;
+            }
+        }
+
+        Command::Peek => {
+            let search = search(stream, library, session).await?;
+            let copies = library.collapse_copies(search).await;
+            let choice = choose_copies(stream, library, &copies, guest, session.cols).await?;
+            let Some(index) = picked(stream, library, choice).await? else {
+                return Ok(Passback::Continue);
+            };
+            let (_sim, book_id, meta, _matched) = copies[index].best;
+            peek(stream, library, session, book_id, meta).await?;
+        }
+
+        Command::Quit => {
+            say(stream, library, Msg::Bye).await?;
+            return Ok(Passback::Quit);
+        }
+
+        Command::CheckOut => {
+            let search = search(stream, library, session).await?;
+            let copies = library.collapse_copies(search).await;
+            let choice = choose_copies(stream, library, &copies, guest, session.cols).await?;
+            if let Some(index) = picked(stream, library, choice).await? {
+                check_out(stream, library, session, &copies[index], index + 1).await?;
+            }
+        }
+
+        Command::CheckIn => {
+            let checked_out: Vec<(BookID, Metadata)> =
+                library.lookup_checkouts_by_guest(guest).await;
+            if checked_out.is_empty() {
+                say(stream, library, Msg::CheckOutFirst).await?;
+                return Ok(Passback::Continue);
+            }
+
+            say(stream, library, Msg::OldestCheckoutFirst).await?;
+            let choice = choose_entry(
+                stream,
+                library,
+                checked_out
+                    .iter()
+                    .map(|&(book, meta)| (1.0, book, meta, None)),
+                session.cols,
+            )
+            .await?;
+            if let Some(index) = picked(stream, library, choice).await? {
+                let (book_id, _meta) = checked_out[index];
+                check_in(stream, library, session, book_id, index + 1).await?;
+            }
+        }
+
+        Command::Shelf => {
+            /* listed again only once returning something changes it */
+            let mut shelf = library.lookup_checkouts_by_guest(guest).await;
+            let mut stale = true;
+            let mut tries = 0;
+            loop {
+                if shelf.is_empty() {
+                    say(stream, library, Msg::CheckOutFirst).await?;
+                    break;
+                }
+                if stale {
+                    say(stream, library, Msg::OldestCheckoutFirst).await?;
+                    let entries = shelf.iter().map(|&(book, meta)| (1.0, book, meta, None));
+                    enumerate_entries(stream, library, entries, session.cols).await?;
+                    stale = false;
+                }
+                if tries == PROMPT_TRIES {
+                    say(stream, library, Msg::Nevermind).await?;
+                    break;
+                }
+                let answer = readln(stream, "read <n>, return <n>, or q? ").await?;
+                if answer.is_empty() || answer == "q" {
+                    break;
+                }
+                let (action, rank) = answer.split_once(' ').unwrap_or((&answer, ""));
+                let index = match parse_rank(rank.trim(), shelf.len()) {
+                    Ok(index) => index,
+                    Err(msg) => {
+                        say(stream, library, msg).await?;
+                        tries += 1;
+                        continue;
+                    }
+                };
+                let (book_id, _meta) = shelf[index];
+                match action {
+                    "read" | "r" => {
+                        tries = 0;
+                        /* returned elsewhere since it was listed */
+                        if library.lookup_metadata(book_id).checked_out_by != Some(guest) {
+                            say(stream, library, Msg::AlreadyCheckedIn { rank: index + 1 }).await?;
+                        } else {
+                            read_checkout(stream, library, session, book_id).await?;
+                            continue;
+                        }
+                    }
+                    "return" | "ci" => {
+                        tries = 0;
+                        check_in(stream, library, session, book_id, index + 1).await?;
+                    }
+                    _ => {
+                        let listed = "read, return";
+                        say(stream, library, Msg::PickOneOf { listed }).await?;
+                        tries += 1;
+                        continue;
+                    }
+                }
+                shelf = library.lookup_checkouts_by_guest(guest).await;
+                stale = true;
+            }
+        }
+
+        Command::CheckInAll => {
+            let checked_out = library.lookup_checkouts_by_guest(guest).await;
+            if checked_out.is_empty() {
+                say(stream, library, Msg::CheckOutFirst).await?;
+                return Ok(Passback::Continue);
+            }
+
+            let mut returned = 0;
+            for (book_id, _meta) in checked_out {
+                if session
+                    .last_checkout
+                    .is_some_and(|(last, _at)| last == book_id)
+                {
+                    session.last_checkout = None;
+                }
+                /* one going missing in the meantime shouldn't keep the rest */
+                let err = match library.checkin(book_id, guest) {
+                    Ok(_held) => {
+                        returned += 1;
+                        continue;
+                    }
+                    Err(err) => err,
+                };
+                let book = library.lookup_book_by_id(book_id).await;
+                let title = &book.title;
+                let msg = match err {
+                    UpdateEntryError::AlreadyCheckedIn => Msg::TitleAlreadyCheckedIn { title },
+                    UpdateEntryError::GuestMismatch => Msg::TitleCheckedOutBySomebodyElse { title },
+                    err @ (UpdateEntryError::AlreadyCheckedOut(_)
+                    | UpdateEntryError::Communal
+                    | UpdateEntryError::Removed
+                    | UpdateEntryError::NotTrashed
+                    | UpdateEntryError::BeingRead
+                    | UpdateEntryError::Protected) => return Err(err.into()),
+                };
+                say(stream, library, msg).await?;
+            }
+            say(stream, library, Msg::ReturnedBooks { count: returned }).await?;
+        }
+
+        Command::Read => {
+            let checked_out = readable_books(library, guest).await;
+            if checked_out.is_empty() {
+                say(stream, library, Msg::CheckOutFirst).await?;
+                return Ok(Passback::Continue);
+            }
+
+            say(stream, library, Msg::OldestCheckoutFirst).await?;
+            let choice = choose_entry(
+                stream,
+                library,
+                checked_out
+                    .iter()
+                    .map(|&(book, meta)| (1.0, book, meta, None)),
+                session.cols,
+            )
+            .await?;
+            if let Some(index) = picked(stream, library, choice).await? {
+                let (book_id, _meta) = checked_out[index];
+                read_checkout(stream, library, session, book_id).await?;
+            }
+        }
+
+        Command::Add => {
+            if library.at_contribution_limit(guest) {
+                say(stream, library, Msg::ContributionLimit).await?;
+                return Ok(Passback::Continue);
+            }
+            let mut title = String::new();
+            let mut author = String::new();
+            let mut description = String::new();
+
+            for (dst, prompt) in [
+                (&mut title, "Title? "),
+                (&mut author, "Author? "),
+                (&mut description, "Description? "),
+            ] {
+                /* a blank answer offers to stop, so any title can still be typed */
+                while dst.is_empty() {
+                    let answer = match readln_or_eof(stream, prompt).await? {
+                        Some(answer) if answer.is_empty() => {
+                            if !confirm(stream, library, "stop adding this book?", true).await? {
+                                continue;
+                            }
+                            None
+                        }
+                        answer => answer,
+                    };
+                    let Some(answer) = answer else {
+                        say(stream, library, Msg::Nevermind).await?;
+                        return Ok(Passback::Continue);
+                    };
+                    *dst = answer;
+                }
+            }
+
+            /* blank is English; "none" leaves it unsaid */
+            let mut tries = 0;
+            const MAX_TRIES: usize = 2;
+            let language = loop {
+                let answer = readln(stream, "Language? [en] ").await?;
+                if answer.is_empty() {
+                    break Some(String::from("en"));
+                }
+                if answer.eq_ignore_ascii_case("none") {
+                    break None;
+                }
+                if let Some(tag) = Book::language_tag(&answer) {
+                    break Some(tag);
+                }
+                say(stream, library, Msg::NotALanguage).await?;
+                tries += 1;
+                if MAX_TRIES <= tries {
+                    say(stream, library, Msg::Nevermind).await?;
+                    return Ok(Passback::Continue);
+                }
+            };
+
+            let draft = Draft {
+                title,
+                author,
+                description,
+                language,
+                lines: Vec::new(),
+            };
+            write_draft(stream, library, session, draft).await?;
+        }
+
+        Command::Upload => {
+            /* framed for scripts: no prompts, and a single line back */
+            let mut upload = Upload::new(Library::UPLOAD_MAX_LEN);
+            let hash = loop {
+                let Some(line) = read_text_line(stream, "", Library::UPLOAD_MAX_LEN).await? else {
+                    /* gone partway through; nothing was added */
+                    return Ok(Passback::Quit);
+                };
+                match upload::terminator(&line) {
+                    Some(hash) => break hash.to_string(),
+                    None => upload.push_line(&line),
+                }
+            };
+            let added = match upload.finish(&hash) {
+                Ok(book) => library
+                    .add_unique(book, guest)
+                    .await
+                    .map_err(|err| match err {
+                        AddError::ContributionLimit => UploadError::ContributionLimit,
+                        AddError::Duplicate(book_id) => UploadError::Duplicate(book_id),
+                    }),
+                Err(err) => Err(err),
+            };
+            let reply = match added {
+                Ok(book_id) => {
+                    tracing::info!(book = format_args!("{book_id}"), "uploaded a book");
+                    format!("OK {book_id}\n")
+                }
+                Err(err) => format!("ERR {err}\n"),
+            };
+            stream.write_all(reply.as_bytes()).await?;
+        }
+
+        Command::Resume => {
+            let Some(draft) = session.draft.take() else {
+                say(stream, library, Msg::NoDraft).await?;
+                return Ok(Passback::Continue);
+            };
+            write_draft(stream, library, session, draft).await?;
+        }
 
-    if num_items == 0 {
-        return Ok(None);
-    }
+        Command::Edit => {
+            let search = search(stream, library, session).await?;
+            let choice =
+                choose_entry(stream, library, search.iter().copied(), session.cols).await?;
+            let Some(index) = picked(stream, library, choice).await? else {
+                return Ok(Passback::Continue);
+            };
+            let (_sim, book_id, _meta, _matched) = search[index];
+            let rank = index + 1;
 
-    let min_rank = 1;
-    let max_rank = num_items;
+            const FIELDS: &[&str] = &["title", "author", "description", "content"];
+            let Some(field) = choose_one(stream, library, "which field?", FIELDS).await? else {
+                say(stream, library, Msg::Nevermind).await?;
+                return Ok(Passback::Continue);
+            };
+            let field = FIELDS[field];
+            if field == "content" {
+                edit_content(stream, library, session, book_id, rank).await?;
+                return Ok(Passback::Continue);
+            }
+            let prompt = format!("new {field}? ");
+            let value = readln(stream, &prompt).await?;
+            if value.is_empty() && field != "description" {
+                say(stream, library, Msg::Nevermind).await?;
+                return Ok(Passback::Continue);
+            }
 
-    match readln(stream, "which item number? ")
-        .await?
-        .parse::<usize>()
-        .map_err::<(Option<ParseIntError>, Option<RankError>), _>(|err| match err.kind() {
-            IntErrorKind::PosOverflow => (Some(err), Some(RankError::TooLarge)),
-            _ => (Some(err), None),
-        })
-        .and_then(|rank| {
-            if rank < min_rank {
-                Err((None, Some(RankError::TooSmall)))
-            } else if max_rank < rank {
-                Err((None, Some(RankError::TooLarge)))
-            } else {
-                Ok(rank)
+            let (mut title, mut author, mut description) = (None, None, None);
+            match field {
+                "title" => title = Some(value),
+                "author" => author = Some(value),
+                _ => description = Some(value),
             }
-        }) {
-        Ok(rank) => {
-            let index = rank.checked_sub(1).unwrap();
-            Ok(Some(index))
-        }
-        Err((_std_err, Some(our_err))) => {
-            stream.write_all(b"item number must be ").await?;
-            match our_err {
-                RankError::TooSmall => {
-                    stream
-                        .write_all(format!("at least {min_rank}.\n").as_bytes())
-                        .await?;
+            match library
+                .update_metadata_fields(book_id, title, author, description, guest)
+                .await
+            {
+                Ok(()) => {
+                    say(stream, library, Msg::Updated { field, rank }).await?;
+                }
+                Err(UpdateEntryError::GuestMismatch) => {
+                    say(stream, library, Msg::OnlyAdderEdits { rank }).await?;
                 }
-                RankError::TooLarge => {
-                    stream
-                        .write_all(format!("at most {max_rank}.\n").as_bytes())
-                        .await?;
+                Err(UpdateEntryError::Protected) => {
+                    say(stream, library, Msg::LibraryNotice { rank }).await?;
                 }
+                Err(UpdateEntryError::Removed) => {
+                    say(stream, library, Msg::TakenOffShelves { rank }).await?;
+                }
+                Err(
+                    err @ (UpdateEntryError::AlreadyCheckedOut(_)
+                    | UpdateEntryError::AlreadyCheckedIn
+                    | UpdateEntryError::Communal
+                    | UpdateEntryError::NotTrashed
+                    | UpdateEntryError::BeingRead),
+                ) => return Err(err.into()),
             }
-            Ok(None)
         }
-        Err((Some(std_err), _our_err)) => match std_err.kind() {
-            IntErrorKind::Empty => Ok(None),
-            _ => {
-                stream.write_all(format!("{std_err}.\n").as_bytes()).await?;
-                Ok(None)
+
+        Command::Undo => match session.last_checkout.take() {
+            None => {
+                say(stream, library, Msg::NothingToUndo).await?;
+            }
+            Some((_book_id, at)) if Session::UNDO_WINDOW < at.elapsed() => {
+                say(stream, library, Msg::TooLateToUndo).await?;
+            }
+            Some((book_id, _at)) => {
+                let book = library.lookup_book_by_id(book_id).await;
+                let title = &book.title;
+                match library.undo_checkout(book_id, guest) {
+                    Ok(()) => say(stream, library, Msg::UndidCheckout { title }).await?,
+                    Err(_) => say(stream, library, Msg::NoLongerYours { title }).await?,
+                }
             }
         },
-        Err((None, None)) => unreachable!(),
-    }
-}
-
-pub async fn choose_entry<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
-    stream: &mut S,
-    library: &Library,
-    entries: impl ExactSizeIterator<Item = (f64, BookID, Metadata)>,
-) -> anyhow::Result<Option<usize>> {
-    let len = entries.len();
-    enumerate_entries(stream, library, entries).await?;
-    choose_rank(stream, len).await
-}
-
-pub async fn search<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
-    stream: &mut S,
-    library: &Library,
-) -> anyhow::Result<(String, Vec<(f64, BookID, Metadata)>)> {
-    let query = readln(stream, "search query? ").await?;
-    let search = library.search(&query).await;
 
-    if search.is_empty() {
-        if query.is_empty() {
-            stream.write_all(b"the library is empty!\n").await?;
-        } else {
-            stream.write_all(b"no matching books!\n").await?;
+        Command::Login => {
+            let token = readln(stream, TOKEN_PROMPT).await?;
+            match library.login(&token).await {
+                Some(guest) => {
+                    session.guest = guest;
+                    session.last_checkout = None;
+                    let nick = library.lookup_nick(guest).await.unwrap_or_default();
+                    say(stream, library, Msg::NowYou { nick: &nick }).await?;
+                }
+                None => say(stream, library, Msg::UnknownToken).await?,
+            }
         }
-    }
-
-    Ok((query, search))
-}
 
-pub async fn do_cmd<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
-    stream: &mut S,
-    cmd: Command,
-    library: &Library,
-    guest: IpAddr,
-) -> anyhow::Result<Passback> {
-    tracing::trace!(cmd = format_args!("{cmd:?}"), "received command");
+        Command::Export => {
+            let books = readable_books(library, guest).await;
+            if books.is_empty() {
+                say(stream, library, Msg::CheckOutFirst).await?;
+                return Ok(Passback::Continue);
+            }
 
-    match cmd {
-        Command::None => {}
+            say(stream, library, Msg::OldestCheckoutFirst).await?;
+            let choice = choose_entry(
+                stream,
+                library,
+                books.iter().map(|&(book, meta)| (1.0, book, meta, None)),
+                session.cols,
+            )
+            .await?;
+            if let Some(index) = picked(stream, library, choice).await? {
+                let (book_id, _meta) = books[index];
+                let book = library.lookup_book_by_id(book_id).await;
+                stream
+                    .write_all(export::write_block(&book).as_bytes())
+                    .await?;
+            }
+        }
 
-        Command::Help => {
-            // NOTE: assumes Command names are single-width ASCII ⊆ Unicode characters (which is correct, now).
-            let short_long_len = Command::ALL
-                .iter()
-                .map(|cmd| cmd.short().len() + cmd.long().len())
-                .max()
-                .expect("Command::ALL must not be empty");
-            for cmd in Command::ALL {
-                const EXTRA: usize = 8;
-                let padding = (short_long_len - (cmd.short().len() + cmd.long().len())) + EXTRA;
-                let help_text = match cmd {
-                    Command::None => "doesn't do anything.",
-                    Command::Help => "ask for assistance.",
-                    Command::Quit => "Abandon all Data.",
-                    Command::Search => "search the library.",
-                    Command::CheckOut => "acquire a book, if it is available!",
-                    Command::CheckIn => "return a book.",
-                    Command::Read => "peruse your checked out books.",
-                    Command::Add => "add a New Book to the library's collection.",
-                    Command::Meow => "(warning: meows at you).",
-                };
+        Command::Delete
+        | Command::Trash
+        | Command::Protect
+        | Command::Admin
+        | Command::Guests
+        | Command::Merge
+        | Command::Announce
+        | Command::Dashboard
+        | Command::Records
+        | Command::Reload
+            if guest != Library::OPERATOR =>
+        {
+            say(stream, library, Msg::OperatorOnly).await?;
+        }
 
-                stream.write_all(cmd.short().as_bytes()).await?;
-                stream.write_all(b", ").await?;
-                stream.write_all(cmd.long().as_bytes()).await?;
-                for _ in 0..padding {
-                    stream.write_all(b" ").await?;
+        Command::Delete => {
+            let search = search(stream, library, session).await?;
+            let choice =
+                choose_entry(stream, library, search.iter().copied(), session.cols).await?;
+            let Some(index) = picked(stream, library, choice).await? else {
+                return Ok(Passback::Continue);
+            };
+            let (_sim, book_id, _meta, _matched) = search[index];
+            let rank = index + 1;
+            match library.soft_delete(book_id, library.now(), guest) {
+                Ok(()) => say(stream, library, Msg::Trashed { rank }).await?,
+                Err(UpdateEntryError::Removed) => {
+                    say(stream, library, Msg::AlreadyGone { rank }).await?;
                 }
-                stream.write_all(help_text.as_bytes()).await?;
-                stream.write_all(b"\n").await?;
+                Err(UpdateEntryError::BeingRead) => {
+                    say(stream, library, Msg::BeingRead { rank }).await?;
+                }
+                Err(err) => return Err(err.into()),
             }
         }
 
-        Command::Search => {
-            let (_query, search) = search(stream, library).await?;
-            enumerate_entries(stream, library, search.iter().copied()).await?;
+        Command::Protect => {
+            let search = search(stream, library, session).await?;
+            let choice =
+                choose_entry(stream, library, search.iter().copied(), session.cols).await?;
+            let Some(index) = picked(stream, library, choice).await? else {
+                return Ok(Passback::Continue);
+            };
+            let (_sim, book_id, _meta, _matched) = search[index];
+            let rank = index + 1;
+            let msg = if library.toggle_protected(book_id) {
+                Msg::Protected { rank }
+            } else {
+                Msg::Unprotected { rank }
+            };
+            say(stream, library, msg).await?;
         }
 
-        Command::Quit => return Ok(Passback::Quit),
+        Command::Trash => {
+            let trash = library.trash();
+            if trash.is_empty() {
+                say(stream, library, Msg::TrashEmpty).await?;
+                return Ok(Passback::Continue);
+            }
+            for (idx, &(book_id, at)) in trash.iter().enumerate() {
+                let rank = idx + 1;
+                let book = library.lookup_book_by_id(book_id).await;
+                stream
+                    .write_all(
+                        format!(
+                            "{rank}. '{}', by {}. (trashed {})\n",
+                            book.title,
+                            book.author,
+                            date::format_date(at)
+                        )
+                        .as_bytes(),
+                    )
+                    .await?;
+            }
 
-        Command::CheckOut => {
-            let (_query, search) = search(stream, library).await?;
-            if let Some(index) = choose_entry(stream, library, search.iter().copied()).await? {
-                let (_sim, book_id, _meta) = search[index];
-                let rank = index + 1;
-                match library.checkout(book_id, guest) {
-                    Ok(()) => {
-                        stream
-                            .write_all(format!("checked out item {rank}!\n").as_bytes())
-                            .await?;
-                    }
-                    Err(err) => match err {
-                        UpdateEntryError::AlreadyCheckedOut(by) => {
-                            stream
-                                .write_all(format!("item {rank} is already checked out").as_bytes())
-                                .await?;
-                            if let Some(by_nick) = library.lookup_guest_by_addr(by).await {
-                                stream
-                                    .write_all(format!(" by '{by_nick}'").as_bytes())
-                                    .await?;
-                            }
-                            stream.write_all(b".\n").await?;
-                        }
-                        UpdateEntryError::GuestMismatch | UpdateEntryError::AlreadyCheckedIn => {
-                            unreachable!()
-                        }
-                    },
+            let answer = readln(stream, "item number to restore, or 'empty'? ").await?;
+            if answer == "empty" {
+                let count = library.purge_trash(library.now(), Duration::ZERO).await;
+                say(stream, library, Msg::Purged { count }).await?;
+                return Ok(Passback::Continue);
+            }
+            let restored = answer
+                .parse::<usize>()
+                .ok()
+                .and_then(|rank| trash.get(rank.checked_sub(1)?))
+                .map(|&(book_id, _at)| library.restore(book_id));
+            match restored {
+                Some(Ok(())) => say(stream, library, Msg::Restored { rank: &answer }).await?,
+                Some(Err(_)) => {
+                    say(stream, library, Msg::NotInTrash { rank: &answer }).await?;
                 }
-            } else {
-                stream.write_all(b"nevermind.\n").await?;
+                None => say(stream, library, Msg::Nevermind).await?,
             }
         }
 
-        Command::CheckIn => {
-            let checked_out: Vec<(BookID, Metadata)> =
-                library.lookup_checkouts_by_guest(guest).await;
-            if checked_out.is_empty() {
-                stream.write_all(b"check out some books first!\n").await?;
+        Command::Admin => {
+            let line = readln(stream, "ban <nick> or unban <nick>? ").await?;
+            let (action, nick) = line.split_once(' ').unwrap_or((&line, ""));
+            let nick = nick.trim();
+            if !matches!(action, "ban" | "unban") || nick.is_empty() {
+                say(stream, library, Msg::Nevermind).await?;
                 return Ok(Passback::Continue);
             }
-
-            if let Some(index) = choose_entry(
-                stream,
-                library,
-                checked_out.iter().map(|&(book, meta)| (1.0, book, meta)),
-            )
-            .await?
-            {
-                let (book_id, _meta) = checked_out[index];
-                let rank = index + 1;
-                match library.checkin(book_id, guest) {
-                    Ok(()) => {
-                        stream
-                            .write_all(format!("returned item {rank}.\n").as_bytes())
-                            .await?;
-                    }
-                    Err(err) => match err {
-                        UpdateEntryError::AlreadyCheckedIn => {
-                            stream
-                                .write_all(
-                                    format!("item {rank} is already checked in.\n").as_bytes(),
-                                )
-                                .await?;
-                        }
-                        UpdateEntryError::GuestMismatch => {
-                            stream
-                                .write_all(
-                                    format!("item {rank} is checked out by somebody else.\n")
-                                        .as_bytes(),
-                                )
-                                .await?;
-                        }
-                        UpdateEntryError::AlreadyCheckedOut(_) => unreachable!(),
-                    },
+            let Some(guest) = library.lookup_guest_by_nick(nick).await else {
+                say(stream, library, Msg::NobodyCalled { nick }).await?;
+                return Ok(Passback::Continue);
+            };
+            let reply = match action {
+                "ban" if guest == Library::OPERATOR => Msg::CantBanOperator,
+                "ban" if library.ban_guest(guest).await => {
+                    tracing::info!(nick, "banned a guest");
+                    Msg::Banned { nick }
                 }
+                "ban" => Msg::AlreadyBanned { nick },
+                _ if library.unban_guest(guest).await => {
+                    tracing::info!(nick, "unbanned a guest");
+                    Msg::Unbanned { nick }
+                }
+                _ => Msg::NotBanned { nick },
+            };
+            say(stream, library, reply).await?;
+        }
+
+        Command::Guests => {
+            let count = library.metrics().await.guests;
+            let limit = library.guest_limit();
+            let msg = if limit == usize::MAX {
+                Msg::GuestCount { count }
             } else {
-                stream.write_all(b"nevermind.\n").await?;
+                Msg::GuestCountOf { count, limit }
+            };
+            say(stream, library, msg).await?;
+            for (reader, book_id) in library.readers() {
+                let nick = library.lookup_nick(reader).await.unwrap_or_default();
+                let book = library.lookup_book_by_id(book_id).await;
+                let title = &book.title;
+                say(stream, library, Msg::GuestReading { nick: &nick, title }).await?;
+            }
+            let stale_after = library.guest_stale_after();
+            let days = stale_after.as_secs() / (24 * 60 * 60);
+            let prompt = format!("prune guests idle for {days} days or more?");
+            if !confirm(stream, library, &prompt, false).await? {
+                say(stream, library, Msg::Nevermind).await?;
+                return Ok(Passback::Continue);
             }
+            let now = library.now();
+            let evicted = library
+                .evict_stale(now.checked_sub(stale_after).unwrap_or(now))
+                .await;
+            tracing::info!(evicted, "pruned idle guests");
+            say(stream, library, Msg::Evicted { count: evicted }).await?;
         }
 
-        Command::Read => {
-            let checked_out: Vec<(BookID, Metadata)> =
-                library.lookup_checkouts_by_guest(guest).await;
-            if checked_out.is_empty() {
-                stream.write_all(b"check out some books first!\n").await?;
+        Command::Merge => {
+            /* nicknames may have spaces, so `into` can mark where one ends */
+            let line = readln(stream, "merge <nick> into <nick>? ").await?;
+            let (from, into) = line
+                .split_once(" into ")
+                .or_else(|| line.split_once(' '))
+                .unwrap_or((&line, ""));
+            let (from, into) = (from.trim(), into.trim());
+            if from.is_empty() || into.is_empty() {
+                say(stream, library, Msg::Nevermind).await?;
+                return Ok(Passback::Continue);
+            }
+            let mut ids = [None; 2];
+            for (id, nick) in ids.iter_mut().zip([from, into]) {
+                *id = library.lookup_guest_by_nick(nick).await;
+                if id.is_none() {
+                    say(stream, library, Msg::NobodyCalled { nick }).await?;
+                    return Ok(Passback::Continue);
+                }
+            }
+            let [Some(from_id), Some(into_id)] = ids else {
+                unreachable!("both were looked up");
+            };
+
+            /* a dry run first, to see what would move */
+            let merged = match library.merge_guests(from_id, into_id, true).await {
+                Ok(merged) => merged,
+                Err(err) => {
+                    say(stream, library, merge_refusal(err, from)).await?;
+                    return Ok(Passback::Continue);
+                }
+            };
+            say(stream, library, Msg::MergePlan { from, into, merged }).await?;
+            if !confirm(stream, library, "merge them?", false).await? {
+                say(stream, library, Msg::Nevermind).await?;
                 return Ok(Passback::Continue);
             }
+            let msg = match library.merge_guests(from_id, into_id, false).await {
+                Ok(merged) => {
+                    tracing::info!(from, into, ?merged, "merged guests");
+                    Msg::MergedGuests { from, into }
+                }
+                Err(err) => merge_refusal(err, from),
+            };
+            say(stream, library, msg).await?;
+        }
 
-            if let Some(index) = choose_entry(
-                stream,
-                library,
-                checked_out.iter().map(|&(book, meta)| (1.0, book, meta)),
-            )
-            .await?
-            {
-                let (book_id, meta) = checked_out[index];
-                let book: &Book = &*library.lookup_book_by_id(book_id).await;
-                editor::read_book(stream, library, book, meta).await?;
-            } else {
-                stream.write_all(b"nevermind.\n").await?;
+        Command::Announce => {
+            let message = readln(stream, "announcement? ").await?;
+            match library.announce(&message) {
+                Some(text) => {
+                    tracing::info!(text = &*text, "announced");
+                    say(stream, library, Msg::Announced).await?;
+                }
+                None => say(stream, library, Msg::Nevermind).await?,
             }
         }
 
-        Command::Add => {
-            let mut title = String::new();
-            let mut author = String::new();
-            let mut description = String::new();
+        Command::Dashboard => {
+            let mut height = 0;
+            loop {
+                let lines = Dashboard::gather(library).await.render();
+                redraw_region(stream, height, &lines).await?;
+                let Some(answer) = readln_or_eof(stream, "-- enter refreshes, q quits --").await?
+                else {
+                    break;
+                };
+                if answer == "q" {
+                    break;
+                }
+                /* the prompt's line is redrawn too */
+                height = lines.len() + 1;
+            }
+        }
 
-            for (dst, prompt) in [
-                (&mut title, "Title? "),
-                (&mut author, "Author? "),
-                (&mut description, "Description? "),
-            ] {
-                let mut tries = 0;
-                const MAX_TRIES: usize = 2;
-                while dst.is_empty() {
-                    if MAX_TRIES <= tries {
-                        stream.write_all(b"nevermind.\n").await?;
-                        return Ok(Passback::Continue);
-                    }
+        Command::Records => {
+            let answer = readln(stream, "Format, csv or json? [csv] ").await?;
+            let format = match answer.as_str() {
+                "" => Some(Format::Csv),
+                name => Format::from_name(name),
+            };
+            let Some(format) = format else {
+                say(stream, library, Msg::UnknownRecordFormat).await?;
+                return Ok(Passback::Continue);
+            };
+            let records = records::gather(library).await;
+            stream
+                .write_all(records::write_block(format, &records).as_bytes())
+                .await?;
+        }
 
-                    *dst = readln(stream, prompt).await?;
-                    tries += 1;
+        Command::Reload => {
+            let Some(path) = library.data_file() else {
+                say(stream, library, Msg::NoDataFile).await?;
+                return Ok(Passback::Continue);
+            };
+            let msg = match snapshot::reload_from(library, path).await {
+                Ok(done) => Msg::Reloaded {
+                    added: done.added,
+                    updated: done.updated,
+                    removed: done.removed,
+                    returned: done.returned.len(),
+                },
+                Err(err) => {
+                    tracing::warn!("couldn't reload: {err:#}");
+                    Msg::ReloadFailed
                 }
+            };
+            say(stream, library, msg).await?;
+        }
+
+        Command::History => {
+            let width = session
+                .history
+                .iter()
+                .last()
+                .map_or(1, |(n, _)| n.to_string().len());
+            for (n, line) in session.history.iter() {
+                stream
+                    .write_all(format!("{n:>width$}  {line}\n").as_bytes())
+                    .await?;
             }
+        }
 
-            let mut lines = Vec::new();
-            let mut content = String::new();
-            {
-                let mut editor = Editor::new(&mut lines, false);
-                editor.enter(stream).await?;
+        Command::Alias => {
+            let line = readln(stream, "<name> <command line>? ").await?;
+            if line.is_empty() {
+                return Ok(Passback::Continue);
             }
-            for line in lines {
-                content.push_str(&line);
-                content.push_str("\n");
+            let (name, expansion) = line.split_once(' ').unwrap_or((&line, ""));
+            let msg = match session.aliases.set(name, expansion) {
+                Ok(()) => Msg::AliasSet {
+                    name,
+                    expansion: expansion.trim(),
+                },
+                Err(AliasError::InvalidName) => Msg::InvalidAlias,
+                Err(AliasError::Reserved) => Msg::AlreadyACommand { name },
+                Err(AliasError::EmptyExpansion) => Msg::ShortForWhat { name },
+            };
+            say(stream, library, msg).await?;
+        }
+
+        Command::Prompt => {
+            let template = readln(stream, "new prompt? ").await?;
+            if template.is_empty() {
+                session.prompt = Prompt::default();
+                say(stream, library, Msg::PromptReset).await?;
+                return Ok(Passback::Continue);
             }
+            let msg = match Prompt::new(&template) {
+                Ok(prompt) => {
+                    session.prompt = prompt;
+                    Msg::PromptSet
+                }
+                Err(PromptError::Invalid) => Msg::InvalidPrompt,
+                Err(PromptError::TooLong) => Msg::TooLong {
+                    max: Prompt::MAX_LEN,
+                },
+            };
+            say(stream, library, msg).await?;
+        }
 
-            stream.write_all(b"adding the book '").await?;
-            stream.write_all(title.as_bytes()).await?;
-            stream.write_all(b"'...").await?;
-            stream.flush().await?;
+        Command::Size => {
+            let answer = readln(stream, "columns x rows? ").await?;
+            if answer.is_empty() {
+                (session.cols, session.rows) = (None, None);
+                say(stream, library, Msg::SizeReset).await?;
+                return Ok(Passback::Continue);
+            }
+            let msg = match parse_size(&answer) {
+                Some((cols, rows)) => {
+                    (session.cols, session.rows) = (Some(cols), Some(rows));
+                    Msg::SizeSet { cols, rows }
+                }
+                None => Msg::InvalidSize,
+            };
+            say(stream, library, msg).await?;
+        }
 
-            let book = Book {
-                title,
-                author,
-                description,
-                content,
+        Command::Notify => {
+            let answer = readln(stream, "on or off? ").await?;
+            let msg = match answer.to_ascii_lowercase().as_str() {
+                "on" => {
+                    library.set_notify(guest, true);
+                    Msg::NotifyOn
+                }
+                "off" => {
+                    library.set_notify(guest, false);
+                    Msg::NotifyOff
+                }
+                "" if library.notifies(guest) => Msg::NotifyOn,
+                "" => Msg::NotifyOff,
+                _ => Msg::AnswerOnOrOff,
             };
-            library.add(book, guest).await;
-            stream.write_all(b"done!\n").await?;
+            say(stream, library, msg).await?;
+        }
+
+        Command::Unalias => {
+            let name = readln(stream, "which alias? ").await?;
+            if session.aliases.remove(&name).is_some() {
+                say(stream, library, Msg::ForgotAlias { name: &name }).await?;
+            } else {
+                say(stream, library, Msg::NoSuchAlias).await?;
+            }
+        }
+
+        Command::Sign => {
+            let message = readln(stream, "your message? ").await?;
+            if message.is_empty() {
+                say(stream, library, Msg::Nevermind).await?;
+                return Ok(Passback::Continue);
+            }
+            match library
+                .append_guestbook(guest, &message, library.now())
+                .await
+            {
+                Ok(()) => say(stream, library, Msg::Signed).await?,
+                Err(GuestbookError::NoGuestbook) => say(stream, library, Msg::NoGuestbook).await?,
+                Err(GuestbookError::Empty) => say(stream, library, Msg::Nevermind).await?,
+                Err(GuestbookError::TooLong) => {
+                    let max = Library::GUESTBOOK_MAX_LEN;
+                    say(stream, library, Msg::TooLong { max }).await?;
+                }
+                Err(GuestbookError::DailyLimit) => say(stream, library, Msg::DailyLimit).await?,
+            }
         }
 
         Command::Meow => {
-            stream.write_all(b"meow?\n").await?;
+            const VARIANTS: &[&str] = &["meow!", "mrrp", "prrr"];
+            /* the line that got us here; more meows get more back */
+            let meows = session
+                .history
+                .recall(Recall::Last)
+                .and_then(Command::meows)
+                .unwrap_or(1)
+                .min(Command::MAX_MEOWS);
+            let mut out = "\x07".repeat(meows);
+            for idx in 0..meows {
+                if idx != 0 {
+                    out.push(' ');
+                }
+                let noise = library.random() as usize;
+                let variant = VARIANTS[noise % VARIANTS.len()];
+                if meows == Command::MAX_MEOWS {
+                    out.push_str(&variant.to_uppercase());
+                } else {
+                    out.push_str(variant);
+                }
+            }
+            out.push('\n');
+            stream.write_all(out.as_bytes()).await?;
         }
     }
 
     Ok(Passback::Continue)
 }
+
+/// How much of a connection to buffer each way, in bytes.
+///
+/// Bigger write buffers send a long book in fewer, larger writes, but every
+/// connection holds on to both buffers for as long as it's open. Writes at
+/// least as big as the write buffer skip it and go straight to the socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferSizes {
+    pub read: usize,
+    pub write: usize,
+}
+
+impl BufferSizes {
+    /// What [`BufStream::new`] uses.
+    pub const DEFAULT: usize = 8 * 1024;
+
+    /// `stream`, buffered this much.
+    pub fn wrap<S: AsyncRead + AsyncWrite>(self, stream: S) -> BufStream<S> {
+        BufStream::with_capacity(self.read, self.write, stream)
+    }
+}
+
+impl Default for BufferSizes {
+    fn default() -> Self {
+        Self {
+            read: Self::DEFAULT,
+            write: Self::DEFAULT,
+        }
+    }
+}
+
+/// Serve one guest until they quit or hang up.
+///
+/// [`readln`] flushes before every read, and whatever is left when the
+/// session ends (e.g. a parting message) is flushed here, so nothing else
+/// needs to remember to.
+pub async fn process_socket<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    addr: SocketAddr,
+    library: &Library,
+    caps: Capabilities,
+) -> ShellResult<()> {
+    if library.is_banned(addr.ip()) {
+        tracing::info!("turned away a banned address");
+        say(stream, library, Msg::NotWelcome).await?;
+        return Ok(stream.flush().await?);
+    }
+    if library.is_soft_banned(addr.ip()) {
+        tracing::info!("turned away a soft-banned address");
+        say(stream, library, Msg::SoftBanned).await?;
+        return Ok(stream.flush().await?);
+    }
+    let result = shell_loop(stream, addr, library, caps).await;
+    let flushed = stream.flush().await;
+    result?;
+    Ok(flushed?)
+}
+
+/// `session`'s prompt, as it should look right now.
+async fn render_prompt(library: &Library, session: &Session) -> String {
+    let nick = if session.is_registered() {
+        library.lookup_nick(session.guest).await.unwrap_or_default()
+    } else {
+        Arc::from(session.visitor.as_deref().unwrap_or_default())
+    };
+    let checkouts = if session.prompt.shows_checkouts() {
+        library.lookup_checkouts_by_guest(session.guest).await.len()
+    } else {
+        0
+    };
+    session.prompt.render(&nick, checkouts)
+}
+
+async fn shell_loop<S: AsyncRead + AsyncBufReadExt + AsyncWrite + Unpin>(
+    stream: &mut S,
+    addr: SocketAddr,
+    library: &Library,
+    caps: Capabilities,
+) -> ShellResult<()> {
+    /* returning guests are known by address; new ones may look around first */
+    let mut session = match library.lookup_guest_by_addr(addr.ip()).await {
+        Some(_) if caps.register => {
+            let guest = register_guest(stream, library, addr)
+                .await
+                .context("failed to register guest")?;
+            Session::new(guest)
+        }
+        None if caps.register => {
+            say(stream, library, Msg::Browsing).await?;
+            Session::browsing(addr)
+        }
+        _ => {
+            say(stream, library, Msg::BrowsingMirror).await?;
+            Session::browsing(addr)
+        }
+    };
+    session.caps = caps;
+    session.notices = Some(Notices::new(library));
+    let visit = library.visit(session.guest);
+
+    loop {
+        if let Some(notices) = &mut session.notices {
+            notices.drain(stream, library, session.guest).await?;
+        }
+        let prompt = render_prompt(library, &session).await;
+        let mut try_cmd = readln(stream, &prompt).await?;
+        if let Some(recall) = Recall::parse(&try_cmd) {
+            let Some(line) = session.history.recall(recall) else {
+                say(stream, library, Msg::NoSuchHistoryEntry).await?;
+                continue;
+            };
+            try_cmd = line.to_string();
+            /* show what's about to run */
+            stream.write_all(try_cmd.as_bytes()).await?;
+            stream.write_all(b"\n").await?;
+        } else if !try_cmd.is_empty() {
+            session.history.push(try_cmd.clone());
+        }
+
+        if let Some(expanded) = session.aliases.expand(&try_cmd) {
+            try_cmd = expanded;
+            /* show what's about to run */
+            stream.write_all(try_cmd.as_bytes()).await?;
+            stream.write_all(b"\n").await?;
+        }
+
+        if let Some((cmd, rest)) = Command::parse_line(&try_cmd) {
+            library.forgive(addr.ip());
+            let activity = (cmd != Command::None).then(|| cmd.long());
+            visit.update(session.guest, activity);
+            match do_cmd_typed_ahead(stream, cmd, rest, library, &mut session).await? {
+                Passback::Continue => {}
+                Passback::Quit => return Ok(()),
+            }
+            visit.update(session.guest, None);
+        } else {
+            say(stream, library, Msg::UnknownCommand).await?;
+            strike(stream, library, addr).await?;
+        }
+    }
+}
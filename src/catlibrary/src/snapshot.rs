@@ -0,0 +1,395 @@
+//! The books on the shelves, saved to a file every so often, so a crash
+//! loses no more than the last few minutes of them.
+//!
+//! A snapshot starts with a `Snapshot: <n> books` line, then has each book
+//! as an [`export`] block. Before a block may come a `Notice` line, if it's a
+//! library notice, an `Added-By: <nickname>` line, if a guest added it, and a
+//! `Trashed: <timestamp>` line, if it's in the trash.
+//!
+//! Guests and checkouts aren't saved. A loaded book is the operator's, and
+//! the nickname that added it is only shown: whoever registers it next proves
+//! nothing about who they were before the restart.
+//!
+//! The operator may edit a snapshot by hand and [`reload`] it while guests
+//! are connected.
+//!
+//! Each save goes to `<path>.tmp` first and is renamed over `<path>`, so the
+//! file is never half written. The copy it replaces becomes `<path>.1`, the
+//! one before that `<path>.2`, and so on, as many as are kept.
+
+use anyhow::Context;
+use core::time::Duration;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+
+use crate::date;
+use crate::export;
+use crate::library::{collapse_whitespace, Book, BookID, Library, Reshelve, Reshelved};
+
+/// How often to save, if not set otherwise.
+pub const DEFAULT_EVERY: Duration = Duration::from_secs(5 * 60);
+/// How many older snapshots to keep, if not set otherwise.
+pub const DEFAULT_KEEP: usize = 3;
+
+const HEADER: &str = "Snapshot: ";
+const NOTICE: &str = "Notice";
+const ADDED_BY: &str = "Added-By: ";
+const TRASHED: &str = "Trashed: ";
+
+/// One book in a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub book: Book,
+    /// Whether it's a library notice.
+    pub notice: bool,
+    /// The nickname of the guest who added it, if it wasn't the operator.
+    pub added_by: Option<String>,
+    /// When it went in the trash, if it's there.
+    pub trashed: Option<SystemTime>,
+}
+
+/// Every book on `library`'s shelves or in its trash but the communal ones,
+/// oldest first.
+pub async fn gather(library: &Library) -> Vec<Entry> {
+    gather_ids(library)
+        .await
+        .into_iter()
+        .map(|(_book_id, entry)| entry)
+        .collect()
+}
+
+/// Like [`gather`], with where each book is in `library`.
+async fn gather_ids(library: &Library) -> Vec<(BookID, Entry)> {
+    let trash = library.trash();
+    let mut book_ids = library.shelved();
+    book_ids.extend(trash.iter().map(|&(book_id, _at)| book_id));
+    book_ids.sort_unstable();
+
+    let mut entries = Vec::new();
+    for book_id in book_ids {
+        let meta = library.lookup_metadata(book_id);
+        if meta.communal {
+            continue;
+        }
+        /* a save goes over every book; it mustn't push out what's being read */
+        let book = library.lookup_book_uncached(book_id).await;
+        let entry = Entry {
+            book: Book::clone(&book),
+            notice: meta.protected,
+            added_by: library
+                .added_by_nick(book_id)
+                .await
+                .map(|nick| nick.to_string()),
+            trashed: trash
+                .iter()
+                .find(|&&(trashed, _at)| trashed == book_id)
+                .map(|&(_, at)| at),
+        };
+        entries.push((book_id, entry));
+    }
+    entries
+}
+
+/// Put `entries` back in `library`, on the shelves or in the trash as they
+/// were, each added by the nickname that added it.
+pub async fn restore(library: &Library, entries: Vec<Entry>) {
+    for entry in entries {
+        let book_id = match &entry.added_by {
+            Some(nick) => library.add_as(entry.book, nick).await,
+            None => library
+                .add(entry.book, Library::OPERATOR)
+                .await
+                .expect("the operator has no contribution limit"),
+        };
+        if entry.notice {
+            library.toggle_protected(book_id);
+        }
+        if let Some(at) = entry.trashed {
+            library
+                .soft_delete(book_id, at, Library::OPERATOR)
+                .expect("nobody is reading what was only just added");
+        }
+    }
+}
+
+/// Make `library` match `entries`, as [`read`] from a snapshot the operator
+/// edited by hand, without a restart. A book is the same one if its title and
+/// author are; one that's changed otherwise is updated in place, and
+/// checkouts, reading and bookmarks carry on. Books the snapshot doesn't have
+/// go away for good, returned first if anyone had them out, which is logged.
+/// Guests and who added what aren't changed, as snapshots don't say.
+pub async fn reload(library: &Library, entries: Vec<Entry>) -> Reshelved {
+    let mut live: Vec<Option<(BookID, Entry)>> =
+        gather_ids(library).await.into_iter().map(Some).collect();
+    let mut changes = Vec::new();
+    for entry in entries {
+        let title = collapse_whitespace(&entry.book.title);
+        let author = collapse_whitespace(&entry.book.author);
+        let same = live
+            .iter_mut()
+            .find(|had| {
+                had.as_ref()
+                    .is_some_and(|(_, had)| had.book.title == title && had.book.author == author)
+            })
+            .and_then(Option::take);
+        match same {
+            Some((_, had))
+                if had.book == entry.book
+                    && had.notice == entry.notice
+                    /* as saved, to the second */
+                    && had.trashed.map(date::format_timestamp)
+                        == entry.trashed.map(date::format_timestamp) => {}
+            Some((book_id, _)) => changes.push(Reshelve::Update {
+                book_id,
+                book: entry.book,
+                notice: entry.notice,
+                trashed: entry.trashed,
+            }),
+            None => changes.push(Reshelve::Add {
+                book: entry.book,
+                added_by: entry.added_by,
+                notice: entry.notice,
+                trashed: entry.trashed,
+            }),
+        }
+    }
+    changes.extend(
+        live.into_iter()
+            .flatten()
+            .map(|(book_id, _)| Reshelve::Remove(book_id)),
+    );
+
+    let done = library.reshelve(changes).await;
+    for &(book_id, guest) in &done.returned {
+        let nick = library.lookup_nick(guest).await;
+        tracing::warn!(
+            book = format_args!("{book_id}"),
+            guest = format_args!("{}", nick.as_deref().unwrap_or("?")),
+            "returned a checkout the reloaded snapshot took off the shelves"
+        );
+    }
+    done
+}
+
+/// [`reload`] `library` from the snapshot at `path`, and only that one: a
+/// file broken by hand is refused rather than swapped for an older copy.
+pub async fn reload_from(library: &Library, path: &Path) -> anyhow::Result<Reshelved> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("couldn't read {}", path.display()))?;
+    let entries =
+        read(&text).with_context(|| format!("{} is corrupt or cut short", path.display()))?;
+    Ok(reload(library, entries).await)
+}
+
+/// `entries` as a snapshot, ready to [`save`].
+pub fn write(entries: &[Entry]) -> String {
+    let mut out = format!("{HEADER}{} books\n", entries.len());
+    for entry in entries {
+        if entry.notice {
+            out.push_str(NOTICE);
+            out.push('\n');
+        }
+        if let Some(nick) = &entry.added_by {
+            out.push_str(&format!("{ADDED_BY}{nick}\n"));
+        }
+        if let Some(at) = entry.trashed {
+            out.push_str(&format!("{TRASHED}{}\n", date::format_timestamp(at)));
+        }
+        out.push_str(&export::write_block(&entry.book));
+    }
+    out
+}
+
+/// Parse what [`write`] wrote, or `None` if it's malformed or cut short.
+pub fn read(text: &str) -> Option<Vec<Entry>> {
+    let mut lines = text.lines();
+    let count: usize = lines
+        .next()?
+        .strip_prefix(HEADER)?
+        .strip_suffix(" books")?
+        .parse()
+        .ok()?;
+
+    let mut entries = Vec::new();
+    while let Some(mut line) = lines.next() {
+        let (mut notice, mut added_by, mut trashed) = (false, None, None);
+        while line != export::BEGIN {
+            if line == NOTICE {
+                notice = true;
+            } else if let Some(nick) = line.strip_prefix(ADDED_BY) {
+                added_by = Some(nick.to_string());
+            } else if let Some(at) = line.strip_prefix(TRASHED) {
+                trashed = Some(date::parse_timestamp(at)?);
+            } else {
+                return None;
+            }
+            line = lines.next()?;
+        }
+        let mut block = format!("{}\n", export::BEGIN);
+        for line in lines.by_ref() {
+            block.push_str(line);
+            block.push('\n');
+            if line == export::END {
+                break;
+            }
+        }
+        let book = export::read_block(&block)?;
+        entries.push(Entry {
+            book,
+            notice,
+            added_by,
+            trashed,
+        });
+    }
+    (entries.len() == count).then_some(entries)
+}
+
+/// `path` with `.<suffix>` on the end.
+pub fn sibling(path: &Path, suffix: impl core::fmt::Display) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{suffix}"));
+    PathBuf::from(name)
+}
+
+/// Write `text` over `path` in one go, keeping the last `keep` versions of
+/// it as `<path>.1` (the newest) to `<path>.<keep>`.
+pub fn save(path: &Path, text: &str, keep: usize) -> io::Result<()> {
+    let tmp = sibling(path, "tmp");
+    {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+    }
+
+    if keep != 0 && path.exists() {
+        for n in (1..keep).rev() {
+            let older = sibling(path, n);
+            if older.exists() {
+                std::fs::rename(&older, sibling(path, n + 1))?;
+            }
+        }
+        /* copied, not moved, so there's always a `path` to load */
+        std::fs::copy(path, sibling(path, 1))?;
+    }
+    std::fs::rename(&tmp, path)
+}
+
+/// The newest snapshot that can be read: `path`, or if that's missing or
+/// corrupt, `<path>.1`, then `<path>.2` and so on up to `<path>.<keep>`.
+/// Returns which one it was, too, or `None` if there are none at all.
+/// Having some but being able to read none of them is an error, so a broken
+/// file is never quietly replaced with an empty library.
+pub fn load(path: &Path, keep: usize) -> anyhow::Result<Option<(Vec<Entry>, PathBuf)>> {
+    let mut broken = Vec::new();
+    for candidate in [path.to_path_buf()]
+        .into_iter()
+        .chain((1..=keep).map(|n| sibling(path, n)))
+    {
+        let text = match std::fs::read_to_string(&candidate) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                tracing::error!("couldn't read snapshot {}: {err}", candidate.display());
+                broken.push(candidate);
+                continue;
+            }
+        };
+        match read(&text) {
+            Some(entries) => {
+                if candidate != path {
+                    tracing::error!(
+                        "snapshot {} is missing or corrupt, so falling back to the older {}",
+                        path.display(),
+                        candidate.display()
+                    );
+                }
+                return Ok(Some((entries, candidate)));
+            }
+            None => {
+                tracing::error!("snapshot {} is corrupt", candidate.display());
+                broken.push(candidate);
+            }
+        }
+    }
+    match broken.first() {
+        None => Ok(None),
+        Some(_) => anyhow::bail!(
+            "no readable snapshot among {}",
+            broken
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Where to save a library, and how much of it is saved already.
+pub struct Saver {
+    path: PathBuf,
+    keep: usize,
+    /// What [`Library::changes`] said as of the last save. Held while saving,
+    /// so two saves never write the same file at once.
+    saved: Mutex<u64>,
+}
+
+impl Saver {
+    /// Saves to `path`, keeping `keep` older copies, starting from `library`
+    /// as it is now, which is taken to be saved already.
+    pub fn new(library: &Library, path: PathBuf, keep: usize) -> Self {
+        Self {
+            path,
+            keep,
+            saved: Mutex::new(library.changes()),
+        }
+    }
+
+    /// Save `library` if its books have changed since the last save, and say
+    /// whether it did. Failures are logged, and the next try saves again.
+    pub async fn save_if_changed(&self, library: &Library) -> bool {
+        let mut saved = self.saved.lock().await;
+        let changes = library.changes();
+        if changes == *saved {
+            return false;
+        }
+        let entries = gather(library).await;
+        let text = write(&entries);
+        let (path, keep) = (self.path.clone(), self.keep);
+        match tokio::task::spawn_blocking(move || save(&path, &text, keep)).await {
+            Ok(Ok(())) => {
+                tracing::info!(
+                    books = entries.len(),
+                    path = format_args!("{}", self.path.display()),
+                    "saved a snapshot"
+                );
+                *saved = changes;
+                true
+            }
+            Ok(Err(err)) => {
+                let path = self.path.display();
+                tracing::warn!("couldn't save a snapshot to {path}: {err}");
+                false
+            }
+            Err(err) => {
+                let path = self.path.display();
+                tracing::warn!("saving a snapshot to {path} died: {err}");
+                false
+            }
+        }
+    }
+
+    /// Every `every`, save `library` if it's changed, forever.
+    pub async fn run(&self, library: &Library, every: Duration) {
+        let mut interval = tokio::time::interval(every);
+        /* the first tick is right away, and nothing's changed yet */
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.save_if_changed(library).await;
+        }
+    }
+}
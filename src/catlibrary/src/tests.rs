@@ -1,5 +1,33 @@
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream, DuplexStream};
+
+/// Build a server-side stream whose client has already sent `input` and
+/// hung up, so shell code can be driven as if over a socket.
+async fn scripted(input: &str) -> (BufStream<DuplexStream>, DuplexStream) {
+    let (server, mut client) = tokio::io::duplex(1 << 20);
+    client.write_all(input.as_bytes()).await.unwrap();
+    client.shutdown().await.unwrap();
+    (BufStream::new(server), client)
+}
+
+/// Everything the server wrote to the client of a [`scripted`] stream.
+async fn transcript<S: AsyncWrite + Unpin>(mut server: S, mut client: DuplexStream) -> String {
+    server.flush().await.unwrap();
+    drop(server);
+    let mut out = String::new();
+    client.read_to_string(&mut out).await.unwrap();
+    out
+}
+
 mod library {
-    use crate::library::{Book, Library, Metadata, UpdateEntryError};
+    use crate::clock::{ManualClock, OsRng, Rng, SeededRng};
+    use crate::library::{
+        self, AddError, Book, BookID, ClaimError, Field, GuestbookError, Library, LibraryEvent,
+        Mail, MergeError, Merged, Metadata, RegisterError, Removal, UpdateEntryError,
+    };
+    use core::net::IpAddr;
+    use core::time::Duration;
+    use std::sync::Arc;
+    use std::time::{SystemTime, UNIX_EPOCH};
 
     #[tokio::test]
     async fn add_and_search() {
@@ -8,14 +36,18 @@ mod library {
             author: String::from("cat 1"),
             description: String::from("bar"),
             content: String::from("baz"),
+            language: None,
         };
         let lib = Library::new();
         let guest = Library::OPERATOR;
-        let id = lib.add(book, guest).await;
-        assert_eq!(vec![(1.0, id, Metadata::new(guest))], lib.search("").await);
+        let id = lib.add(book, guest).await.unwrap();
+        assert_eq!(
+            vec![(1.0, id, Metadata::new(guest), None)],
+            lib.search("", Library::OPERATOR).await
+        );
         assert_eq!(
-            vec![(1.0, id, Metadata::new(guest))],
-            lib.search("foo").await
+            vec![(1.0, id, Metadata::new(guest), None)],
+            lib.search("foo", Library::OPERATOR).await
         );
     }
 
@@ -27,28 +59,282 @@ mod library {
             author: String::from("cat 1"),
             description: String::from("bar"),
             content: String::from("baz"),
+            language: None,
         };
         let book2 = Book {
             title: String::from("foo"),
             author: String::from("cat 1"),
             description: String::from("bar"),
             content: String::from("haha!"),
+            language: None,
         };
         let guest = Library::OPERATOR;
         {
             let mut expect = Vec::new();
             for _ in 1..=3 {
-                let id = lib.add(book.clone(), guest).await;
+                let id = lib.add(book.clone(), guest).await.unwrap();
                 let meta = lib.lookup_metadata(id);
-                expect.push((1.0, id, meta));
-                assert_eq!(expect, lib.search("").await);
+                expect.push((1.0, id, meta, None));
+                assert_eq!(expect, lib.search("", Library::OPERATOR).await);
             }
         }
-        let id2 = lib.add(book2.clone(), guest).await;
+        let id2 = lib.add(book2.clone(), guest).await.unwrap();
+        assert_eq!(
+            vec![(Field::Content.weight(), id2, Metadata::new(guest), None)],
+            lib.search("haha!", Library::OPERATOR).await
+        );
+    }
+
+    #[tokio::test]
+    async fn titles_outrank_content() {
+        let lib = Library::new();
+        let book = |title: &str, content: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from(content),
+            language: None,
+        };
+        /* just as good a match either way, and the older book would win a tie */
+        let inside = lib
+            .add(book("Sunbeams", "Naps"), Library::OPERATOR)
+            .await
+            .unwrap();
+        let titled = lib
+            .add(book("Naps", "zzz"), Library::OPERATOR)
+            .await
+            .unwrap();
+
+        let found = lib.search("Naps", Library::OPERATOR).await;
+        let ranked: Vec<_> = found.iter().map(|&(sim, id, ..)| (id, sim)).collect();
         assert_eq!(
-            vec![(1.0, id2, Metadata::new(guest))],
-            lib.search("haha!").await
+            vec![(titled, 1.0), (inside, Field::Content.weight())],
+            ranked
         );
+        let top = lib.search_top_k("Naps", Library::OPERATOR, 1).await;
+        assert_eq!(titled, top[0].1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn search_during_adds_and_checkouts() {
+        let book = |title: String| Book {
+            title,
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Arc::new(Library::new());
+        let mut adders = Vec::new();
+        for (n, nick) in ["whiskers", "mittens", "reader"].into_iter().enumerate() {
+            let addr = IpAddr::from([10, 0, 0, n as u8 + 2]);
+            adders.push(lib.register_guest(addr, nick).await.unwrap());
+        }
+        let reader = adders.pop().unwrap();
+        let mut seeds = Vec::new();
+        for n in 0..10 {
+            seeds.push(
+                lib.add(book(format!("seed {n}")), Library::OPERATOR)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let mut tasks = Vec::new();
+        for &adder in &adders {
+            let lib = Arc::clone(&lib);
+            tasks.push(tokio::spawn(async move {
+                for n in 0..50 {
+                    lib.add(book(format!("{adder:?} {n}")), adder)
+                        .await
+                        .unwrap();
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+        {
+            let lib = Arc::clone(&lib);
+            tasks.push(tokio::spawn(async move {
+                for n in 0..500 {
+                    let id = seeds[n % seeds.len()];
+                    if lib.checkout(id, reader).is_err() {
+                        lib.checkin(id, reader).unwrap();
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+
+        let mut seen = 0;
+        for _ in 0..200 {
+            let found = lib.search("", Library::OPERATOR).await;
+            /* the pool only grows, and never shows a book twice */
+            assert!(seen <= found.len());
+            seen = found.len();
+            assert!(found.windows(2).all(|pair| pair[0].1 < pair[1].1));
+            for (sim, id, meta, matched) in found {
+                assert_eq!((1.0, None), (sim, matched));
+                let book = lib.lookup_book_by_id(id).await;
+                let expected_by = adders
+                    .iter()
+                    .copied()
+                    .find(|adder| book.title.starts_with(&format!("{adder:?} ")))
+                    .unwrap_or(Library::OPERATOR);
+                assert_eq!(expected_by, meta.added_by, "{}", book.title);
+                assert!(meta.checked_out_by.is_none_or(|by| by == reader));
+            }
+            tokio::task::yield_now().await;
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(110, lib.search("", Library::OPERATOR).await.len());
+    }
+
+    /// Many tasks doing whatever guests do, all at once, for a couple of
+    /// seconds (or `CAT_LIBRARY_STRESS_SECS`). Each task's choices follow from
+    /// `CAT_LIBRARY_STRESS_SEED`, printed on failure, though how they
+    /// interleave doesn't.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn stress_invariants() {
+        const TASKS: u8 = 8;
+        let env = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        let seed = env("CAT_LIBRARY_STRESS_SEED").unwrap_or_else(|| OsRng.next_u64());
+        let secs = env("CAT_LIBRARY_STRESS_SECS").unwrap_or(2);
+        println!("CAT_LIBRARY_STRESS_SEED={seed}");
+
+        let book = |title: String| Book {
+            title,
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Arc::new(Library::new());
+        lib.open_guestbook().await;
+        let mut seeds = Vec::new();
+        for n in 0..20 {
+            seeds.push(
+                lib.add(book(format!("seed {n}")), Library::OPERATOR)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(secs);
+        let mut tasks = Vec::new();
+        for task in 0..TASKS {
+            let lib = Arc::clone(&lib);
+            let mut books = seeds.clone();
+            tasks.push(tokio::spawn(async move {
+                let rng = SeededRng::new(seed.wrapping_add(u64::from(task)));
+                let pick = |len: usize| (rng.next_u64() % len as u64) as usize;
+                let mut guests = vec![Library::OPERATOR];
+                let mut registered = 0u16;
+                while tokio::time::Instant::now() < deadline {
+                    let guest = guests[pick(guests.len())];
+                    let book_id = books[pick(books.len())];
+                    match pick(9) {
+                        0 => books.push(
+                            lib.add(book(format!("{task} {guest:?}")), guest)
+                                .await
+                                .unwrap(),
+                        ),
+                        1 => {
+                            let _ = lib.checkout(book_id, guest);
+                        }
+                        2 => {
+                            let held = lib.lookup_checkouts_by_guest(guest).await;
+                            if let Some(&(book_id, _meta)) = held.get(pick(held.len() + 1)) {
+                                /* or somebody trashed it in the meantime */
+                                let result = lib.checkin(book_id, guest);
+                                assert!(
+                                    matches!(
+                                        result,
+                                        Ok(_) | Err(UpdateEntryError::AlreadyCheckedIn)
+                                    ),
+                                    "{result:?}"
+                                );
+                            }
+                        }
+                        3 => {
+                            let [hi, lo] = registered.to_be_bytes();
+                            let addr = IpAddr::from([10, task, hi, lo]);
+                            let nick = format!("cat {task}.{registered}");
+                            guests.push(lib.register_guest(addr, nick).await.unwrap());
+                            registered += 1;
+                        }
+                        4 => {
+                            let title = Some(format!("renamed {}", rng.next_u64()));
+                            let _ = lib
+                                .update_metadata_fields(book_id, title, None, None, guest)
+                                .await;
+                        }
+                        5 => {
+                            for (_sim, id, _meta, _matched) in lib.search("cat", guest).await {
+                                books.push(id);
+                            }
+                            books.truncate(1000);
+                        }
+                        6 => {
+                            if lib
+                                .soft_delete(book_id, lib.now(), Library::OPERATOR)
+                                .is_err()
+                            {
+                                let _ = lib.restore(book_id);
+                            }
+                        }
+                        7 if guest != Library::OPERATOR => {
+                            /* a few addresses, so claims take them from each other */
+                            lib.set_passphrase(guest, "tuna").await;
+                            let nick = lib.lookup_nick(guest).await.unwrap();
+                            let addr = IpAddr::from([10, 255, 0, pick(4) as u8]);
+                            lib.claim_guest(addr, &nick, "tuna").await.unwrap();
+                        }
+                        _ => {
+                            lib.issue_token(guest).await;
+                        }
+                    }
+                    tokio::task::yield_now().await;
+                }
+                guests
+            }));
+        }
+        let mut guests = vec![];
+        for task in tasks {
+            guests.extend(
+                task.await
+                    .expect("stress task panicked; rerun with its seed"),
+            );
+        }
+        guests.sort_unstable();
+        guests.dedup();
+
+        lib.check_invariants().await;
+
+        /* every checkout a guest can list is one a brute-force scan finds */
+        let everything = lib.search("", Library::OPERATOR).await;
+        let metrics = lib.metrics().await;
+        assert_eq!(metrics.books + metrics.trashed, everything.len());
+        let mut checked_out = 0;
+        assert_eq!(metrics.guests, guests.len());
+        for guest in guests {
+            let mut listed: Vec<_> = lib
+                .lookup_checkouts_by_guest(guest)
+                .await
+                .into_iter()
+                .map(|(id, _meta)| id)
+                .collect();
+            listed.sort_unstable();
+            let scanned: Vec<_> = everything
+                .iter()
+                .filter(|&&(_sim, id, ..)| lib.lookup_metadata(id).checked_out_by == Some(guest))
+                .map(|&(_sim, id, ..)| id)
+                .collect();
+            assert_eq!(scanned, listed, "{guest:?}'s checkouts");
+            checked_out += listed.len();
+        }
+        assert_eq!(metrics.checked_out, checked_out);
     }
 
     #[tokio::test]
@@ -58,21 +344,5847 @@ mod library {
             author: String::from("cat 1"),
             description: String::from("bar"),
             content: String::from("baz"),
+            language: None,
         };
         let lib = Library::new();
         let guest = Library::OPERATOR;
-        let id = lib.add(book.clone(), guest).await;
+        let id = lib.add(book.clone(), guest).await.unwrap();
 
         assert_eq!(Ok(()), lib.checkout(id, guest));
         assert_eq!(
             Err(UpdateEntryError::AlreadyCheckedOut(guest)),
             lib.checkout(id, guest)
         );
-        assert_eq!(Ok(()), lib.checkin(id, guest));
+        assert!(lib.checkin(id, guest).is_ok());
         assert_eq!(
             Err(UpdateEntryError::AlreadyCheckedIn),
             lib.checkin(id, guest)
         );
         assert_eq!(Ok(()), lib.checkout(id, guest));
     }
+
+    #[tokio::test]
+    async fn edit_author_and_search() {
+        let book = Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Library::new();
+        let guest = Library::OPERATOR;
+        let id = lib.add(book, guest).await.unwrap();
+
+        assert_eq!(
+            Ok(()),
+            lib.update_metadata_fields(id, None, Some(String::from("whiskers")), None, guest)
+                .await
+        );
+        let updated = lib.lookup_book_by_id(id).await;
+        assert_eq!("whiskers", updated.author);
+        assert_eq!("baz", updated.content);
+        assert_eq!(
+            vec![(Field::Author.weight(), id, Metadata::new(guest), None)],
+            lib.search("whiskers", Library::OPERATOR).await
+        );
+
+        let stranger = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "stranger")
+            .await
+            .unwrap();
+        assert_eq!(
+            Err(UpdateEntryError::GuestMismatch),
+            lib.update_metadata_fields(id, Some(String::from("mine")), None, None, stranger)
+                .await
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn replace_during_reads() {
+        let version = |n: usize| Book {
+            title: format!("draft {n}"),
+            author: String::from("cat 1"),
+            description: format!("revision {n}"),
+            content: format!("{n}\n").repeat(1000),
+            language: None,
+        };
+        let lib = Arc::new(Library::new());
+        let id = lib.add(version(0), Library::OPERATOR).await.unwrap();
+
+        let writer = {
+            let lib = Arc::clone(&lib);
+            tokio::spawn(async move {
+                for n in 1..=200 {
+                    lib.replace_book(id, version(n), Library::OPERATOR)
+                        .await
+                        .unwrap();
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        let mut readers = Vec::new();
+        for _ in 0..3 {
+            let lib = Arc::clone(&lib);
+            readers.push(tokio::spawn(async move {
+                let mut last = 0;
+                for _ in 0..500 {
+                    let book = lib.lookup_book_by_id(id).await;
+                    let n: usize = book.title["draft ".len()..].parse().unwrap();
+                    /* all of one version, never older than what was seen */
+                    assert_eq!(version(n), *book);
+                    assert!(last <= n);
+                    last = n;
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        assert_eq!(version(200), *lib.lookup_book_by_id(id).await);
+
+        lib.soft_delete(id, lib.now(), Library::OPERATOR).unwrap();
+        assert_eq!(
+            Err(UpdateEntryError::Removed),
+            lib.replace_book(id, version(0), Library::OPERATOR).await
+        );
+    }
+
+    #[tokio::test]
+    async fn search_by_language() {
+        let book = |title: &str, language: Option<&str>| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::new(),
+            content: String::from("zzz\n"),
+            language: language.map(String::from),
+        };
+        let lib = Library::new();
+        let sieste = lib
+            .add(book("La Sieste", Some("fr")), Library::OPERATOR)
+            .await
+            .unwrap();
+        let quebec = lib
+            .add(book("La Sieste 2", Some("fr-CA")), Library::OPERATOR)
+            .await
+            .unwrap();
+        let nap = lib
+            .add(book("The Nap", Some("en")), Library::OPERATOR)
+            .await
+            .unwrap();
+        let unsaid = lib
+            .add(book("The Nap 2", None), Library::OPERATOR)
+            .await
+            .unwrap();
+        let found = |query: &'static str| {
+            let lib = &lib;
+            async move {
+                let mut ids: Vec<_> = lib
+                    .search(query, Library::OPERATOR)
+                    .await
+                    .into_iter()
+                    .map(|(_, id, ..)| id)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        assert_eq!(vec![sieste, quebec, nap, unsaid], found("").await);
+        assert_eq!(vec![sieste, quebec], found("lang:fr").await);
+        assert_eq!(vec![quebec], found("lang:FR-ca").await);
+        assert_eq!(vec![nap], found("Nap lang:en").await);
+        assert_eq!(vec![nap, unsaid], found("Nap").await);
+        assert_eq!(Vec::<BookID>::new(), found("lang:de").await);
+        assert_eq!(
+            vec![sieste, quebec],
+            lib.search_top_k("lang:fr", Library::OPERATOR, 5)
+                .await
+                .iter()
+                .map(|(_, id, ..)| *id)
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(Some(String::from("pt-BR")), Book::language_tag(" PT-br "));
+        assert_eq!(Some(String::from("zh-Hant")), Book::language_tag("zh-HANT"));
+        assert_eq!(Some(String::from("en")), Book::language_tag("en"));
+        for junk in [
+            "",
+            "e",
+            "en-",
+            "en_US",
+            "12",
+            "english language",
+            "en-toolongtag",
+        ] {
+            assert_eq!(None, Book::language_tag(junk), "{junk}");
+        }
+    }
+
+    #[tokio::test]
+    async fn search_available() {
+        let book = |title: &str, language: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::new(),
+            content: String::from("zzz\n"),
+            language: Some(String::from(language)),
+        };
+        let lib = Library::new();
+        let taken = lib
+            .add(book("The Nap", "en"), Library::OPERATOR)
+            .await
+            .unwrap();
+        let free = lib
+            .add(book("The Nap 2", "en"), Library::OPERATOR)
+            .await
+            .unwrap();
+        let sieste = lib
+            .add(book("The Nap 3", "fr"), Library::OPERATOR)
+            .await
+            .unwrap();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        lib.checkout(taken, whiskers).unwrap();
+        let ids = |found: Vec<(f64, BookID, Metadata, Option<library::Match>)>| {
+            let mut ids: Vec<_> = found.into_iter().map(|(_, id, ..)| id).collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            vec![taken, free, sieste],
+            ids(lib.search("The Nap", whiskers).await)
+        );
+        assert_eq!(
+            vec![free, sieste],
+            ids(lib.search("The Nap available:", whiskers).await)
+        );
+        assert_eq!(
+            vec![free],
+            ids(lib
+                .search_top_k("available: The Nap lang:en", whiskers, 5)
+                .await)
+        );
+        lib.soft_delete(free, lib.now(), Library::OPERATOR).unwrap();
+        assert_eq!(
+            Vec::<BookID>::new(),
+            ids(lib.search("lang:en available:", Library::OPERATOR).await)
+        );
+    }
+
+    #[tokio::test]
+    async fn contribution_limit() {
+        let book = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::new(),
+            content: String::from("junk\n"),
+            language: None,
+        };
+        let lib = Library::builder().contribution_limit(2).build().await;
+        let spammer = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "spammer")
+            .await
+            .unwrap();
+        let first = lib.add(book("junk 1"), spammer).await.unwrap();
+        lib.add(book("junk 2"), spammer).await.unwrap();
+        assert!(lib.at_contribution_limit(spammer));
+        assert_eq!(
+            Err(AddError::ContributionLimit),
+            lib.add(book("junk 3"), spammer).await
+        );
+        for n in 1..=3 {
+            lib.add(book(&format!("welcome {n}")), Library::OPERATOR)
+                .await
+                .unwrap();
+        }
+        assert_eq!(2, lib.contributions(spammer));
+
+        /* taking one down makes room for another */
+        lib.soft_delete(first, lib.now(), Library::OPERATOR)
+            .unwrap();
+        assert_eq!(1, lib.contributions(spammer));
+        lib.add(book("junk 3"), spammer).await.unwrap();
+        assert_eq!(
+            Err(AddError::ContributionLimit),
+            lib.add(book("junk 4"), spammer).await
+        );
+    }
+
+    #[tokio::test]
+    async fn no_pulling_books_from_under_readers() {
+        let book = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::from("zzz"),
+            content: String::from("zzz\n"),
+            language: None,
+        };
+        let lib = Library::new();
+        let id = lib
+            .add(book("The Long Nap"), Library::OPERATOR)
+            .await
+            .unwrap();
+        let reader = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "reader")
+            .await
+            .unwrap();
+        let reading = lib.start_reading(id, reader);
+        assert!(lib.has_readers(id));
+        assert_eq!(
+            Err(UpdateEntryError::BeingRead),
+            lib.soft_delete(id, lib.now(), Library::OPERATOR)
+        );
+        assert_eq!(
+            Err(UpdateEntryError::BeingRead),
+            lib.replace_book(id, book("The Longer Nap"), Library::OPERATOR)
+                .await
+        );
+        assert_eq!("The Long Nap", lib.lookup_title(id).await);
+
+        drop(reading);
+        assert!(!lib.has_readers(id));
+        lib.replace_book(id, book("The Longer Nap"), Library::OPERATOR)
+            .await
+            .unwrap();
+        lib.soft_delete(id, lib.now(), Library::OPERATOR).unwrap();
+    }
+
+    #[tokio::test]
+    async fn whitespace_in_covers() {
+        let book = |title: &str, author: &str| Book {
+            title: String::from(title),
+            author: String::from(author),
+            description: String::from("zzz"),
+            content: String::from("zzz\n"),
+            language: None,
+        };
+        let lib = Library::new();
+        let id = lib
+            .add(book("  The  Long\tNap ", "cat 1"), Library::OPERATOR)
+            .await
+            .unwrap();
+        assert_eq!("The Long Nap", lib.lookup_title(id).await);
+        assert_eq!(
+            (String::from("the long nap"), String::from("cat 1")),
+            book("The  long nap", "Cat  1").cover_key()
+        );
+
+        /* the same book, however it's spaced or cased */
+        let same = book("the long  nap", " Cat 1");
+        assert_eq!(Some(id), lib.find_duplicate(&same).await);
+        let copy = lib.add(same, Library::OPERATOR).await.unwrap();
+        let found = lib.search("", Library::OPERATOR).await;
+        let copies = lib.collapse_copies(found).await;
+        assert_eq!(1, copies.len());
+        assert_eq!(vec![id, copy], copies[0].ids);
+
+        /* uploads refuse it, even two at once */
+        let (first, second) = tokio::join!(
+            lib.add_unique(book("Naps", "cat 2"), Library::OPERATOR),
+            lib.add_unique(book("naps", "Cat 2"), Library::OPERATOR),
+        );
+        let first = first.unwrap();
+        assert_eq!(Err(AddError::Duplicate(first)), second);
+        assert_eq!(
+            Err(AddError::Duplicate(id)),
+            lib.add_unique(book("The Long Nap", "cat 1"), Library::OPERATOR)
+                .await
+        );
+
+        /* and edits are tidied up the same way */
+        lib.update_metadata_fields(id, Some("A  Nap ".into()), None, None, Library::OPERATOR)
+            .await
+            .unwrap();
+        assert_eq!("A Nap", lib.lookup_title(id).await);
+    }
+
+    #[tokio::test]
+    async fn authors() {
+        let book = |title: &str, author: &str| Book {
+            title: String::from(title),
+            author: String::from(author),
+            description: String::from("zzz"),
+            content: String::from("zzz\n"),
+            language: None,
+        };
+        let lib = Library::new();
+        assert!(lib.authors(Library::OPERATOR).await.is_empty());
+        let mut ids = Vec::new();
+        for (title, author) in [
+            ("Nap", "Cat 1"),
+            ("Tuna", "bob"),
+            ("Box", " cat  1 "),
+            ("Yarn", "Alice"),
+            ("Mew", "CAT 1"),
+        ] {
+            ids.push(
+                lib.add(book(title, author), Library::OPERATOR)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        /* most books first, then by name, however the name was typed */
+        assert_eq!(
+            vec![
+                (String::from("Cat 1"), 3),
+                (String::from("Alice"), 1),
+                (String::from("bob"), 1),
+            ],
+            lib.authors(Library::OPERATOR).await
+        );
+
+        let by = lib.books_by_author("cat\t1", Library::OPERATOR).await;
+        let by: Vec<_> = by.into_iter().map(|(_, id, _, _)| id).collect();
+        assert_eq!(vec![ids[0], ids[2], ids[4]], by);
+        assert!(lib
+            .books_by_author("cat", Library::OPERATOR)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn checkouts_in_order() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let book = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::new(),
+            content: String::from("zzz\n"),
+            language: None,
+        };
+        let mut ids = Vec::new();
+        for title in ["a", "b", "c", "d"] {
+            ids.push(lib.add(book(title), Library::OPERATOR).await.unwrap());
+        }
+        let guest = lib
+            .register_guest(IpAddr::from([10, 0, 0, 2]), "whiskers")
+            .await
+            .unwrap();
+        for &idx in &[2, 0, 3] {
+            lib.checkout(ids[idx], guest).unwrap();
+            clock.advance(Duration::from_secs(60));
+        }
+        let order = |found: Vec<(BookID, Metadata)>| -> Vec<BookID> {
+            found.into_iter().map(|(id, _)| id).collect()
+        };
+        let expect = vec![ids[2], ids[0], ids[3]];
+        for _ in 0..10 {
+            assert_eq!(expect, order(lib.lookup_checkouts_by_guest(guest).await));
+        }
+
+        /* nothing else going on changes it */
+        lib.checkout(ids[1], Library::OPERATOR).unwrap();
+        lib.add(book("e"), Library::OPERATOR).await.unwrap();
+        lib.register_read(ids[0], Library::NOBODY);
+        lib.checkin(ids[1], Library::OPERATOR).unwrap();
+        assert_eq!(expect, order(lib.lookup_checkouts_by_guest(guest).await));
+
+        /* and what's checked out again goes to the end */
+        lib.checkin(ids[2], guest).unwrap();
+        lib.checkout(ids[2], guest).unwrap();
+        let expect = vec![ids[0], ids[3], ids[2]];
+        assert_eq!(expect, order(lib.lookup_checkouts_by_guest(guest).await));
+    }
+
+    #[tokio::test]
+    async fn similar_books() {
+        let book = |title: &str, author: &str, description: &str| Book {
+            title: String::from(title),
+            author: String::from(author),
+            description: String::from(description),
+            content: String::from("zzz\n"),
+            language: None,
+        };
+        let lib = Library::new();
+        let guest = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let add = async |title, author, description| {
+            lib.add(book(title, author, description), Library::OPERATOR)
+                .await
+                .unwrap()
+        };
+        let nap = add("The Long Nap", "cat 1", "sleeping all day").await;
+        let copy = add("the long nap", "Cat 1", "sleeping all day").await;
+        let longer = add("The Longer Nap", "cat 2", "sleeping all week").await;
+        let naps = add("Naps", "cat 1", "a book of naps").await;
+        let out = add("The Long Nap II", "cat 1", "sleeping again").await;
+        let tuna = add("Tuna", "cat 3", "a memoir").await;
+        let trashed = add("The Lost Nap", "cat 1", "sleeping all day").await;
+        lib.soft_delete(trashed, lib.now(), Library::OPERATOR)
+            .unwrap();
+        lib.checkout(out, guest).unwrap();
+
+        /* not itself, its copy, the trash, or what the guest has out */
+        let similar = lib.similar_to(nap, 3, guest).await;
+        assert_eq!(vec![longer, naps], similar);
+        assert!(!similar.contains(&tuna));
+        assert_eq!(similar, lib.similar_to(nap, 3, guest).await);
+        assert_eq!(
+            vec![out, longer],
+            lib.similar_to(copy, 2, Library::OPERATOR).await
+        );
+        for id in similar {
+            let title = lib.lookup_title(id).await;
+            assert_eq!(id, lib.search(&title, guest).await[0].1);
+        }
+    }
+
+    #[tokio::test]
+    async fn library_notices() {
+        let book = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("the management"),
+            description: String::new(),
+            content: String::from("no kippers\n"),
+            language: None,
+        };
+        let lib = Library::builder().books([book("Kippers")]).build().await;
+        let (_sim, id, meta, _matched) = lib.search("Kippers", Library::OPERATOR).await[0];
+        assert!(meta.protected);
+        let mine = lib.add(book("Mine"), Library::OPERATOR).await.unwrap();
+        assert!(!lib.lookup_metadata(mine).protected);
+
+        /* even a guest who somehow passes for the one who added it */
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let guest_id = lib.add(book("Theirs"), whiskers).await.unwrap();
+        assert!(lib.toggle_protected(guest_id));
+        for (id, guest) in [(id, whiskers), (guest_id, whiskers)] {
+            assert_eq!(
+                Err(UpdateEntryError::Protected),
+                lib.update_metadata_fields(id, Some("Fish".into()), None, None, guest)
+                    .await
+            );
+            assert_eq!(
+                Err(UpdateEntryError::Protected),
+                lib.replace_book(id, book("Fish"), guest).await
+            );
+            assert_eq!(
+                Err(UpdateEntryError::Protected),
+                lib.soft_delete(id, lib.now(), guest)
+            );
+        }
+        assert_eq!("Kippers", lib.lookup_title(id).await);
+        assert!(!lib.lookup_metadata(id).is_hidden());
+
+        /* the operator may still */
+        lib.update_metadata_fields(id, Some("Kippers!".into()), None, None, Library::OPERATOR)
+            .await
+            .unwrap();
+        lib.replace_book(id, book("Kippers, Please"), Library::OPERATOR)
+            .await
+            .unwrap();
+
+        /* and undoing it puts things back as they were */
+        assert!(!lib.toggle_protected(guest_id));
+        lib.update_metadata_fields(guest_id, Some("Fish".into()), None, None, whiskers)
+            .await
+            .unwrap();
+        lib.soft_delete(guest_id, lib.now(), whiskers).unwrap();
+        lib.soft_delete(id, lib.now(), Library::OPERATOR).unwrap();
+    }
+
+    #[tokio::test]
+    async fn author_mail() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let whiskers = lib
+            .register_guest(IpAddr::from([10, 0, 0, 1]), "whiskers")
+            .await
+            .unwrap();
+        let mittens = lib
+            .register_guest(IpAddr::from([10, 0, 0, 2]), "mittens")
+            .await
+            .unwrap();
+        let titled = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("whiskers"),
+            description: String::new(),
+            content: String::from("purr\n"),
+            language: None,
+        };
+        let zoomies = lib.add(titled("Midnight Zoomies"), whiskers).await.unwrap();
+        let naps = lib.add(titled("Naps"), whiskers).await.unwrap();
+        let checked_out = |by, book_id| Mail::CheckedOut { by, book_id };
+
+        /* their own checkouts aren't news */
+        lib.checkout(zoomies, whiskers).unwrap();
+        lib.checkin(zoomies, whiskers).unwrap();
+        assert_eq!(Vec::<Mail>::new(), lib.take_mail(whiskers));
+
+        /* once per book per day, however popular */
+        for _ in 0..3 {
+            lib.checkout(zoomies, mittens).unwrap();
+            lib.checkin(zoomies, mittens).unwrap();
+        }
+        lib.checkout(naps, Library::OPERATOR).unwrap();
+        assert_eq!(
+            vec![
+                checked_out(mittens, zoomies),
+                checked_out(Library::OPERATOR, naps)
+            ],
+            lib.take_mail(whiskers)
+        );
+        assert_eq!(Vec::<Mail>::new(), lib.take_mail(whiskers));
+        lib.checkout(zoomies, mittens).unwrap();
+        assert_eq!(Vec::<Mail>::new(), lib.take_mail(whiskers));
+        lib.checkin(zoomies, mittens).unwrap();
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        lib.checkout(zoomies, mittens).unwrap();
+        lib.checkin(zoomies, mittens).unwrap();
+        assert_eq!(vec![checked_out(mittens, zoomies)], lib.take_mail(whiskers));
+
+        /* opting out drops what's waiting too */
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        lib.checkout(zoomies, mittens).unwrap();
+        assert!(lib.notifies(whiskers));
+        lib.set_notify(whiskers, false);
+        assert!(!lib.notifies(whiskers));
+        lib.checkin(naps, Library::OPERATOR).unwrap();
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        lib.checkout(naps, mittens).unwrap();
+        assert_eq!(Vec::<Mail>::new(), lib.take_mail(whiskers));
+        lib.set_notify(whiskers, true);
+        lib.checkin(naps, mittens).unwrap();
+        lib.checkout(naps, Library::OPERATOR).unwrap();
+        assert_eq!(
+            vec![checked_out(Library::OPERATOR, naps)],
+            lib.take_mail(whiskers)
+        );
+    }
+
+    #[tokio::test]
+    async fn reading_stats() {
+        assert_eq!(0, library::percent_through(0, 8));
+        assert_eq!(62, library::percent_through(5, 8));
+        assert_eq!(100, library::percent_through(9, 8));
+        assert_eq!(100, library::percent_through(0, 0));
+
+        let lib = Library::new();
+        let numbered = |lines: usize| Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: (1..=lines).map(|n| format!("line {n}\n")).collect(),
+            language: None,
+        };
+        let id = lib.add(numbered(10), Library::OPERATOR).await.unwrap();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mittens = lib
+            .register_guest("10.0.0.3".parse().unwrap(), "mittens")
+            .await
+            .unwrap();
+        assert_eq!(
+            library::ReadingStats::default(),
+            lib.reading_stats(id).await
+        );
+        assert_eq!(None, lib.completion(id, whiskers).await);
+
+        lib.record_progress(id, whiskers, 10);
+        lib.record_progress(id, mittens, 3);
+        /* going back over it doesn't undo getting further */
+        lib.record_progress(id, mittens, 1);
+        assert_eq!(Some(3), lib.progress(id, mittens));
+        assert_eq!(Some(30), lib.completion(id, mittens).await);
+        assert_eq!(
+            library::ReadingStats {
+                readers: 2,
+                average_completion: 65,
+            },
+            lib.reading_stats(id).await
+        );
+
+        /* against the book as it is now */
+        lib.replace_book(id, numbered(20), Library::OPERATOR)
+            .await
+            .unwrap();
+        assert_eq!(Some(50), lib.completion(id, whiskers).await);
+        assert_eq!(32, lib.reading_stats(id).await.average_completion);
+        lib.replace_book(id, numbered(5), Library::OPERATOR)
+            .await
+            .unwrap();
+        assert_eq!(80, lib.reading_stats(id).await.average_completion);
+
+        /* one cat registered twice is one reader, as far as they got */
+        lib.merge_guests(mittens, whiskers, false).await.unwrap();
+        assert_eq!(
+            library::ReadingStats {
+                readers: 1,
+                average_completion: 100,
+            },
+            lib.reading_stats(id).await
+        );
+    }
+
+    #[tokio::test]
+    async fn events() {
+        let book = Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Library::new();
+        let guest = Library::OPERATOR;
+        let mut events = lib.subscribe();
+
+        let id = lib.add(book, guest).await.unwrap();
+        lib.checkout(id, guest).unwrap();
+        lib.checkin(id, guest).unwrap();
+        assert_eq!(Some(LibraryEvent::Added(id)), events.recv().await.ok());
+        assert_eq!(Some(LibraryEvent::CheckedOut(id)), events.recv().await.ok());
+        assert_eq!(Some(LibraryEvent::CheckedIn(id)), events.recv().await.ok());
+    }
+
+    #[tokio::test]
+    async fn announcements() {
+        let lib = Library::new();
+        let mut first = lib.listen();
+        let mut second = lib.listen();
+
+        assert_eq!(None, lib.announce(" \x1b[1m\n\x07 "));
+        let said = lib.announce("\x1b[31mclosing\x07 in\n5 minutes").unwrap();
+        assert_eq!("closing in 5 minutes", &*said);
+        for listener in [&mut first, &mut second] {
+            assert_eq!(Some(Arc::clone(&said)), library::try_recv_event(listener));
+            assert_eq!(None, library::try_recv_event(listener));
+        }
+
+        let long = lib.announce(&"m".repeat(1000)).unwrap();
+        assert_eq!(Library::ANNOUNCEMENT_MAX_LEN + 1, long.chars().count());
+        assert!(long.ends_with('…'));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn soft_bans() {
+        let mut lib = Library::new();
+        let minute = Duration::from_secs(60);
+        lib.set_strike_limit(3, minute, 10 * minute);
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+
+        /* strikes too far apart, or forgiven, don't add up */
+        assert!(!lib.strike(addr));
+        assert!(!lib.strike(addr));
+        tokio::time::advance(minute + Duration::from_secs(1)).await;
+        assert!(!lib.strike(addr));
+        assert!(!lib.strike(addr));
+        lib.forgive(addr);
+        assert!(!lib.strike(addr));
+        assert!(!lib.strike(addr));
+        assert!(!lib.is_soft_banned(addr));
+        assert!(lib.strike(addr));
+        assert!(lib.is_soft_banned(addr));
+
+        for _ in 0..10 {
+            assert!(!lib.strike(Library::OPERATOR_ADDR));
+        }
+        assert!(!lib.is_soft_banned(Library::OPERATOR_ADDR));
+
+        let other: IpAddr = "10.0.0.3".parse().unwrap();
+        assert!(!lib.strike(other));
+        tokio::time::advance(10 * minute).await;
+        assert_eq!(1, lib.prune_strikes());
+        assert!(!lib.is_soft_banned(addr));
+        /* `other`'s strike was too old to keep */
+        assert!(!lib.strike(other));
+        assert!(!lib.strike(other));
+        assert!(lib.strike(other));
+    }
+
+    #[tokio::test]
+    async fn lagged_subscriber_skips_ahead() {
+        let book = Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Library::new();
+        let guest = Library::OPERATOR;
+        let mut events = lib.subscribe();
+
+        let mut last = None;
+        for _ in 0..Library::EVENT_CAPACITY + 3 {
+            last = Some(lib.add(book.clone(), guest).await.unwrap());
+        }
+        let mut seen = Vec::new();
+        while !events.is_empty() {
+            seen.push(library::recv_event(&mut events).await.unwrap());
+        }
+        assert_eq!(Library::EVENT_CAPACITY, seen.len());
+        assert_eq!(Some(&LibraryEvent::Added(last.unwrap())), seen.last());
+    }
+
+    #[tokio::test]
+    async fn builder_options() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let day = Duration::from_secs(24 * 60 * 60);
+        let lib = Library::builder()
+            .clock(clock.clone())
+            .guest_limit(1, day)
+            .preview_lines(3)
+            .books(["naps", "tuna"].map(|title| Book {
+                title: String::from(title),
+                author: String::from("cat 1"),
+                description: String::new(),
+                content: String::new(),
+                language: None,
+            }))
+            .guestbook(true)
+            .build()
+            .await;
+        assert_eq!(UNIX_EPOCH, lib.now());
+        assert_eq!((1, day), (lib.guest_limit(), lib.guest_stale_after()));
+        assert_eq!(3, lib.preview_lines());
+        let mut titles = Vec::new();
+        for (_sim, id, _meta, _matched) in lib.search("", Library::OPERATOR).await {
+            titles.push(lib.lookup_title(id).await);
+        }
+        assert_eq!(vec!["naps", "tuna", "Guestbook"], titles);
+    }
+
+    #[tokio::test]
+    async fn operator_nick() {
+        let lib = Library::builder()
+            .books([Book {
+                title: String::from("seed"),
+                author: String::from("Server Operator"),
+                description: String::new(),
+                content: String::new(),
+                language: None,
+            }])
+            .operator_nick("mx. operator")
+            .build()
+            .await;
+        let operator = lib.lookup_guest_by_addr(Library::OPERATOR_ADDR).await;
+        assert_eq!(Some(Library::OPERATOR), operator);
+        assert_eq!(
+            Some("mx. operator".into()),
+            lib.lookup_nick(Library::OPERATOR).await
+        );
+
+        /* still sorted among everybody else's */
+        for (n, nick) in ["aardvark", "zebra", Library::DEFAULT_OPERATOR_NICK]
+            .into_iter()
+            .enumerate()
+        {
+            let addr = format!("10.0.0.{}", n + 2).parse().unwrap();
+            lib.register_guest(addr, nick).await.unwrap();
+        }
+        assert_eq!(
+            Err(RegisterError::NicknameTaken),
+            lib.register_guest("10.0.0.9".parse().unwrap(), "mx. operator")
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn search_top_k_matches_full_sort() {
+        let lib = Library::new();
+        for title in ["cat", "cats", "cat nap", "dog", "catalog", "cat", "scat"] {
+            lib.add(
+                Book {
+                    title: String::from(title),
+                    author: String::from("cat 1"),
+                    description: String::new(),
+                    content: String::new(),
+                    language: None,
+                },
+                Library::OPERATOR,
+            )
+            .await
+            .unwrap();
+        }
+
+        for query in ["cat", "dog", ""] {
+            let all = lib.search(query, Library::OPERATOR).await;
+            for k in [0, 1, 3, all.len(), all.len() + 5] {
+                let top = lib.search_top_k(query, Library::OPERATOR, k).await;
+                assert_eq!(all[..k.min(all.len())], top[..], "{query:?} top {k}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn register_guests() {
+        let lib = Library::new();
+        let home = "10.0.0.2".parse().unwrap();
+        let id = lib.register_guest(home, "whiskers").await.unwrap();
+        assert_eq!(Some(id), lib.lookup_guest_by_addr(home).await);
+        assert_eq!(Some("whiskers".into()), lib.lookup_nick(id).await);
+        assert_eq!(
+            Err(RegisterError::AlreadyRegistered),
+            lib.register_guest(home, "mittens").await
+        );
+        assert_eq!(
+            Err(RegisterError::NicknameTaken),
+            lib.register_guest("10.0.0.3".parse().unwrap(), "whiskers")
+                .await
+        );
+        /* the rejected nickname must still be free */
+        assert!(lib
+            .register_guest("10.0.0.3".parse().unwrap(), "mittens")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn claim_from_new_address() {
+        let book = Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Library::new();
+        let desktop = "10.0.0.2".parse().unwrap();
+        let laptop = "10.0.0.3".parse().unwrap();
+        let id = lib.register_guest(desktop, "whiskers").await.unwrap();
+        let book_id = lib.add(book, Library::OPERATOR).await.unwrap();
+        lib.checkout(book_id, id).unwrap();
+
+        assert_eq!(
+            Err(ClaimError::NoPassphrase),
+            lib.claim_guest(laptop, "whiskers", "").await
+        );
+        lib.set_passphrase(id, "tuna").await;
+        assert_eq!(
+            Err(ClaimError::WrongPassphrase),
+            lib.claim_guest(laptop, "whiskers", "salmon").await
+        );
+        assert_eq!(
+            Err(ClaimError::UnknownNickname),
+            lib.claim_guest(laptop, "mittens", "tuna").await
+        );
+        assert_eq!(Ok(id), lib.claim_guest(laptop, "whiskers", "tuna").await);
+
+        assert_eq!(Some(id), lib.lookup_guest_by_addr(laptop).await);
+        assert_eq!(None, lib.lookup_guest_by_addr(desktop).await);
+        let checkouts = lib.lookup_checkouts_by_guest(id).await;
+        assert_eq!(
+            vec![book_id],
+            checkouts.iter().map(|c| c.0).collect::<Vec<_>>()
+        );
+        assert!(lib.checkin(book_id, id).is_ok());
+
+        /* taking over somebody's address leaves them nowhere, not there */
+        let den = "10.0.0.4".parse().unwrap();
+        let mittens = lib.register_guest(den, "mittens").await.unwrap();
+        lib.set_passphrase(mittens, "salmon").await;
+        assert_eq!(
+            Ok(mittens),
+            lib.claim_guest(laptop, "mittens", "salmon").await
+        );
+        assert!(!lib.ban_guest(id).await);
+        assert_eq!(Ok(id), lib.claim_guest(desktop, "whiskers", "tuna").await);
+        assert_eq!(Some(mittens), lib.lookup_guest_by_addr(laptop).await);
+        assert_eq!(Some(id), lib.lookup_guest_by_addr(desktop).await);
+        assert_eq!(None, lib.lookup_guest_by_addr(den).await);
+        lib.check_invariants().await;
+    }
+
+    #[tokio::test]
+    async fn login_tokens() {
+        let lib = Library::new();
+        let id = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let old = lib.issue_token(id).await;
+        assert_eq!(Some(id), lib.login(&old).await);
+        assert_eq!(None, lib.login("not a token").await);
+
+        let new = lib.issue_token(id).await;
+        assert_ne!(old, new);
+        assert_eq!(None, lib.login(&old).await);
+        assert_eq!(Some(id), lib.login(&new).await);
+    }
+
+    #[tokio::test]
+    async fn guestbook() {
+        let lib = Library::new();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        assert_eq!(
+            Err(GuestbookError::NoGuestbook),
+            lib.append_guestbook(whiskers, "hi", SystemTime::now())
+                .await
+        );
+
+        let id = lib.open_guestbook().await;
+        assert_eq!(id, lib.open_guestbook().await);
+        assert_eq!(Err(UpdateEntryError::Communal), lib.checkout(id, whiskers));
+
+        let day = UNIX_EPOCH + Duration::from_secs(19875 * 86_400);
+        assert_eq!(
+            Ok(()),
+            lib.append_guestbook(whiskers, " lovely stacks,\x1b[31m would nap\tagain ", day)
+                .await
+        );
+        assert_eq!(
+            Err(GuestbookError::Empty),
+            lib.append_guestbook(whiskers, "\x07\x07 ", day).await
+        );
+        assert_eq!(
+            Err(GuestbookError::TooLong),
+            lib.append_guestbook(whiskers, &"a".repeat(Library::GUESTBOOK_MAX_LEN + 1), day)
+                .await
+        );
+        for _ in 1..Library::GUESTBOOK_DAILY_LIMIT {
+            lib.append_guestbook(whiskers, "again", day).await.unwrap();
+        }
+        assert_eq!(
+            Err(GuestbookError::DailyLimit),
+            lib.append_guestbook(whiskers, "again", day).await
+        );
+        let tomorrow = day + Duration::from_secs(86_400);
+        assert_eq!(
+            Ok(()),
+            lib.append_guestbook(whiskers, "hello", tomorrow).await
+        );
+
+        let content = lib.lookup_book_by_id(id).await.content.clone();
+        let mut lines = content.lines();
+        assert_eq!(
+            Some("2024-06-01 whiskers: lovely stacks, would nap again"),
+            lines.next()
+        );
+        assert_eq!(Some("2024-06-02 whiskers: hello"), lines.last());
+    }
+
+    #[tokio::test]
+    async fn trash_hides_everywhere() {
+        let book = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Library::new();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let id = lib.add(book("foo"), whiskers).await.unwrap();
+        let other = lib.add(book("food"), whiskers).await.unwrap();
+        let guestbook = lib.open_guestbook().await;
+        lib.checkout(id, whiskers).unwrap();
+        let mut events = lib.subscribe();
+
+        let day = UNIX_EPOCH + Duration::from_secs(19875 * 86_400);
+        assert_eq!(Ok(()), lib.soft_delete(id, day, Library::OPERATOR));
+        assert_eq!(Ok(()), lib.soft_delete(guestbook, day, Library::OPERATOR));
+        assert_eq!(
+            Err(UpdateEntryError::Removed),
+            lib.soft_delete(id, day, Library::OPERATOR)
+        );
+        assert_eq!(Some(LibraryEvent::CheckedIn(id)), events.recv().await.ok());
+        assert_eq!(Some(LibraryEvent::Removed(id)), events.recv().await.ok());
+        assert_eq!(
+            Some(LibraryEvent::Removed(guestbook)),
+            events.recv().await.ok()
+        );
+
+        /* existing checkouts were returned */
+        assert!(lib.lookup_checkouts_by_guest(whiskers).await.is_empty());
+        assert!(lib.lookup_metadata(id).is_free());
+        /* and nothing else sees it */
+        let found: Vec<_> = lib
+            .search("foo", whiskers)
+            .await
+            .into_iter()
+            .map(|r| r.1)
+            .collect();
+        assert_eq!(vec![other], found);
+        assert_eq!(Err(UpdateEntryError::Removed), lib.checkout(id, whiskers));
+        assert_eq!(
+            Err(UpdateEntryError::Removed),
+            lib.update_metadata_fields(id, Some("bar".into()), None, None, whiskers)
+                .await
+        );
+        assert_eq!(
+            Err(GuestbookError::NoGuestbook),
+            lib.append_guestbook(whiskers, "hi", day).await
+        );
+        let metrics = lib.metrics().await;
+        assert_eq!(
+            (1, 2, 0),
+            (metrics.books, metrics.trashed, metrics.checked_out)
+        );
+        assert_eq!(vec![(id, day), (guestbook, day)], lib.trash());
+
+        assert_eq!(Ok(()), lib.restore(id));
+        assert_eq!(Err(UpdateEntryError::NotTrashed), lib.restore(id));
+        assert_eq!(Err(UpdateEntryError::NotTrashed), lib.restore(other));
+        assert_eq!(Some(LibraryEvent::Restored(id)), events.recv().await.ok());
+        assert_eq!(2, lib.search("foo", whiskers).await.len());
+        lib.checkout(id, whiskers).unwrap();
+    }
+
+    #[tokio::test]
+    async fn search_skips_trash_for_guests() {
+        let lib = Library::new();
+        let whiskers = lib
+            .register_guest("10.0.0.1".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let stranger = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "stranger")
+            .await
+            .unwrap();
+        let diary = Book {
+            title: String::from("diary"),
+            author: String::from("whiskers"),
+            description: String::from("private thoughts"),
+            content: String::from("the tuna is hidden behind the xylophone"),
+            language: None,
+        };
+        let public = Book {
+            title: String::from("foo"),
+            author: String::from("whiskers"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let id = lib.add(diary, whiskers).await.unwrap();
+        lib.add(public, whiskers).await.unwrap();
+
+        let day = UNIX_EPOCH + Duration::from_secs(19875 * 86_400);
+        lib.soft_delete(id, day, Library::OPERATOR).unwrap();
+        for guest in [stranger, whiskers] {
+            assert!(lib.search("xylophone", guest).await.is_empty());
+            assert_eq!(1, lib.search("", guest).await.len());
+        }
+        let found = lib.search("xylophone", Library::OPERATOR).await;
+        assert_eq!(vec![id], found.iter().map(|r| r.1).collect::<Vec<_>>());
+        assert_eq!(2, lib.search("", Library::OPERATOR).await.len());
+
+        lib.restore(id).unwrap();
+        assert_eq!(1, lib.search("xylophone", stranger).await.len());
+    }
+
+    #[tokio::test]
+    async fn evict_stale_guests() {
+        let day = Duration::from_secs(86_400);
+        let start = UNIX_EPOCH + 19875 * day;
+        let clock = Arc::new(ManualClock::new(start));
+        let mut lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        lib.set_guest_limit(6, 30 * day);
+        let register = async |nick: &str, last: u8| {
+            let addr = IpAddr::from([10, 0, 0, last]);
+            lib.register_guest(addr, nick).await.unwrap()
+        };
+        let idle = register("idle", 1).await;
+        let borrower = register("borrower", 2).await;
+        let author = register("author", 3).await;
+        let trashed_author = register("trashed author", 4).await;
+        lib.set_passphrase(idle, "hunter2").await;
+        let token = lib.issue_token(idle).await;
+
+        let book = Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lent = lib.add(book.clone(), Library::OPERATOR).await.unwrap();
+        lib.checkout(lent, borrower).unwrap();
+        lib.add(book.clone(), author).await.unwrap();
+        let gone = lib.add(book.clone(), trashed_author).await.unwrap();
+        lib.soft_delete(gone, start, Library::OPERATOR).unwrap();
+
+        /* last seen exactly at the cutoff isn't before it */
+        assert_eq!(0, lib.evict_stale(start).await);
+        clock.advance(31 * day);
+        let recent = register("recent", 5).await;
+        assert_eq!(6, lib.metrics().await.guests);
+
+        /* full, but the idle guest can make room */
+        let newcomer = register("newcomer", 6).await;
+        assert_eq!(6, lib.metrics().await.guests);
+        assert_eq!(
+            None,
+            lib.lookup_guest_by_addr(IpAddr::from([10, 0, 0, 1])).await
+        );
+        assert_eq!(None, lib.lookup_guest_by_nick("idle").await);
+        assert_eq!(None, lib.login(&token).await);
+        assert_eq!(
+            Err(ClaimError::UnknownNickname),
+            lib.claim_guest(IpAddr::from([10, 0, 0, 9]), "idle", "hunter2")
+                .await
+        );
+        for guest in [borrower, author, trashed_author, recent] {
+            assert!(lib.lookup_nick(guest).await.is_some());
+        }
+
+        /* nobody else is stale: not the borrower, the authors or the operator */
+        clock.advance(365 * day);
+        lib.touch(recent);
+        lib.touch(newcomer);
+        assert_eq!(
+            Err(RegisterError::Full),
+            lib.register_guest(IpAddr::from([10, 0, 0, 7]), "latecomer")
+                .await
+        );
+
+        /* the nickname and address are free for anyone again */
+        lib.checkin(lent, borrower).unwrap();
+        clock.advance(day);
+        lib.touch(recent);
+        assert_eq!(2, lib.evict_stale(lib.now()).await);
+        assert!(lib.lookup_nick(recent).await.is_some());
+        let idle = lib
+            .register_guest(IpAddr::from([10, 0, 0, 1]), "idle")
+            .await
+            .unwrap();
+        assert_eq!(Some(idle), lib.lookup_guest_by_nick("idle").await);
+        assert_eq!(
+            Some(idle),
+            lib.lookup_guest_by_addr(IpAddr::from([10, 0, 0, 1])).await
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_guests() {
+        let day = Duration::from_secs(86_400);
+        let start = UNIX_EPOCH + 19875 * day;
+        let clock = Arc::new(ManualClock::new(start));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let home = IpAddr::from([10, 0, 0, 2]);
+        let work = IpAddr::from([10, 0, 0, 3]);
+        let whiskers = lib.register_guest(home, "whiskers").await.unwrap();
+        let twice = lib.register_guest(work, "whiskers2").await.unwrap();
+        lib.set_passphrase(twice, "hunter2").await;
+        let token = lib.issue_token(twice).await;
+        let book = |title: &str| Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+
+        /* one of each thing a guest can have */
+        let added = lib.add(book("added"), twice).await.unwrap();
+        let out = lib.add(book("out"), Library::OPERATOR).await.unwrap();
+        let back = lib.add(book("back"), Library::OPERATOR).await.unwrap();
+        let both = lib.add(book("both"), Library::OPERATOR).await.unwrap();
+        let theirs = lib.add(book("theirs"), whiskers).await.unwrap();
+        lib.checkout(back, twice).unwrap();
+        clock.advance(day);
+        lib.checkin(back, twice).unwrap();
+        lib.checkout(out, twice).unwrap();
+        lib.checkout(added, Library::OPERATOR).unwrap();
+        lib.checkout(theirs, twice).unwrap();
+        lib.set_bookmark(back, twice, Some(4));
+        lib.set_bookmark(both, twice, Some(7));
+        lib.set_bookmark(both, whiskers, Some(2));
+        lib.record_progress(back, twice, 5);
+        lib.record_progress(both, twice, 9);
+        lib.record_progress(both, whiskers, 3);
+        lib.touch(twice);
+
+        let expect = Merged {
+            books: 1,
+            checkouts: 2,
+            loans: 3,
+            bookmarks: 1,
+            progress: 2,
+            mail: 1,
+        };
+        /* looking first changes nothing */
+        assert_eq!(Ok(expect), lib.merge_guests(twice, whiskers, true).await);
+        assert_eq!(Some(twice), lib.lookup_guest_by_nick("whiskers2").await);
+        assert_eq!(twice, lib.lookup_metadata(added).added_by);
+        assert_eq!(Some(twice), lib.lookup_metadata(out).checked_out_by);
+        assert_eq!(Some(4), lib.bookmark(back, twice));
+
+        for (from, into, err) in [
+            (twice, twice, MergeError::SameGuest),
+            (Library::OPERATOR, whiskers, MergeError::Operator),
+            (Library::NOBODY, whiskers, MergeError::Unregistered),
+            (twice, Library::NOBODY, MergeError::Unregistered),
+        ] {
+            assert_eq!(Err(err), lib.merge_guests(from, into, false).await);
+        }
+        {
+            let _visit = lib.visit(twice);
+            assert_eq!(
+                Err(MergeError::Connected),
+                lib.merge_guests(twice, whiskers, false).await
+            );
+        }
+
+        assert_eq!(Ok(expect), lib.merge_guests(twice, whiskers, false).await);
+        lib.check_invariants().await;
+
+        /* everything is whiskers's now */
+        assert_eq!(whiskers, lib.lookup_metadata(added).added_by);
+        assert_eq!(Some(whiskers), lib.lookup_metadata(out).checked_out_by);
+        assert_eq!(Some(whiskers), lib.lookup_metadata(theirs).checked_out_by);
+        assert!(lib.loans().iter().all(|loan| loan.guest != twice));
+        assert_eq!(
+            vec![out, theirs],
+            lib.lookup_checkouts_by_guest(whiskers)
+                .await
+                .into_iter()
+                .map(|(id, _meta)| id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(4), lib.bookmark(back, whiskers));
+        assert_eq!(Some(2), lib.bookmark(both, whiskers));
+        assert_eq!(Some(5), lib.progress(back, whiskers));
+        assert_eq!(Some(9), lib.progress(both, whiskers));
+        assert_eq!(None, lib.progress(back, twice));
+        /* not a word about whiskers borrowing their own book */
+        assert_eq!(
+            vec![Mail::CheckedOut {
+                by: Library::OPERATOR,
+                book_id: added
+            }],
+            lib.take_mail(whiskers)
+        );
+        assert_eq!(Some(start + day), lib.last_seen(whiskers));
+        assert_eq!(Ok(()), lib.checkin(out, whiskers).map(|_| ()));
+
+        /* and the second nickname is gone, address, passphrase and all */
+        assert_eq!(None, lib.lookup_guest_by_nick("whiskers2").await);
+        assert_eq!(None, lib.lookup_guest_by_addr(work).await);
+        assert_eq!(Some(whiskers), lib.lookup_guest_by_addr(home).await);
+        assert_eq!(None, lib.login(&token).await);
+        assert_eq!(
+            Err(ClaimError::UnknownNickname),
+            lib.claim_guest(work, "whiskers2", "hunter2").await
+        );
+        assert_eq!(
+            Err(MergeError::Unregistered),
+            lib.merge_guests(twice, whiskers, false).await
+        );
+        lib.register_guest(work, "whiskers2").await.unwrap();
+        lib.check_invariants().await;
+    }
+
+    #[tokio::test]
+    async fn purge_trash() {
+        let book = Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        };
+        let lib = Library::new();
+        let old = lib.add(book.clone(), Library::OPERATOR).await.unwrap();
+        let new = lib.add(book.clone(), Library::OPERATOR).await.unwrap();
+        let day = UNIX_EPOCH + Duration::from_secs(19875 * 86_400);
+        let week = Duration::from_secs(7 * 86_400);
+        lib.soft_delete(old, day, Library::OPERATOR).unwrap();
+        lib.soft_delete(new, day + week, Library::OPERATOR).unwrap();
+
+        assert_eq!(1, lib.purge_trash(day + week, week).await);
+        assert_eq!(vec![(new, day + week)], lib.trash());
+        assert_eq!(Some(Removal::Purged), lib.lookup_metadata(old).removed);
+        assert_eq!("", lib.lookup_book_by_id(old).await.content);
+        assert_eq!(Err(UpdateEntryError::NotTrashed), lib.restore(old));
+        assert_eq!(
+            Err(UpdateEntryError::Removed),
+            lib.soft_delete(old, day, Library::OPERATOR)
+        );
+
+        /* their IDs aren't reused */
+        assert!(new < lib.add(book, Library::OPERATOR).await.unwrap());
+        assert_eq!(1, lib.metrics().await.trashed);
+    }
+
+    #[tokio::test]
+    async fn guestbook_concurrent_signers() {
+        let lib = Arc::new(Library::new());
+        let id = lib.open_guestbook().await;
+
+        let mut tasks = Vec::new();
+        for n in 0..32u8 {
+            let lib = Arc::clone(&lib);
+            tasks.push(tokio::spawn(async move {
+                let guest = lib
+                    .register_guest(IpAddr::from([10, 0, 1, n]), format!("cat {n}"))
+                    .await
+                    .unwrap();
+                lib.append_guestbook(guest, &format!("meow from {n}"), SystemTime::now())
+                    .await
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        let content = lib.lookup_book_by_id(id).await.content.clone();
+        assert_eq!(32, content.lines().count());
+        for n in 0..32 {
+            assert!(content.contains(&format!("cat {n}: meow from {n}\n")));
+        }
+    }
+}
+
+mod metrics {
+    use super::{scripted, transcript};
+    use crate::library::{Book, Library, LibraryMetrics};
+    use crate::metrics;
+    use crate::shell::{self, Command, Session};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn scrape(port: u16) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        body.to_string()
+    }
+
+    fn value(page: &str, series: &str) -> u64 {
+        page.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .map(|value| value.parse().unwrap())
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn scrape_after_session() {
+        let lib = Arc::new(Library::new());
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(metrics::serve(listener, Arc::clone(&lib)));
+
+        let before = scrape(port).await;
+
+        lib.add(
+            Book {
+                title: String::from("foo"),
+                author: String::from("cat 1"),
+                description: String::from("bar"),
+                content: String::from("baz"),
+                language: None,
+            },
+            Library::OPERATOR,
+        )
+        .await
+        .unwrap();
+        let (mut stream, client) = scripted("foo\n1\n").await;
+        shell::do_cmd(
+            &mut stream,
+            Command::CheckOut,
+            &lib,
+            &mut Session::new(Library::OPERATOR),
+        )
+        .await
+        .unwrap();
+        transcript(stream, client).await;
+
+        let after = scrape(port).await;
+
+        // other tests share the process-wide counters, so only lower bounds hold
+        for (series, delta) in [
+            ("catlib_books_added_total", 1),
+            ("catlib_checkouts_total", 1),
+            ("catlib_search_duration_seconds_count", 1),
+        ] {
+            assert!(
+                value(&after, series) >= value(&before, series) + delta,
+                "{series} did not advance"
+            );
+        }
+        assert!(after.contains("# TYPE catlib_connections_active gauge"));
+
+        /* these are the library's own, so exact */
+        assert_eq!(1, value(&after, "catlib_books_total"));
+        assert_eq!(1, value(&after, "catlib_books_checked_out"));
+        assert_eq!(1, value(&after, "catlib_guests_total"));
+        assert_eq!(
+            1,
+            value(&after, "catlib_commands_total{command=\"checkout\"}")
+        );
+    }
+
+    #[test]
+    fn exposition_parses() {
+        let page = metrics::render(LibraryMetrics {
+            books: 3,
+            trashed: 1,
+            checked_out: 2,
+            guests: 1,
+            commands: vec![("search", 5), ("checkout", 2)],
+        });
+
+        let mut types = HashMap::new();
+        let mut samples = HashMap::new();
+        for line in page.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut words = comment.splitn(3, ' ');
+                let (kind, name, rest) = (words.next(), words.next(), words.next());
+                assert!(name.is_some() && rest.is_some(), "{line}");
+                if kind == Some("TYPE") {
+                    assert!(types.insert(name.unwrap(), rest.unwrap()).is_none());
+                }
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let value: f64 = value.parse().unwrap_or_else(|_| panic!("{line}"));
+            let name = match series.split_once('{') {
+                Some((name, labels)) => {
+                    assert!(labels.ends_with('}') && labels.contains("=\""), "{line}");
+                    name
+                }
+                None => series,
+            };
+            assert!(
+                name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                "{line}"
+            );
+            let family = ["_bucket", "_sum", "_count"]
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .filter(|family| types.get(family) == Some(&"histogram"))
+                .unwrap_or(name);
+            assert!(types.contains_key(family), "no TYPE for {line}");
+            samples.insert(series.to_string(), value);
+        }
+
+        for (name, kind) in [
+            ("catlib_books_total", "gauge"),
+            ("catlib_books_checked_out", "gauge"),
+            ("catlib_guests_total", "gauge"),
+            ("catlib_connections_active", "gauge"),
+            ("catlib_connections_accepted_total", "counter"),
+            ("catlib_checkouts_total", "counter"),
+            ("catlib_commands_total", "counter"),
+            ("catlib_search_duration_seconds", "histogram"),
+        ] {
+            assert_eq!(Some(&kind), types.get(name), "{name}");
+        }
+        assert_eq!(Some(&3.0), samples.get("catlib_books_total"));
+        assert_eq!(Some(&2.0), samples.get("catlib_books_checked_out"));
+        assert_eq!(
+            Some(&5.0),
+            samples.get("catlib_commands_total{command=\"search\"}")
+        );
+    }
+}
+
+mod dashboard {
+    use super::{scripted, transcript};
+    use crate::clock::{ManualClock, OsRng};
+    use crate::dashboard::{self, Dashboard};
+    use crate::library::{Book, Library, LibraryEvent};
+    use crate::shell::{self, Command};
+    use core::net::IpAddr;
+    use core::time::Duration;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    #[tokio::test]
+    async fn gather() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let whiskers = lib
+            .register_guest(IpAddr::from([10, 0, 0, 1]), "whiskers")
+            .await
+            .unwrap();
+        let content = "purr\npurr\n";
+        let naps = Book {
+            title: String::from("naps"),
+            author: String::from("whiskers"),
+            description: String::new(),
+            content: String::from(content),
+            language: None,
+        };
+        let id = lib.add(naps.clone(), whiskers).await.unwrap();
+        clock.advance(Duration::from_secs(3723));
+        lib.checkout(id, whiskers).unwrap();
+
+        let browsing = lib.visit(Library::NOBODY);
+        let reading = lib.visit(whiskers);
+        reading.update(whiskers, Some(Command::Read.long()));
+
+        let dash = Dashboard::gather(&lib).await;
+        assert_eq!(Duration::from_secs(3723), dash.uptime);
+        assert_eq!(
+            vec![
+                (None, None, None),
+                (
+                    Some(Arc::from("whiskers")),
+                    Some("read"),
+                    Some(Duration::from_secs(3723))
+                )
+            ],
+            dash.sessions
+        );
+        assert_eq!(
+            (1, 1, 2),
+            (
+                dash.metrics.books,
+                dash.metrics.checked_out,
+                dash.metrics.guests
+            )
+        );
+        assert_eq!(
+            vec![
+                (
+                    Duration::from_secs(3723),
+                    LibraryEvent::Added(id),
+                    String::from("naps")
+                ),
+                (
+                    Duration::ZERO,
+                    LibraryEvent::CheckedOut(id),
+                    String::from("naps")
+                ),
+            ],
+            dash.events
+        );
+        assert_eq!(
+            (1, content.len()),
+            (dash.pool.books, dash.pool.content_bytes)
+        );
+        assert!(0 < dash.pool.stored_bytes);
+
+        let stored = dash.pool.stored_bytes;
+        assert_eq!(
+            vec![
+                String::from("up 1h02m03s"),
+                String::from("2 connected:"),
+                String::from("  (looking around): at the prompt"),
+                String::from("  whiskers: read, idle 1h02m03s"),
+                String::from("1 on the shelves, 0 in the trash, 1 checked out; 2 guests"),
+                String::from("recent events:"),
+                String::from("  1h02m03s ago: added \"naps\""),
+                String::from("  0s ago: checked out \"naps\""),
+                format!(
+                    "pool: 1 books, {} bytes of content kept in {stored}",
+                    content.len()
+                ),
+            ],
+            dash.render()
+        );
+
+        /* only the last few events are kept, and visits end with their connections */
+        drop((browsing, reading));
+        for _ in 0..Library::RECENT_EVENTS {
+            lib.add(naps.clone(), Library::OPERATOR).await.unwrap();
+        }
+        let dash = Dashboard::gather(&lib).await;
+        assert!(dash.sessions.is_empty());
+        assert_eq!(Library::RECENT_EVENTS, dash.events.len());
+        assert!(!dash
+            .events
+            .iter()
+            .any(|(_, event, _)| event.book_id() == id));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!("0s", dashboard::format_duration(Duration::ZERO));
+        assert_eq!(
+            "59s",
+            dashboard::format_duration(Duration::from_millis(59_999))
+        );
+        assert_eq!("1m00s", dashboard::format_duration(Duration::from_secs(60)));
+        assert_eq!(
+            "26h00m01s",
+            dashboard::format_duration(Duration::from_secs(26 * 3600 + 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn redraw_region() {
+        let lines = |lines: &[&str]| {
+            lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        /* nothing to draw over yet */
+        let (mut stream, client) = scripted("").await;
+        shell::redraw_region(&mut stream, 0, &lines(&["a", "b"]))
+            .await
+            .unwrap();
+        assert_eq!("\x1B[2Ka\n\x1B[2Kb\n", transcript(stream, client).await);
+
+        /* as tall as before */
+        let (mut stream, client) = scripted("").await;
+        shell::redraw_region(&mut stream, 2, &lines(&["a", "b"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            "\x1B[F\x1B[F\x1B[2Ka\n\x1B[2Kb\n",
+            transcript(stream, client).await
+        );
+
+        /* shorter: the rest is blanked and the cursor comes back up */
+        let (mut stream, client) = scripted("").await;
+        shell::redraw_region(&mut stream, 3, &lines(&["a"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            "\x1B[F\x1B[F\x1B[F\x1B[2Ka\n\x1B[2K\n\x1B[2K\n\x1B[F\x1B[F",
+            transcript(stream, client).await
+        );
+    }
+}
+
+mod shell {
+    use super::{scripted, transcript};
+    use crate::client::Client;
+    use crate::clock::{ManualClock, OsRng};
+    use crate::date;
+    use crate::editor;
+    use crate::export;
+    use crate::library::{Book, BookID, Field, Library, UpdateEntryError};
+    use crate::messages::{Flavor, Msg};
+    use crate::records::{self, Format};
+    use crate::shell::{
+        self, AliasError, Aliases, BufferSizes, Capabilities, Choice, Command, History, Notices,
+        Prompt, PromptError, Recall, Session, ShellError, SEARCH_RESULTS,
+    };
+    use core::net::{Ipv4Addr, SocketAddr};
+    use core::time::Duration;
+    use std::io;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufStream};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn book(title: &str) -> Book {
+        Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from("baz"),
+            language: None,
+        }
+    }
+
+    /// Run one command with `input` as everything the guest types, returning
+    /// what they saw.
+    async fn run(lib: &Library, session: &mut Session, cmd: Command, input: &str) -> String {
+        let (mut stream, client) = scripted(input).await;
+        shell::do_cmd(&mut stream, cmd, lib, session).await.unwrap();
+        transcript(stream, client).await
+    }
+
+    /// `msg` as guests see it by default.
+    fn said(msg: Msg<'_>) -> String {
+        msg.render(Flavor::Plain)
+    }
+
+    async fn checked_out_book(lib: &Library, session: &mut Session) -> BookID {
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let out = run(lib, session, Command::CheckOut, "foo\n1\n").await;
+        assert!(out.contains(&said(Msg::CheckedOut { rank: 1 })), "{out}");
+        id
+    }
+
+    #[tokio::test]
+    async fn shelf() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::Shelf, "").await;
+        assert_eq!(said(Msg::CheckOutFirst), out);
+
+        let mut ids = Vec::new();
+        for title in ["Nap", "Tuna"] {
+            let book = Book {
+                content: format!("all about {title}\n"),
+                ..book(title)
+            };
+            let id = lib.add(book, Library::OPERATOR).await.unwrap();
+            lib.checkout(id, Library::OPERATOR).unwrap();
+            ids.push(id);
+        }
+
+        /* back and forth without searching or listing again */
+        let input = "read 1\nq\nread 2\nq\nlook 1\nread 3\nreturn 1\nq\n";
+        let out = run(&lib, &mut session, Command::Shelf, input).await;
+        assert!(
+            out.contains("all about Nap") && out.contains("all about Tuna"),
+            "{out}"
+        );
+        assert_eq!(1, lib.lookup_metadata(ids[0]).reads);
+        assert_eq!(1, lib.lookup_metadata(ids[1]).reads);
+        let listed = |rank: &str| out.lines().filter(|line| line.starts_with(rank)).count();
+        assert_eq!((2, 1), (listed("1. "), listed("2. ")), "{out}");
+        assert!(out.contains(&said(Msg::PickOneOf {
+            listed: "read, return"
+        })));
+        assert!(out.contains(&said(Msg::RankTooLarge { max: 2 })));
+
+        /* nonsense is told apart, and only put up with for so long */
+        let nonsense = run(&lib, &mut session, Command::Shelf, "read x\nread 0\nr\n").await;
+        assert!(nonsense.contains(&said(Msg::NotANumber)), "{nonsense}");
+        assert!(
+            nonsense.contains(&said(Msg::RankTooSmall { min: 1 })),
+            "{nonsense}"
+        );
+        assert!(nonsense.ends_with(&said(Msg::Nevermind)), "{nonsense}");
+        assert_eq!(
+            shell::PROMPT_TRIES,
+            nonsense.matches("read <n>, return <n>, or q? ").count()
+        );
+
+        /* returning one lists what's left */
+        assert!(out.contains(&said(Msg::Returned { rank: 1 })), "{out}");
+        assert!(lib.lookup_metadata(ids[0]).is_free());
+        let after = out.rsplit("for less than a minute.\n").next().unwrap();
+        assert!(
+            after.contains("'Tuna'") && !after.contains("'Nap'"),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_touch_last_seen() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        assert_eq!(Some(UNIX_EPOCH), lib.last_seen(whiskers));
+        assert_eq!(None, lib.last_seen(Library::NOBODY));
+
+        clock.advance(Duration::from_secs(90));
+        let mut session = Session::new(whiskers);
+        run(&lib, &mut session, Command::Search, "nap\n").await;
+        let seen = UNIX_EPOCH + Duration::from_secs(90);
+        assert_eq!(Some(seen), lib.last_seen(whiskers));
+    }
+
+    #[tokio::test]
+    async fn checkin_lost_race() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        /* returned elsewhere while the guest is picking it */
+        let (server, mut client) = tokio::io::duplex(1 << 16);
+        let mut stream = BufStream::new(server);
+        let guest = async {
+            let mut seen = Vec::new();
+            let mut buf = [0; 256];
+            while !String::from_utf8_lossy(&seen).contains("which item number? ") {
+                let n = client.read(&mut buf).await.unwrap();
+                seen.extend_from_slice(&buf[..n]);
+            }
+            lib.checkin(id, Library::OPERATOR).unwrap();
+            client.write_all(b"1\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            let mut rest = String::new();
+            client.read_to_string(&mut rest).await.unwrap();
+            rest
+        };
+        let (done, out) = tokio::join!(
+            async {
+                let done = shell::do_cmd(&mut stream, Command::CheckIn, &lib, &mut session).await;
+                stream.shutdown().await.unwrap();
+                done
+            },
+            guest
+        );
+        done.unwrap();
+        assert_eq!(said(Msg::AlreadyCheckedIn { rank: 1 }), out);
+        assert!(lib.lookup_metadata(id).is_free());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn undo_checkout() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        tokio::time::advance(Session::UNDO_WINDOW - Duration::from_secs(1)).await;
+        let out = run(&lib, &mut session, Command::Undo, "").await;
+        assert!(
+            out.contains(&said(Msg::UndidCheckout { title: "foo" })),
+            "{out}"
+        );
+        let meta = lib.lookup_metadata(id);
+        assert!(meta.is_free());
+        assert_eq!(0, meta.checkouts);
+
+        let out = run(&lib, &mut session, Command::Undo, "").await;
+        assert!(out.contains(&said(Msg::NothingToUndo)), "{out}");
+    }
+
+    #[tokio::test]
+    async fn checkin_all() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::CheckInAll, "").await;
+        assert!(out.contains(&said(Msg::CheckOutFirst)), "{out}");
+
+        let foo = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let bar = lib.add(book("bar"), Library::OPERATOR).await.unwrap();
+        lib.checkout(foo, Library::OPERATOR).unwrap();
+        lib.checkout(bar, Library::OPERATOR).unwrap();
+        let out = run(&lib, &mut session, Command::CheckInAll, "").await;
+        assert!(
+            out.contains(&said(Msg::ReturnedBooks { count: 2 })),
+            "{out}"
+        );
+        assert!(lib.lookup_metadata(foo).is_free());
+        assert!(lib.lookup_metadata(bar).is_free());
+    }
+
+    #[tokio::test]
+    async fn command_counts() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        assert!(lib.command_counts().is_empty());
+
+        for (cmd, input) in [
+            (Command::Search, "foo\n"),
+            (Command::None, ""),
+            (Command::Help, ""),
+            (Command::Search, "bar\n"),
+        ] {
+            run(&lib, &mut session, cmd, input).await;
+        }
+        assert_eq!(vec![("help", 1), ("search", 2)], lib.command_counts());
+    }
+
+    #[test]
+    fn recall_syntax() {
+        assert_eq!(Some(Recall::Last), Recall::parse("!!"));
+        assert_eq!(Some(Recall::Entry(12)), Recall::parse("!12"));
+        for line in [
+            "",
+            "!",
+            "!!!",
+            "!-1",
+            "!+1",
+            "! 1",
+            "!x",
+            "search",
+            "!99999999999999999999999",
+        ] {
+            assert_eq!(None, Recall::parse(line), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn history_numbering() {
+        let mut history = History::default();
+        assert_eq!(None, history.recall(Recall::Last));
+        for n in 1..=History::CAPACITY + 2 {
+            history.push(n.to_string());
+        }
+        assert_eq!(History::CAPACITY, history.iter().count());
+        assert_eq!(Some((3, "3")), history.iter().next());
+        assert_eq!(None, history.recall(Recall::Entry(2)));
+        assert_eq!(Some("3"), history.recall(Recall::Entry(3)));
+        assert_eq!(Some("102"), history.recall(Recall::Last));
+        assert_eq!(None, history.recall(Recall::Entry(103)));
+    }
+
+    #[test]
+    fn alias_expansion() {
+        let mut aliases = Aliases::default();
+        assert_eq!(Ok(()), aliases.set("sd", "search  dune "));
+        assert_eq!(Some(String::from("search  dune")), aliases.expand("sd"));
+        assert_eq!(
+            Some(String::from("search  dune messiah")),
+            aliases.expand("sd  messiah")
+        );
+        assert_eq!(None, aliases.expand("sdx"));
+        assert_eq!(
+            Some((Command::Search, "dune  messiah")),
+            Command::parse_line("search dune  messiah")
+        );
+        assert_eq!(Some((Command::Meow, "")), Command::parse_line("meow meow"));
+
+        assert_eq!(Err(AliasError::Reserved), aliases.set("co", "quit"));
+        assert_eq!(Err(AliasError::Reserved), aliases.set("checkout", "quit"));
+        assert_eq!(Err(AliasError::InvalidName), aliases.set("!1", "quit"));
+        assert_eq!(Err(AliasError::EmptyExpansion), aliases.set("nap", " "));
+
+        /* expanded once, never again */
+        aliases.set("loop", "loop").unwrap();
+        aliases.set("outer", "inner").unwrap();
+        aliases.set("inner", "quit").unwrap();
+        assert_eq!(Some(String::from("loop")), aliases.expand("loop"));
+        assert_eq!(Some(String::from("inner")), aliases.expand("outer"));
+        assert_eq!(None, Command::parse_line("inner"));
+
+        assert_eq!(Some(String::from("quit")), aliases.remove("inner"));
+        assert_eq!(None, aliases.remove("inner"));
+        assert_eq!(
+            vec![("loop", "loop"), ("outer", "inner"), ("sd", "search  dune")],
+            aliases.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn size_session() {
+        let lib = Library::new();
+        let input = "size 80x30\nhelp\n\nsize 0x5\nsize\n\nhelp\nquit\n";
+        let (mut stream, client) = scripted(input).await;
+        let addr = SocketAddr::new(Library::OPERATOR_ADDR, 1234);
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+
+        assert!(
+            out.contains(&said(Msg::SizeSet { cols: 80, rows: 30 })),
+            "{out}"
+        );
+        /* help runs past a screenful only the first time */
+        assert!(Command::ALL.len() > 30);
+        assert_eq!(1, out.matches("-- more --").count(), "{out}");
+        assert!(out.contains(&said(Msg::InvalidSize)), "{out}");
+        assert!(out.contains(&said(Msg::SizeReset)), "{out}");
+    }
+
+    #[tokio::test]
+    async fn alias_session() {
+        let lib = Library::new();
+        lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let input = "alias sd search\nalias\nsd foo\nalias co x\nunalias sd\nsd foo\nquit\n";
+        let (mut stream, client) = scripted(input).await;
+        let addr = SocketAddr::new(Library::OPERATOR_ADDR, 1234);
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+
+        assert!(
+            out.contains(&said(Msg::AliasSet {
+                name: "sd",
+                expansion: "search"
+            })),
+            "{out}"
+        );
+        assert!(out.contains("; sd = search\n; "), "{out}");
+        /* the rest of the line answers the search */
+        assert!(out.contains("; search foo\nsearch query? "), "{out}");
+        assert_eq!(1, out.matches("'foo', by cat 1").count(), "{out}");
+        assert!(
+            out.contains(&said(Msg::AlreadyACommand { name: "co" })),
+            "{out}"
+        );
+        assert!(
+            out.contains(&format!(
+                "{}; {}",
+                said(Msg::ForgotAlias { name: "sd" }),
+                said(Msg::UnknownCommand)
+            )),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_prompt() {
+        assert_eq!("; ", Prompt::default().render("whiskers", 1));
+        let prompt = Prompt::new(" %n has %c, 100%% ").unwrap();
+        assert_eq!("whiskers has 1, 100% ", prompt.render("whiskers", 1));
+        for invalid in ["%", "%x>", "100%", "bell\x07"] {
+            assert_eq!(
+                Err(PromptError::Invalid),
+                Prompt::new(invalid),
+                "{invalid:?}"
+            );
+        }
+        let long = "m".repeat(Prompt::MAX_LEN + 1);
+        assert_eq!(Err(PromptError::TooLong), Prompt::new(&long));
+
+        let lib = Library::new();
+        lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        lib.register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let input = "prompt %n (%c)>\ncheckout foo\n1\nprompt %x\nprompt\n\nquit\n";
+        let (mut stream, client) = scripted(input).await;
+        shell::process_socket(
+            &mut stream,
+            "10.0.0.2:1".parse().unwrap(),
+            &lib,
+            Capabilities::FULL,
+        )
+        .await
+        .unwrap();
+        let out = transcript(stream, client).await;
+
+        let set = said(Msg::PromptSet);
+        assert!(out.contains(&format!("{set}whiskers (0)> ")), "{out}");
+        let checked_out = said(Msg::CheckedOut { rank: 1 });
+        assert!(
+            out.contains(&format!("{checked_out}whiskers (1)> ")),
+            "{out}"
+        );
+        let invalid = said(Msg::InvalidPrompt);
+        assert!(out.contains(&format!("{invalid}whiskers (1)> ")), "{out}");
+        assert!(
+            out.ends_with(&format!("{}; {}", said(Msg::PromptReset), said(Msg::Bye))),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_search() {
+        let lib = Library::new();
+        lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let (mut stream, client) = scripted("search\nfoo\n!!\nfoo\n!3\nhistory\nquit\n").await;
+        let addr = SocketAddr::new(Library::OPERATOR_ADDR, 1234);
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+
+        /* only the replay is echoed; the guest's own typing isn't */
+        assert_eq!(2, out.matches("search query? ").count(), "{out}");
+        assert_eq!(1, out.matches("; search\nsearch query? ").count(), "{out}");
+        assert_eq!(2, out.matches("'foo', by cat 1").count(), "{out}");
+        assert!(
+            out.contains(&format!("; {}", said(Msg::NoSuchHistoryEntry))),
+            "{out}"
+        );
+        assert!(
+            out.contains(&format!("; 1  search\n2  history\n; {}", said(Msg::Bye))),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn confirm_answers() {
+        let lib = Library::new();
+        for (input, default, expect) in [
+            ("y\n", false, true),
+            ("YES\n", false, true),
+            ("no\n", true, false),
+            ("\n", true, true),
+            ("\n", false, false),
+            ("maybe\ny\n", false, true),
+            ("what\nhuh\neh\ny\n", true, false),
+            ("", true, false),
+        ] {
+            let (mut stream, client) = scripted(input).await;
+            let answer = shell::confirm(&mut stream, &lib, "sure?", default)
+                .await
+                .unwrap();
+            assert_eq!(expect, answer, "{input:?}");
+            let out = transcript(stream, client).await;
+            let hint = if default {
+                "sure? (Y/n) "
+            } else {
+                "sure? (y/N) "
+            };
+            assert!(out.starts_with(hint), "{out}");
+        }
+
+        let (mut stream, client) = scripted("what\nhuh\neh\n").await;
+        shell::confirm(&mut stream, &lib, "sure?", true)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        assert_eq!(
+            shell::PROMPT_TRIES,
+            out.matches(&said(Msg::AnswerYesOrNo)).count()
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_found_is_not_nevermind() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::CheckOut, "\n").await;
+        assert_eq!(format!("search query? {}", said(Msg::LibraryEmpty)), out);
+
+        lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        for cmd in [
+            Command::Preview,
+            Command::Peek,
+            Command::CheckOut,
+            Command::Edit,
+            Command::Delete,
+        ] {
+            let out = run(&lib, &mut session, cmd, "zzz\n").await;
+            assert_eq!(format!("search query? {}", said(Msg::NoMatches)), out);
+        }
+
+        /* giving up on a choice there was still gets one */
+        let out = run(&lib, &mut session, Command::CheckOut, "foo\n\n").await;
+        assert!(out.ends_with(&said(Msg::Nevermind)), "{out}");
+    }
+
+    #[tokio::test]
+    async fn choose_one_answers() {
+        const OPTIONS: &[&str] = &["title", "author", "add"];
+        let lib = Library::new();
+        for (input, expect) in [
+            ("author\n", Some(1)),
+            ("3\n", Some(2)),
+            ("t\n", Some(0)),
+            ("au\n", Some(1)),
+            ("add\n", Some(2)),
+            ("a\nad\n", Some(2)),
+            ("4\n0\n2\n", Some(1)),
+            ("\n", None),
+            ("x\ny\nz\ntitle\n", None),
+            ("", None),
+        ] {
+            let (mut stream, client) = scripted(input).await;
+            let chosen = shell::choose_one(&mut stream, &lib, "which?", OPTIONS)
+                .await
+                .unwrap();
+            assert_eq!(expect, chosen, "{input:?}");
+            let out = transcript(stream, client).await;
+            assert!(out.starts_with("which? (title, author, add) "), "{out}");
+        }
+
+        let (mut stream, client) = scripted("a\nt\n").await;
+        shell::choose_one(&mut stream, &lib, "which?", OPTIONS)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        assert_eq!(
+            1,
+            out.matches(&said(Msg::PickOneOf {
+                listed: "title, author, add"
+            }))
+            .count()
+        );
+    }
+
+    #[tokio::test]
+    async fn choose_rank_answers() {
+        let lib = Library::new();
+        for (input, expect) in [
+            ("2\n", Choice::Picked(1)),
+            ("x\n3\n", Choice::Picked(2)),
+            ("0\n4\n1\n", Choice::Picked(0)),
+            ("\n", Choice::Cancelled),
+            ("q\n", Choice::Cancelled),
+            ("x\n\n2\n", Choice::Cancelled),
+            ("x\ny\nz\n1\n", Choice::GaveUp),
+            ("", Choice::Cancelled),
+        ] {
+            let (mut stream, client) = scripted(input).await;
+            let chosen = shell::choose_rank(&mut stream, &lib, 3).await.unwrap();
+            assert_eq!(expect, chosen, "{input:?}");
+            transcript(stream, client).await;
+        }
+
+        let (mut stream, client) = scripted("2\n").await;
+        assert_eq!(
+            Choice::Nothing,
+            shell::choose_rank(&mut stream, &lib, 0).await.unwrap()
+        );
+        assert_eq!("", transcript(stream, client).await);
+
+        let (mut stream, client) = scripted("one\n0\n99999999999999999999999\n").await;
+        assert_eq!(
+            Choice::GaveUp,
+            shell::choose_rank(&mut stream, &lib, 3).await.unwrap()
+        );
+        let out = transcript(stream, client).await;
+        assert_eq!(
+            shell::PROMPT_TRIES,
+            out.matches("which item number? ").count()
+        );
+        assert!(out.contains(&said(Msg::NotANumber)), "{out}");
+        assert!(out.contains(&said(Msg::RankTooSmall { min: 1 })), "{out}");
+        assert!(out.contains(&said(Msg::RankTooLarge { max: 3 })), "{out}");
+    }
+
+    #[tokio::test]
+    async fn pause_until_enter() {
+        let (mut stream, client) = scripted("anything\n").await;
+        assert!(shell::pause(&mut stream).await.unwrap());
+        assert!(!shell::pause(&mut stream).await.unwrap());
+        assert_eq!("-- more ---- more --", transcript(stream, client).await);
+    }
+
+    #[tokio::test]
+    async fn identical_copies_collapse() {
+        let lib = Library::new();
+        let ids = [
+            lib.add(book("foo"), Library::OPERATOR).await.unwrap(),
+            lib.add(book("foo"), Library::OPERATOR).await.unwrap(),
+            lib.add(book("foo"), Library::OPERATOR).await.unwrap(),
+        ];
+        lib.add(book("food"), Library::OPERATOR).await.unwrap();
+        lib.checkout(ids[0], Library::OPERATOR).unwrap();
+
+        let found = lib.search("foo", Library::OPERATOR).await;
+        let copies = lib.collapse_copies(found).await;
+        assert_eq!(2, copies.len());
+        /* "food" contains "foo" more, so it comes first */
+        let foo = &copies[1];
+        assert_eq!(ids.to_vec(), foo.ids);
+        assert_eq!(2, foo.available);
+        assert!(foo.best.2.is_free());
+        assert_eq!(1, foo.best.2.checkouts);
+
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::Search, "foo\n").await;
+        assert!(out.contains("1. [in]  'food', by cat 1.\n"), "{out}");
+        assert!(
+            out.contains("2. [in]  'foo',  by cat 1. (3 copies, 2 available)\n"),
+            "{out}"
+        );
+
+        for _ in 0..2 {
+            let out = run(&lib, &mut session, Command::CheckOut, "foo\n2\n").await;
+            assert!(out.ends_with("checked out item 2!\n"), "{out}");
+        }
+        assert!(ids.iter().all(|&id| !lib.lookup_metadata(id).is_free()));
+        let out = run(&lib, &mut session, Command::CheckOut, "foo\n2\n").await;
+        assert!(
+            out.contains("2. [yours] 'foo',  by cat 1. (3 copies, 0 available)"),
+            "{out}"
+        );
+        assert!(
+            out.ends_with("you already have this one; 'read' it!\n"),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn meows_back() {
+        assert_eq!(Some(Command::Meow), Command::from_str("meow"));
+        assert_eq!(Some(Command::Meow), Command::from_str("Meow  MEOW"));
+        assert_eq!(Some(3), Command::meows("meow meow meow"));
+        for line in ["homeowner", "meow?", "meow help", "meowmeow"] {
+            assert_eq!(None, Command::from_str(line), "{line:?}");
+        }
+
+        let lib = Library::new();
+        let (mut stream, client) = scripted("meow\nmeow meow meow meow\nhomeowner\nquit\n").await;
+        let addr = SocketAddr::new(Library::OPERATOR_ADDR, 1234);
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        let replies: Vec<&str> = out
+            .split("; ")
+            .filter(|reply| reply.starts_with('\x07'))
+            .collect();
+        assert_eq!(2, replies.len(), "{out:?}");
+        assert_eq!(1, replies[0].matches('\x07').count());
+        assert!(["meow!\n", "mrrp\n", "prrr\n"].contains(&replies[0].trim_start_matches('\x07')));
+        /* capped, and louder */
+        assert_eq!(Command::MAX_MEOWS, replies[1].matches('\x07').count());
+        let loud = replies[1].trim_start_matches('\x07').trim_end();
+        assert_eq!(Command::MAX_MEOWS, loud.split(' ').count());
+        assert_eq!(loud.to_uppercase(), loud);
+        assert!(out.contains(&said(Msg::UnknownCommand)), "{out:?}");
+    }
+
+    #[tokio::test]
+    async fn crlf_lines() {
+        let (mut stream, client) = scripted("s\r\n\r\n \r \r\n").await;
+        let cmd = shell::readln(&mut stream, "; ").await.unwrap();
+        assert_eq!(Some(Command::Search), Command::from_str(&cmd));
+        let empty = shell::readln(&mut stream, "; ").await.unwrap();
+        assert_eq!(Some(Command::None), Command::from_str(&empty));
+        assert_eq!("", shell::readln(&mut stream, "; ").await.unwrap());
+        transcript(stream, client).await;
+    }
+
+    #[tokio::test]
+    async fn change_keeps_indentation() {
+        let lib = Library::new();
+        let mut writer = Session::new(Library::OPERATOR);
+        let input =
+            "Code\ncat 1\nsnippets\n\na\nloop {\nnap();\n}\n.\n3\nc\n\t  nap(); \nq\npublish\n";
+        let out = run(&lib, &mut writer, Command::Add, input).await;
+        assert!(out.ends_with("done!\n"), "{out}");
+
+        let found = lib.search("Code", Library::OPERATOR).await;
+        let book = lib.lookup_book_by_id(found[0].1).await;
+        assert_eq!("\nloop {\n\t  nap(); \n}\n", book.content);
+    }
+
+    #[tokio::test]
+    async fn content_round_trip() {
+        let lib = Library::new();
+        let mut writer = Session::new(Library::OPERATOR);
+        let input = "Blanks\ncat 1\nmostly nothing\n\na\n  indented \n\n\r\n.\nq\npublish\n";
+        let out = run(&lib, &mut writer, Command::Add, input).await;
+        assert!(out.ends_with("done!\n"), "{out}");
+
+        let found = lib.search("Blanks", Library::OPERATOR).await;
+        let id = found[0].1;
+        let book = lib.lookup_book_by_id(id).await;
+        assert_eq!("\n  indented \n\n\n", book.content);
+        assert_eq!(
+            vec!["", "  indented ", "", ""],
+            book.lines().collect::<Vec<_>>()
+        );
+        assert_eq!(book.content, Book::content_from_lines(book.lines()));
+
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut reader = Session::new(whiskers);
+        run(&lib, &mut reader, Command::CheckOut, "Blanks\n1\n").await;
+        let out = run(&lib, &mut reader, Command::Read, "1\nG\nq\n").await;
+        assert!(
+            out.contains("2 |   indented \n\x1B[2K3 | \n\x1B[2K4 > \n:"),
+            "{out:?}"
+        );
+        assert!(!out.contains("5 |"), "{out:?}");
+    }
+
+    #[tokio::test]
+    async fn margin_modes() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        lib.add(numbered("pair", 2), Library::OPERATOR)
+            .await
+            .unwrap();
+        let read = |input: &'static str| format!("pair\n1\n{input}");
+
+        let out = run(&lib, &mut session, Command::Preview, &read("j\nq\n")).await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        assert_eq!("1 | line 1\n:\x1B[F\x1B[2K2 | line 2\n:", text);
+
+        let input = read("set nomargin\nj\nset margin\nq\n");
+        let out = run(&lib, &mut session, Command::Preview, &input).await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        assert_eq!(
+            "1 | line 1\n:\x1B[2Kline 1\n:\x1B[F\x1B[2Kline 2\n:\x1B[2K2 | line 2\n:",
+            text
+        );
+
+        session.cols = Some(20);
+        let out = run(&lib, &mut session, Command::Preview, &read("j\nq\n")).await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        assert_eq!("1|line 1\n:\x1B[F\x1B[2K2|line 2\n:", text);
+
+        /* typing lines up with the lines around it, margin or not */
+        let input = "Kittens\ncat 1\nsmall\n\nset nomargin\na\nmew\n.\nset margin\nq\nkeep\n";
+        let out = run(&lib, &mut session, Command::Add, input).await;
+        assert!(out.contains(":\x1B[2K\n:\x1B[2Kmew\n"), "{out:?}");
+        assert!(out.contains(":\x1B[2K2|mew\n:"), "{out:?}");
+    }
+
+    #[tokio::test]
+    async fn browse_before_registering() {
+        let lib = Library::new();
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let addr: SocketAddr = "10.0.0.2:1234".parse().unwrap();
+        let input = "search\nfoo\nhistory\ncheckout\nwhiskers\n\nfoo\n1\nquit\n";
+        let (mut stream, client) = scripted(input).await;
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+
+        /* looking around doesn't need a nickname */
+        let (browsing, registering) = out.split_once("what is it? ").expect("{out}");
+        assert!(browsing.contains("'foo', by cat 1"), "{out}");
+        assert!(browsing.contains("1  search\n2  history\n"), "{out}");
+        assert_eq!(1, out.matches("what is it? ").count(), "{out}");
+        /* checking out does, and goes on once they have one */
+        assert!(registering.contains("your login token is "), "{out}");
+        assert!(registering.contains("checked out item 1!"), "{out}");
+
+        let whiskers = lib.lookup_guest_by_addr(addr.ip()).await.unwrap();
+        assert_eq!(Some(whiskers), lib.lookup_metadata(id).checked_out_by);
+    }
+
+    #[tokio::test]
+    async fn resume_draft() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+
+        let input = "Naps\ncat 1\nwhere to take them\n\na\nthe sofa\n.\nq\nkeep\n";
+        let out = run(&lib, &mut session, Command::Add, input).await;
+        assert!(out.ends_with("kept it as a draft. 'resume' to keep writing.\n"));
+        assert!(lib.is_empty().await);
+
+        /* step out for something else meanwhile */
+        run(&lib, &mut session, Command::Search, "sofa\n").await;
+
+        let out = run(
+            &lib,
+            &mut session,
+            Command::Resume,
+            "G\na\nthe sun\n.\nq\npublish\n",
+        )
+        .await;
+        assert!(out.contains("2 > the sofa\n"), "{out}");
+        assert!(out.ends_with("adding the book 'Naps'...done!\n"), "{out}");
+        assert_eq!(None, session.draft);
+
+        let found = lib.search("Naps", Library::OPERATOR).await;
+        let book = lib.lookup_book_by_id(found[0].1).await;
+        assert_eq!("where to take them", book.description);
+        assert_eq!("\nthe sofa\nthe sun\n", book.content);
+
+        let out = run(&lib, &mut session, Command::Resume, "").await;
+        assert_eq!(said(Msg::NoDraft), out);
+    }
+
+    #[tokio::test]
+    async fn review_draft() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+
+        /* a typo noticed while writing, fixed before anyone sees it */
+        let input = "Npas\ncat 1\nwhere to take them\n\na\nthe sofa\n.\nq\ntitle\nNaps\npublish\n";
+        let out = run(&lib, &mut session, Command::Add, input).await;
+        let summary = |title| {
+            said(Msg::DraftSummary {
+                title,
+                author: "cat 1",
+                description: "where to take them",
+                lines: 2,
+            })
+        };
+        assert!(out.contains(&summary("Npas")), "{out}");
+        assert!(out.contains(&summary("Naps")), "{out}");
+        assert!(out.ends_with("adding the book 'Naps'...done!\n"), "{out}");
+        let found = lib.search("", Library::OPERATOR).await;
+        assert_eq!(1, found.len());
+        assert_eq!("Naps", lib.lookup_book_by_id(found[0].1).await.title);
+
+        /* back into the editor, then thrown away for good */
+        let input = "Zoomies\ncat 1\nfast\n\nq\ncontent\na\nzoom\n.\nq\n\n";
+        let out = run(&lib, &mut session, Command::Add, input).await;
+        assert!(out.contains("(1 line)"), "{out}");
+        assert!(out.ends_with(&said(Msg::KeptDraft)), "{out}");
+        let out = run(&lib, &mut session, Command::Resume, "q\ndiscard\n").await;
+        assert!(out.contains("(2 lines)"), "{out}");
+        assert!(out.ends_with(&said(Msg::DiscardedDraft)), "{out}");
+        assert_eq!(None, session.draft);
+        assert_eq!(1, lib.search("", Library::OPERATOR).await.len());
+        let out = run(&lib, &mut session, Command::Resume, "").await;
+        assert_eq!(said(Msg::NoDraft), out);
+    }
+
+    #[tokio::test]
+    async fn add_over_contribution_limit() {
+        let mut lib = Library::new();
+        lib.set_contribution_limit(1);
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut session = Session::new(whiskers);
+
+        /* a draft that didn't fit is kept for when there's room */
+        run(
+            &lib,
+            &mut session,
+            Command::Add,
+            "Naps\ncat 1\nzzz\n\nq\nkeep\n",
+        )
+        .await;
+        lib.add(book("Fish"), whiskers).await.unwrap();
+        let out = run(&lib, &mut session, Command::Resume, "q\npublish\n").await;
+        let expect = [Msg::ContributionLimit, Msg::KeptDraft].map(said).concat();
+        assert!(out.ends_with(&expect), "{out}");
+        assert!(session.draft.is_some());
+
+        let out = run(&lib, &mut session, Command::Add, "").await;
+        assert_eq!(said(Msg::ContributionLimit), out);
+    }
+
+    #[tokio::test]
+    async fn cancel_add() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+
+        /* a blank answer, then yes, stops right there */
+        let out = run(
+            &lib,
+            &mut session,
+            Command::Add,
+            "Naps
+
+y
+",
+        )
+        .await;
+        assert!(out.contains("stop adding this book? (Y/n) "), "{out}");
+        assert!(out.ends_with(&said(Msg::Nevermind)), "{out}");
+        assert!(!out.contains("Description? "), "{out}");
+        assert_eq!(None, session.draft);
+        assert!(lib.is_empty().await);
+
+        /* no goes back to the same question, and anything typed is kept */
+        let input = "
+n
+:q
+cat 1
+where
+
+q
+publish
+";
+        let out = run(&lib, &mut session, Command::Add, input).await;
+        assert_eq!(2, out.matches("Title? ").count(), "{out}");
+        assert!(
+            out.ends_with(
+                "adding the book ':q'...done!
+"
+            ),
+            "{out}"
+        );
+        assert_eq!(1, lib.search(":q", Library::OPERATOR).await.len());
+
+        /* hanging up partway is a nevermind, too */
+        let out = run(
+            &lib,
+            &mut session,
+            Command::Add,
+            "Zoomies
+",
+        )
+        .await;
+        assert!(out.ends_with(&said(Msg::Nevermind)), "{out}");
+        assert_eq!(1, lib.search("", Library::OPERATOR).await.len());
+    }
+
+    #[tokio::test]
+    async fn binary_content() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let binary = "\x00\x01\x02\x03PK\x04\x05\x06\x07";
+
+        /* flagged at publishing, and easily backed out of */
+        let input = format!("Noise\ncat 1\nstatic\n\na\n{binary}\n.\nq\npublish\nn\ndiscard\n");
+        let out = run(&lib, &mut session, Command::Add, &input).await;
+        assert!(out.contains(&said(Msg::NotText)), "{out}");
+        assert!(out.ends_with(&said(Msg::DiscardedDraft)), "{out}");
+        assert!(lib.is_empty().await);
+
+        /* but theirs to insist on */
+        let input = format!("Noise\ncat 1\nstatic\n\na\n{binary}\n.\nq\npublish\ny\n");
+        let out = run(&lib, &mut session, Command::Add, &input).await;
+        assert!(out.ends_with("adding the book 'Noise'...done!\n"), "{out}");
+
+        /* text with a little color in it goes straight through */
+        let input = "Loud\ncat 1\nred\n\na\n\x1b[31mmew\x1b[0m\n.\nq\npublish\n";
+        let out = run(&lib, &mut session, Command::Add, input).await;
+        assert!(!out.contains(&said(Msg::NotText)), "{out}");
+        assert!(out.ends_with("adding the book 'Loud'...done!\n"), "{out}");
+
+        /* editing garbage in is checked the same way */
+        let edit = format!("Loud\n1\n4\n1\nc\n{binary}\nq\n");
+        let out = run(&lib, &mut session, Command::Edit, &format!("{edit}\n")).await;
+        assert!(out.contains(&said(Msg::NotText)), "{out}");
+        assert!(out.ends_with(&said(Msg::NotSaved { rank: 1 })), "{out}");
+        let out = run(&lib, &mut session, Command::Edit, &format!("{edit}y\n")).await;
+        let field = "content";
+        assert!(
+            out.ends_with(&said(Msg::Updated { field, rank: 1 })),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_feedback() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let input = format!(
+            "Naps\ncat 1\nwhere\n\na\n{}.\nl\nq\npublish\n",
+            "purr\n".repeat(250)
+        );
+        let out = run(&lib, &mut session, Command::Add, &input).await;
+        assert!(out.ends_with("done!\n"), "{out}");
+        assert_eq!(1, out.matches("(100 lines added.)\n").count(), "{out}");
+        assert_eq!(1, out.matches("(200 lines added.)\n").count(), "{out}");
+        assert!(!out.contains("(300 lines"), "{out}");
+        /* the one it started with is blank */
+        assert!(
+            out.contains("251 lines, 250 words, 1000 characters.\n"),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn languages() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::Languages, "").await;
+        assert_eq!(said(Msg::LibraryEmpty), out);
+
+        /* asked for while adding, English unless told otherwise */
+        for input in [
+            "Naps\ncat 1\nwhere\n\nq\npublish\n",
+            "Siestes\ncat 1\nou\nfrench\nFR\nq\npublish\n",
+            "Rien\ncat 1\nnada\nnone\nq\npublish\n",
+        ] {
+            let out = run(&lib, &mut session, Command::Add, input).await;
+            assert!(out.ends_with("done!\n"), "{out}");
+        }
+        lib.add(numbered("Mew", 1), Library::OPERATOR)
+            .await
+            .unwrap();
+        let found = lib.search("Siestes", Library::OPERATOR).await;
+        let siestes = found[0].1;
+        assert_eq!(
+            Some("fr"),
+            lib.lookup_book_by_id(siestes).await.language.as_deref()
+        );
+
+        let out = run(&lib, &mut session, Command::Languages, "").await;
+        let expect = [(Some("en"), 1), (Some("fr"), 1), (None, 2)]
+            .map(|(language, count)| said(Msg::BooksIn { language, count }))
+            .concat();
+        assert_eq!(expect, out);
+
+        let out = run(&lib, &mut session, Command::Preview, "lang:fr\n1\nq\n").await;
+        assert!(out.contains("'Siestes'"), "{out}");
+        assert!(out.contains("\tYou might also like:\n"), "{out}");
+        assert!(out.contains("\t\t'Naps' by cat 1\n"), "{out}");
+        assert!(out.contains("\t[Written in fr.]\n"), "{out}");
+        /* listed, that is; the cover may still suggest it */
+        assert!(!out.contains("'Naps',"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn records_command() {
+        let lib = Library::new();
+        lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut guest = Session::new(whiskers);
+        let mut operator = Session::new(Library::OPERATOR);
+        checked_out_book(&lib, &mut guest).await;
+
+        let out = run(&lib, &mut guest, Command::Records, "").await;
+        assert_eq!(said(Msg::OperatorOnly), out);
+
+        let out = run(&lib, &mut operator, Command::Records, "xml\n").await;
+        assert_eq!(
+            format!(
+                "Format, csv or json? [csv] {}",
+                said(Msg::UnknownRecordFormat)
+            ),
+            out
+        );
+
+        let out = run(&lib, &mut operator, Command::Records, "\n").await;
+        assert!(out.contains(records::BEGIN), "{out}");
+        assert!(out.contains(&records::COLUMNS.join(",")), "{out}");
+
+        /* typed ahead, it answers the question */
+        let (mut stream, client) = scripted("").await;
+        shell::do_cmd_typed_ahead(&mut stream, Command::Records, "json", &lib, &mut operator)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        let body = out
+            .strip_prefix(&format!("Format, csv or json? [csv] {}\n", records::BEGIN))
+            .and_then(|out| out.strip_suffix(&format!("{}\n", records::END)))
+            .expect(&out);
+        let read = records::read(Format::Json, body).unwrap();
+        assert_eq!(1, read.len());
+        assert_eq!(
+            ("foo", "whiskers", None),
+            (&*read[0].title, &*read[0].borrower, read[0].back)
+        );
+
+        /* and export is left to books */
+        let out = run(&lib, &mut operator, Command::Export, "records\n").await;
+        assert!(!out.contains(records::BEGIN), "{out}");
+    }
+
+    #[tokio::test]
+    async fn operator_trash() {
+        let lib = Library::new();
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let guestbook = lib.open_guestbook().await;
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut guest = Session::new(whiskers);
+        let mut operator = Session::new(Library::OPERATOR);
+
+        for cmd in [
+            Command::Delete,
+            Command::Trash,
+            Command::Admin,
+            Command::Merge,
+        ] {
+            let out = run(&lib, &mut guest, cmd, "foo\n1\n").await;
+            assert_eq!(said(Msg::OperatorOnly), out);
+        }
+        let out = run(&lib, &mut operator, Command::Trash, "").await;
+        assert_eq!(said(Msg::TrashEmpty), out);
+
+        let out = run(&lib, &mut operator, Command::Delete, "foo\n1\n").await;
+        assert!(out.ends_with("put item 1 in the trash.\n"), "{out}");
+        let out = run(&lib, &mut operator, Command::Delete, "guestbook\n1\n").await;
+        assert!(out.ends_with("put item 1 in the trash.\n"), "{out}");
+
+        /* guests can't find, read or sign either of them */
+        let out = run(&lib, &mut guest, Command::Search, "foo\n").await;
+        assert!(!out.contains("'foo'"), "{out}");
+        let out = run(&lib, &mut guest, Command::Read, "1\n").await;
+        assert_eq!(said(Msg::CheckOutFirst), out);
+        let out = run(&lib, &mut guest, Command::Sign, "hi\n").await;
+        assert!(!out.contains(&said(Msg::Signed)), "{out}");
+
+        let out = run(&lib, &mut operator, Command::Trash, "2\n").await;
+        assert!(out.contains("1. 'foo', by cat 1. (trashed "), "{out}");
+        assert!(
+            out.contains("2. 'Guestbook', by everyone. (trashed "),
+            "{out}"
+        );
+        assert!(out.ends_with("restored item 2.\n"), "{out}");
+        assert!(!lib.lookup_metadata(guestbook).is_hidden());
+
+        let out = run(&lib, &mut operator, Command::Trash, "empty\n").await;
+        assert!(out.ends_with("purged 1 book for good.\n"), "{out}");
+        assert!(lib.trash().is_empty());
+        assert!(lib.lookup_metadata(id).is_hidden());
+    }
+
+    #[tokio::test]
+    async fn banned_guest_refused() {
+        let lib = Library::new();
+        let addr = SocketAddr::new("10.0.0.2".parse().unwrap(), 1234);
+        lib.register_guest(addr.ip(), "whiskers").await.unwrap();
+        let mut operator = Session::new(Library::OPERATOR);
+
+        let out = run(&lib, &mut operator, Command::Admin, "ban whiskers\n").await;
+        assert!(out.ends_with("banned whiskers.\n"), "{out}");
+        let out = run(
+            &lib,
+            &mut operator,
+            Command::Admin,
+            "ban cat in the machine\n",
+        )
+        .await;
+        assert!(out.ends_with("can't ban the operator.\n"), "{out}");
+        let out = run(&lib, &mut operator, Command::Admin, "ban mittens\n").await;
+        assert!(out.ends_with("nobody is called mittens.\n"), "{out}");
+
+        let (mut stream, client) = scripted("search\nfoo\n").await;
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        assert_eq!(said(Msg::NotWelcome), transcript(stream, client).await);
+
+        let out = run(&lib, &mut operator, Command::Admin, "unban whiskers\n").await;
+        assert!(out.ends_with("unbanned whiskers.\n"), "{out}");
+        let (mut stream, client) = scripted("quit\n").await;
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap();
+        assert!(transcript(stream, client).await.ends_with("bye!\n"));
+    }
+
+    #[tokio::test]
+    async fn merge() {
+        let lib = Library::new();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let twice = lib
+            .register_guest("10.0.0.3".parse().unwrap(), "mr whiskers")
+            .await
+            .unwrap();
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        lib.checkout(id, twice).unwrap();
+        let mut operator = Session::new(Library::OPERATOR);
+
+        /* shown what would move, then thinking better of it */
+        let out = run(
+            &lib,
+            &mut operator,
+            Command::Merge,
+            "mr whiskers into whiskers
+\n",
+        )
+        .await;
+        let merged = lib.merge_guests(twice, whiskers, true).await.unwrap();
+        let plan = said(Msg::MergePlan {
+            from: "mr whiskers",
+            into: "whiskers",
+            merged,
+        });
+        assert!(out.contains(&plan), "{out}");
+        assert!(plan.contains("\t1 book they have out\n"), "{plan}");
+        assert!(out.ends_with(&said(Msg::Nevermind)), "{out}");
+        assert_eq!(Some(twice), lib.lookup_metadata(id).checked_out_by);
+
+        for (input, refusal) in [
+            ("whiskers whiskers\n", Msg::MergeSameGuest),
+            ("cat in the machine into whiskers\n", Msg::CantMergeOperator),
+            ("mittens whiskers\n", Msg::NobodyCalled { nick: "mittens" }),
+            ("whiskers mittens\n", Msg::NobodyCalled { nick: "mittens" }),
+            ("whiskers\n", Msg::Nevermind),
+        ] {
+            let out = run(&lib, &mut operator, Command::Merge, input).await;
+            assert!(out.ends_with(&said(refusal)), "{input:?}: {out}");
+        }
+        {
+            let _visit = lib.visit(twice);
+            let out = run(
+                &lib,
+                &mut operator,
+                Command::Merge,
+                "mr whiskers into whiskers\n",
+            )
+            .await;
+            let nick = "mr whiskers";
+            assert!(out.ends_with(&said(Msg::StillConnected { nick })), "{out}");
+        }
+
+        let out = run(
+            &lib,
+            &mut operator,
+            Command::Merge,
+            "mr whiskers into whiskers\ny\n",
+        )
+        .await;
+        let msg = Msg::MergedGuests {
+            from: "mr whiskers",
+            into: "whiskers",
+        };
+        assert!(out.ends_with(&said(msg)), "{out}");
+        assert_eq!(Some(whiskers), lib.lookup_metadata(id).checked_out_by);
+        assert_eq!(None, lib.lookup_guest_by_nick("mr whiskers").await);
+        lib.check_invariants().await;
+    }
+
+    #[tokio::test]
+    async fn protect() {
+        let lib = Library::new();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        lib.add(book("foo"), whiskers).await.unwrap();
+        let mut guest = Session::new(whiskers);
+        let mut operator = Session::new(Library::OPERATOR);
+
+        let out = run(&lib, &mut guest, Command::Protect, "foo\n1\n").await;
+        assert_eq!(said(Msg::OperatorOnly), out);
+        let out = run(&lib, &mut operator, Command::Protect, "foo\n1\n").await;
+        assert!(out.ends_with(&said(Msg::Protected { rank: 1 })), "{out}");
+        let out = run(&lib, &mut guest, Command::Search, "foo\n").await;
+        assert!(out.contains("'foo', by cat 1. [library notice]\n"), "{out}");
+
+        let out = run(&lib, &mut guest, Command::Edit, "foo\n1\n1\nbar\n").await;
+        assert!(
+            out.ends_with(&said(Msg::LibraryNotice { rank: 1 })),
+            "{out}"
+        );
+        run(&lib, &mut guest, Command::CheckOut, "foo\n1\n").await;
+        let out = run(&lib, &mut guest, Command::Read, "1\nq\n").await;
+        assert!(out.contains("\t[A library notice.]\n"), "{out}");
+
+        let out = run(&lib, &mut operator, Command::Protect, "foo\n1\n").await;
+        assert!(out.ends_with(&said(Msg::Unprotected { rank: 1 })), "{out}");
+        let out = run(&lib, &mut guest, Command::Edit, "foo\n1\n1\nbar\n").await;
+        assert!(out.ends_with("updated the title of item 1.\n"), "{out}");
+        let out = run(&lib, &mut guest, Command::Search, "bar\n").await;
+        assert!(!out.contains("library notice"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn paged_help() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let whole = run(&lib, &mut session, Command::Help, "").await;
+        assert!(!whole.contains("-- more --"));
+        let lines = whole.lines().count();
+
+        session.rows = Some(4);
+        let out = run(&lib, &mut session, Command::Help, &"\n".repeat(lines)).await;
+        let pages: Vec<&str> = out.split("-- more --").collect();
+        assert_eq!(lines.div_ceil(3), pages.len());
+        assert!(pages.iter().all(|page| page.lines().count() <= 3));
+        assert_eq!(whole, pages.concat());
+
+        /* the editor's help too */
+        checked_out_book(&lib, &mut session).await;
+        let out = run(&lib, &mut session, Command::Read, "1\nh\n\n\n\n\n\nq\n").await;
+        assert_eq!(5, out.matches("-- more --").count(), "{out}");
+    }
+
+    #[tokio::test]
+    async fn readonly_session() {
+        let lib = Library::new();
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        session.caps = Capabilities::READ_ONLY;
+
+        for cmd in [Command::Add, Command::CheckOut, Command::Edit] {
+            let out = run(&lib, &mut session, cmd, "foo\n1\n").await;
+            assert_eq!(said(Msg::ReadOnly), out);
+        }
+        /* authors can be looked up, but not checked out from */
+        let out = run(&lib, &mut session, Command::Author, "cat 1\n1\n").await;
+        assert!(out.contains("'foo', by cat 1"), "{out}");
+        assert!(out.ends_with(&said(Msg::ReadOnly)), "{out}");
+        assert!(lib.lookup_metadata(id).is_free());
+        assert_eq!(1, lib.search("", Library::OPERATOR).await.len());
+
+        let out = run(&lib, &mut session, Command::Search, "foo\n").await;
+        assert!(out.contains("'foo', by cat 1"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn mirror_session() {
+        let lib = Library::new();
+        lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        let bar = lib.add(book("bar"), Library::OPERATOR).await.unwrap();
+        let mittens = lib
+            .register_guest("10.0.0.3".parse().unwrap(), "mittens")
+            .await
+            .unwrap();
+        lib.checkout(bar, mittens).unwrap();
+
+        let guests = lib.metrics().await.guests;
+
+        let input = "add\ncheckout\ncheckin\nedit\nrm\nsign\nlogin\nnotify\n\
+                     search foo\nread bar\n1\nq\nread bar\n1\nq\nquit\n";
+        let (mut stream, client) = scripted(input).await;
+        let addr: SocketAddr = "10.0.0.9:1234".parse().unwrap();
+        shell::process_socket(&mut stream, addr, &lib, Capabilities::MIRROR)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+
+        assert!(out.starts_with(&said(Msg::BrowsingMirror)), "{out}");
+        assert_eq!(8, out.matches(&said(Msg::Mirror)).count(), "{out}");
+        assert!(out.contains("'foo', by cat 1"), "{out}");
+        /* reading needs no checkout, even of a book somebody else has out */
+        assert_eq!(2, out.matches("baz").count(), "{out}");
+        assert_eq!(Some(mittens), lib.lookup_metadata(bar).checked_out_by);
+        assert_eq!(2, lib.search("", Library::OPERATOR).await.len());
+
+        /* reading is what first needs a name, and one is made up once, for
+         * this session alone */
+        assert_eq!(1, out.matches("you're 'visitor ").count(), "{out}");
+        assert_eq!(guests, lib.metrics().await.guests);
+        assert_eq!(None, lib.lookup_guest_by_addr(addr.ip()).await);
+        assert_eq!(None, lib.progress(bar, Library::NOBODY));
+        assert_eq!(0, lib.lookup_metadata(bar).reads);
+    }
+
+    #[tokio::test]
+    async fn just_returned() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::JustReturned, "").await;
+        assert_eq!(said(Msg::NothingReturned), out);
+
+        let foo = checked_out_book(&lib, &mut session).await;
+        let out = run(&lib, &mut session, Command::CheckIn, "1\n").await;
+        assert!(out.contains(&said(Msg::Returned { rank: 1 })), "{out}");
+        let out = run(&lib, &mut session, Command::JustReturned, "").await;
+        assert_eq!(
+            said(Msg::JustReturned {
+                rank: 1,
+                title: "foo"
+            }),
+            out
+        );
+
+        /* newest first, each book once, and only so many */
+        let mut ids = vec![foo];
+        for n in 0..Library::JUST_RETURNED {
+            let title = format!("bar {n}");
+            let id = lib.add(book(&title), Library::OPERATOR).await.unwrap();
+            lib.checkout(id, Library::OPERATOR).unwrap();
+            lib.checkin(id, Library::OPERATOR).unwrap();
+            ids.push(id);
+        }
+        lib.checkout(ids[1], Library::OPERATOR).unwrap();
+        lib.checkin(ids[1], Library::OPERATOR).unwrap();
+        let expected: Vec<BookID> = [ids[1]]
+            .into_iter()
+            .chain(ids[2..].iter().rev().copied())
+            .collect();
+        assert_eq!(Library::JUST_RETURNED, expected.len());
+        assert_eq!(expected, lib.just_returned());
+
+        /* what's gone out again isn't on offer */
+        lib.checkout(ids[1], Library::OPERATOR).unwrap();
+        let out = run(&lib, &mut session, Command::JustReturned, "").await;
+        assert_eq!(Library::JUST_RETURNED - 1, out.lines().count(), "{out}");
+        assert!(!out.contains("'bar 0'"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn flavored_messages() {
+        for &flavor in Flavor::ALL {
+            assert_eq!(Some(flavor), Flavor::from_name(flavor.name()));
+        }
+        assert_eq!(None, Flavor::from_name("dog"));
+
+        let mut lib = Library::new();
+        lib.set_flavor(Flavor::Cat);
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::Read, "").await;
+        assert_eq!(Msg::CheckOutFirst.render(Flavor::Cat), out);
+        assert_ne!(said(Msg::CheckOutFirst), out);
+
+        /* anything the flavor doesn't word its own way is worded plainly */
+        let out = run(&lib, &mut session, Command::Undo, "").await;
+        assert_eq!(said(Msg::NothingToUndo), out);
+    }
+
+    #[tokio::test]
+    async fn checkout_your_own() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        let out = run(&lib, &mut session, Command::CheckOut, "foo\n1\n").await;
+        assert!(out.contains("1. [yours] 'foo', by cat 1.\n"), "{out}");
+        assert!(
+            out.ends_with("you already have this one; 'read' it!\n"),
+            "{out}"
+        );
+        assert_eq!(1, lib.lookup_metadata(id).checkouts);
+
+        /* nobody else sees it as theirs */
+        let mut other = Session::new(Library::NOBODY);
+        let out = run(&lib, &mut other, Command::Search, "foo\n").await;
+        assert!(out.contains("1. [out] 'foo', by cat 1.\n"), "{out}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn undo_checkout_too_late() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        tokio::time::advance(Session::UNDO_WINDOW + Duration::from_secs(1)).await;
+        let out = run(&lib, &mut session, Command::Undo, "").await;
+        assert!(out.contains(&said(Msg::TooLateToUndo)), "{out}");
+        assert_eq!(1, lib.lookup_metadata(id).checkouts);
+        assert!(!lib.lookup_metadata(id).is_free());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn undo_after_read() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        let out = run(&lib, &mut session, Command::Undo, "").await;
+        assert!(out.contains(&said(Msg::NothingToUndo)), "{out}");
+        assert!(!lib.lookup_metadata(id).is_free());
+    }
+
+    fn numbered(title: &str, lines: usize) -> Book {
+        Book {
+            content: (1..=lines).map(|n| format!("line {n}\n")).collect(),
+            ..book(title)
+        }
+    }
+
+    #[tokio::test]
+    async fn edit_content() {
+        let lib = Library::new();
+        let id = lib
+            .add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+
+        let edit = "long\n1\n4\n3\nc\nline three\n5\nd\nq\n";
+        let out = run(&lib, &mut session, Command::Edit, &format!("{edit}n\n")).await;
+        let diff = [
+            "@@ -1,8 +1,7 @@",
+            " line 1",
+            " line 2",
+            "-line 3",
+            "+line three",
+            " line 4",
+            "-line 5",
+            " line 6",
+            " line 7",
+            " line 8",
+        ];
+        let shown = format!(
+            "{}\n{}save these changes? (Y/n) ",
+            diff.join("\n"),
+            said(Msg::DiffSummary {
+                added: 1,
+                removed: 2
+            })
+        );
+        assert!(out.contains(&shown), "{out}");
+        assert!(out.ends_with(&said(Msg::NotSaved { rank: 1 })), "{out}");
+        assert_eq!(numbered("long", 10), *lib.lookup_book_by_id(id).await);
+
+        let out = run(&lib, &mut session, Command::Edit, &format!("{edit}\n")).await;
+        let field = "content";
+        assert!(
+            out.ends_with(&said(Msg::Updated { field, rank: 1 })),
+            "{out}"
+        );
+        let content = lib.lookup_book_by_id(id).await.content.clone();
+        assert!(content.starts_with("line 1\nline 2\nline three\nline 4\nline 6\n"));
+
+        /* nothing to show or save */
+        let out = run(&lib, &mut session, Command::Edit, "long\n1\n4\nq\n").await;
+        assert!(out.ends_with(&said(Msg::NoChanges { rank: 1 })), "{out}");
+
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut guest = Session::new(whiskers);
+        let out = run(&lib, &mut guest, Command::Edit, "long\n1\n4\n").await;
+        assert!(
+            out.ends_with(&said(Msg::OnlyAdderEdits { rank: 1 })),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_reading() {
+        let lib = Library::new();
+        let id = lib
+            .add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        /* a page of one line, so reading opens on line 1 alone */
+        session.rows = Some(2);
+        run(&lib, &mut session, Command::CheckOut, "long\n1\n").await;
+
+        let out = run(&lib, &mut session, Command::Read, "1\n5\nq\n").await;
+        assert!(!out.contains("left off"), "{out}");
+        assert_eq!(Some(4), lib.bookmark(id, Library::OPERATOR));
+
+        let out = run(&lib, &mut session, Command::Read, "1\n\nq\n").await;
+        let (_cover, text) = out
+            .split_once("you left off at line 5. pick up there? (Y/n) ")
+            .unwrap();
+        let text = text
+            .strip_prefix(&said(Msg::ResumingAt { line: 5 }))
+            .unwrap();
+        assert_eq!("\x1B[2K 5 | line 5\n:", text);
+
+        /* starting over, and then finishing, leaves nothing to come back to */
+        let out = run(&lib, &mut session, Command::Read, "1\nn\nq\n").await;
+        assert!(out.ends_with("\x1B[2K 1 | line 1\n:"), "{out:?}");
+        assert_eq!(None, lib.bookmark(id, Library::OPERATOR));
+        run(&lib, &mut session, Command::Read, "1\nj3\nq\n").await;
+        assert_eq!(Some(3), lib.bookmark(id, Library::OPERATOR));
+        run(&lib, &mut session, Command::Read, "1\ny\nG\nq\n").await;
+        assert_eq!(None, lib.bookmark(id, Library::OPERATOR));
+    }
+
+    #[tokio::test]
+    async fn read_highlights_search() {
+        let lib = Library::new();
+        lib.add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        run(&lib, &mut session, Command::CheckOut, "line 7\n1\n").await;
+
+        /* straight to the first match */
+        let out = run(&lib, &mut session, Command::Read, "1\nk\nq\n").await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        assert_eq!(" 7 | \x1B[7mline 7\x1B[27m\n:\x1B[2K 6 | line 6\n:", text);
+
+        /* not picking up at line 6, where they left off */
+        let out = run(
+            &lib,
+            &mut session,
+            Command::Read,
+            "1\nn\nset nohighlight\nq\n",
+        )
+        .await;
+        assert!(out.ends_with(":\x1B[2K 7 | line 7\n:"), "{out:?}");
+
+        /* only our escapes, even where the book had its own */
+        assert_eq!(
+            "\x1B[7mnap\x1B[27m time, \x1B[7mnap\x1B[27m",
+            editor::highlight("\x1B[31mnap\x1B[0m time, nap", "nap")
+        );
+        assert_eq!("\x1B[31mawake", editor::highlight("\x1B[31mawake", "nap"));
+    }
+
+    #[test]
+    fn address_forms() {
+        use editor::Address;
+        for (input, expect) in [
+            ("7", Some(Address::Absolute(7))),
+            ("0", Some(Address::Absolute(0))),
+            ("+3", Some(Address::Relative(3))),
+            ("-3", Some(Address::Relative(-3))),
+            ("-0", Some(Address::Relative(0))),
+            (".", Some(Address::Current)),
+            ("$", Some(Address::Last)),
+            (
+                "99999999999999999999999",
+                Some(Address::Absolute(usize::MAX)),
+            ),
+            (
+                "+99999999999999999999999",
+                Some(Address::Relative(i64::MAX)),
+            ),
+            (
+                "-99999999999999999999999",
+                Some(Address::Relative(-i64::MAX)),
+            ),
+            /* a sign alone could mean anything */
+            ("+", None),
+            ("-", None),
+            ("+-3", None),
+            ("--3", None),
+            ("++3", None),
+            ("+ 3", None),
+            ("3+", None),
+            ("..", None),
+            ("$1", None),
+            ("", None),
+            ("j3", None),
+        ] {
+            assert_eq!(expect, Address::parse(input), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn address_resolution() {
+        use editor::{Address, OutOfBounds};
+        for (addr, cur_line, expect) in [
+            (Address::Absolute(1), 4, (0, None)),
+            (Address::Absolute(10), 4, (9, None)),
+            (Address::Absolute(0), 4, (0, Some(OutOfBounds::BeforeFirst))),
+            (Address::Absolute(11), 4, (9, Some(OutOfBounds::PastLast))),
+            (
+                Address::Absolute(usize::MAX),
+                4,
+                (9, Some(OutOfBounds::PastLast)),
+            ),
+            (Address::Relative(3), 4, (7, None)),
+            (Address::Relative(-4), 4, (0, None)),
+            (
+                Address::Relative(-5),
+                4,
+                (0, Some(OutOfBounds::BeforeFirst)),
+            ),
+            (Address::Relative(6), 4, (9, Some(OutOfBounds::PastLast))),
+            (
+                Address::Relative(i64::MAX),
+                9,
+                (9, Some(OutOfBounds::PastLast)),
+            ),
+            (
+                Address::Relative(-i64::MAX),
+                0,
+                (0, Some(OutOfBounds::BeforeFirst)),
+            ),
+            (Address::Current, 4, (4, None)),
+            (Address::Last, 4, (9, None)),
+        ] {
+            assert_eq!(
+                expect,
+                addr.resolve(cur_line, 10),
+                "{addr:?} from {cur_line}"
+            );
+        }
+        assert_eq!((0, None), Address::Last.resolve(0, 0));
+    }
+
+    #[tokio::test]
+    async fn relative_jumps() {
+        let lib = Library::new();
+        lib.add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        /* a page of one line, so reading opens on line 1 alone */
+        session.rows = Some(2);
+        run(&lib, &mut session, Command::CheckOut, "long\n1\n").await;
+
+        let input = "1\n+3\n-2\n.\n$\n+1\n-99\n+\nq\n";
+        let out = run(&lib, &mut session, Command::Read, input).await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        let shown: Vec<&str> = text
+            .split(':')
+            .map(|shown| {
+                shown
+                    .trim_start_matches("\x1B[F")
+                    .trim_start_matches("\x1B[2K")
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                " 1 | line 1\n",
+                " 2 | line 2\n\x1B[2K 3 | line 3\n\x1B[2K 4 > line 4\n\x1B[2K 5 | line 5\n\x1B[2K 6 | line 6\n",
+                " 1 | line 1\n\x1B[2K 2 > line 2\n\x1B[2K 3 | line 3\n\x1B[2K 4 | line 4\n",
+                " 1 | line 1\n\x1B[2K 2 > line 2\n\x1B[2K 3 | line 3\n\x1B[2K 4 | line 4\n",
+                " 8 | line 8\n\x1B[2K 9 | line 9\n\x1B[2K10 > line 10\n",
+                " 8 | line 8\n\x1B[2K 9 | line 9\n\x1B[2K10 > line 10\nthere are only 10 lines.\n",
+                " 1 > line 1\n\x1B[2K 2 | line 2\n\x1B[2K 3 | line 3\nthere's no line before 1.\n",
+                "unknown command. type \"help\".\n",
+                "",
+            ],
+            shown
+        );
+    }
+
+    #[tokio::test]
+    async fn jump_context() {
+        let lib = Library::new();
+        lib.add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        /* a page of one line, so reading opens on line 1 alone */
+        session.rows = Some(2);
+        run(&lib, &mut session, Command::CheckOut, "long\n1\n").await;
+
+        let input = "1\n5\n\nj\n3\n3\nk\n1\ng\nG\n\nset context 0\n6\nset context 1\n10\nq\n";
+        let out = run(&lib, &mut session, Command::Read, input).await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        let shown: Vec<String> = text
+            .split(':')
+            .map(|shown| shown.replace("\x1B[F", "").replace("\x1B[2K", ""))
+            .collect();
+        let lines = |from: usize, to: usize, marked: Option<usize>| {
+            (from..=to)
+                .map(|n| {
+                    let sep = if Some(n) == marked { '>' } else { '|' };
+                    format!("{n:>2} {sep} line {n}\n")
+                })
+                .collect::<String>()
+        };
+        assert_eq!(
+            vec![
+                lines(1, 1, None),
+                /* forward, then stepping on from under what it showed */
+                lines(3, 7, Some(5)),
+                lines(8, 8, None),
+                lines(9, 9, None),
+                /* back, and to where it already is */
+                lines(1, 5, Some(3)),
+                lines(1, 5, Some(3)),
+                lines(2, 2, None),
+                /* up against either end */
+                lines(1, 3, Some(1)),
+                lines(1, 3, Some(1)),
+                lines(8, 10, Some(10)),
+                String::new(),
+                String::new(),
+                lines(6, 6, Some(6)),
+                String::new(),
+                lines(9, 10, Some(10)),
+                String::new(),
+            ],
+            shown
+        );
+    }
+
+    #[tokio::test]
+    async fn pager() {
+        let lib = Library::new();
+        lib.add(numbered("long", 30), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        session.rows = Some(6);
+        run(&lib, &mut session, Command::CheckOut, "long\n1\n").await;
+
+        let input = "1\nset pager\n\n \nb\nb\nf\nq\n";
+        let out = run(&lib, &mut session, Command::Read, input).await;
+        let (_cover, text) = out.split_once("\n\n\x1B[2K").unwrap();
+        let shown: Vec<String> = text
+            .split(':')
+            .map(|shown| shown.replace("\x1B[F", "").replace("\x1B[2K", ""))
+            .collect();
+        let lines = |from: usize, to: usize| {
+            (from..=to)
+                .map(|n| format!("{n:>2} | line {n}\n"))
+                .collect::<String>()
+        };
+        assert_eq!(
+            vec![
+                /* a screenful at a time, less a row for the prompt */
+                lines(1, 5),
+                String::new(),
+                lines(6, 10),
+                lines(11, 15),
+                /* back a page, then no further back than the first */
+                lines(6, 10),
+                lines(1, 5),
+                lines(6, 10),
+                String::new(),
+            ],
+            shown
+        );
+
+        /* it sticks for the visit, and a terminal that didn't say how tall
+         * it is gets the usual screenful */
+        assert!(session.paging);
+        session.rows = None;
+        let out = run(
+            &lib,
+            &mut session,
+            Command::Read,
+            "1\nn\n\nset nopager\nq\n",
+        )
+        .await;
+        let text = out.replace("\x1B[2K", "");
+        assert!(text.contains(&format!("{}:", lines(1, 23))), "{out}");
+        assert!(text.contains(&lines(24, 30)), "{out}");
+        let out = run(&lib, &mut session, Command::Read, "1\n\nq\n").await;
+        assert!(out.contains(":\x1B[F\x1B[2K24 | line 24\n:"), "{out}");
+        assert!(!out.contains("25 |"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn cover_shows_progress() {
+        let lib = Library::new();
+        lib.add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut session = Session::new(whiskers);
+        session.rows = Some(2);
+        run(&lib, &mut session, Command::CheckOut, "long\n1\n").await;
+
+        let out = run(&lib, &mut session, Command::Read, "1\nj\nj\nq\n").await;
+        assert!(!out.contains("through"), "{out}");
+        /* picking up where they left off, not that it changes anything here */
+        let out = run(&lib, &mut session, Command::Read, "1\n\nq\n").await;
+        assert!(out.contains("\t[You're 30% through.]\n"), "{out}");
+        assert!(!out.contains("on average"), "{out}");
+
+        /* the author sees how everyone's getting on */
+        let mut author = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut author, Command::Preview, "long\n1\nq\n").await;
+        assert!(
+            out.contains("\t[1 reader, 30% through on average.]\n"),
+            "{out}"
+        );
+    }
+
+    #[tokio::test]
+    async fn preview_stops_at_boundary() {
+        let mut lib = Library::new();
+        lib.set_preview_lines(3);
+        let id = lib
+            .add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+
+        let out = run(&lib, &mut session, Command::Preview, "long\n1\nj5\nG\nq\n").await;
+        assert!(out.contains(" 3 | line 3\n"), "{out}");
+        assert!(!out.contains("line 4"), "{out}");
+        assert_eq!(2, out.matches("check it out to keep reading.\n").count());
+
+        lib.checkout(id, Library::OPERATOR).unwrap();
+        let out = run(&lib, &mut session, Command::Preview, "long\n1\nG\nq\n").await;
+        assert!(out.contains("check it out when it's back to keep reading.\n"));
+        assert!(!out.contains("line 4"), "{out}");
+        assert_eq!(0, lib.lookup_metadata(id).reads);
+    }
+
+    #[tokio::test]
+    async fn preview_short_book() {
+        let lib = Library::new();
+        lib.add(numbered("short", 2), Library::OPERATOR)
+            .await
+            .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+
+        let out = run(&lib, &mut session, Command::Preview, "short\n1\nj5\nq\n").await;
+        assert!(out.contains("2 | line 2\n"), "{out}");
+        assert!(!out.contains("keep reading"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn peek_without_checkout() {
+        let mut lib = Library::new();
+        lib.set_preview_lines(3);
+        let id = lib
+            .add(numbered("long", 10), Library::OPERATOR)
+            .await
+            .unwrap();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let mut session = Session::new(whiskers);
+
+        let out = run(&lib, &mut session, Command::Peek, "long\n1\nG\nq\n").await;
+        assert!(out.contains("\t'long'\n"), "{out}");
+        assert!(out.contains("10 > line 10\n"), "{out}");
+        assert!(!out.contains("keep reading"), "{out}");
+        let meta = lib.lookup_metadata(id);
+        assert_eq!(
+            (None, 0, 1),
+            (meta.checked_out_by, meta.checkouts, meta.reads)
+        );
+
+        /* once it's out, only whoever has it reads it whole */
+        lib.checkout(id, Library::OPERATOR).unwrap();
+        let out = run(&lib, &mut session, Command::Peek, "long\n1\nG\nq\n").await;
+        assert!(out.contains("check it out when it's back to keep reading.\n"));
+        assert!(!out.contains("line 4"), "{out}");
+        let meta = lib.lookup_metadata(id);
+        assert_eq!(
+            (Some(Library::OPERATOR), 1),
+            (meta.checked_out_by, meta.reads)
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_counted_apart_from_checkouts() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        let out = run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        assert!(out.contains("\t[Read 1 time, by 1 reader.]\n"), "{out}");
+        let out = run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        assert!(out.contains("\t[Read 2 times, by 1 reader.]\n"), "{out}");
+        let meta = lib.lookup_metadata(id);
+        assert_eq!(1, meta.checkouts);
+        assert_eq!(2, meta.reads);
+    }
+
+    #[tokio::test]
+    async fn unique_readers() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+
+        /* the same guest counts once, wherever they read from */
+        for addr in ["10.0.0.2:4000", "10.0.0.3:4000"] {
+            session.addr = Some(addr.parse().unwrap());
+            run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        }
+        assert_eq!(1, lib.unique_readers(id));
+        lib.register_read(id, Library::NOBODY);
+        assert_eq!(1, lib.unique_readers(id));
+
+        let mut cats = Vec::new();
+        for (addr, nick) in [("10.0.0.4", "whiskers"), ("10.0.0.5", "whiskers too")] {
+            let cat = lib
+                .register_guest(addr.parse().unwrap(), nick)
+                .await
+                .unwrap();
+            lib.register_read(id, cat);
+            cats.push(cat);
+        }
+        assert_eq!(3, lib.unique_readers(id));
+        let out = run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        assert!(out.contains("\t[Read 6 times, by 3 readers.]\n"), "{out}");
+
+        /* a guest registered twice is one reader once they're merged */
+        lib.merge_guests(cats[1], cats[0], false).await.unwrap();
+        assert_eq!(2, lib.unique_readers(id));
+    }
+
+    #[tokio::test]
+    async fn browse_by_author() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut session, Command::Authors, "").await;
+        assert_eq!(said(Msg::LibraryEmpty), out);
+
+        lib.add(book("Nap"), Library::OPERATOR).await.unwrap();
+        lib.add(book("Tuna"), Library::OPERATOR).await.unwrap();
+        let mut mew = book("Mew");
+        mew.author = String::from("bob");
+        lib.add(mew, Library::OPERATOR).await.unwrap();
+
+        let out = run(&lib, &mut session, Command::Authors, "").await;
+        let expect = [("cat 1", 2), ("bob", 1)]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (author, count))| {
+                said(Msg::BooksBy {
+                    rank: idx + 1,
+                    author,
+                    count,
+                })
+            })
+            .collect::<String>();
+        assert_eq!(expect, out);
+
+        /* by number from that list, or by name */
+        let out = run(&lib, &mut session, Command::Author, "1\n2\n").await;
+        assert!(out.contains("'Nap'") && out.contains("'Tuna'"), "{out}");
+        assert!(out.ends_with(&said(Msg::CheckedOut { rank: 2 })), "{out}");
+        let out = run(&lib, &mut session, Command::Author, "Cat  1\n2\n").await;
+        assert!(out.ends_with(&said(Msg::AlreadyYours)), "{out}");
+        let out = run(&lib, &mut session, Command::Author, "BOB\n1\n").await;
+        assert!(out.contains("'Mew'") && !out.contains("'Nap'"), "{out}");
+
+        let out = run(&lib, &mut session, Command::Author, "alice\n").await;
+        assert!(
+            out.ends_with(&said(Msg::NoBooksBy { author: "alice" })),
+            "{out}"
+        );
+        let out = run(&lib, &mut session, Command::Author, "\n").await;
+        assert!(out.ends_with(&said(Msg::Nevermind)), "{out}");
+    }
+
+    #[tokio::test]
+    async fn info() {
+        let lib = Library::new().with_operator_nick("librarian");
+        let mut session = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut session).await;
+        session.addr = Some("10.0.0.2:4000".parse().unwrap());
+        run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        lib.toggle_protected(id);
+
+        let today = date::format_date(lib.now());
+        let out = run(&lib, &mut session, Command::Info, "foo\n1\n").await;
+        for field in [
+            String::from("\ttitle:     'foo'\n"),
+            String::from("\tauthor:    cat 1\n"),
+            String::from("\tlanguage:  not given\n"),
+            String::from("\tadded by:  'librarian'\n"),
+            format!("\tadded on:  {today}\n"),
+            format!("\tstatus:    checked out by 'librarian' since {today}\n"),
+            String::from("\tcheckouts: 1\n"),
+            String::from("\treads:     1\n"),
+            String::from("\treaders:   1\n"),
+            String::from("\tnotice:    a library notice\n"),
+        ] {
+            assert!(out.contains(&field), "{field:?} in {out}");
+        }
+        /* the cover, not the book */
+        assert!(!out.contains("baz"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn search_results_capped() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        for n in 0..10_000 {
+            lib.add(book(&format!("Nap {n:05}")), Library::OPERATOR)
+                .await
+                .unwrap();
+        }
+
+        let (mut stream, _client) = scripted("\n").await;
+        let found = shell::search(&mut stream, &lib, &mut session)
+            .await
+            .unwrap();
+        assert_eq!(SEARCH_RESULTS, found.len());
+        assert_eq!(None, session.last_query);
+        /* what was asked for is kept to highlight, less its filters */
+        let (mut stream, _client) = scripted("available: Nap\n").await;
+        shell::search(&mut stream, &lib, &mut session)
+            .await
+            .unwrap();
+        assert_eq!(Some("Nap"), session.last_query.as_deref());
+
+        let out = run(&lib, &mut session, Command::Info, "Nap\nq\n").await;
+        let shown = SEARCH_RESULTS;
+        assert!(out.contains(&said(Msg::MoreMatches { shown })), "{out}");
+        let ranks = out.lines().filter(|line| line.contains("[in]")).count();
+        assert_eq!(SEARCH_RESULTS, ranks);
+
+        /* the last one listed is still the one picked, whatever changed since */
+        let last = out
+            .lines()
+            .find(|line| line.trim_start().starts_with(&format!("{shown}. ")))
+            .unwrap();
+        let title = last.split('\'').nth(1).unwrap().to_string();
+        let found = lib.search(&title, Library::OPERATOR).await;
+        let id = found[0].1;
+        lib.checkout(id, Library::OPERATOR).unwrap();
+        let out = run(&lib, &mut session, Command::Info, &format!("{title}\n1\n")).await;
+        assert!(out.contains(&format!("\ttitle:     '{title}'\n")), "{out}");
+        assert!(out.contains("\tstatus:    checked out"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn content_match_snippet() {
+        let lib = Library::new();
+        let guest = Library::OPERATOR;
+        let content = "It was a dark and stormy night. ".repeat(4)
+            + "Suddenly, a kipper appeared in the utility closet. "
+            + &"The rain fell in torrents. ".repeat(4);
+        let id = lib
+            .add(
+                Book {
+                    title: String::from("Stormy"),
+                    author: String::from("cat 1"),
+                    description: String::from("a novel"),
+                    content,
+                    language: None,
+                },
+                guest,
+            )
+            .await
+            .unwrap();
+        let title_only = lib
+            .add(
+                Book {
+                    title: String::from("kipper"),
+                    author: String::from("cat 2"),
+                    description: String::from("fish"),
+                    content: String::from("fish"),
+                    language: None,
+                },
+                guest,
+            )
+            .await
+            .unwrap();
+
+        let found = lib.search("kipper", Library::OPERATOR).await;
+        let (_sim, _id, _meta, matched) = found.iter().find(|hit| hit.1 == id).unwrap();
+        let matched = matched.expect("content match should say where");
+        assert_eq!(Field::Content, matched.field);
+        let (_sim, _id, _meta, matched) = found.iter().find(|hit| hit.1 == title_only).unwrap();
+        assert_eq!(&None, matched);
+
+        let (mut stream, client) = scripted("").await;
+        shell::enumerate_entries(&mut stream, &lib, found.iter().copied(), None)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        let snippet_line = out
+            .lines()
+            .find(|line| line.starts_with('\t'))
+            .expect("a snippet line");
+        assert!(snippet_line.contains("a kipper appeared"));
+        assert!(snippet_line.contains('…'));
+        assert!(snippet_line.chars().count() < 70);
+    }
+
+    #[tokio::test]
+    async fn entries_in_columns() {
+        let lib = Library::new();
+        let mut found = Vec::new();
+        for (title, author) in [
+            ("foo", "cat 1"),
+            (
+                "The Extraordinarily Long and Winding Chronicle of a Cat Who Sat",
+                "cat 2",
+            ),
+            ("Naps", "猫の先生と仲間たち"),
+        ] {
+            let book = Book {
+                title: String::from(title),
+                author: String::from(author),
+                description: String::from("bar"),
+                content: String::from("baz"),
+                language: None,
+            };
+            let id = lib.add(book, Library::OPERATOR).await.unwrap();
+            found.push((1.0, id, lib.lookup_metadata(id), None));
+        }
+        /* enough of them for two-digit ranks */
+        let mut many = found.clone();
+        for _ in 0..3 {
+            many.extend(found.iter().copied());
+        }
+        lib.checkout(found[1].1, Library::OPERATOR).unwrap();
+        found[1].2 = lib.lookup_metadata(found[1].1);
+
+        let (mut stream, client) = scripted("").await;
+        shell::enumerate_entries(&mut stream, &lib, found.into_iter(), Some(60))
+            .await
+            .unwrap();
+        assert_eq!(
+            concat!(
+                "1. [in]  'foo',                          by cat 1.\n",
+                "2. [out] 'The Extraordinarily Long an…', by cat 2.\n",
+                "3. [in]  'Naps',                         by 猫の先生と仲….\n",
+            ),
+            transcript(stream, client).await
+        );
+
+        let (mut stream, client) = scripted("").await;
+        shell::enumerate_entries(&mut stream, &lib, many.into_iter().skip(2), None)
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        assert!(
+            out.starts_with(concat!(
+                " 1. [in]  'Naps',                                        by 猫の先生と仲間たち.\n",
+                " 2. [in]  'foo',                                         by cat 1.\n",
+            )),
+            "{out}"
+        );
+        assert!(
+            out.ends_with(
+                "10. [in]  'Naps',                                        by 猫の先生と仲間たち.\n"
+            ),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn truncate_wide() {
+        assert_eq!("foo", shell::truncate("foo", 3));
+        assert_eq!("fo…", shell::truncate("foox", 3));
+        assert_eq!("猫…", shell::truncate("猫の先生", 4));
+        assert_eq!("猫…", shell::truncate("猫の先生", 3));
+        assert_eq!("…", shell::truncate("猫の先生", 1));
+        assert_eq!(4, shell::display_width("猫の"));
+    }
+
+    #[test]
+    fn pad_wide() {
+        assert_eq!("foo  |", format!("{}|", shell::pad("foo", 5)));
+        assert_eq!("猫の |", format!("{}|", shell::pad("猫の", 5)));
+        assert_eq!(
+            "cafe\u{301} |",
+            format!("{}|", shell::pad("cafe\u{301}", 5))
+        );
+        assert_eq!("猫の先生|", format!("{}|", shell::pad("猫の先生", 5)));
+    }
+
+    #[tokio::test]
+    async fn hangup_is_not_a_bug() {
+        let lib = Library::new();
+        let (server, client) = tokio::io::duplex(64);
+        drop(client);
+        let mut stream = BufStream::new(server);
+        let addr = SocketAddr::new(Library::OPERATOR_ADDR, 1234);
+        let err = shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ShellError::ClientGone), "{err:?}");
+
+        /* however deep in a helper the write failed */
+        let broken = anyhow::Error::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        let err = ShellError::from(broken.context("failed to register guest"));
+        assert!(matches!(err, ShellError::ClientGone), "{err:?}");
+        let err = ShellError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(err, ShellError::Io(_)), "{err:?}");
+        let err = ShellError::from(anyhow::anyhow!("no such book"));
+        assert!(matches!(err, ShellError::Internal(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn garbage_is_a_protocol_error() {
+        let lib = Library::new();
+        let (server, mut client) = tokio::io::duplex(1 << 16);
+        client.write_all(b"search\xff\xfe\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut stream = BufStream::new(server);
+        let addr = SocketAddr::new(Library::OPERATOR_ADDR, 1234);
+        let err = shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ShellError::Protocol(_)), "{err:?}");
+        assert_eq!("client sent a line that isn't UTF-8", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn would_block_is_an_io_error() {
+        struct Blocked;
+        impl tokio::io::AsyncRead for Blocked {
+            fn poll_read(
+                self: std::pin::Pin<&mut Self>,
+                _cx: &mut std::task::Context<'_>,
+                _buf: &mut tokio::io::ReadBuf<'_>,
+            ) -> std::task::Poll<io::Result<()>> {
+                std::task::Poll::Ready(Err(io::ErrorKind::WouldBlock.into()))
+            }
+        }
+
+        let lib = Library::new();
+        let mut stream = BufStream::new(tokio::io::join(Blocked, tokio::io::sink()));
+        let mut session = Session::new(Library::OPERATOR);
+        let Err(err) = shell::do_cmd(&mut stream, Command::Dashboard, &lib, &mut session).await
+        else {
+            panic!("reading on when the stream would block");
+        };
+        assert!(
+            matches!(&err, ShellError::Io(e) if e.kind() == io::ErrorKind::WouldBlock),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn library_refusals_are_sorted_out() {
+        let refused = anyhow::Error::from(UpdateEntryError::Communal);
+        let err = ShellError::from(refused.context("checking out"));
+        assert!(
+            matches!(err, ShellError::Library(UpdateEntryError::Communal)),
+            "{err:?}"
+        );
+        /* and survive a trip through a helper's anyhow::Result */
+        let err = ShellError::from(anyhow::Error::from(ShellError::from(
+            UpdateEntryError::NotTrashed,
+        )));
+        assert!(
+            matches!(err, ShellError::Library(UpdateEntryError::NotTrashed)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn soft_ban_for_nonsense() {
+        let mut lib = Library::new();
+        let minute = Duration::from_secs(60);
+        lib.set_strike_limit(3, minute, 10 * minute);
+        lib.register_guest("10.0.0.3".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let connect = |input: &'static str, addr: &'static str| {
+            let lib = &lib;
+            async move {
+                let (mut stream, client) = scripted(input).await;
+                let result = shell::process_socket(
+                    &mut stream,
+                    addr.parse().unwrap(),
+                    lib,
+                    Capabilities::FULL,
+                )
+                .await;
+                (result, transcript(stream, client).await)
+            }
+        };
+
+        /* a scanner that can't pick a nickname */
+        let (result, out) = connect("checkout\nwhiskers\nwhiskers\nwhiskers\n", "10.0.0.2:1").await;
+        assert!(matches!(result, Err(ShellError::TurnedAway)), "{result:?}");
+        assert!(out.ends_with(&said(Msg::SoftBanned)), "{out}");
+        let (result, out) = connect("help\n", "10.0.0.2:2").await;
+        assert!(result.is_ok());
+        assert_eq!(said(Msg::SoftBanned), out);
+
+        /* only nonsense in a row counts */
+        let (result, out) =
+            connect("bogus\nbogus\nhelp\nbogus\nbogus\nbogus\n", "10.0.0.3:1").await;
+        assert!(matches!(result, Err(ShellError::TurnedAway)), "{result:?}");
+        assert_eq!(5, out.matches(&said(Msg::UnknownCommand)).count(), "{out}");
+
+        let (result, out) = connect("bogus\nbogus\nbogus\nbogus\nquit\n", "127.0.0.1:1").await;
+        assert!(result.is_ok(), "{out}");
+
+        tokio::time::advance(10 * minute).await;
+        let (result, out) = connect("quit\n", "10.0.0.2:3").await;
+        assert!(result.is_ok());
+        assert!(out.starts_with(&said(Msg::Browsing)), "{out}");
+    }
+
+    #[tokio::test]
+    async fn registered_while_typing() {
+        let lib = Arc::new(Library::new());
+        let addr: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let (server, client) = tokio::io::duplex(1 << 16);
+        let registering = tokio::spawn({
+            let lib = Arc::clone(&lib);
+            async move {
+                let mut stream = BufStream::new(server);
+                let guest = shell::register_guest(&mut stream, &lib, addr).await;
+                stream.flush().await.unwrap();
+                guest.unwrap()
+            }
+        });
+
+        let mut client = Client::new(client);
+        client.expect("what is it? ").await.unwrap();
+        /* another connection from the same address beats them to it */
+        let first = lib.register_guest(addr.ip(), "whiskers").await.unwrap();
+        client.send("mittens").await.unwrap();
+
+        assert_eq!(first, registering.await.unwrap());
+        let mut rest = String::new();
+        client.into_inner().read_to_string(&mut rest).await.unwrap();
+        assert_eq!(said(Msg::AlreadyYou { nick: "whiskers" }), rest);
+        assert_eq!(None, lib.lookup_guest_by_nick("mittens").await);
+    }
+
+    #[tokio::test]
+    async fn login_from_new_address() {
+        let lib = Library::new();
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+
+        let (mut stream, client) = scripted("whiskers\n\n").await;
+        let guest = shell::register_guest(&mut stream, &lib, "10.0.0.2:1".parse().unwrap())
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        let (_before, token) = out.split_once("your login token is ").unwrap();
+        let token = token.lines().next().unwrap().to_string();
+        lib.checkout(id, guest).unwrap();
+
+        /* same cat, different address */
+        let (mut stream, client) = scripted(&format!("login\n{token}\n")).await;
+        let returned = shell::register_guest(&mut stream, &lib, "10.0.0.3:1".parse().unwrap())
+            .await
+            .unwrap();
+        let out = transcript(stream, client).await;
+        assert_eq!(guest, returned);
+        assert!(
+            out.contains(&said(Msg::LoggedIn { nick: "whiskers" })),
+            "{out}"
+        );
+
+        let mut session = Session::new(returned);
+        let out = run(&lib, &mut session, Command::CheckIn, "1\n").await;
+        assert!(out.contains(&said(Msg::Returned { rank: 1 })), "{out}");
+    }
+
+    #[tokio::test]
+    async fn buffer_sizes() {
+        use tokio::io::AsyncBufReadExt;
+
+        assert_eq!(
+            BufferSizes {
+                read: 8192,
+                write: 8192
+            },
+            BufferSizes::default()
+        );
+        let sizes = BufferSizes { read: 4, write: 16 };
+        let (server, mut client) = tokio::io::duplex(1024);
+        let mut server = sizes.wrap(server);
+
+        /* reads come in at most a read buffer at a time */
+        client.write_all(b"meow meow\n").await.unwrap();
+        assert_eq!(b"meow", server.fill_buf().await.unwrap());
+
+        /* small writes wait for a flush; big ones push them out and follow */
+        let mut got = [0; 64];
+        server.write_all(b"purr").await.unwrap();
+        let waiting = tokio::time::timeout(Duration::from_millis(50), client.read(&mut got));
+        assert!(waiting.await.is_err());
+        server.write_all(b"zzzzzzzzzzzzzzzz").await.unwrap();
+        let n = client.read(&mut got).await.unwrap();
+        assert_eq!(b"purrzzzzzzzzzzzzzzzz", &got[..n]);
+    }
+
+    #[tokio::test]
+    async fn checkin_says_how_long() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let mut session = Session::new(Library::OPERATOR);
+        let id = lib.add(book("foo"), Library::OPERATOR).await.unwrap();
+        for (held, said_for) in [
+            (Duration::from_secs(30), "less than a minute"),
+            (Duration::from_secs(61), "1 minute"),
+            (Duration::from_secs(5 * 60 * 60 + 59 * 60), "5 hours"),
+            (Duration::from_secs(3 * 24 * 60 * 60 + 60 * 60), "3 days"),
+        ] {
+            run(&lib, &mut session, Command::CheckOut, "foo\n1\n").await;
+            assert_eq!(
+                Some(Duration::ZERO),
+                lib.lookup_metadata(id).held_for(lib.now())
+            );
+            clock.advance(held);
+            let out = run(&lib, &mut session, Command::CheckIn, "1\n").await;
+            assert!(
+                out.ends_with(&format!("you had 'foo' for {said_for}.\n")),
+                "{out}"
+            );
+            assert_eq!(None, lib.lookup_metadata(id).checked_out_at);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_guestbook_without_checkout() {
+        let lib = Library::new();
+        let id = lib.open_guestbook().await;
+        let mut session = Session::new(Library::OPERATOR);
+
+        let out = run(&lib, &mut session, Command::Sign, "hello, stacks\n").await;
+        assert!(out.contains(&said(Msg::Signed)), "{out}");
+        let out = run(&lib, &mut session, Command::Read, "1\nq\n").await;
+        assert!(out.contains("'Guestbook'"), "{out}");
+        assert!(out.contains("cat in the machine: hello, stacks"), "{out}");
+        assert!(lib.lookup_metadata(id).is_free());
+    }
+
+    #[tokio::test]
+    async fn export_checked_out_book() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        checked_out_book(&lib, &mut session).await;
+
+        let out = run(&lib, &mut session, Command::Export, "1\n").await;
+        let block = &out[out.find(export::BEGIN).unwrap()..];
+        assert!(block.contains("Title: foo\n"), "{out}");
+        assert!(
+            block.ends_with(&format!("\nbaz\n{}\n", export::END)),
+            "{out}"
+        );
+    }
+
+    /// Read from `client` until what the server sent so far ends with
+    /// `prompt`, the way a person would wait before typing.
+    async fn expect(client: &mut TcpStream, seen: &mut String, prompt: &str) {
+        let wait = async {
+            while !seen.ends_with(prompt) {
+                let mut buf = [0; 64];
+                let n = client.read(&mut buf).await.unwrap();
+                assert_ne!(0, n, "hung up waiting for {prompt:?}: {seen}");
+                seen.push_str(core::str::from_utf8(&buf[..n]).unwrap());
+            }
+        };
+        if tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .is_err()
+        {
+            panic!("never got {prompt:?}: {seen}");
+        }
+    }
+
+    #[tokio::test]
+    async fn prompts_arrive_before_reads() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let lib = Library::new();
+            let (stream, addr) = listener.accept().await.unwrap();
+            /* tiny buffers, so nothing sits in them by luck */
+            let mut stream = BufStream::with_capacity(4, 4, stream);
+            shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL).await
+        });
+
+        let mut seen = String::new();
+        for (prompt, reply) in [
+            ("; ", "add"),
+            ("Title? ", "Flush"),
+            ("Author? ", "cat 1"),
+            ("Description? ", "plumbing"),
+            ("Language? [en] ", ""),
+            (":", "q"),
+            ("discard) ", "publish"),
+            ("done!\n; ", "checkout"),
+            ("search query? ", "flush"),
+            ("which item number? ", "1"),
+            ("; ", "read"),
+            ("which item number? ", "1"),
+            (":", "q"),
+            ("; ", "quit"),
+        ] {
+            expect(&mut client, &mut seen, prompt).await;
+            client
+                .write_all(format!("{reply}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+
+        assert!(seen.contains("\t[Total 1 checkout.]\n"), "{seen}");
+
+        server.await.unwrap().unwrap();
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(said(Msg::Bye), rest);
+    }
+
+    #[tokio::test]
+    async fn reading_ends_with_connection() {
+        let lib = Arc::new(Library::new());
+        let mut operator = Session::new(Library::OPERATOR);
+        let id = checked_out_book(&lib, &mut operator).await;
+        let guest = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+
+        let (server, client) = tokio::io::duplex(1 << 16);
+        let reading = tokio::spawn({
+            let lib = Arc::clone(&lib);
+            async move {
+                let mut stream = BufStream::new(server);
+                shell::do_cmd(&mut stream, Command::Read, &lib, &mut operator).await
+            }
+        });
+        let mut client = Client::new(client);
+        client.ask("which item number? ", "1").await.unwrap();
+        client.expect(":").await.unwrap();
+        assert_eq!(vec![(Library::OPERATOR, id)], lib.readers());
+
+        /* others see it from the cover, and the operator from `guests` */
+        let out = run(
+            &lib,
+            &mut Session::new(guest),
+            Command::Preview,
+            "foo\n1\nq\n",
+        )
+        .await;
+        assert!(
+            out.contains("[Someone is reading this right now.]"),
+            "{out}"
+        );
+        let out = run(
+            &lib,
+            &mut Session::new(Library::OPERATOR),
+            Command::Guests,
+            "n\n",
+        )
+        .await;
+        let nick = lib.lookup_nick(Library::OPERATOR).await.unwrap();
+        let msg = Msg::GuestReading {
+            nick: &nick,
+            title: "foo",
+        };
+        assert!(out.contains(&said(msg)), "{out}");
+
+        /* hanging up mid-read */
+        drop(client);
+        assert!(reading.await.unwrap().is_err());
+        assert!(lib.readers().is_empty());
+        let out = run(
+            &lib,
+            &mut Session::new(guest),
+            Command::Preview,
+            "foo\n1\nq\n",
+        )
+        .await;
+        assert!(!out.contains("reading this right now"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn announce_to_everyone() {
+        let lib = Library::new();
+        let mut notices = [Notices::new(&lib), Notices::new(&lib)];
+        let guest = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+
+        let out = run(&lib, &mut Session::new(guest), Command::Announce, "hi\n").await;
+        assert_eq!(said(Msg::OperatorOnly), out);
+        let mut operator = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut operator, Command::Announce, "closing soon\n").await;
+        assert!(out.ends_with(&said(Msg::Announced)), "{out}");
+
+        for notices in &mut notices {
+            let (mut stream, client) = scripted("").await;
+            notices.drain(&mut stream, &lib, guest).await.unwrap();
+            let out = transcript(stream, client).await;
+            assert_eq!(
+                said(Msg::Announcement {
+                    text: "closing soon"
+                }),
+                out
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn mail_at_the_prompt() {
+        let lib = Library::new();
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let zoomies = lib.add(book("Midnight Zoomies"), whiskers).await.unwrap();
+        let mut session = Session::new(whiskers);
+        let mut notices = Notices::new(&lib);
+
+        lib.checkout(zoomies, Library::OPERATOR).unwrap();
+        let (mut stream, client) = scripted("").await;
+        notices.drain(&mut stream, &lib, whiskers).await.unwrap();
+        assert_eq!(
+            said(Msg::CheckedOutYours {
+                nick: Library::DEFAULT_OPERATOR_NICK,
+                title: "Midnight Zoomies",
+            }),
+            transcript(stream, client).await
+        );
+
+        let out = run(&lib, &mut session, Command::Notify, "maybe\n").await;
+        assert!(out.ends_with(&said(Msg::AnswerOnOrOff)), "{out}");
+        let out = run(&lib, &mut session, Command::Notify, "off\n").await;
+        assert!(out.ends_with(&said(Msg::NotifyOff)), "{out}");
+        let out = run(&lib, &mut session, Command::Notify, "\n").await;
+        assert!(out.ends_with(&said(Msg::NotifyOff)), "{out}");
+
+        lib.checkin(zoomies, Library::OPERATOR).unwrap();
+        lib.checkout(zoomies, Library::OPERATOR).unwrap();
+        let (mut stream, client) = scripted("").await;
+        notices.drain(&mut stream, &lib, whiskers).await.unwrap();
+        assert_eq!("", transcript(stream, client).await);
+    }
+
+    #[tokio::test]
+    async fn notices_wait_for_prompts() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let lib = Arc::new(Library::new());
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        let server = tokio::spawn({
+            let lib = Arc::clone(&lib);
+            async move {
+                let (stream, addr) = listener.accept().await.unwrap();
+                let mut stream = BufStream::with_capacity(4, 4, stream);
+                shell::process_socket(&mut stream, addr, &lib, Capabilities::FULL).await
+            }
+        });
+
+        let mut seen = String::new();
+        for (prompt, reply) in [
+            ("; ", "add"),
+            ("Title? ", "Naps"),
+            ("Author? ", "cat 1"),
+            ("Description? ", "where to take them"),
+            ("Language? [en] ", ""),
+            (":", "a"),
+        ] {
+            expect(&mut client, &mut seen, prompt).await;
+            client
+                .write_all(format!("{reply}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+
+        /* somebody adds a book while the guest is halfway through theirs */
+        expect(&mut client, &mut seen, "2 | ").await;
+        lib.add(book("Fish"), whiskers).await.unwrap();
+        client.write_all(b"the sofa\n").await.unwrap();
+        expect(&mut client, &mut seen, "3 | ").await;
+        client.write_all(b".\n").await.unwrap();
+        expect(&mut client, &mut seen, ":").await;
+        let notice = "(new on the shelves: 'Fish' by cat 1.)\n";
+        let (writing, after) = seen.rsplit_once("3 | ").unwrap();
+        assert!(!writing.contains(notice), "{seen}");
+        assert!(after.ends_with(&format!("{notice}:")), "{seen}");
+
+        for (prompt, reply) in [(":", "q"), ("discard) ", "keep"), ("; ", "quit")] {
+            expect(&mut client, &mut seen, prompt).await;
+            client
+                .write_all(format!("{reply}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        server.await.unwrap().unwrap();
+        assert_eq!(1, seen.matches(notice).count(), "{seen}");
+    }
+
+    #[test]
+    fn snippet_bounds() {
+        assert_eq!(Some("abc"), shell::snippet("abc", 1, 1, 60).as_deref());
+        assert_eq!(Some("…cd…"), shell::snippet("abcdef", 2, 1, 2).as_deref());
+        assert_eq!(
+            Some("line one line two"),
+            shell::snippet("line one\nline two", 5, 3, 60).as_deref()
+        );
+        /* offsets from before the text was cut short or rewritten */
+        assert_eq!(None, shell::snippet("abc", 4, 1, 60));
+        assert_eq!(None, shell::snippet("abc", 2, 5, 60));
+        assert_eq!(None, shell::snippet("abc", 2, usize::MAX, 60));
+        assert_eq!(None, shell::snippet("añb", 2, 1, 60));
+        assert_eq!(None, shell::snippet("añb", 0, 2, 60));
+    }
+}
+
+mod transcript {
+    use super::{scripted, transcript};
+    use crate::library::Library;
+    use crate::shell::{self, Command, Session};
+    use crate::transcript::Transcript;
+    use core::net::SocketAddr;
+    use core::time::Duration;
+
+    #[tokio::test]
+    async fn preserves_stream() {
+        const INPUT: &str = "whiskers\ntuna\n";
+        let addr: SocketAddr = "10.0.0.2:1234".parse().unwrap();
+
+        let expected = {
+            let lib = Library::new();
+            let (mut stream, client) = scripted(INPUT).await;
+            let guest = shell::register_guest(&mut stream, &lib, addr)
+                .await
+                .unwrap();
+            shell::do_cmd(&mut stream, Command::Help, &lib, &mut Session::new(guest))
+                .await
+                .unwrap();
+            transcript(stream, client).await
+        };
+        /* login tokens are random, so skip past them */
+        let without_token = |out: &str| {
+            out.lines()
+                .filter(|line| !line.contains("login token is"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("catlib-transcript-{}.log", std::process::id()));
+        let actual = {
+            let lib = Library::new();
+            let (stream, client) = scripted(INPUT).await;
+            let mut stream = Transcript::create(stream, &path, shell::SECRET_PROMPTS)
+                .await
+                .map_err(|(_stream, err)| err)
+                .unwrap();
+            let guest = shell::register_guest(&mut stream, &lib, addr)
+                .await
+                .unwrap();
+            shell::do_cmd(&mut stream, Command::Help, &lib, &mut Session::new(guest))
+                .await
+                .unwrap();
+            transcript(stream, client).await
+        };
+        assert_eq!(without_token(&expected), without_token(&actual));
+
+        /* the transcript is written in the background */
+        let mut log = String::new();
+        for _ in 0..100 {
+            log = std::fs::read_to_string(&path).unwrap_or_default();
+            if log.contains("ask for assistance") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(log.contains("< whiskers\\n"), "{log}");
+        assert!(log.contains("> Welcome to the Cat Library!"), "{log}");
+        assert!(log.contains("< [redacted]"), "{log}");
+        assert!(log.contains("> [redacted]"), "{log}");
+        assert!(!log.contains("tuna"), "{log}");
+    }
+}
+
+mod diff {
+    use crate::diff::{self, Line};
+
+    #[test]
+    fn empty_to_something() {
+        let changes = diff::diff("", "zzz\nzzz\n");
+        assert_eq!(vec![Line::Added("zzz"), Line::Added("zzz")], changes);
+        assert_eq!((2, 0), diff::counts(&changes));
+        assert_eq!(
+            vec!["@@ -0,0 +1,2 @@", "+zzz", "+zzz"],
+            diff::unified(&changes)
+        );
+        assert_eq!(vec![Line::Removed("zzz")], diff::diff("zzz\n", ""));
+    }
+
+    #[test]
+    fn identical() {
+        let changes = diff::diff("nap\ntuna\n", "nap\ntuna\n");
+        assert_eq!(vec![Line::Same("nap"), Line::Same("tuna")], changes);
+        assert_eq!((0, 0), diff::counts(&changes));
+        assert!(diff::unified(&changes).is_empty());
+        assert!(diff::diff("", "").is_empty());
+    }
+
+    #[test]
+    fn interleaved() {
+        let changes = diff::diff("a\nb\nc\nd\ne\n", "a\nB\nc\ne\nf\n");
+        assert_eq!(
+            vec![
+                Line::Same("a"),
+                Line::Removed("b"),
+                Line::Added("B"),
+                Line::Same("c"),
+                Line::Removed("d"),
+                Line::Same("e"),
+                Line::Added("f"),
+            ],
+            changes
+        );
+        assert_eq!((2, 2), diff::counts(&changes));
+    }
+
+    #[test]
+    fn trailing_newline() {
+        let changes = diff::diff("nap\ntuna", "nap\ntuna\n");
+        assert_eq!((0, 0), diff::counts(&changes));
+        let changes = diff::diff("nap\ntuna", "nap\ntuna\n\n");
+        assert_eq!(
+            vec![Line::Same("nap"), Line::Same("tuna"), Line::Added("")],
+            changes
+        );
+    }
+
+    #[test]
+    fn hunks() {
+        let old: String = (1..=20).map(|n| format!("{n}\n")).collect();
+        let new = old.replace("\n2\n", "\ntwo\n").replace("\n18\n", "\n");
+        let lines = diff::unified(&diff::diff(&old, &new));
+        assert_eq!(
+            vec![
+                "@@ -1,5 +1,5 @@",
+                " 1",
+                "-2",
+                "+two",
+                " 3",
+                " 4",
+                " 5",
+                "@@ -15,6 +15,5 @@",
+                " 15",
+                " 16",
+                " 17",
+                "-18",
+                " 19",
+                " 20",
+            ],
+            lines
+        );
+    }
+}
+
+mod date {
+    use crate::date;
+    use core::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!((1970, 1, 1), date::civil_from_days(0));
+        assert_eq!((2000, 2, 29), date::civil_from_days(11016));
+        assert_eq!((1969, 12, 31), date::civil_from_days(-1));
+        let at = UNIX_EPOCH + Duration::from_secs(19875 * 86_400 + 86_399);
+        assert_eq!("2024-06-01", date::format_date(at));
+    }
+
+    #[test]
+    fn timestamps() {
+        let at = UNIX_EPOCH + Duration::from_secs(19875 * 86_400 + 3723);
+        assert_eq!("2024-06-01T01:02:03Z", date::format_timestamp(at));
+        assert_eq!(Some(at), date::parse_timestamp("2024-06-01T01:02:03Z"));
+        assert_eq!(11016, date::days_from_civil(2000, 2, 29));
+        assert_eq!(-1, date::days_from_civil(1969, 12, 31));
+        assert_eq!(None, date::parse_timestamp("2024-06-01T24:00:00Z"));
+        assert_eq!(None, date::parse_timestamp("2024-06-01 01:02:03"));
+    }
+}
+
+mod clock {
+    use crate::clock::{self, Clock, ManualClock, OsRng, Rng, SeededRng};
+    use crate::library::{Book, Library};
+    use crate::shell::{self, Command, Session};
+    use core::time::Duration;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn seeded_rng_repeats() {
+        let (a, b) = (SeededRng::new(7), SeededRng::new(7));
+        let a: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        let b: Vec<u64> = (0..4).map(|_| b.next_u64()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a[0], a[1]);
+        assert_ne!(
+            a,
+            (0..4)
+                .map(|_| SeededRng::new(8).next_u64())
+                .collect::<Vec<_>>()
+        );
+        let bytes: [u8; 12] = clock::random_bytes(&SeededRng::new(7));
+        assert_eq!(a[0].to_le_bytes(), bytes[..8]);
+        assert_eq!(a[1].to_le_bytes()[..4], bytes[8..]);
+    }
+
+    #[test]
+    fn os_rng_varies() {
+        let draws: std::collections::HashSet<u64> = (0..8).map(|_| OsRng.next_u64()).collect();
+        assert_eq!(8, draws.len());
+    }
+
+    #[tokio::test]
+    async fn library_time_and_dice() {
+        let day = UNIX_EPOCH + Duration::from_secs(19875 * 86_400);
+        let clock = Arc::new(ManualClock::new(day));
+        let lib = Library::new_with(clock.clone(), Arc::new(SeededRng::new(7)));
+        let other = Library::new_with(Arc::new(ManualClock::new(day)), Arc::new(SeededRng::new(7)));
+        assert_eq!(day, lib.now());
+
+        /* the same seed rolls the same dice, but never picks the secrets */
+        assert_eq!(lib.random(), other.random());
+        assert_ne!(
+            lib.issue_token(Library::OPERATOR).await,
+            other.issue_token(Library::OPERATOR).await
+        );
+        lib.set_passphrase(Library::OPERATOR, "tuna").await;
+        other.set_passphrase(Library::OPERATOR, "tuna").await;
+        assert_eq!(lib.random(), other.random());
+
+        /* trash dates come from the library's clock, not the real one */
+        lib.add(
+            Book {
+                title: String::from("foo"),
+                author: String::from("cat 1"),
+                description: String::from("bar"),
+                content: String::from("baz\n"),
+                language: None,
+            },
+            Library::OPERATOR,
+        )
+        .await
+        .unwrap();
+        let mut session = Session::new(Library::OPERATOR);
+        let (mut stream, client) = super::scripted("foo\n1\n").await;
+        shell::do_cmd(&mut stream, Command::Delete, &lib, &mut session)
+            .await
+            .unwrap();
+        super::transcript(stream, client).await;
+        clock.advance(Duration::from_secs(3 * 86_400));
+        let (mut stream, client) = super::scripted("\n").await;
+        shell::do_cmd(&mut stream, Command::Trash, &lib, &mut session)
+            .await
+            .unwrap();
+        let out = super::transcript(stream, client).await;
+        assert!(
+            out.starts_with("1. 'foo', by cat 1. (trashed 2024-06-01)\n"),
+            "{out}"
+        );
+        assert_eq!(day + Duration::from_secs(3 * 86_400), clock.now());
+    }
+}
+
+mod export {
+    use crate::export;
+    use crate::library::Book;
+    use crate::sanitize;
+
+    #[test]
+    fn round_trip() {
+        let book = Book {
+            title: String::from("Dashes"),
+            author: String::from("cat 1"),
+            description: String::from("a book about --- lines"),
+            content: String::from("first\n---END BOOK---\n- listed\n\nlast\n"),
+            language: None,
+        };
+        let block = export::write_block(&book);
+        assert!(block.starts_with("---BEGIN BOOK---\nTitle: Dashes\n"));
+        assert!(block.ends_with("\nlast\n---END BOOK---\n"));
+        assert!(block.contains("\n- ---END BOOK---\n- - listed\n"));
+        assert_eq!(
+            Some(book),
+            export::read_block(&format!("junk\n{block}junk\n"))
+        );
+    }
+
+    #[test]
+    fn language_header() {
+        let book = Book {
+            title: String::from("La Sieste"),
+            author: String::from("cat 1"),
+            description: String::new(),
+            content: String::from("zzz\n"),
+            language: Some(String::from("fr-CA")),
+        };
+        let block = export::write_block(&book);
+        assert!(block.contains("\nLanguage: fr-CA\n\n"), "{block}");
+        assert_eq!(Some(book), export::read_block(&block));
+
+        let block = "---BEGIN BOOK---\nTitle: Nap\nLanguage: PT-br\n\nzzz\n---END BOOK---\n";
+        let read = export::read_block(block).unwrap();
+        assert_eq!(Some("pt-BR"), read.language.as_deref());
+        let block = block.replace("PT-br", "cat, mostly");
+        assert_eq!(None, export::read_block(&block).unwrap().language);
+    }
+
+    #[test]
+    fn strips_escapes() {
+        let book = Book {
+            title: String::from("\x1b[1mLoud\x1b[0m"),
+            author: String::from("cat 1"),
+            description: String::new(),
+            content: String::from("\x1b]0;pwned\x07plain\x07 text\x1b[2J\n"),
+            language: None,
+        };
+        let block = export::write_block(&book);
+        assert!(
+            !block.contains('\x1b') && !block.contains('\x07'),
+            "{block:?}"
+        );
+        let read = export::read_block(&block).unwrap();
+        assert_eq!("Loud", read.title);
+        assert_eq!("plain text\n", read.content);
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!("ab\tc\n", sanitize::strip_ansi("a\x1b[31;1mb\t\x1b(Bc\r\n"));
+        assert_eq!("x", sanitize::strip_ansi("\x1bPdevice\x1b\\x"));
+        assert_eq!("", sanitize::strip_ansi("\x1b[12;"));
+
+        /* colors are the sanitizer's business, not binary */
+        assert!(sanitize::looks_like_text(
+            "a \x1b[31mred\x1b[0m nap\r\n\tzzz\n"
+        ));
+        assert!(sanitize::looks_like_text(""));
+        assert!(sanitize::looks_like_text("one odd \x07 bell in a line\n"));
+        assert!(!sanitize::looks_like_text("PK\x03\x04\x14\0\x08\0\x08\0"));
+        assert!(!sanitize::looks_like_text(
+            "\u{fffd}\u{fffd}PNG\u{fffd}\u{fffd}\n"
+        ));
+    }
+}
+
+mod compress {
+    use crate::compress::{BookCache, StoredBook};
+    use crate::library::{Book, Library};
+    use std::sync::Arc;
+
+    fn book(content: &str) -> Book {
+        Book {
+            title: String::from("foo"),
+            author: String::from("cat 1"),
+            description: String::from("bar"),
+            content: String::from(content),
+            language: None,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for content in [
+            "",
+            "one line",
+            "two\nlines\n",
+            "\n\nblank\r\nfirst\n",
+            "ñ🐈\n",
+        ] {
+            let book = book(content);
+            let stored = StoredBook::new(&book);
+            assert_eq!(book, stored.unpack());
+            assert_eq!(content.len(), stored.content_len());
+            assert_eq!(content.lines().count(), stored.line_count());
+            let starts: Vec<&str> = stored
+                .line_offsets()
+                .iter()
+                .map(|&offset| content[offset..].lines().next().unwrap())
+                .collect();
+            assert_eq!(content.lines().collect::<Vec<_>>(), starts);
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_eviction() {
+        let lib = Library::new();
+        let mut ids = Vec::new();
+        let mut stored = Vec::new();
+        for n in 0..3 {
+            let book = book(&format!("book {n}"));
+            stored.push(Arc::new(StoredBook::new(&book)));
+            ids.push(lib.add(book, Library::OPERATOR).await.unwrap());
+        }
+
+        let mut cache = BookCache::new(2);
+        for (&id, stored) in ids.iter().zip(&stored) {
+            cache.insert(id, Arc::clone(stored), Arc::new(stored.unpack()));
+        }
+        assert_eq!(2, cache.len());
+        assert!(cache.get(ids[0], &stored[0]).is_none());
+        assert!(cache.get(ids[1], &stored[1]).is_some());
+
+        /* 1 was used last, so 2 goes first */
+        cache.insert(ids[0], Arc::clone(&stored[0]), Arc::new(stored[0].unpack()));
+        assert!(cache.get(ids[2], &stored[2]).is_none());
+        assert!(cache.get(ids[1], &stored[1]).is_some());
+
+        /* a replaced book is never served stale */
+        let replaced = Arc::new(StoredBook::new(&book("edited")));
+        assert!(cache.get(ids[1], &replaced).is_none());
+    }
+
+    /// Fifty 1 MB books of made-up prose, which deflates about as well as
+    /// real prose does.
+    #[tokio::test]
+    async fn corpus_memory() {
+        const WORDS: &[&str] = &[
+            "the",
+            "cat",
+            "sat",
+            "on",
+            "a",
+            "warm",
+            "windowsill",
+            "and",
+            "watched",
+            "birds",
+            "library",
+            "shelves",
+            "of",
+            "dusty",
+            "books",
+            "whiskers",
+            "twitched",
+            "quietly",
+            "while",
+            "rain",
+            "fell",
+            "over",
+            "city",
+            "purring",
+            "softly",
+            "dreaming",
+            "fish",
+            "kippers",
+            "yarn",
+            "midnight",
+            "stacks",
+            "pages",
+            "turned",
+            "slowly",
+            "in",
+            "dark",
+        ];
+        const BOOK_LEN: usize = 1 << 20;
+
+        let mut seed: u64 = 0x5eed;
+        let (mut raw, mut stored) = (0, 0);
+        let mut last = None;
+        for n in 0..50 {
+            let mut content = String::with_capacity(BOOK_LEN + 64);
+            while content.len() < BOOK_LEN {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                content.push_str(WORDS[(seed >> 33) as usize % WORDS.len()]);
+                content.push(if (seed >> 20).is_multiple_of(12) {
+                    '\n'
+                } else {
+                    ' '
+                });
+            }
+            let book = Book {
+                title: format!("volume {n}"),
+                ..book(&content)
+            };
+            let packed = StoredBook::new(&book);
+            raw += packed.content_len();
+            stored += packed.stored_len();
+            last = Some(book);
+        }
+
+        assert!(50 << 20 <= raw);
+        let lib = Library::new();
+        assert!(stored * 2 < raw, "{stored} of {raw} bytes");
+        let last = last.unwrap();
+        let id = lib.add(last.clone(), Library::OPERATOR).await.unwrap();
+        assert_eq!(last, *lib.lookup_book_by_id(id).await);
+    }
+}
+
+mod upload {
+    use super::{scripted, transcript};
+    use crate::library::{Book, Library};
+    use crate::shell::{self, Command, Session, ShellError};
+    use crate::upload::{self, Upload, UploadError};
+    use tokio::io::{AsyncWriteExt, BufStream};
+
+    fn framed(title: &str, content: &str, hash: &str) -> String {
+        format!("TITLE: {title}\nAUTHOR: cat 1\nDESC: bar\n\n{content}EOF {hash}\n")
+    }
+
+    #[test]
+    fn parse() {
+        let content = "first\n\nEOF but not the end\n";
+        let mut upload = Upload::new(Library::UPLOAD_MAX_LEN);
+        for line in framed("foo", content, "").lines().take(7) {
+            assert_eq!(None, upload::terminator(line));
+            upload.push_line(line);
+        }
+        let book = upload.finish(&upload::digest(content)).unwrap();
+        assert_eq!(
+            Book {
+                title: String::from("foo"),
+                author: String::from("cat 1"),
+                description: String::from("bar"),
+                content: String::from(content),
+                language: None,
+            },
+            book
+        );
+
+        let hash = upload::digest("");
+        assert_eq!(Some(&*hash), upload::terminator(&format!("EOF {hash}")));
+        assert_eq!(None, upload::terminator("EOF abc"));
+
+        let mut upload = Upload::new(Library::UPLOAD_MAX_LEN);
+        upload.push_line("TITLE: foo");
+        upload.push_line("");
+        assert_eq!(Err(UploadError::MissingAuthor), upload.finish(&hash));
+
+        let mut upload = Upload::new(Library::UPLOAD_MAX_LEN);
+        let binary = "\x00\x01\x02\x03PK\x04\x05\x06\x07";
+        for line in framed("foo", &format!("{binary}\n"), "").lines().take(5) {
+            upload.push_line(line);
+        }
+        let hash = upload::digest(&format!("{binary}\n"));
+        assert_eq!(Err(UploadError::NotText), upload.finish(&hash));
+
+        let hash = upload::digest("");
+        let mut upload = Upload::new(Library::UPLOAD_MAX_LEN);
+        upload.push_line("Title: foo");
+        upload.push_line("AUTHOR: cat 1");
+        upload.push_line("");
+        assert_eq!(Err(UploadError::BadHeader), upload.finish(&hash));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut upload = Upload::new(8);
+        for line in ["TITLE: foo", "AUTHOR: cat 1", "", "1234567", "8"] {
+            upload.push_line(line);
+        }
+        let hash = upload::digest("1234567\n8\n");
+        assert_eq!(Err(UploadError::TooLarge), upload.finish(&hash));
+
+        let mut upload = Upload::new(8);
+        for line in ["TITLE: foo", "AUTHOR: cat 1", "", "1234567"] {
+            upload.push_line(line);
+        }
+        assert!(upload.finish(&upload::digest("1234567\n")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn long_lines() {
+        let (mut stream, client) = scripted("12345678\r\n123456789\n").await;
+        let line = shell::read_text_line(&mut stream, "", 8).await.unwrap();
+        assert_eq!(Some("12345678"), line.as_deref());
+        let err = shell::read_text_line(&mut stream, "", 8).await.unwrap_err();
+        assert!(matches!(err, ShellError::Protocol(_)), "{err:?}");
+        transcript(stream, client).await;
+
+        /* refused without waiting for the end of it */
+        let (server, mut client) = tokio::io::duplex(1 << 16);
+        client.write_all(&[b'z'; 100]).await.unwrap();
+        let mut stream = BufStream::new(server);
+        let err = shell::read_text_line(&mut stream, "", 8).await.unwrap_err();
+        assert_eq!("client sent a line longer than 8 bytes", err.to_string());
+    }
+
+    #[tokio::test]
+    async fn replies() {
+        let lib = Library::new();
+        let mut session = Session::new(Library::OPERATOR);
+        let content = "a nap\nanother nap\n";
+
+        let mut input = framed("foo", content, &upload::digest(content));
+        input.push_str("checkin\n");
+        let (mut stream, client) = scripted(&input).await;
+        shell::do_cmd(&mut stream, Command::Upload, &lib, &mut session)
+            .await
+            .unwrap();
+        /* the rest is for the prompt, as if the upload never happened */
+        assert_eq!("checkin", shell::readln(&mut stream, "").await.unwrap());
+        assert_eq!("OK 0\n", transcript(stream, client).await);
+        let (_sim, id, ..) = lib.search("foo", Library::OPERATOR).await[0];
+        assert_eq!(content, lib.lookup_book_by_id(id).await.content);
+
+        let input = framed("foo", content, &upload::digest("something else"));
+        let (mut stream, client) = scripted(&input).await;
+        shell::do_cmd(&mut stream, Command::Upload, &lib, &mut session)
+            .await
+            .unwrap();
+        assert_eq!("ERR hash mismatch\n", transcript(stream, client).await);
+
+        let input = framed("foo", content, &upload::digest(content));
+        let (mut stream, client) = scripted(&input).await;
+        shell::do_cmd(&mut stream, Command::Upload, &lib, &mut session)
+            .await
+            .unwrap();
+        assert_eq!("ERR duplicate 0\n", transcript(stream, client).await);
+
+        /* hanging up partway adds nothing, not even a draft */
+        let input = framed("bar", content, "");
+        let (mut stream, client) = scripted(input.trim_end_matches("EOF \n")).await;
+        shell::do_cmd(&mut stream, Command::Upload, &lib, &mut session)
+            .await
+            .unwrap();
+        assert_eq!("", transcript(stream, client).await);
+        assert!(session.draft.is_none());
+        assert_eq!(1, lib.search("", Library::OPERATOR).await.len());
+    }
+}
+
+mod records {
+    use crate::clock::{ManualClock, OsRng};
+    use crate::library::{Book, Library, Loan};
+    use crate::records::{self, Format, Record};
+    use core::net::IpAddr;
+    use core::time::Duration;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn round_trip() {
+        let out = UNIX_EPOCH + Duration::from_secs(19875 * 86_400);
+        let records = vec![
+            Record {
+                title: String::from("commas \",\" and\nnewlines"),
+                borrower: String::from("whiskers"),
+                out,
+                back: Some(out + Duration::from_secs(90)),
+            },
+            Record {
+                title: String::from("plain"),
+                borrower: String::from("say \"meow\"\\"),
+                out,
+                back: None,
+            },
+        ];
+        for format in [Format::Csv, Format::Json] {
+            let text = records::write(format, &records);
+            assert_eq!(
+                Some(records.clone()),
+                records::read(format, &text),
+                "{text}"
+            );
+        }
+
+        let csv = records::write(Format::Csv, &records);
+        assert_eq!(
+            "title,borrower,out,back,seconds\n\
+             \"commas \"\",\"\" and\nnewlines\",whiskers,2024-06-01T00:00:00Z,2024-06-01T00:01:30Z,90\n\
+             plain,\"say \"\"meow\"\"\\\",2024-06-01T00:00:00Z,,\n",
+            csv
+        );
+        let json = records::write(Format::Json, &records[1..]);
+        assert!(json.contains("\"back\": null, \"seconds\": null"), "{json}");
+        assert_eq!(
+            Some(vec![]),
+            records::read(Format::Json, &records::write(Format::Json, &[]))
+        );
+        assert_eq!(None, records::read(Format::Csv, "title,borrower\n"));
+    }
+
+    #[tokio::test]
+    async fn loans() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lib = Library::new_with(clock.clone(), Arc::new(OsRng));
+        let whiskers = lib
+            .register_guest(IpAddr::from([10, 0, 0, 1]), "whiskers")
+            .await
+            .unwrap();
+        let book = Book {
+            title: String::from("naps"),
+            author: String::from("whiskers"),
+            description: String::new(),
+            content: String::from("zzz"),
+            language: None,
+        };
+        let id = lib.add(book, Library::OPERATOR).await.unwrap();
+
+        lib.checkout(id, whiskers).unwrap();
+        clock.advance(Duration::from_secs(60));
+        lib.checkin(id, whiskers).unwrap();
+        lib.checkout(id, whiskers).unwrap();
+        lib.undo_checkout(id, whiskers).unwrap();
+        lib.checkout(id, Library::OPERATOR).unwrap();
+        let back = UNIX_EPOCH + Duration::from_secs(60);
+        assert_eq!(
+            vec![
+                Loan {
+                    book_id: id,
+                    guest: whiskers,
+                    out: UNIX_EPOCH,
+                    back: Some(back),
+                },
+                Loan {
+                    book_id: id,
+                    guest: Library::OPERATOR,
+                    out: back,
+                    back: None,
+                },
+            ],
+            lib.loans()
+        );
+
+        let records = records::gather(&lib).await;
+        assert_eq!(2, records.len());
+        assert_eq!(
+            ("naps", "whiskers"),
+            (&*records[0].title, &*records[0].borrower)
+        );
+        assert_eq!(Some(Duration::from_secs(60)), records[0].duration());
+        assert_eq!(None, records[1].duration());
+    }
+}
+
+mod seed {
+    use crate::library::{Book, Library};
+    use crate::seed;
+
+    #[test]
+    fn front_matter() {
+        let book = seed::parse(
+            "long_nap",
+            "---\ntitle: The Long Nap\nauthor: whiskers\n---\nzzz\r\nzzz\n",
+        );
+        assert_eq!(
+            Book {
+                title: String::from("The Long Nap"),
+                author: String::from("whiskers"),
+                description: String::new(),
+                content: String::from("zzz\nzzz\n"),
+                language: None,
+            },
+            book
+        );
+
+        /* without an end, it's all content */
+        let book = seed::parse("long_nap", "---\ntitle: nope\n");
+        assert_eq!("long nap", book.title);
+        assert_eq!(seed::UNKNOWN_AUTHOR, book.author);
+        assert_eq!("---\ntitle: nope\n", book.content);
+    }
+
+    #[tokio::test]
+    async fn seed_dir() {
+        let dir = std::env::temp_dir().join(format!("catlib-seed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in [
+            ("naps.txt", &b"a whole afternoon of naps\n"[..]),
+            (
+                "tuna.md",
+                b"---\ntitle: On Tuna\nauthor: mittens\ndescription: fish\n---\nit's good\n",
+            ),
+            ("empty.txt", b"\n\n"),
+            ("binary.txt", b"\xff\xfe\x00kipper"),
+            ("notes.json", b"{}"),
+        ] {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let books = seed::read_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let books = books.unwrap();
+        let titles: Vec<&str> = books.iter().map(|book| book.title.as_str()).collect();
+        assert_eq!(vec!["naps", "On Tuna"], titles);
+
+        let lib = Library::builder().books(books).build().await;
+        for query in ["afternoon", "tuna"] {
+            assert_eq!(
+                1,
+                lib.search(query, Library::OPERATOR).await.len(),
+                "{query}"
+            );
+        }
+    }
+}
+
+mod server {
+    use crate::library::{Book, Library};
+    use crate::messages::{Flavor, Msg};
+    use crate::server::{self, Args};
+    use crate::shell::{BufferSizes, Command};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn sessions_outlive_each_other() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut seen = 0;
+        tokio::spawn(async move {
+            server::accept_each(&listener, |mut stream, _addr| {
+                seen += 1;
+                let first = seen == 1;
+                async move {
+                    assert!(!first, "the first guest finds a bug");
+                    stream.write_all(b"meow\n").await?;
+                    Ok(())
+                }
+            })
+            .await
+        });
+
+        let mut out = String::new();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.read_to_string(&mut out).await.unwrap();
+        assert_eq!("", out);
+
+        /* the listener carries on without them */
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.read_to_string(&mut out).await.unwrap();
+        assert_eq!("meow\n", out);
+    }
+
+    #[tokio::test]
+    async fn read_only_flag() {
+        let argv = ["--bind", "127.0.0.1:0", "--read-only"];
+        let args = Args::parse(argv.map(String::from)).unwrap();
+        let bind = args.binds[0];
+        let listener = TcpListener::bind(bind.addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let lib = Arc::new(Library::new());
+        let kippers = Book {
+            title: String::from("Kippers"),
+            author: String::from("tabby"),
+            description: String::from("a classic"),
+            content: String::from("fishy\n"),
+            language: None,
+        };
+        let kippers = lib.add(kippers, Library::OPERATOR).await.unwrap();
+        tokio::spawn(server::serve(
+            listener,
+            bind,
+            Arc::clone(&lib),
+            BufferSizes::default(),
+            None,
+        ));
+
+        let blocked: Vec<&str> = Command::ALL
+            .iter()
+            .filter(|cmd| cmd.mutates() || cmd.registers())
+            .map(|cmd| cmd.long())
+            .collect();
+        let mut input = blocked.join("\n");
+        input.push_str(
+            "\nsearch Kippers\ninfo Kippers\n1\nauthor tabby\n1\nq\nread Kippers\n1\nq\nquit\n",
+        );
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(input.as_bytes()).await.unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+
+        let refusal = Msg::Mirror.render(Flavor::Plain);
+        assert_eq!(blocked.len(), out.matches(&refusal).count(), "{out}");
+        assert!(out.contains("'Kippers', by tabby"), "{out}");
+        /* info, then reading by author and by title, with nothing checked out */
+        assert!(out.contains("a classic"), "{out}");
+        assert_eq!(2, out.matches("fishy").count(), "{out}");
+        assert!(lib.lookup_metadata(kippers).is_free());
+        assert!(lib.loans().is_empty());
+    }
+}
+
+mod snapshot {
+    use crate::clock::ManualClock;
+    use crate::library::{Book, Library, UpdateEntryError};
+    use crate::messages::{Flavor, Msg};
+    use crate::shell::{self, Command, Session};
+    use crate::snapshot::{self, Entry, Saver};
+    use core::time::Duration;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    fn book(title: &str) -> Book {
+        Book {
+            title: String::from(title),
+            author: String::from("cat 1"),
+            description: String::from("zzz"),
+            content: String::from("- a list\n---END BOOK---\n"),
+            language: Some(String::from("en")),
+        }
+    }
+
+    /// An empty directory of its own for `test`.
+    fn scratch(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("catlib-snapshot-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let trashed_at = UNIX_EPOCH + Duration::from_secs(1000);
+        let lib = Library::builder()
+            .clock(Arc::new(ManualClock::new(trashed_at)))
+            .books([book("Kippers")])
+            .guestbook(true)
+            .build()
+            .await;
+        let whiskers = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        lib.add(book("Naps"), whiskers).await.unwrap();
+        let gone = lib.add(book("Gone"), Library::OPERATOR).await.unwrap();
+        lib.soft_delete(gone, lib.now(), Library::OPERATOR).unwrap();
+
+        /* no guestbook, but the trash and who added what */
+        let entries = snapshot::gather(&lib).await;
+        let entry = |title: &str, notice, added_by: Option<&str>, trashed| Entry {
+            book: book(title),
+            notice,
+            added_by: added_by.map(String::from),
+            trashed,
+        };
+        let expected = vec![
+            entry("Kippers", true, None, None),
+            entry("Naps", false, Some("whiskers"), None),
+            entry("Gone", false, None, Some(trashed_at)),
+        ];
+        assert_eq!(expected, entries);
+        let text = snapshot::write(&entries);
+        assert_eq!(Some(entries), snapshot::read(&text));
+
+        /* cut short anywhere, it's corrupt */
+        let cut = text.rfind("---END BOOK---").unwrap();
+        assert_eq!(None, snapshot::read(&text[..cut]));
+        let cut = text.rfind("Notice").unwrap();
+        assert_eq!(None, snapshot::read(&text[..cut]));
+        let cut = text.rfind("Added-By").unwrap();
+        assert_eq!(None, snapshot::read(&text[..cut]));
+        assert_eq!(None, snapshot::read(""));
+        assert_eq!(None, snapshot::read(&text.replace("Added-By", "Added")));
+
+        let restored = Library::new();
+        snapshot::restore(&restored, expected.clone()).await;
+        let mut titles = Vec::new();
+        for id in restored.shelved() {
+            let meta = restored.lookup_metadata(id);
+            titles.push((restored.lookup_title(id).await, meta.protected));
+        }
+        assert_eq!(
+            vec![
+                (String::from("Kippers"), true),
+                (String::from("Naps"), false)
+            ],
+            titles
+        );
+        let trash = restored.trash();
+        assert_eq!(
+            vec![trashed_at],
+            trash.iter().map(|&(_, at)| at).collect::<Vec<_>>()
+        );
+        assert_eq!("Gone", restored.lookup_title(trash[0].0).await);
+
+        /* the operator's for good, but still saved as whiskers' */
+        let naps = restored.shelved()[1];
+        assert_eq!(Library::OPERATOR, restored.lookup_metadata(naps).added_by);
+        assert_eq!(expected, snapshot::gather(&restored).await);
+        let whiskers = restored
+            .register_guest("10.0.0.3".parse().unwrap(), "whiskers")
+            .await
+            .unwrap();
+        assert_eq!(Library::OPERATOR, restored.lookup_metadata(naps).added_by);
+        assert_eq!(
+            Err(UpdateEntryError::GuestMismatch),
+            restored
+                .update_metadata_fields(naps, Some("Mine".into()), None, None, whiskers)
+                .await
+        );
+        assert_eq!(expected, snapshot::gather(&restored).await);
+    }
+
+    async fn run(lib: &Library, session: &mut Session, cmd: Command, input: &str) -> String {
+        let (mut stream, client) = super::scripted(input).await;
+        shell::do_cmd(&mut stream, cmd, lib, session).await.unwrap();
+        super::transcript(stream, client).await
+    }
+
+    #[tokio::test]
+    async fn reload_mid_session() {
+        let path = scratch("reload").join("library");
+        let lib = Library::builder().data_file(path.clone()).build().await;
+        let naps = lib.add(book("Naps"), Library::OPERATOR).await.unwrap();
+        let kippers = lib.add(book("Kippers"), Library::OPERATOR).await.unwrap();
+        let gone = lib.add(book("Gone"), Library::OPERATOR).await.unwrap();
+        let mittens = lib
+            .register_guest("10.0.0.2".parse().unwrap(), "mittens")
+            .await
+            .unwrap();
+        lib.checkout(kippers, mittens).unwrap();
+        lib.checkout(gone, mittens).unwrap();
+        let mut session = Session::new(mittens);
+        let out = run(&lib, &mut session, Command::Search, "Gone\n").await;
+        assert!(out.contains("'Gone', by cat 1"), "{out}");
+
+        /* the operator rewrites one book, drops another and adds a third */
+        let mut entries = snapshot::gather(&lib).await;
+        entries[1].book.content = String::from("fishy\n");
+        entries.retain(|entry| entry.book.title != "Gone");
+        entries.push(Entry {
+            book: book("Tuna"),
+            notice: false,
+            added_by: None,
+            trashed: None,
+        });
+        snapshot::save(&path, &snapshot::write(&entries), 0).unwrap();
+        let mut operator = Session::new(Library::OPERATOR);
+        let out = run(&lib, &mut operator, Command::Reload, "").await;
+        let reloaded = Msg::Reloaded {
+            added: 1,
+            updated: 1,
+            removed: 1,
+            returned: 1,
+        };
+        assert_eq!(reloaded.render(Flavor::Plain), out);
+
+        /* the session goes on in the reloaded library */
+        let out = run(&lib, &mut session, Command::Search, "Tuna\n").await;
+        assert!(out.contains("'Tuna', by cat 1"), "{out}");
+        let out = run(&lib, &mut session, Command::Search, "Gone\n").await;
+        assert!(!out.contains("'Gone'"), "{out}");
+        let checkouts = lib.lookup_checkouts_by_guest(mittens).await;
+        assert_eq!(
+            vec![kippers],
+            checkouts.iter().map(|&(id, _)| id).collect::<Vec<_>>()
+        );
+        assert_eq!("fishy\n", lib.lookup_book_by_id(kippers).await.content);
+        assert!(lib
+            .loans()
+            .iter()
+            .any(|loan| loan.book_id == gone && loan.back.is_some()));
+        lib.check_invariants().await;
+
+        /* nothing's different the second time */
+        let out = run(&lib, &mut operator, Command::Reload, "").await;
+        let unchanged = Msg::Reloaded {
+            added: 0,
+            updated: 0,
+            removed: 0,
+            returned: 0,
+        };
+        assert_eq!(unchanged.render(Flavor::Plain), out);
+        assert_eq!(entries, snapshot::gather(&lib).await);
+
+        /* a file broken by hand changes nothing */
+        std::fs::write(&path, "Snapshot: 7 books\n").unwrap();
+        let out = run(&lib, &mut operator, Command::Reload, "").await;
+        assert_eq!(Msg::ReloadFailed.render(Flavor::Plain), out);
+        assert_eq!(entries, snapshot::gather(&lib).await);
+        assert!(lib.lookup_metadata(naps).is_free());
+
+        let out = run(&Library::new(), &mut operator, Command::Reload, "").await;
+        assert_eq!(Msg::NoDataFile.render(Flavor::Plain), out);
+    }
+
+    #[tokio::test]
+    async fn saves_only_changes() {
+        let path = scratch("changes").join("library");
+        let lib = Library::new();
+        lib.add(book("Naps"), Library::OPERATOR).await.unwrap();
+        let saver = Saver::new(&lib, path.clone(), 2);
+
+        assert!(!saver.save_if_changed(&lib).await);
+        assert!(!path.exists());
+
+        let tuna = lib.add(book("Tuna"), Library::OPERATOR).await.unwrap();
+        assert!(saver.save_if_changed(&lib).await);
+        let (entries, from) = snapshot::load(&path, 2).unwrap().unwrap();
+        assert_eq!(path, from);
+        assert_eq!(2, entries.len());
+        assert!(!saver.save_if_changed(&lib).await);
+
+        /* checkouts aren't saved, but edits are */
+        lib.checkout(tuna, Library::OPERATOR).unwrap();
+        assert!(!saver.save_if_changed(&lib).await);
+        lib.update_metadata_fields(tuna, Some("Sardines".into()), None, None, Library::OPERATOR)
+            .await
+            .unwrap();
+        assert!(saver.save_if_changed(&lib).await);
+
+        /* a failed save is tried again */
+        let missing = path.with_extension("d");
+        let saver = Saver::new(&lib, missing.join("library"), 2);
+        lib.add(book("Naps"), Library::OPERATOR).await.unwrap();
+        assert!(!saver.save_if_changed(&lib).await);
+        std::fs::create_dir(&missing).unwrap();
+        assert!(saver.save_if_changed(&lib).await);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = scratch("rotation");
+        let path = dir.join("library");
+        for n in 1..=4 {
+            snapshot::save(&path, &format!("version {n}\n"), 2).unwrap();
+        }
+        let read = |path: PathBuf| std::fs::read_to_string(path).ok();
+        assert_eq!(Some(String::from("version 4\n")), read(path.clone()));
+        assert_eq!(
+            Some(String::from("version 3\n")),
+            read(snapshot::sibling(&path, 1))
+        );
+        assert_eq!(
+            Some(String::from("version 2\n")),
+            read(snapshot::sibling(&path, 2))
+        );
+        assert_eq!(None, read(snapshot::sibling(&path, 3)));
+        assert_eq!(None, read(snapshot::sibling(&path, "tmp")));
+
+        /* the old file stays put until the new one is whole */
+        std::fs::create_dir(snapshot::sibling(&path, "tmp")).unwrap();
+        assert!(snapshot::save(&path, "version 5\n", 2).is_err());
+        assert_eq!(Some(String::from("version 4\n")), read(path.clone()));
+
+        let path = dir.join("unkept");
+        snapshot::save(&path, "only\n", 0).unwrap();
+        snapshot::save(&path, "only, again\n", 0).unwrap();
+        assert_eq!(None, read(snapshot::sibling(&path, 1)));
+    }
+
+    #[test]
+    fn corrupt_falls_back() {
+        let path = scratch("corrupt").join("library");
+        assert!(snapshot::load(&path, 2).unwrap().is_none());
+
+        let entries = vec![Entry {
+            book: book("Naps"),
+            notice: false,
+            added_by: None,
+            trashed: None,
+        }];
+        snapshot::save(&path, &snapshot::write(&entries), 2).unwrap();
+        let text = snapshot::write(&[]);
+        snapshot::save(&path, &text[..text.len() - 3], 2).unwrap();
+
+        let (loaded, from) = snapshot::load(&path, 2).unwrap().unwrap();
+        assert_eq!(entries, loaded);
+        assert_eq!(snapshot::sibling(&path, 1), from);
+
+        /* nothing readable at all is an error, not an empty library */
+        std::fs::write(snapshot::sibling(&path, 1), "junk").unwrap();
+        assert!(snapshot::load(&path, 2).is_err());
+        assert!(snapshot::load(&path, 0).is_err());
+    }
+}
+
+/// Whole sessions checked against transcripts in `tests/golden`.
+///
+/// A golden file is everything the server wrote, with each line the guest
+/// sent written in where they sent it, as `«line»`. Those lines are sent
+/// again on every run, and what comes back has to match the file exactly.
+/// To start a new one, write a file of just the `«line»`s to send, one per
+/// line; to accept changed output, run the tests with `UPDATE_GOLDEN=1` and
+/// look over the diff.
+mod golden {
+    use crate::clock::{ManualClock, SeededRng};
+    use crate::diff;
+    use crate::library::{Book, Library};
+    use crate::shell;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::collections::VecDeque;
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+    use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+    const OPEN: char = '«';
+    const CLOSE: char = '»';
+
+    /// A guest who sends `input` a line at a time, each only once the last
+    /// has been read, so the transcript shows exactly where it went.
+    struct Replay {
+        input: VecDeque<String>,
+        /// What's left of the line being read.
+        line: Vec<u8>,
+        transcript: String,
+    }
+
+    impl Replay {
+        fn new(input: Vec<String>) -> Self {
+            Self {
+                input: input.into(),
+                line: Vec::new(),
+                transcript: String::new(),
+            }
+        }
+    }
+
+    impl AsyncBufRead for Replay {
+        fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            if this.line.is_empty() {
+                /* hanging up instead would leave some prompts asking forever */
+                let Some(line) = this.input.pop_front() else {
+                    panic!("ran out of lines to send:\n{}", this.transcript);
+                };
+                this.transcript.push(OPEN);
+                this.transcript.push_str(&line);
+                this.transcript.push(CLOSE);
+                this.transcript.push('\n');
+                this.line = format!("{line}\n").into_bytes();
+            }
+            Poll::Ready(Ok(&this.line))
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            self.get_mut().line.drain(..amt);
+        }
+    }
+
+    impl AsyncRead for Replay {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let Poll::Ready(filled) = self.as_mut().poll_fill_buf(cx) else {
+                unreachable!("replays are always ready");
+            };
+            let n = filled?.len().min(buf.remaining());
+            buf.put_slice(&self.line[..n]);
+            self.consume(n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Replay {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.transcript.push_str(&String::from_utf8_lossy(buf));
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// The lines `golden` says the guest sent.
+    fn inputs(golden: &str) -> Vec<String> {
+        golden
+            .lines()
+            .filter_map(|line| {
+                let start = line.find(OPEN)?;
+                let line = line[start + OPEN.len_utf8()..].strip_suffix(CLOSE)?;
+                Some(line.to_string())
+            })
+            .collect()
+    }
+
+    /// The same library every time: a stopped clock, fixed dice, and a few
+    /// books.
+    async fn library() -> Library {
+        let lib = Library::new_with(
+            Arc::new(ManualClock::new(UNIX_EPOCH)),
+            Arc::new(SeededRng::new(7)),
+        );
+        let numbered =
+            |word: &str, n: usize| (1..=n).map(|i| format!("{word} {i}\n")).collect::<String>();
+        for (title, content) in [
+            ("The Long Nap", String::from("zzz\nzzz\nzzz\n")),
+            ("Tuna, a Memoir", String::from("it was delicious.\n")),
+            ("Five Purrs", numbered("purr", 5)),
+            ("Fifty Meows", numbered("meow", 50)),
+        ] {
+            let book = Book {
+                title: String::from(title),
+                author: String::from("whiskers"),
+                description: String::from("a classic"),
+                content,
+                language: None,
+            };
+            lib.add(book, Library::OPERATOR).await.unwrap();
+        }
+        lib
+    }
+
+    /// `transcript` with each login token, fresh from the OS every run,
+    /// written as `<token>`.
+    fn hide_tokens(transcript: &str) -> String {
+        const SHOWN: &str = "your login token is ";
+        transcript
+            .split_inclusive('\n')
+            .map(|line| match line.strip_prefix(SHOWN) {
+                Some(_) => format!("{SHOWN}<token>\n"),
+                None => line.to_owned(),
+            })
+            .collect()
+    }
+
+    /// Play `tests/golden/<name>.txt` back to a new guest and compare.
+    async fn check(name: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+            .iter()
+            .collect::<PathBuf>()
+            .with_extension("txt");
+        let golden = std::fs::read_to_string(&path).unwrap();
+
+        let lib = library().await;
+        let mut replay = Replay::new(inputs(&golden));
+        let addr = "10.0.0.2:1234".parse().unwrap();
+        shell::process_socket(&mut replay, addr, &lib, shell::Capabilities::FULL)
+            .await
+            .unwrap();
+        let actual = hide_tokens(&replay.transcript);
+        assert!(replay.input.is_empty(), "{name} ended early:\n{actual}");
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &actual).unwrap();
+            return;
+        }
+        if actual != golden {
+            let changes = diff::unified(&diff::diff(&golden, &actual)).join("\n");
+            panic!(
+                "{} doesn't match (UPDATE_GOLDEN=1 to accept):\n{changes}",
+                path.display()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn help() {
+        check("help").await;
+    }
+
+    #[tokio::test]
+    async fn register() {
+        check("register").await;
+    }
+
+    #[tokio::test]
+    async fn borrow_and_read() {
+        check("borrow_and_read").await;
+    }
+
+    #[tokio::test]
+    async fn read_short_book() {
+        check("read_short_book").await;
+    }
+
+    #[tokio::test]
+    async fn read_long_book() {
+        check("read_long_book").await;
+    }
 }
@@ -0,0 +1,213 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::mpsc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    In,
+    Out,
+}
+
+struct Entry {
+    at: SystemTime,
+    direction: Direction,
+    bytes: Vec<u8>,
+    redacted: bool,
+}
+
+/// Stream wrapper that copies everything read from and written to the guest
+/// into a transcript file for the operator.
+///
+/// Transcript writes happen on a separate task, so a full disk or a deleted
+/// directory only ever shows up in the server log, never to the guest.
+pub struct Transcript<S> {
+    inner: S,
+    log: Option<mpsc::UnboundedSender<Entry>>,
+
+    /// Whatever is read or written right after one of these (e.g. the answer
+    /// to a passphrase prompt) is logged as redacted.
+    secret_prompts: &'static [&'static str],
+    /// The last few bytes written, to recognize secret prompts.
+    tail: Vec<u8>,
+    /// Copy of the inner buffer from the last `poll_fill_buf`, so `consume`
+    /// knows what it consumed.
+    filled: Vec<u8>,
+}
+
+impl<S> Transcript<S> {
+    const TAIL_LEN: usize = 256;
+
+    /// Pass everything straight through without logging.
+    pub fn disabled(inner: S) -> Self {
+        Self {
+            inner,
+            log: None,
+            secret_prompts: &[],
+            tail: Vec::new(),
+            filled: Vec::new(),
+        }
+    }
+
+    /// Log to a new file at `path`, which must not already exist. On failure,
+    /// `inner` is handed back so the session can go on without a transcript.
+    pub async fn create(
+        inner: S,
+        path: &Path,
+        secret_prompts: &'static [&'static str],
+    ) -> Result<Self, (S, io::Error)> {
+        let open = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await;
+        let mut file = match open {
+            Ok(file) => file,
+            Err(err) => return Err((inner, err)),
+        };
+        let (log, mut entries) = mpsc::unbounded_channel::<Entry>();
+
+        let path = path.to_owned();
+        tokio::spawn(async move {
+            while let Some(entry) = entries.recv().await {
+                let result = async {
+                    file.write_all(entry.render().as_bytes()).await?;
+                    file.flush().await
+                }
+                .await;
+                if let Err(err) = result {
+                    tracing::warn!(
+                        path = format_args!("{}", path.display()),
+                        "transcript write failed: {err}"
+                    );
+                }
+            }
+        });
+
+        Ok(Self {
+            inner,
+            log: Some(log),
+            secret_prompts,
+            tail: Vec::new(),
+            filled: Vec::new(),
+        })
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        let redacted = self
+            .secret_prompts
+            .iter()
+            .any(|prompt| self.tail.ends_with(prompt.as_bytes()));
+
+        if direction == Direction::Out {
+            self.tail.extend_from_slice(bytes);
+            let excess = self.tail.len().saturating_sub(Self::TAIL_LEN);
+            self.tail.drain(..excess);
+        }
+
+        if self.log.is_none() {
+            return;
+        }
+        let entry = Entry {
+            at: SystemTime::now(),
+            direction,
+            bytes: bytes.to_vec(),
+            redacted,
+        };
+        if let Some(log) = &self.log {
+            if log.send(entry).is_err() {
+                self.log = None;
+            }
+        }
+    }
+}
+
+impl Entry {
+    fn render(&self) -> String {
+        let at = self.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let marker = match self.direction {
+            Direction::In => '<',
+            Direction::Out => '>',
+        };
+        let body = if self.redacted {
+            String::from("[redacted]")
+        } else {
+            String::from_utf8_lossy(&self.bytes)
+                .escape_debug()
+                .to_string()
+        };
+        format!(
+            "{}.{:03} {marker} {body}\n",
+            at.as_secs(),
+            at.subsec_millis()
+        )
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Transcript<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = buf.filled()[before..].to_vec();
+            this.record(Direction::In, &read);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncBufRead + Unpin> AsyncBufRead for Transcript<S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_fill_buf(cx) {
+            Poll::Ready(Ok(buf)) => {
+                this.filled.clear();
+                this.filled.extend_from_slice(buf);
+                Poll::Ready(Ok(buf))
+            }
+            other => other,
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        let consumed: Vec<u8> = this.filled.drain(..amt.min(this.filled.len())).collect();
+        this.record(Direction::In, &consumed);
+        Pin::new(&mut this.inner).consume(amt);
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Transcript<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            this.record(Direction::Out, &buf[..n]);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,156 @@
+//! The framed format `upload` reads, so scripts can add books without
+//! answering prompts:
+//!
+//! ```text
+//! TITLE: The Long Nap
+//! AUTHOR: whiskers
+//! DESC: optional, like this line
+//! LANG: optional, a language tag like en or pt-BR
+//!
+//! the content, as many lines as it takes
+//! EOF <SHA-256 of the content, in hex>
+//! ```
+//!
+//! The content is every line between the blank line and the `EOF` line,
+//! each ending in `\n`. The library answers with a single line, `OK <book
+//! id>` or `ERR <reason>`.
+
+use core::fmt;
+use sha2::{Digest, Sha256};
+
+use crate::library::{Book, BookID};
+use crate::sanitize;
+
+pub const TERMINATOR: &str = "EOF ";
+
+/// Why an upload wasn't added, as written after `ERR `.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadError {
+    BadHeader,
+    MissingTitle,
+    MissingAuthor,
+    TooLarge,
+    HashMismatch,
+    /// The content looks binary, as in [`sanitize::looks_like_text`].
+    NotText,
+    /// The guest has as many books on the shelves as they may.
+    ContributionLimit,
+    /// The same book is already on the shelves.
+    Duplicate(BookID),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadHeader => f.write_str("bad header"),
+            Self::MissingTitle => f.write_str("missing title"),
+            Self::MissingAuthor => f.write_str("missing author"),
+            Self::TooLarge => f.write_str("too large"),
+            Self::HashMismatch => f.write_str("hash mismatch"),
+            Self::NotText => f.write_str("not text"),
+            Self::ContributionLimit => f.write_str("too many books"),
+            Self::Duplicate(book_id) => write!(f, "duplicate {book_id}"),
+        }
+    }
+}
+
+/// The hex SHA-256 that ends an upload of `content`.
+pub fn digest(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// The hash on the line that ends an upload, if `line` is that line.
+pub fn terminator(line: &str) -> Option<&str> {
+    let hash = line.strip_prefix(TERMINATOR)?;
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())).then_some(hash)
+}
+
+/// An upload read so far, a line at a time. Whatever is wrong with it is
+/// only reported once it's over, so the rest of it is never mistaken for
+/// commands.
+#[derive(Debug)]
+pub struct Upload {
+    book: Book,
+    /// Past the blank line after the headers.
+    in_content: bool,
+    /// Most bytes of content to take.
+    max_len: usize,
+    error: Option<UploadError>,
+}
+
+impl Upload {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            book: Book {
+                title: String::new(),
+                author: String::new(),
+                description: String::new(),
+                content: String::new(),
+                language: None,
+            },
+            in_content: false,
+            max_len,
+            error: None,
+        }
+    }
+
+    /// Take the next line, which mustn't be the [`terminator`].
+    pub fn push_line(&mut self, line: &str) {
+        if self.in_content {
+            if self.max_len < self.book.content.len() + line.len() + 1 {
+                self.error.get_or_insert(UploadError::TooLarge);
+                self.book.content = String::new();
+            }
+            if self.error.is_none() {
+                self.book.content.push_str(line);
+                self.book.content.push('\n');
+            }
+            return;
+        }
+
+        if line.is_empty() {
+            self.in_content = true;
+            return;
+        }
+        if let Some(("LANG", value)) = line.split_once(": ") {
+            match Book::language_tag(value) {
+                Some(tag) => self.book.language = Some(tag),
+                None => {
+                    self.error.get_or_insert(UploadError::BadHeader);
+                }
+            }
+            return;
+        }
+        let (field, value) = match line.split_once(": ") {
+            Some(("TITLE", value)) => (&mut self.book.title, value),
+            Some(("AUTHOR", value)) => (&mut self.book.author, value),
+            Some(("DESC", value)) => (&mut self.book.description, value),
+            _ => {
+                self.error.get_or_insert(UploadError::BadHeader);
+                return;
+            }
+        };
+        *field = value.trim().to_string();
+    }
+
+    /// The book, if it was all there and its content hashes to `hash`.
+    pub fn finish(self, hash: &str) -> Result<Book, UploadError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        if self.book.title.is_empty() {
+            return Err(UploadError::MissingTitle);
+        }
+        if self.book.author.is_empty() {
+            return Err(UploadError::MissingAuthor);
+        }
+        if !digest(&self.book.content).eq_ignore_ascii_case(hash) {
+            return Err(UploadError::HashMismatch);
+        }
+        /* nobody is there to ask whether they meant it */
+        if !sanitize::looks_like_text(&self.book.content) {
+            return Err(UploadError::NotText);
+        }
+        Ok(self.book)
+    }
+}